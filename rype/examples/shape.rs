@@ -3,7 +3,7 @@
 use std::env;
 
 use geom::size2;
-use rype::{Direction, Face, Script};
+use rype::{Direction, Face, Script, ShapingOptions};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -20,8 +20,10 @@ fn try_main(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
     let face = Face::open(&args[1], 0)?;
     let scaled1 = face.scale(12, size2(96, 96));
     let scaled2 = face.scale(24, size2(96, 96));
-    let shaped1 = scaled1.shape(&args[2], Script::Default, Direction::LeftToRight);
-    let shaped2 = scaled2.shape(&args[2], Script::Latin, Direction::LeftToRight);
+    let options1 = ShapingOptions::new(Script::Default, Direction::LeftToRight);
+    let options2 = ShapingOptions::new(Script::Latin, Direction::LeftToRight);
+    let shaped1 = scaled1.shape(&args[2], &options1);
+    let shaped2 = scaled2.shape(&args[2], &options2);
     eprintln!("Shaped1: {:#?}", shaped1);
     eprintln!("Shaped2: {:#?}", shaped2);
     Ok(())
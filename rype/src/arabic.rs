@@ -0,0 +1,300 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cmp::Ordering;
+
+use crate::common::SubstGlyph;
+use crate::features::{Features, GlyphFeatures};
+use JoiningType::{C, D, L, R, T, U};
+
+/// Features applied by the Arabic shaper, on top of the defaults
+pub(crate) static ARABIC_FEATURES: [Features; 9] = [
+    Features::IsolatedForms,
+    Features::TerminalForms,
+    Features::TerminalForm2,
+    Features::TerminalForm3,
+    Features::MedialForms,
+    Features::MedialForms2,
+    Features::InitialForms,
+    Features::ContextualAlternatives,
+    Features::Mset,
+];
+
+/// Enable positional forms for glyphs, based on how their characters join with their neighbours.
+/// Expects one glyph per character, as before substitution.
+pub(crate) fn setup_glyph_features(codepoints: &[char], glyphs: &mut [SubstGlyph]) {
+    let mut prev: Option<usize> = None;
+    let mut state = 0;
+    for (i, c) in codepoints.iter().enumerate() {
+        let column = match column(*c) {
+            Some(column) => column,
+            None => continue, // Transparent characters don't affect joining
+        };
+        let (prev_action, cur_action, next_state) = STATE_TABLE[state][column];
+        // A later character can change the form of the previous one, which replaces its old form
+        if let Some(prev) = prev {
+            if !prev_action.is_empty() {
+                glyphs[prev].features.remove(FORMS);
+                glyphs[prev].features |= prev_action;
+            }
+        }
+        glyphs[i].features.remove(FORMS);
+        glyphs[i].features |= cur_action;
+        prev = Some(i);
+        state = next_state;
+    }
+}
+
+// Column in the state table for a character, or None if it is transparent
+fn column(c: char) -> Option<usize> {
+    match c {
+        '\u{0710}' => return Some(4), // Alaph
+        '\u{0715}' | '\u{0716}' | '\u{072A}' | '\u{072F}' => return Some(5), // Dalath, Rish
+        _ => {}
+    }
+    match joining_type(c) {
+        JoiningType::U => Some(0),
+        JoiningType::L => Some(1),
+        JoiningType::R => Some(2),
+        JoiningType::D | JoiningType::C => Some(3),
+        JoiningType::T => None,
+    }
+}
+
+fn joining_type(c: char) -> JoiningType {
+    let c = c as u32;
+    JOINING_TYPES
+        .binary_search_by(|(start, end, _)| {
+            if *end < c {
+                Ordering::Less
+            } else if *start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map(|i| JOINING_TYPES[i].2)
+        .unwrap_or(JoiningType::U)
+}
+
+const NONE: GlyphFeatures = GlyphFeatures::empty();
+const ISOL: GlyphFeatures = GlyphFeatures::ISOL;
+const INIT: GlyphFeatures = GlyphFeatures::INIT;
+const MEDI: GlyphFeatures = GlyphFeatures::MEDI;
+const MED2: GlyphFeatures = GlyphFeatures::MED2;
+const FINA: GlyphFeatures = GlyphFeatures::FINA;
+const FIN2: GlyphFeatures = GlyphFeatures::FIN2;
+const FIN3: GlyphFeatures = GlyphFeatures::FIN3;
+
+// All positional forms, of which a glyph gets at most one
+const FORMS: GlyphFeatures = GlyphFeatures::from_bits_truncate(
+    ISOL.bits() | INIT.bits() | MEDI.bits() | MED2.bits() | FINA.bits() | FIN2.bits() | FIN3.bits(),
+);
+
+// Joining state machine. Indexed by state, and then by the column of the current character
+// (U, L, R, D, Alaph, Dalath/Rish). Entries are the form for the previous character, the form for
+// the current character, and the next state.
+static STATE_TABLE: [[(GlyphFeatures, GlyphFeatures, usize); 6]; 7] = [
+    // Previous character was U, and doesn't join
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (NONE, ISOL, 1),
+        (NONE, ISOL, 2),
+        (NONE, ISOL, 1),
+        (NONE, ISOL, 6),
+    ],
+    // Previous character was R or an isolated Alaph, and doesn't join
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (NONE, ISOL, 1),
+        (NONE, ISOL, 2),
+        (NONE, FIN2, 5),
+        (NONE, ISOL, 6),
+    ],
+    // Previous character was D or L in isolated form, and joins
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (INIT, FINA, 1),
+        (INIT, FINA, 3),
+        (INIT, FINA, 4),
+        (INIT, FINA, 6),
+    ],
+    // Previous character was D in final form, and joins
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (MEDI, FINA, 1),
+        (MEDI, FINA, 3),
+        (MEDI, FINA, 4),
+        (MEDI, FINA, 6),
+    ],
+    // Previous character was a final Alaph, and doesn't join
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (MED2, ISOL, 1),
+        (MED2, ISOL, 2),
+        (MED2, FIN2, 5),
+        (MED2, ISOL, 6),
+    ],
+    // Previous character was an Alaph in second or third final form, and doesn't join
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (ISOL, ISOL, 1),
+        (ISOL, ISOL, 2),
+        (ISOL, FIN2, 5),
+        (ISOL, ISOL, 6),
+    ],
+    // Previous character was Dalath or Rish, and doesn't join
+    [
+        (NONE, NONE, 0),
+        (NONE, ISOL, 2),
+        (NONE, ISOL, 1),
+        (NONE, ISOL, 2),
+        (NONE, FIN3, 5),
+        (NONE, ISOL, 6),
+    ],
+];
+
+// Unicode joining types
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum JoiningType {
+    U, // Non-joining
+    L, // Left-joining
+    R, // Right-joining
+    D, // Dual-joining
+    C, // Join-causing
+    T, // Transparent
+}
+
+// Joining types for ranges of characters, from ArabicShaping.txt. Characters with general category
+// Mn, Me or Cf are transparent. Everything else is non-joining.
+static JOINING_TYPES: [(u32, u32, JoiningType); 124] = [
+    (0x0300, 0x036F, T),
+    (0x0483, 0x0489, T),
+    (0x0591, 0x05BD, T),
+    (0x05BF, 0x05BF, T),
+    (0x05C1, 0x05C2, T),
+    (0x05C4, 0x05C5, T),
+    (0x05C7, 0x05C7, T),
+    (0x0610, 0x061A, T),
+    (0x061C, 0x061C, T),
+    (0x0620, 0x0620, D),
+    (0x0621, 0x0621, U),
+    (0x0622, 0x0625, R),
+    (0x0626, 0x0626, D),
+    (0x0627, 0x0627, R),
+    (0x0628, 0x0628, D),
+    (0x0629, 0x0629, R),
+    (0x062A, 0x062E, D),
+    (0x062F, 0x0632, R),
+    (0x0633, 0x063F, D),
+    (0x0640, 0x0640, C),
+    (0x0641, 0x0647, D),
+    (0x0648, 0x0648, R),
+    (0x0649, 0x064A, D),
+    (0x064B, 0x065F, T),
+    (0x066E, 0x066F, D),
+    (0x0670, 0x0670, T),
+    (0x0671, 0x0673, R),
+    (0x0675, 0x0677, R),
+    (0x0678, 0x0687, D),
+    (0x0688, 0x0699, R),
+    (0x069A, 0x06BF, D),
+    (0x06C0, 0x06C0, R),
+    (0x06C1, 0x06C2, D),
+    (0x06C3, 0x06CB, R),
+    (0x06CC, 0x06CC, D),
+    (0x06CD, 0x06CD, R),
+    (0x06CE, 0x06CE, D),
+    (0x06CF, 0x06CF, R),
+    (0x06D0, 0x06D1, D),
+    (0x06D2, 0x06D3, R),
+    (0x06D5, 0x06D5, R),
+    (0x06D6, 0x06DC, T),
+    (0x06DF, 0x06E4, T),
+    (0x06E7, 0x06E8, T),
+    (0x06EA, 0x06ED, T),
+    (0x06EE, 0x06EF, R),
+    (0x06FA, 0x06FC, D),
+    (0x06FF, 0x06FF, D),
+    (0x0710, 0x0710, R),
+    (0x0711, 0x0711, T),
+    (0x0712, 0x0714, D),
+    (0x0715, 0x0719, R),
+    (0x071A, 0x071D, D),
+    (0x071E, 0x071E, R),
+    (0x071F, 0x0727, D),
+    (0x0728, 0x0728, R),
+    (0x0729, 0x0729, D),
+    (0x072A, 0x072A, R),
+    (0x072B, 0x072B, D),
+    (0x072C, 0x072C, R),
+    (0x072D, 0x072E, D),
+    (0x072F, 0x072F, R),
+    (0x0730, 0x074A, T),
+    (0x074D, 0x074D, R),
+    (0x074E, 0x0758, D),
+    (0x0759, 0x075B, R),
+    (0x075C, 0x076A, D),
+    (0x076B, 0x076C, R),
+    (0x076D, 0x0770, D),
+    (0x0771, 0x0771, R),
+    (0x0772, 0x0772, D),
+    (0x0773, 0x0774, R),
+    (0x0775, 0x0777, D),
+    (0x0778, 0x0779, R),
+    (0x077A, 0x077F, D),
+    (0x07A6, 0x07B0, T),
+    (0x07CA, 0x07EA, D),
+    (0x07EB, 0x07F3, T),
+    (0x07FA, 0x07FA, C),
+    (0x07FD, 0x07FD, T),
+    (0x0816, 0x0819, T),
+    (0x081B, 0x0823, T),
+    (0x0825, 0x0827, T),
+    (0x0829, 0x082D, T),
+    (0x0859, 0x085B, T),
+    (0x0860, 0x0860, D),
+    (0x0862, 0x0865, D),
+    (0x0867, 0x0867, R),
+    (0x0868, 0x0868, D),
+    (0x0869, 0x086A, R),
+    (0x0898, 0x089F, T),
+    (0x08A0, 0x08A9, D),
+    (0x08AA, 0x08AC, R),
+    (0x08AE, 0x08AE, R),
+    (0x08AF, 0x08B0, D),
+    (0x08B1, 0x08B2, R),
+    (0x08B3, 0x08B8, D),
+    (0x08B9, 0x08B9, R),
+    (0x08BA, 0x08C8, D),
+    (0x08CA, 0x08E1, T),
+    (0x08E3, 0x08FF, T),
+    (0x1807, 0x1807, D),
+    (0x180A, 0x180A, C),
+    (0x180B, 0x180D, T),
+    (0x180F, 0x180F, T),
+    (0x1820, 0x1878, D),
+    (0x1885, 0x1886, T),
+    (0x1887, 0x18A8, D),
+    (0x18A9, 0x18A9, T),
+    (0x18AA, 0x18AA, D),
+    (0x1AB0, 0x1ACE, T),
+    (0x1DC0, 0x1DFF, T),
+    (0x200B, 0x200B, T),
+    (0x200D, 0x200D, C),
+    (0x200E, 0x200F, T),
+    (0x202A, 0x202E, T),
+    (0x2060, 0x2064, T),
+    (0x2066, 0x206F, T),
+    (0x20D0, 0x20F0, T),
+    (0xA840, 0xA871, D),
+    (0xA872, 0xA872, L),
+    (0xFE00, 0xFE0F, T),
+    (0xFE20, 0xFE2F, T),
+    (0xFEFF, 0xFEFF, T),
+];
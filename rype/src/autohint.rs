@@ -0,0 +1,227 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{point2, Point2D};
+
+use crate::outline::{Outline, Segment};
+
+/// Light grid-fitting for outlines without usable instructions. Horizontal edges are aligned to
+/// blue zones and the pixel grid, only moving points vertically, so advances are unchanged
+#[derive(Debug)]
+pub(crate) struct Autohinter {
+    blues: Vec<f32>, // Heights of blue zones in pixels, including the baseline
+    ppem: f32,
+}
+
+impl Autohinter {
+    /// Create autohinter from blue zones in font units, scaled vertically to pixels
+    pub(crate) fn new(blues: &[f32], scale: f32, ppem: f32) -> Autohinter {
+        Autohinter {
+            blues: blues.iter().map(|b| b * scale).collect(),
+            ppem,
+        }
+    }
+
+    /// Grid-fit an outline which has been scaled to pixels
+    pub(crate) fn hint(&self, outline: &Outline) -> Outline {
+        let mut edges = find_edges(outline, self.ppem * MIN_EDGE_LEN);
+        if edges.is_empty() {
+            return outline.clone();
+        }
+        self.align_to_blues(&mut edges);
+        self.align_stems(&mut edges);
+        for edge in edges.iter_mut().filter(|e| e.fitted.is_none()) {
+            edge.fitted = Some(edge.y.round());
+        }
+        // Move points between edges by interpolating, keeping their order
+        let mut map = edges
+            .iter()
+            .map(|e| (e.y, e.fitted.unwrap_or(e.y)))
+            .collect::<Vec<_>>();
+        map.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        map.dedup_by(|a, b| a.0 == b.0);
+        for i in 1..map.len() {
+            map[i].1 = map[i].1.max(map[i - 1].1);
+        }
+        outline.map_points(|p| point2(p.x, interpolate(&map, p.y)))
+    }
+
+    // Snap edges near a blue zone to it. Tops of shapes snap to zones above the baseline, and
+    // bottoms snap to the baseline. Overshoots are kept only if they're at least half a pixel
+    fn align_to_blues(&self, edges: &mut [Edge]) {
+        let (fuzz, max_overshoot) = (self.ppem * BLUE_FUZZ, self.ppem * MAX_OVERSHOOT);
+        for edge in edges.iter_mut() {
+            for blue in &self.blues {
+                let is_baseline = *blue == 0.0;
+                if edge.is_top == is_baseline {
+                    continue;
+                }
+                let overshoot = if edge.is_top {
+                    edge.y - blue
+                } else {
+                    blue - edge.y
+                };
+                if overshoot < -fuzz || overshoot > max_overshoot {
+                    continue;
+                }
+                let overshoot = if overshoot >= 0.5 {
+                    overshoot.round()
+                } else {
+                    0.0
+                };
+                edge.fitted = Some(if edge.is_top {
+                    blue.round() + overshoot
+                } else {
+                    blue.round() - overshoot
+                });
+                edge.is_blue = true;
+                break;
+            }
+        }
+    }
+
+    // Pair each top edge with the nearest bottom edge below it which overlaps horizontally, and
+    // round the distance between them to whole pixels
+    fn align_stems(&self, edges: &mut [Edge]) {
+        let max_width = self.ppem * MAX_STEM_WIDTH;
+        for i in 0..edges.len() {
+            if !edges[i].is_top {
+                continue;
+            }
+            let bottom = (0..edges.len())
+                .filter(|j| {
+                    let (top, bottom) = (&edges[i], &edges[*j]);
+                    !bottom.is_top
+                        && bottom.y < top.y
+                        && top.y - bottom.y <= max_width
+                        && bottom.x_min <= top.x_max
+                        && top.x_min <= bottom.x_max
+                })
+                .min_by(|a, b| edges[*b].y.partial_cmp(&edges[*a].y).unwrap());
+            let j = match bottom {
+                Some(j) => j,
+                None => continue,
+            };
+            let width = edges[i].y - edges[j].y;
+            let fitted_width = width.round().max(1.0);
+            match (edges[i].is_blue, edges[j].is_blue) {
+                (true, true) => continue,
+                (true, false) => {
+                    let top = edges[i].fitted.unwrap_or(edges[i].y);
+                    edges[j].fitted = Some(top - fitted_width);
+                }
+                (false, true) => {
+                    let bottom = edges[j].fitted.unwrap_or(edges[j].y);
+                    edges[i].fitted = Some(bottom + fitted_width);
+                }
+                (false, false) => {
+                    // Keep the middle of the stem in place as far as possible
+                    let bottom = ((edges[i].y + edges[j].y - fitted_width) / 2.0).round();
+                    edges[j].fitted = Some(bottom);
+                    edges[i].fitted = Some(bottom + fitted_width);
+                }
+            }
+        }
+    }
+}
+
+// Sizes, relative to the em
+const MIN_EDGE_LEN: f32 = 0.02;
+const BLUE_FUZZ: f32 = 0.02;
+const MAX_OVERSHOOT: f32 = 0.03;
+const MAX_STEM_WIDTH: f32 = 0.25;
+
+// Horizontal edge in the outline
+#[derive(Debug)]
+struct Edge {
+    y: f32,
+    x_min: f32,
+    x_max: f32,
+    is_top: bool,        // Is the shape filled below this edge?
+    is_blue: bool,       // Is this aligned to a blue zone?
+    fitted: Option<f32>, // Grid-fitted height
+}
+
+// Find horizontal lines, and curves which start or end horizontally
+fn find_edges(outline: &Outline, min_len: f32) -> Vec<Edge> {
+    // Direction of the outer contours decides which side of an edge is filled
+    let clockwise = signed_area(outline) < 0.0;
+    let mut edges = Vec::new();
+    // Add edge along the line from a to b, at the given height
+    let mut add = |a: Point2D<f32>, b: Point2D<f32>, y: f32| {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        if dx.abs() < min_len || dy.abs() > dx.abs() * 0.1 {
+            return;
+        }
+        edges.push(Edge {
+            y,
+            x_min: a.x.min(b.x),
+            x_max: a.x.max(b.x),
+            is_top: (dx > 0.0) == clockwise,
+            is_blue: false,
+            fitted: None,
+        });
+    };
+    let (mut start, mut cur) = (point2(0.0, 0.0), point2(0.0, 0.0));
+    for seg in outline.segments() {
+        match *seg {
+            // Contours are closed with a line
+            Segment::MoveTo(p) => {
+                add(cur, start, (cur.y + start.y) / 2.0);
+                start = p;
+                cur = p;
+            }
+            Segment::LineTo(p) => {
+                add(cur, p, (cur.y + p.y) / 2.0);
+                cur = p;
+            }
+            Segment::QuadTo(c, p) => {
+                add(cur, c, cur.y);
+                add(c, p, p.y);
+                cur = p;
+            }
+            Segment::CubicTo(c1, c2, p) => {
+                add(cur, c1, cur.y);
+                add(c2, p, p.y);
+                cur = p;
+            }
+        }
+    }
+    add(cur, start, (cur.y + start.y) / 2.0);
+    edges
+}
+
+// Twice the signed area of the polygon through all points, positive if counter-clockwise
+fn signed_area(outline: &Outline) -> f32 {
+    let mut area = 0.0;
+    let (mut start, mut cur) = (point2(0.0, 0.0), point2(0.0, 0.0));
+    for seg in outline.segments() {
+        let p = match *seg {
+            Segment::MoveTo(p) => {
+                area += cur.x * start.y - start.x * cur.y;
+                start = p;
+                cur = p;
+                continue;
+            }
+            Segment::LineTo(p) | Segment::QuadTo(_, p) | Segment::CubicTo(_, _, p) => p,
+        };
+        area += cur.x * p.y - p.x * cur.y;
+        cur = p;
+    }
+    area + cur.x * start.y - start.x * cur.y
+}
+
+// Map a height using sorted pairs of original and fitted heights
+fn interpolate(map: &[(f32, f32)], y: f32) -> f32 {
+    let i = map
+        .iter()
+        .position(|(orig, _)| *orig > y)
+        .unwrap_or(map.len());
+    if i == 0 {
+        y + map[0].1 - map[0].0
+    } else if i == map.len() {
+        y + map[i - 1].1 - map[i - 1].0
+    } else {
+        let ((o1, f1), (o2, f2)) = (map[i - 1], map[i]);
+        f1 + (y - o1) * (f2 - f1) / (o2 - o1)
+    }
+}
@@ -0,0 +1,58 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::types::get_u16;
+use crate::varstore::get_f2dot14;
+
+/// Wrapper around axis variations table, which adjusts normalized coordinates with piecewise
+/// linear maps
+#[derive(Debug)]
+pub(crate) struct Avar(Vec<Vec<(f32, f32)>>); // From and to coordinates, for each axis
+
+impl Avar {
+    pub(crate) fn load(data: &[u8]) -> Result<Avar> {
+        let axis_count = get_u16(data, offsets::AXIS_COUNT)? as usize;
+        let mut off = offsets::SEGMENT_MAPS;
+        let mut maps = Vec::with_capacity(axis_count);
+        for _ in 0..axis_count {
+            let count = get_u16(data, off)? as usize;
+            off += 2;
+            let map = (0..count)
+                .map(|i| {
+                    Ok((
+                        get_f2dot14(data, off + i * 4)?,
+                        get_f2dot14(data, off + i * 4 + 2)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            off += count * 4;
+            maps.push(map);
+        }
+        Ok(Avar(maps))
+    }
+
+    /// Map normalized coordinate along axis
+    pub(crate) fn map(&self, axis: usize, coord: f32) -> f32 {
+        let map = match self.0.get(axis) {
+            Some(map) if !map.is_empty() => map,
+            _ => return coord,
+        };
+        match map.iter().position(|(from, _)| *from >= coord) {
+            None => map[map.len() - 1].1,
+            Some(0) => map[0].1,
+            Some(i) => {
+                let ((f1, t1), (f2, t2)) = (map[i - 1], map[i]);
+                if f2 == coord {
+                    t2
+                } else {
+                    t1 + (coord - f1) * (t2 - t1) / (f2 - f1)
+                }
+            }
+        }
+    }
+}
+
+mod offsets {
+    pub(super) const AXIS_COUNT: usize = 6;
+    pub(super) const SEGMENT_MAPS: usize = 8;
+}
@@ -0,0 +1,399 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use fnv::FnvHashMap;
+use geom::{point2, rect, size2, vec2, Rect, Size2D, Vector2D};
+
+use crate::common::GlyphID;
+use crate::face::{ScaledFace, ScaledFaceID};
+use crate::raster::{Bitmap, BitmapFormat, RenderMode};
+
+/// Position of a cached glyph in the atlas
+#[derive(Clone, Copy, Debug)]
+pub struct CachedGlyph {
+    pub page: usize,            // Index of atlas page
+    pub rect: Rect<u32>, // Region of the page with the glyph. Empty for glyphs with no pixels
+    pub bearing: Vector2D<i32>, // Offset of top-left pixel from the whole-pixel pen position, y up
+}
+
+/// Region of an atlas page which has changed since it was last reported
+#[derive(Clone, Copy, Debug)]
+pub struct DirtyRegion {
+    pub page: usize,
+    pub rect: Rect<u32>,
+}
+
+/// Page of an atlas, holding glyph bitmaps of one format
+#[derive(Debug)]
+pub struct AtlasPage {
+    format: BitmapFormat,
+    size: Size2D<u32>,
+    data: Vec<u8>,
+    skyline: Vec<SkylineNode>,
+    dirty: Option<Rect<u32>>,
+    last_used: u64, // Frame this page was last used in
+}
+
+impl AtlasPage {
+    fn new(format: BitmapFormat, size: Size2D<u32>) -> AtlasPage {
+        let len = (size.width * size.height) as usize * bytes_per_pixel(format);
+        AtlasPage {
+            format,
+            size,
+            data: vec![0; len],
+            skyline: vec![SkylineNode {
+                x: 0,
+                y: 0,
+                width: size.width,
+            }],
+            dirty: None,
+            last_used: 0,
+        }
+    }
+
+    /// Format of pixels in this page
+    pub fn format(&self) -> BitmapFormat {
+        self.format
+    }
+
+    /// Size of page in pixels
+    pub fn size(&self) -> Size2D<u32> {
+        self.size
+    }
+
+    /// Pixels row by row from the top, in the page's format
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Remove all glyphs, and use page for a (possibly different) format
+    fn reset(&mut self, format: BitmapFormat) {
+        *self = AtlasPage::new(format, self.size);
+        self.dirty = Some(rect(point2(0, 0), self.size));
+    }
+
+    // Find space for a rectangle using the skyline bottom-left heuristic: the lowest position,
+    // preferring narrower skyline segments
+    fn allocate(&mut self, size: Size2D<u32>) -> Option<Rect<u32>> {
+        let mut best: Option<(usize, u32, u32)> = None; // Index, y, width of node
+        for i in 0..self.skyline.len() {
+            let x = self.skyline[i].x;
+            if x + size.width > self.size.width {
+                break;
+            }
+            let mut y = 0;
+            let mut covered = 0;
+            for node in &self.skyline[i..] {
+                if covered >= size.width {
+                    break;
+                }
+                y = y.max(node.y);
+                covered += node.width;
+            }
+            if y + size.height > self.size.height {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((_, best_y, best_width)) => {
+                    y < best_y || (y == best_y && self.skyline[i].width < best_width)
+                }
+            };
+            if better {
+                best = Some((i, y, self.skyline[i].width));
+            }
+        }
+        let (i, y, _) = best?;
+        let x = self.skyline[i].x;
+        self.skyline.insert(
+            i,
+            SkylineNode {
+                x,
+                y: y + size.height,
+                width: size.width,
+            },
+        );
+        // Shrink or remove nodes under the new one
+        let end = x + size.width;
+        while i + 1 < self.skyline.len() && self.skyline[i + 1].x < end {
+            let node = &mut self.skyline[i + 1];
+            let node_end = node.x + node.width;
+            if node_end <= end {
+                self.skyline.remove(i + 1);
+            } else {
+                node.width = node_end - end;
+                node.x = end;
+            }
+        }
+        // Merge neighbours at the same height
+        let mut j = 0;
+        while j + 1 < self.skyline.len() {
+            if self.skyline[j].y == self.skyline[j + 1].y {
+                self.skyline[j].width += self.skyline[j + 1].width;
+                self.skyline.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+        Some(rect(point2(x, y), size))
+    }
+
+    // Copy bitmap into page
+    fn write(&mut self, bitmap: &Bitmap, at: Rect<u32>) {
+        let bpp = bytes_per_pixel(self.format);
+        let row_len = bitmap.size.width as usize * bpp;
+        for row in 0..bitmap.size.height as usize {
+            let src = row * row_len;
+            let dst = ((at.origin.y as usize + row) * self.size.width as usize
+                + at.origin.x as usize)
+                * bpp;
+            self.data[dst..dst + row_len].copy_from_slice(&bitmap.data[src..src + row_len]);
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => union(dirty, at),
+            None => at,
+        });
+    }
+}
+
+/// Cache of rasterized glyphs, packed into atlas pages for a renderer to upload as textures.
+/// When all pages are full, the least recently used page is cleared for new glyphs
+#[derive(Debug)]
+pub struct GlyphCache {
+    page_size: Size2D<u32>,
+    max_pages: usize,
+    pages: Vec<AtlasPage>,
+    glyphs: FnvHashMap<GlyphKey, Entry>,
+    frame: u64,
+}
+
+impl GlyphCache {
+    /// Create cache with at most `max_pages` pages of the given size
+    pub fn new(page_size: Size2D<u32>, max_pages: usize) -> GlyphCache {
+        GlyphCache {
+            page_size,
+            max_pages: max_pages.max(1),
+            pages: Vec::new(),
+            glyphs: FnvHashMap::default(),
+            frame: 1,
+        }
+    }
+
+    /// Start a new frame. Pages used in the current frame are never evicted, so glyphs returned
+    /// during a frame stay valid until the next one
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Get glyph from the cache, rasterizing it if needed. The fractional part of the offset is
+    /// rounded to a quarter of a pixel; the bearing of the returned glyph is from the whole-pixel
+    /// part. Returns `None` if the glyph can't be rasterized, or there's no room for it
+    pub fn get(
+        &mut self,
+        face: &ScaledFace,
+        glyph: GlyphID,
+        offset: Vector2D<f32>,
+        mode: RenderMode,
+    ) -> Option<CachedGlyph> {
+        let ((bucket_x, carry_x), (bucket_y, carry_y)) = (bucket(offset.x), bucket(offset.y));
+        let carry = vec2(carry_x, carry_y);
+        let key = GlyphKey {
+            face: face.id(),
+            glyph,
+            bucket: (bucket_x, bucket_y),
+            mode,
+        };
+        if let Some(entry) = self.glyphs.get(&key) {
+            let mut cached = entry.glyph;
+            cached.bearing += carry;
+            if let Some(page) = entry.page {
+                self.pages[page].last_used = self.frame;
+            }
+            return Some(cached);
+        }
+        let n = SUBPIXEL_BUCKETS as f32;
+        let subpixel = vec2(key.bucket.0 as f32 / n, key.bucket.1 as f32 / n);
+        let bitmap = face.rasterize(glyph, subpixel, mode)?;
+        let entry = if bitmap.size.width == 0 || bitmap.size.height == 0 {
+            Entry {
+                page: None,
+                glyph: CachedGlyph {
+                    page: 0,
+                    rect: rect(point2(0, 0), size2(0, 0)),
+                    bearing: bitmap.bearing,
+                },
+            }
+        } else {
+            let (page, at) = self.allocate(bitmap.format, bitmap.size)?;
+            self.pages[page].write(&bitmap, at);
+            self.pages[page].last_used = self.frame;
+            Entry {
+                page: Some(page),
+                glyph: CachedGlyph {
+                    page,
+                    rect: at,
+                    bearing: bitmap.bearing,
+                },
+            }
+        };
+        self.glyphs.insert(key, entry);
+        let mut cached = entry.glyph;
+        cached.bearing += carry;
+        Some(cached)
+    }
+
+    /// Get atlas pages
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.pages
+    }
+
+    /// Get regions of pages changed since the last call, so only they need to be uploaded
+    pub fn take_dirty(&mut self) -> Vec<DirtyRegion> {
+        self.pages
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(page, p)| p.dirty.take().map(|rect| DirtyRegion { page, rect }))
+            .collect()
+    }
+
+    /// Remove all glyphs
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+        self.pages.clear();
+    }
+
+    // Find space for a bitmap, adding a page or evicting the least recently used one if needed
+    fn allocate(&mut self, format: BitmapFormat, size: Size2D<u32>) -> Option<(usize, Rect<u32>)> {
+        // Leave a gap between glyphs, so they don't bleed into each other when sampled
+        let padded = size2(size.width + PADDING, size.height + PADDING);
+        if padded.width > self.page_size.width || padded.height > self.page_size.height {
+            return None;
+        }
+        for (i, page) in self.pages.iter_mut().enumerate() {
+            if page.format == format {
+                if let Some(at) = page.allocate(padded) {
+                    return Some((i, rect(at.origin, size)));
+                }
+            }
+        }
+        let page = if self.pages.len() < self.max_pages {
+            self.pages.push(AtlasPage::new(format, self.page_size));
+            self.pages.len() - 1
+        } else {
+            let (page, _) = self
+                .pages
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.last_used < self.frame)
+                .min_by_key(|(_, p)| p.last_used)?;
+            self.glyphs.retain(|_, e| e.page != Some(page));
+            self.pages[page].reset(format);
+            page
+        };
+        let at = self.pages[page].allocate(padded)?;
+        Some((page, rect(at.origin, size)))
+    }
+}
+
+// Subpixel positions per pixel, along each axis
+const SUBPIXEL_BUCKETS: u8 = 4;
+
+// Round the fractional part of an offset to a subpixel bucket. Fractions that round up to a whole
+// pixel are carried over, and returned as well
+fn bucket(v: f32) -> (u8, i32) {
+    let bucket = ((v - v.floor()) * SUBPIXEL_BUCKETS as f32).round() as u8;
+    (
+        bucket % SUBPIXEL_BUCKETS,
+        (bucket / SUBPIXEL_BUCKETS) as i32,
+    )
+}
+
+// Gap between glyphs in pages
+const PADDING: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct GlyphKey {
+    face: ScaledFaceID,
+    glyph: GlyphID,
+    bucket: (u8, u8), // Subpixel offset, in units of 1 / SUBPIXEL_BUCKETS
+    mode: RenderMode,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    page: Option<usize>, // Page the glyph's pixels are in, if it has any
+    glyph: CachedGlyph,
+}
+
+// Top of a run of allocated space, from x to x + width
+#[derive(Debug)]
+struct SkylineNode {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+fn bytes_per_pixel(format: BitmapFormat) -> usize {
+    match format {
+        BitmapFormat::Gray => 1,
+        BitmapFormat::Rgb => 3,
+        BitmapFormat::Rgba => 4,
+    }
+}
+
+fn union(a: Rect<u32>, b: Rect<u32>) -> Rect<u32> {
+    let min = point2(a.origin.x.min(b.origin.x), a.origin.y.min(b.origin.y));
+    let max = point2(
+        (a.origin.x + a.size.width).max(b.origin.x + b.size.width),
+        (a.origin.y + a.size.height).max(b.origin.y + b.size.height),
+    );
+    rect(min, size2(max.x - min.x, max.y - min.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subpixel_bucket() {
+        assert_eq!(bucket(2.0), (0, 0));
+        assert_eq!(bucket(2.3), (1, 0));
+        assert_eq!(bucket(-0.25), (3, 0));
+        assert_eq!(bucket(2.9), (0, 1));
+    }
+
+    #[test]
+    fn skyline() {
+        let mut page = AtlasPage::new(BitmapFormat::Gray, size2(10, 10));
+        let mut allocate = |w, h| page.allocate(size2(w, h)).map(|r| (r.origin.x, r.origin.y));
+        assert_eq!(allocate(4, 3), Some((0, 0)));
+        assert_eq!(allocate(4, 5), Some((4, 0)));
+        assert_eq!(allocate(2, 2), Some((8, 0)));
+        // Lowest position first
+        assert_eq!(allocate(4, 2), Some((0, 3)));
+        assert_eq!(allocate(2, 8), Some((8, 2)));
+        assert_eq!(allocate(11, 1), None);
+        assert_eq!(allocate(3, 6), None);
+        assert_eq!(allocate(8, 5), Some((0, 5)));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        // Each page has room for one glyph
+        let mut cache = GlyphCache::new(size2(8, 8), 2);
+        fn allocate(cache: &mut GlyphCache) -> Option<usize> {
+            let (page, _) = cache.allocate(BitmapFormat::Gray, size2(7, 7))?;
+            cache.pages[page].last_used = cache.frame;
+            Some(page)
+        }
+        assert_eq!(allocate(&mut cache), Some(0));
+        cache.begin_frame();
+        assert_eq!(allocate(&mut cache), Some(1));
+        assert_eq!(allocate(&mut cache), Some(0));
+        // Pages used in the current frame aren't evicted
+        assert_eq!(allocate(&mut cache), None);
+        cache.begin_frame();
+        cache.pages[0].last_used = cache.frame;
+        assert_eq!(allocate(&mut cache), Some(1));
+        assert_eq!(cache.pages.len(), 2);
+    }
+}
@@ -0,0 +1,1022 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{bbox, point2, vec2, BBox, Point2D};
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::outline::Outline;
+use crate::types::{get_i16, get_u16, get_u32};
+use crate::varstore::ItemVariationStore;
+
+/// Wrapper around Compact Font Format table
+#[derive(Debug)]
+pub(crate) struct Cff {
+    version: Version,
+    charstrings: Vec<Vec<u8>>,
+    global_subrs: Vec<Vec<u8>>,
+    privates: Vec<Private>,                // Private DICT for each font DICT
+    fd_select: Vec<u16>,                   // Font DICT for each glyph, empty if there is only one
+    charset: Vec<u16>,                     // String ID for each glyph, empty if not known
+    var_store: Option<ItemVariationStore>, // Deltas for blended values in CFF2 charstrings
+    bboxes: Vec<BBox<i16>>,                // Bounding boxes at the default instance
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Version {
+    Cff,
+    Cff2,
+}
+
+// Values from a private DICT needed to run charstrings
+#[derive(Debug)]
+struct Private {
+    subrs: Vec<Vec<u8>>,
+    vs_index: usize, // Default item variation data for blends
+}
+
+impl Cff {
+    pub(crate) fn load(data: &[u8]) -> Result<Cff> {
+        let header_size = *data.get(offsets::HEADER_SIZE).ok_or(Error::Invalid)? as usize;
+        let (_, off) = load_index(data, header_size, Version::Cff)?;
+        let (top_dicts, off) = load_index(data, off, Version::Cff)?;
+        let (_, off) = load_index(data, off, Version::Cff)?;
+        let (global_subrs, _) = load_index(data, off, Version::Cff)?;
+        let top_dict = Dict::load(top_dicts.first().ok_or(Error::Invalid)?)?;
+        if top_dict.get_int(ops::CHARSTRING_TYPE).unwrap_or(2) != 2 {
+            return Err(Error::Invalid);
+        }
+
+        let charstrings_off = top_dict.get_int(ops::CHARSTRINGS).ok_or(Error::Invalid)?;
+        let (charstrings, _) = load_index(data, charstrings_off, Version::Cff)?;
+        let num_glyphs = charstrings.len();
+
+        let (privates, fd_select, charset) = if top_dict.get(ops::ROS).is_some() {
+            // CID-keyed font, with a private DICT for each font DICT
+            let fd_array_off = top_dict.get_int(ops::FD_ARRAY).ok_or(Error::Invalid)?;
+            let fd_select_off = top_dict.get_int(ops::FD_SELECT).ok_or(Error::Invalid)?;
+            let (font_dicts, _) = load_index(data, fd_array_off, Version::Cff)?;
+            let privates = font_dicts
+                .iter()
+                .map(|d| load_private(data, &Dict::load(d)?, Version::Cff))
+                .collect::<Result<Vec<_>>>()?;
+            let fd_select = load_fd_select(data, fd_select_off, num_glyphs)?;
+            (privates, fd_select, Vec::new())
+        } else {
+            let private = load_private(data, &top_dict, Version::Cff)?;
+            let charset = load_charset(data, top_dict.get_int(ops::CHARSET), num_glyphs)?;
+            (vec![private], Vec::new(), charset)
+        };
+
+        Ok(Cff {
+            version: Version::Cff,
+            charstrings,
+            global_subrs,
+            privates,
+            fd_select,
+            charset,
+            var_store: None,
+            bboxes: Vec::new(),
+        }
+        .with_bboxes())
+    }
+
+    /// Load CFF2 table, whose charstrings may vary with the variation instance
+    pub(crate) fn load_cff2(data: &[u8]) -> Result<Cff> {
+        let header_size = *data.get(offsets::HEADER_SIZE).ok_or(Error::Invalid)? as usize;
+        let top_dict_len = get_u16(data, offsets::TOP_DICT_LENGTH)? as usize;
+        let top_dict = data
+            .get(header_size..header_size + top_dict_len)
+            .ok_or(Error::Invalid)
+            .and_then(Dict::load)?;
+        let (global_subrs, _) = load_index(data, header_size + top_dict_len, Version::Cff2)?;
+
+        let charstrings_off = top_dict.get_int(ops::CHARSTRINGS).ok_or(Error::Invalid)?;
+        let (charstrings, _) = load_index(data, charstrings_off, Version::Cff2)?;
+        let num_glyphs = charstrings.len();
+
+        let fd_array_off = top_dict.get_int(ops::FD_ARRAY).ok_or(Error::Invalid)?;
+        let (font_dicts, _) = load_index(data, fd_array_off, Version::Cff2)?;
+        let privates = font_dicts
+            .iter()
+            .map(|d| load_private(data, &Dict::load(d)?, Version::Cff2))
+            .collect::<Result<Vec<_>>>()?;
+        let fd_select = match top_dict.get_int(ops::FD_SELECT) {
+            Some(off) => load_fd_select(data, off, num_glyphs)?,
+            None => Vec::new(),
+        };
+        // The variation store is preceded by its length
+        let var_store = match top_dict.get_int(ops::VSTORE) {
+            Some(off) => Some(
+                data.get(off + 2..)
+                    .ok_or(Error::Invalid)
+                    .and_then(ItemVariationStore::load)?,
+            ),
+            None => None,
+        };
+
+        Ok(Cff {
+            version: Version::Cff2,
+            charstrings,
+            global_subrs,
+            privates,
+            fd_select,
+            charset: Vec::new(),
+            var_store,
+            bboxes: Vec::new(),
+        }
+        .with_bboxes())
+    }
+
+    // Calculate bounding boxes for all glyphs at the default instance
+    fn with_bboxes(mut self) -> Cff {
+        self.bboxes = (0..self.charstrings.len())
+            .map(|i| {
+                self.outline(GlyphID(i as u32), &[])
+                    .map(|o| o.control_bbox())
+                    .unwrap_or_else(|| bbox(point2(0, 0), point2(0, 0)))
+            })
+            .collect();
+        self
+    }
+
+    pub(crate) fn glyph_bbox(&self, glyph: GlyphID, coords: &[f32]) -> BBox<i16> {
+        if self.var_store.is_some() && coords.iter().any(|c| *c != 0.0) {
+            return self
+                .outline(glyph, coords)
+                .map(|o| o.control_bbox())
+                .unwrap_or_else(|| bbox(point2(0, 0), point2(0, 0)));
+        }
+        self.bboxes
+            .get(glyph.0 as usize)
+            .copied()
+            .unwrap_or_else(|| bbox(point2(0, 0), point2(0, 0)))
+    }
+
+    /// Get outline of glyph at the given normalized variation coordinates, in font units
+    pub(crate) fn outline(&self, glyph: GlyphID, coords: &[f32]) -> Option<Outline> {
+        let (mut outline, seac) = self.run_charstring(glyph, coords).ok()?;
+        // Accented glyphs are made up of a base and an accent from the standard encoding
+        if let Some(seac) = seac {
+            let base = self.glyph_for_code(seac.base)?;
+            let accent = self.glyph_for_code(seac.accent)?;
+            outline = self.run_charstring(base, coords).ok()?.0;
+            outline.append(&self.run_charstring(accent, coords).ok()?.0, seac.offset);
+        }
+        Some(outline)
+    }
+
+    fn run_charstring(&self, glyph: GlyphID, coords: &[f32]) -> Result<(Outline, Option<Seac>)> {
+        let charstring = self
+            .charstrings
+            .get(glyph.0 as usize)
+            .ok_or(Error::Invalid)?;
+        let fd = self.fd_select.get(glyph.0 as usize).copied().unwrap_or(0);
+        let private = self.privates.get(fd as usize).ok_or(Error::Invalid)?;
+        let mut interpreter = Interpreter::new(self, private, coords)?;
+        interpreter.run(charstring, 0)?;
+        interpreter.close_contour();
+        Ok((interpreter.outline, interpreter.seac))
+    }
+
+    // Find glyph for a code in the standard encoding
+    fn glyph_for_code(&self, code: u8) -> Option<GlyphID> {
+        let sid = STANDARD_ENCODING
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, sid)| *sid)?;
+        self.charset
+            .iter()
+            .position(|s| *s == sid)
+            .map(|gid| GlyphID(gid as u32))
+    }
+}
+
+// Load INDEX at given offset. Returns the objects in the INDEX, and the offset past its end
+fn load_index(data: &[u8], offset: usize, version: Version) -> Result<(Vec<Vec<u8>>, usize)> {
+    // CFF2 INDEXes have a 32-bit count
+    let (count, count_size) = match version {
+        Version::Cff => (get_u16(data, offset)? as usize, 2),
+        Version::Cff2 => (get_u32(data, offset)? as usize, 4),
+    };
+    if count == 0 {
+        return Ok((Vec::new(), offset + count_size));
+    }
+    let off_size = *data.get(offset + count_size).ok_or(Error::Invalid)? as usize;
+    if off_size == 0 || off_size > 4 {
+        return Err(Error::Invalid);
+    }
+    let offsets_start = offset + count_size + 1;
+    // Offsets are relative to the byte before the object data
+    let data_start = offsets_start + (count + 1) * off_size - 1;
+    let get_offset = |i: usize| -> Result<usize> {
+        let start = offsets_start + i * off_size;
+        let bytes = data.get(start..start + off_size).ok_or(Error::Invalid)?;
+        Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize) + data_start)
+    };
+    let mut objects = Vec::with_capacity(count);
+    let mut start = get_offset(0)?;
+    for i in 1..=count {
+        let end = get_offset(i)?;
+        objects.push(data.get(start..end).ok_or(Error::Invalid)?.to_vec());
+        start = end;
+    }
+    Ok((objects, start))
+}
+
+// Load private DICT pointed to by the given font DICT
+fn load_private(data: &[u8], font_dict: &Dict, version: Version) -> Result<Private> {
+    let (size, offset) = match font_dict.get(ops::PRIVATE) {
+        Some([size, offset]) => (*size as usize, *offset as usize),
+        _ => {
+            return Ok(Private {
+                subrs: Vec::new(),
+                vs_index: 0,
+            })
+        }
+    };
+    let private_dict = Dict::load(data.get(offset..offset + size).ok_or(Error::Invalid)?)?;
+    let subrs = match private_dict.get_int(ops::SUBRS) {
+        // Subroutines are relative to the private DICT
+        Some(subrs_off) => load_index(data, offset + subrs_off, version)?.0,
+        None => Vec::new(),
+    };
+    let vs_index = private_dict.get_int(ops::VSINDEX_DICT).unwrap_or(0);
+    Ok(Private { subrs, vs_index })
+}
+
+// Load string IDs for each glyph
+fn load_charset(data: &[u8], offset: Option<usize>, num_glyphs: usize) -> Result<Vec<u16>> {
+    let offset = match offset {
+        // ISOAdobe charset maps glyph IDs to the same string IDs
+        None | Some(0) => return Ok((0..num_glyphs as u16).collect()),
+        // Expert charsets have no standard encoding glyphs
+        Some(1) | Some(2) => return Ok(Vec::new()),
+        Some(offset) => offset,
+    };
+    let mut charset = vec![0]; // .notdef
+    let format = *data.get(offset).ok_or(Error::Invalid)?;
+    let mut off = offset + 1;
+    while charset.len() < num_glyphs {
+        match format {
+            0 => {
+                charset.push(get_u16(data, off)?);
+                off += 2;
+            }
+            1 | 2 => {
+                let first = get_u16(data, off)?;
+                let num_left = if format == 1 {
+                    *data.get(off + 2).ok_or(Error::Invalid)? as u16
+                } else {
+                    get_u16(data, off + 2)?
+                };
+                charset.extend((0..=num_left).map(|i| first.wrapping_add(i)));
+                off += if format == 1 { 3 } else { 4 };
+            }
+            _ => return Err(Error::Invalid),
+        }
+    }
+    charset.truncate(num_glyphs);
+    Ok(charset)
+}
+
+// Load font DICT index for each glyph
+fn load_fd_select(data: &[u8], offset: usize, num_glyphs: usize) -> Result<Vec<u16>> {
+    match *data.get(offset).ok_or(Error::Invalid)? {
+        0 => Ok(data
+            .get(offset + 1..offset + 1 + num_glyphs)
+            .ok_or(Error::Invalid)?
+            .iter()
+            .map(|fd| *fd as u16)
+            .collect()),
+        3 => {
+            let num_ranges = get_u16(data, offset + 1)? as usize;
+            let mut fd_select = vec![0; num_glyphs];
+            for i in 0..num_ranges {
+                let off = offset + 3 + i * 3;
+                let first = get_u16(data, off)? as usize;
+                let fd = *data.get(off + 2).ok_or(Error::Invalid)? as u16;
+                // The next range's first glyph, or the sentinel
+                let last = (get_u16(data, off + 3)? as usize).min(num_glyphs);
+                if first < last {
+                    fd_select[first..last].iter_mut().for_each(|f| *f = fd);
+                }
+            }
+            Ok(fd_select)
+        }
+        // Only in CFF2
+        4 => {
+            let num_ranges = get_u32(data, offset + 1)? as usize;
+            let mut fd_select = vec![0; num_glyphs];
+            for i in 0..num_ranges {
+                let off = offset + 5 + i * 6;
+                let first = get_u32(data, off)? as usize;
+                let fd = get_u16(data, off + 4)?;
+                let last = (get_u32(data, off + 6)? as usize).min(num_glyphs);
+                if first < last {
+                    fd_select[first..last].iter_mut().for_each(|f| *f = fd);
+                }
+            }
+            Ok(fd_select)
+        }
+        _ => Err(Error::Invalid),
+    }
+}
+
+// Operators and their operands in a DICT
+struct Dict(Vec<(u16, Vec<f32>)>);
+
+impl Dict {
+    fn load(data: &[u8]) -> Result<Dict> {
+        let mut entries = Vec::new();
+        let mut operands = Vec::new();
+        let mut off = 0;
+        while off < data.len() {
+            let b0 = data[off];
+            off += 1;
+            match b0 {
+                0..=11 | 13..=24 => entries.push((b0 as u16, std::mem::take(&mut operands))),
+                12 => {
+                    let b1 = *data.get(off).ok_or(Error::Invalid)?;
+                    off += 1;
+                    entries.push((0x0c00 | b1 as u16, std::mem::take(&mut operands)));
+                }
+                28 => {
+                    operands.push(get_i16(data, off)? as f32);
+                    off += 2;
+                }
+                29 => {
+                    operands.push(get_i32(data, off)? as f32);
+                    off += 4;
+                }
+                30 => {
+                    let (value, len) = load_real(&data[off..])?;
+                    operands.push(value);
+                    off += len;
+                }
+                32..=246 => operands.push(b0 as f32 - 139.0),
+                247..=254 => {
+                    operands.push(short_int(b0, *data.get(off).ok_or(Error::Invalid)?));
+                    off += 1;
+                }
+                _ => return Err(Error::Invalid),
+            }
+        }
+        Ok(Dict(entries))
+    }
+
+    fn get(&self, op: u16) -> Option<&[f32]> {
+        self.0
+            .iter()
+            .find(|(o, _)| *o == op)
+            .map(|(_, operands)| &operands[..])
+    }
+
+    // Get single integer operand, usually an offset
+    fn get_int(&self, op: u16) -> Option<usize> {
+        match self.get(op) {
+            Some([value]) if *value >= 0.0 => Some(*value as usize),
+            _ => None,
+        }
+    }
+}
+
+// Load real number made up of nibbles. Returns the number and the bytes used
+fn load_real(data: &[u8]) -> Result<(f32, usize)> {
+    let mut s = String::new();
+    for (i, b) in data.iter().enumerate() {
+        for nibble in &[b >> 4, b & 0xf] {
+            match nibble {
+                0..=9 => s.push((b'0' + nibble) as char),
+                0xa => s.push('.'),
+                0xb => s.push('E'),
+                0xc => s.push_str("E-"),
+                0xe => s.push('-'),
+                0xf => return s.parse().map(|v| (v, i + 1)).map_err(|_| Error::Invalid),
+                _ => return Err(Error::Invalid),
+            }
+        }
+    }
+    Err(Error::Invalid)
+}
+
+// Integer encoded in two bytes, starting with 247..=254
+fn short_int(b0: u8, b1: u8) -> f32 {
+    if b0 < 251 {
+        (b0 as f32 - 247.0) * 256.0 + b1 as f32 + 108.0
+    } else {
+        -(b0 as f32 - 251.0) * 256.0 - b1 as f32 - 108.0
+    }
+}
+
+fn get_i32(b: &[u8], offset: usize) -> Result<i32> {
+    get_u32(b, offset).map(|v| v as i32)
+}
+
+// Accented character made up of two glyphs from the standard encoding
+#[derive(Debug)]
+struct Seac {
+    base: u8,
+    accent: u8,
+    offset: geom::Vector2D<f32>, // Offset of accent from base
+}
+
+const MAX_STACK: usize = 48;
+const MAX_STACK_CFF2: usize = 513;
+const MAX_SUBR_DEPTH: usize = 10;
+
+// Type 2 charstring interpreter, also handling the CFF2 extensions
+struct Interpreter<'a> {
+    cff: &'a Cff,
+    local_subrs: &'a [Vec<u8>],
+    coords: &'a [f32],
+    scalars: Vec<f32>, // Scalars for the regions of the current item variation data
+    stack: Vec<f32>,
+    outline: Outline,
+    pos: Point2D<f32>,
+    contour_start: Option<Point2D<f32>>,
+    num_stems: usize,
+    seen_width: bool,
+    seac: Option<Seac>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(cff: &'a Cff, private: &'a Private, coords: &'a [f32]) -> Result<Interpreter<'a>> {
+        let mut interpreter = Interpreter {
+            cff,
+            local_subrs: &private.subrs,
+            coords,
+            scalars: Vec::new(),
+            stack: Vec::new(),
+            outline: Outline::default(),
+            pos: point2(0.0, 0.0),
+            contour_start: None,
+            num_stems: 0,
+            // CFF2 charstrings don't have widths
+            seen_width: cff.version == Version::Cff2,
+            seac: None,
+        };
+        interpreter.set_vs_index(private.vs_index)?;
+        Ok(interpreter)
+    }
+
+    fn set_vs_index(&mut self, vs_index: usize) -> Result<()> {
+        if let Some(var_store) = &self.cff.var_store {
+            self.scalars = var_store.region_scalars(vs_index, self.coords)?;
+        }
+        Ok(())
+    }
+
+    // Replace default values and their deltas on the stack with values for the instance
+    fn blend(&mut self) -> Result<()> {
+        let n = self.stack.pop().ok_or(Error::Invalid)? as usize;
+        let k = self.scalars.len();
+        let base = self
+            .stack
+            .len()
+            .checked_sub(n * (k + 1))
+            .ok_or(Error::Invalid)?;
+        for i in 0..n {
+            let deltas = &self.stack[base + n + i * k..base + n + (i + 1) * k];
+            let delta: f32 = deltas.iter().zip(&self.scalars).map(|(d, s)| d * s).sum();
+            self.stack[base + i] += delta;
+        }
+        self.stack.truncate(base + n);
+        Ok(())
+    }
+
+    // Run charstring. Returns true if the end of the glyph was reached
+    fn run(&mut self, code: &[u8], depth: usize) -> Result<bool> {
+        if depth > MAX_SUBR_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let mut off = 0;
+        while off < code.len() {
+            let b0 = code[off];
+            off += 1;
+            match b0 {
+                28 => {
+                    self.push(get_i16(code, off)? as f32)?;
+                    off += 2;
+                }
+                32..=246 => self.push(b0 as f32 - 139.0)?,
+                247..=254 => {
+                    self.push(short_int(b0, *code.get(off).ok_or(Error::Invalid)?))?;
+                    off += 1;
+                }
+                255 => {
+                    self.push(get_i32(code, off)? as f32 / 65536.0)?;
+                    off += 4;
+                }
+                ops::HSTEM | ops::VSTEM | ops::HSTEMHM | ops::VSTEMHM => {
+                    self.take_width(self.stack.len() % 2 == 1);
+                    self.num_stems += self.stack.len() / 2;
+                    self.stack.clear();
+                }
+                ops::HINTMASK | ops::CNTRMASK => {
+                    // Operands are an implicit vstem
+                    self.take_width(self.stack.len() % 2 == 1);
+                    self.num_stems += self.stack.len() / 2;
+                    self.stack.clear();
+                    off += (self.num_stems + 7) / 8;
+                }
+                ops::RMOVETO => {
+                    self.take_width(self.stack.len() > 2);
+                    let [dx, dy] = self.args::<2>()?;
+                    self.move_to(dx, dy);
+                }
+                ops::HMOVETO => {
+                    self.take_width(self.stack.len() > 1);
+                    let [dx] = self.args::<1>()?;
+                    self.move_to(dx, 0.0);
+                }
+                ops::VMOVETO => {
+                    self.take_width(self.stack.len() > 1);
+                    let [dy] = self.args::<1>()?;
+                    self.move_to(0.0, dy);
+                }
+                ops::RLINETO => {
+                    for i in (0..self.stack.len() / 2 * 2).step_by(2) {
+                        self.line_to(self.stack[i], self.stack[i + 1]);
+                    }
+                    self.stack.clear();
+                }
+                ops::HLINETO | ops::VLINETO => {
+                    for i in 0..self.stack.len() {
+                        let d = self.stack[i];
+                        if (i % 2 == 0) == (b0 == ops::HLINETO) {
+                            self.line_to(d, 0.0);
+                        } else {
+                            self.line_to(0.0, d);
+                        }
+                    }
+                    self.stack.clear();
+                }
+                ops::RRCURVETO => {
+                    for i in (0..self.stack.len() / 6 * 6).step_by(6) {
+                        let s = &self.stack[i..i + 6];
+                        let (a, b, c, d, e, f) = (s[0], s[1], s[2], s[3], s[4], s[5]);
+                        self.curve_to(a, b, c, d, e, f);
+                    }
+                    self.stack.clear();
+                }
+                ops::RCURVELINE => {
+                    let num_curves = self.stack.len().saturating_sub(2) / 6;
+                    for i in (0..num_curves * 6).step_by(6) {
+                        let s = &self.stack[i..i + 6];
+                        let (a, b, c, d, e, f) = (s[0], s[1], s[2], s[3], s[4], s[5]);
+                        self.curve_to(a, b, c, d, e, f);
+                    }
+                    if let [dx, dy] = self.stack[num_curves * 6..] {
+                        self.line_to(dx, dy);
+                    }
+                    self.stack.clear();
+                }
+                ops::RLINECURVE => {
+                    let num_lines = self.stack.len().saturating_sub(6) / 2;
+                    for i in (0..num_lines * 2).step_by(2) {
+                        self.line_to(self.stack[i], self.stack[i + 1]);
+                    }
+                    if let [a, b, c, d, e, f] = self.stack[num_lines * 2..] {
+                        self.curve_to(a, b, c, d, e, f);
+                    }
+                    self.stack.clear();
+                }
+                ops::HHCURVETO | ops::VVCURVETO => {
+                    // Optional first operand is the other coordinate of the first control point
+                    let mut first = 0.0;
+                    let mut i = 0;
+                    if self.stack.len() % 4 == 1 {
+                        first = self.stack[0];
+                        i = 1;
+                    }
+                    while i + 4 <= self.stack.len() {
+                        let s = &self.stack[i..i + 4];
+                        if b0 == ops::HHCURVETO {
+                            self.curve_to(s[0], first, s[1], s[2], s[3], 0.0);
+                        } else {
+                            self.curve_to(first, s[0], s[1], s[2], 0.0, s[3]);
+                        }
+                        first = 0.0;
+                        i += 4;
+                    }
+                    self.stack.clear();
+                }
+                ops::HVCURVETO | ops::VHCURVETO => {
+                    // Curves alternate between starting horizontal and vertical. The last curve
+                    // may have an extra operand for the other coordinate of its end point
+                    let mut horizontal = b0 == ops::HVCURVETO;
+                    let mut i = 0;
+                    while i + 4 <= self.stack.len() {
+                        let s = &self.stack[i..i + 4];
+                        let last = if self.stack.len() - i == 5 {
+                            self.stack[i + 4]
+                        } else {
+                            0.0
+                        };
+                        if horizontal {
+                            self.curve_to(s[0], 0.0, s[1], s[2], last, s[3]);
+                        } else {
+                            self.curve_to(0.0, s[0], s[1], s[2], s[3], last);
+                        }
+                        horizontal = !horizontal;
+                        i += 4;
+                    }
+                    self.stack.clear();
+                }
+                ops::CALLSUBR | ops::CALLGSUBR => {
+                    let subrs = if b0 == ops::CALLSUBR {
+                        self.local_subrs
+                    } else {
+                        &self.cff.global_subrs[..]
+                    };
+                    let index = self.stack.pop().ok_or(Error::Invalid)? as i32 + bias(subrs);
+                    let subr = subrs.get(index as usize).ok_or(Error::Invalid)?;
+                    if self.run(subr, depth + 1)? {
+                        return Ok(true);
+                    }
+                }
+                ops::RETURN => return Ok(false),
+                ops::VSINDEX if self.cff.version == Version::Cff2 => {
+                    let [vs_index] = self.args::<1>()?;
+                    self.set_vs_index(vs_index as usize)?;
+                }
+                ops::BLEND if self.cff.version == Version::Cff2 => self.blend()?,
+                ops::ENDCHAR => {
+                    self.take_width(self.stack.len() == 1 || self.stack.len() == 5);
+                    if let [adx, ady, base, accent] = self.stack[..] {
+                        self.seac = Some(Seac {
+                            base: base as u8,
+                            accent: accent as u8,
+                            offset: vec2(adx, ady),
+                        });
+                    }
+                    self.stack.clear();
+                    return Ok(true);
+                }
+                ops::ESCAPE => {
+                    let b1 = *code.get(off).ok_or(Error::Invalid)?;
+                    off += 1;
+                    self.run_escape(b1)?;
+                }
+                _ => return Err(Error::Invalid),
+            }
+        }
+        Ok(false)
+    }
+
+    // Run two-byte operator. Only the flex operators are supported
+    fn run_escape(&mut self, op: u8) -> Result<()> {
+        match op {
+            ops::FLEX => {
+                let [a, b, c, d, e, f, g, h, i, j, k, l, _] = self.args::<13>()?;
+                self.curve_to(a, b, c, d, e, f);
+                self.curve_to(g, h, i, j, k, l);
+            }
+            ops::HFLEX => {
+                let [a, b, c, d, e, f, g] = self.args::<7>()?;
+                self.curve_to(a, 0.0, b, c, d, 0.0);
+                self.curve_to(e, 0.0, f, -c, g, 0.0);
+            }
+            ops::HFLEX1 => {
+                let [a, b, c, d, e, f, g, h, i] = self.args::<9>()?;
+                self.curve_to(a, b, c, d, e, 0.0);
+                self.curve_to(f, 0.0, g, h, i, -(b + d + h));
+            }
+            ops::FLEX1 => {
+                let [a, b, c, d, e, f, g, h, i, j, k] = self.args::<11>()?;
+                // The last point is back at the start in the direction of least change
+                let (dx, dy) = (a + c + e + g + i, b + d + f + h + j);
+                self.curve_to(a, b, c, d, e, f);
+                if dx.abs() > dy.abs() {
+                    self.curve_to(g, h, i, j, k, -dy);
+                } else {
+                    self.curve_to(g, h, i, j, -dx, k);
+                }
+            }
+            _ => return Err(Error::Invalid),
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, value: f32) -> Result<()> {
+        let max_stack = match self.cff.version {
+            Version::Cff => MAX_STACK,
+            Version::Cff2 => MAX_STACK_CFF2,
+        };
+        if self.stack.len() >= max_stack {
+            return Err(Error::Invalid);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Take exactly N operands off the stack, and clear it
+    fn args<const N: usize>(&mut self) -> Result<[f32; N]> {
+        let mut args = [0.0; N];
+        if self.stack.len() != N {
+            return Err(Error::Invalid);
+        }
+        args.copy_from_slice(&self.stack);
+        self.stack.clear();
+        Ok(args)
+    }
+
+    // The first stack-clearing operator may have the advance width as an extra first operand
+    fn take_width(&mut self, has_width: bool) {
+        if !self.seen_width && has_width {
+            self.stack.remove(0);
+        }
+        self.seen_width = true;
+    }
+
+    fn move_to(&mut self, dx: f32, dy: f32) {
+        self.close_contour();
+        self.pos += vec2(dx, dy);
+        self.contour_start = Some(self.pos);
+        self.outline.move_to(self.pos);
+    }
+
+    fn line_to(&mut self, dx: f32, dy: f32) {
+        self.pos += vec2(dx, dy);
+        self.outline.line_to(self.pos);
+    }
+
+    // Cubic curve with each point relative to the previous one
+    fn curve_to(&mut self, dxa: f32, dya: f32, dxb: f32, dyb: f32, dxc: f32, dyc: f32) {
+        let a = self.pos + vec2(dxa, dya);
+        let b = a + vec2(dxb, dyb);
+        self.pos = b + vec2(dxc, dyc);
+        self.outline.cubic_to(a, b, self.pos);
+    }
+
+    // Contours are implicitly closed by the next moveto, or the end of the glyph
+    fn close_contour(&mut self) {
+        if let Some(start) = self.contour_start.take() {
+            if start != self.pos {
+                self.outline.line_to(start);
+            }
+        }
+    }
+}
+
+// Bias added to subroutine numbers
+fn bias(subrs: &[Vec<u8>]) -> i32 {
+    match subrs.len() {
+        0..=1239 => 107,
+        1240..=33899 => 1131,
+        _ => 32768,
+    }
+}
+
+// String IDs of the standard encoding, for codes which have a glyph
+static STANDARD_ENCODING: [(u8, u16); 149] = [
+    (32, 1),
+    (33, 2),
+    (34, 3),
+    (35, 4),
+    (36, 5),
+    (37, 6),
+    (38, 7),
+    (39, 8),
+    (40, 9),
+    (41, 10),
+    (42, 11),
+    (43, 12),
+    (44, 13),
+    (45, 14),
+    (46, 15),
+    (47, 16),
+    (48, 17),
+    (49, 18),
+    (50, 19),
+    (51, 20),
+    (52, 21),
+    (53, 22),
+    (54, 23),
+    (55, 24),
+    (56, 25),
+    (57, 26),
+    (58, 27),
+    (59, 28),
+    (60, 29),
+    (61, 30),
+    (62, 31),
+    (63, 32),
+    (64, 33),
+    (65, 34),
+    (66, 35),
+    (67, 36),
+    (68, 37),
+    (69, 38),
+    (70, 39),
+    (71, 40),
+    (72, 41),
+    (73, 42),
+    (74, 43),
+    (75, 44),
+    (76, 45),
+    (77, 46),
+    (78, 47),
+    (79, 48),
+    (80, 49),
+    (81, 50),
+    (82, 51),
+    (83, 52),
+    (84, 53),
+    (85, 54),
+    (86, 55),
+    (87, 56),
+    (88, 57),
+    (89, 58),
+    (90, 59),
+    (91, 60),
+    (92, 61),
+    (93, 62),
+    (94, 63),
+    (95, 64),
+    (96, 65),
+    (97, 66),
+    (98, 67),
+    (99, 68),
+    (100, 69),
+    (101, 70),
+    (102, 71),
+    (103, 72),
+    (104, 73),
+    (105, 74),
+    (106, 75),
+    (107, 76),
+    (108, 77),
+    (109, 78),
+    (110, 79),
+    (111, 80),
+    (112, 81),
+    (113, 82),
+    (114, 83),
+    (115, 84),
+    (116, 85),
+    (117, 86),
+    (118, 87),
+    (119, 88),
+    (120, 89),
+    (121, 90),
+    (122, 91),
+    (123, 92),
+    (124, 93),
+    (125, 94),
+    (126, 95),
+    (161, 96),
+    (162, 97),
+    (163, 98),
+    (164, 99),
+    (165, 100),
+    (166, 101),
+    (167, 102),
+    (168, 103),
+    (169, 104),
+    (170, 105),
+    (171, 106),
+    (172, 107),
+    (173, 108),
+    (174, 109),
+    (175, 110),
+    (177, 111),
+    (178, 112),
+    (179, 113),
+    (180, 114),
+    (182, 115),
+    (183, 116),
+    (184, 117),
+    (185, 118),
+    (186, 119),
+    (187, 120),
+    (188, 121),
+    (189, 122),
+    (191, 123),
+    (193, 124),
+    (194, 125),
+    (195, 126),
+    (196, 127),
+    (197, 128),
+    (198, 129),
+    (199, 130),
+    (200, 131),
+    (202, 132),
+    (203, 133),
+    (205, 134),
+    (206, 135),
+    (207, 136),
+    (208, 137),
+    (225, 138),
+    (227, 139),
+    (232, 140),
+    (233, 141),
+    (234, 142),
+    (235, 143),
+    (241, 144),
+    (245, 145),
+    (248, 146),
+    (249, 147),
+    (250, 148),
+    (251, 149),
+];
+
+mod ops {
+    // DICT operators
+    pub(super) const CHARSET: u16 = 15;
+    pub(super) const CHARSTRINGS: u16 = 17;
+    pub(super) const PRIVATE: u16 = 18;
+    pub(super) const SUBRS: u16 = 19;
+    pub(super) const VSINDEX_DICT: u16 = 22;
+    pub(super) const VSTORE: u16 = 24;
+    pub(super) const CHARSTRING_TYPE: u16 = 0x0c06;
+    pub(super) const ROS: u16 = 0x0c1e;
+    pub(super) const FD_ARRAY: u16 = 0x0c24;
+    pub(super) const FD_SELECT: u16 = 0x0c25;
+
+    // Charstring operators
+    pub(super) const HSTEM: u8 = 1;
+    pub(super) const VSTEM: u8 = 3;
+    pub(super) const VMOVETO: u8 = 4;
+    pub(super) const RLINETO: u8 = 5;
+    pub(super) const HLINETO: u8 = 6;
+    pub(super) const VLINETO: u8 = 7;
+    pub(super) const RRCURVETO: u8 = 8;
+    pub(super) const CALLSUBR: u8 = 10;
+    pub(super) const RETURN: u8 = 11;
+    pub(super) const ESCAPE: u8 = 12;
+    pub(super) const ENDCHAR: u8 = 14;
+    pub(super) const VSINDEX: u8 = 15;
+    pub(super) const BLEND: u8 = 16;
+    pub(super) const HSTEMHM: u8 = 18;
+    pub(super) const HINTMASK: u8 = 19;
+    pub(super) const CNTRMASK: u8 = 20;
+    pub(super) const RMOVETO: u8 = 21;
+    pub(super) const HMOVETO: u8 = 22;
+    pub(super) const VSTEMHM: u8 = 23;
+    pub(super) const RCURVELINE: u8 = 24;
+    pub(super) const RLINECURVE: u8 = 25;
+    pub(super) const VVCURVETO: u8 = 26;
+    pub(super) const HHCURVETO: u8 = 27;
+    pub(super) const CALLGSUBR: u8 = 29;
+    pub(super) const VHCURVETO: u8 = 30;
+    pub(super) const HVCURVETO: u8 = 31;
+
+    // Escaped charstring operators
+    pub(super) const HFLEX: u8 = 34;
+    pub(super) const FLEX: u8 = 35;
+    pub(super) const HFLEX1: u8 = 36;
+    pub(super) const FLEX1: u8 = 37;
+}
+
+mod offsets {
+    pub(super) const HEADER_SIZE: usize = 2;
+    pub(super) const TOP_DICT_LENGTH: usize = 3;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outline::Segment;
+
+    #[test]
+    fn charstring() {
+        let charstring = vec![
+            28, 1, 244, 239, 239, 21, // 500 (width) 100 100 rmoveto
+            32, 10, // -107 callsubr, calling subroutine 0 after the bias
+            28, 0, 200, 7, // 200 vlineto
+            89, 189, 39, 139, 89, 89, 8,  // -50 50 -100 0 -50 -50 rrcurveto
+            14, // endchar
+        ];
+        let subr = vec![28, 0, 200, 6, 11]; // 200 hlineto return
+        let cff = Cff {
+            version: Version::Cff,
+            charstrings: vec![charstring],
+            global_subrs: Vec::new(),
+            privates: vec![Private {
+                subrs: vec![subr],
+                vs_index: 0,
+            }],
+            fd_select: Vec::new(),
+            charset: Vec::new(),
+            var_store: None,
+            bboxes: Vec::new(),
+        };
+        let outline = cff.outline(GlyphID(0), &[]).unwrap();
+        let expected = [
+            Segment::MoveTo(point2(100.0, 100.0)),
+            Segment::LineTo(point2(300.0, 100.0)),
+            Segment::LineTo(point2(300.0, 300.0)),
+            Segment::CubicTo(
+                point2(250.0, 350.0),
+                point2(150.0, 350.0),
+                point2(100.0, 300.0),
+            ),
+            Segment::LineTo(point2(100.0, 100.0)),
+        ];
+        assert_eq!(
+            format!("{:?}", outline.segments()),
+            format!("{:?}", expected)
+        );
+    }
+}
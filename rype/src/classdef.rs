@@ -84,13 +84,13 @@ impl ClassDef {
                     return Some(*g as u32);
                 }
                 for range in ranges {
-                    if (range.end_glyph as u32) < glyph.0 {
+                    if (range.start_glyph as u32) > glyph.0 {
                         break;
                     }
-                    if (range.start_glyph as u32) > glyph.0 {
+                    if (range.end_glyph as u32) < glyph.0 {
                         continue;
                     }
-                    return Some((glyph.0 - range.start_glyph as u32) + range.class as u32);
+                    return Some(range.class as u32);
                 }
                 None
             }
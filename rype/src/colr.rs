@@ -0,0 +1,560 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::f32::consts::PI;
+
+use geom::point2;
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::outline::Outline;
+use crate::paint::{Brush, Color, ColorStop, CompositeMode, Extend, PaintCommand, Transform};
+use crate::types::{get_i16, get_u16, get_u24, get_u32};
+use crate::varstore::{get_f2dot14, DeltaSetIndexMap, ItemVariationStore};
+
+/// Wrapper around color table, which describes glyphs as layers of other glyphs filled with
+/// colors (version 0), or as graphs of paint operations (version 1)
+#[derive(Debug)]
+pub(crate) struct Colr {
+    base_glyphs: Vec<(u16, usize, usize)>, // Glyph, first layer and number of layers
+    layers: Vec<(u16, u16)>,               // Glyph and palette index
+    base_paints: Vec<(u16, usize)>,        // Glyph and offset of its root paint
+    layer_paints: Vec<usize>,              // Offsets of paints for PaintColrLayers
+    clips: Vec<(u16, u16, usize)>,         // Glyph range and offset of its clip box
+    var_index_map: Option<DeltaSetIndexMap>,
+    store: Option<ItemVariationStore>,
+    data: Vec<u8>,
+}
+
+/// What's needed from the face to paint color glyphs
+pub(crate) struct PaintContext<'a> {
+    pub(crate) palette: &'a [Color],
+    pub(crate) foreground: Color,
+    pub(crate) coords: &'a [f32],
+    pub(crate) outline: &'a dyn Fn(GlyphID) -> Option<Outline>, // In font units
+}
+
+impl<'a> PaintContext<'a> {
+    fn color(&self, palette_index: u16, alpha: f32) -> Color {
+        let color = if palette_index == FOREGROUND {
+            self.foreground
+        } else {
+            let color = self.palette.get(palette_index as usize).copied();
+            color.unwrap_or(Color::TRANSPARENT)
+        };
+        color.with_alpha(alpha)
+    }
+
+    fn outline(&self, glyph: u16) -> Outline {
+        (self.outline)(GlyphID(glyph as u32)).unwrap_or_default()
+    }
+}
+
+impl Colr {
+    pub(crate) fn load(data: &[u8]) -> Result<Colr> {
+        let version = get_u16(data, offsets::VERSION)?;
+        let num_base_glyphs = get_u16(data, offsets::NUM_BASE_GLYPHS)? as usize;
+        let base_glyphs_off = get_u32(data, offsets::BASE_GLYPHS)? as usize;
+        let layers_off = get_u32(data, offsets::LAYERS)? as usize;
+        let num_layers = get_u16(data, offsets::NUM_LAYERS)? as usize;
+        let base_glyphs = (0..num_base_glyphs)
+            .map(|i| {
+                let off = base_glyphs_off + i * 6;
+                Ok((
+                    get_u16(data, off)?,
+                    get_u16(data, off + 2)? as usize,
+                    get_u16(data, off + 4)? as usize,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let layers = (0..num_layers)
+            .map(|i| {
+                Ok((
+                    get_u16(data, layers_off + i * 4)?,
+                    get_u16(data, layers_off + i * 4 + 2)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut colr = Colr {
+            base_glyphs,
+            layers,
+            base_paints: Vec::new(),
+            layer_paints: Vec::new(),
+            clips: Vec::new(),
+            var_index_map: None,
+            store: None,
+            data: data.to_vec(),
+        };
+        if version == 0 {
+            return Ok(colr);
+        }
+        let base_list = get_u32(data, offsets::BASE_GLYPH_LIST)? as usize;
+        if base_list != 0 {
+            let count = get_u32(data, base_list)? as usize;
+            colr.base_paints = (0..count)
+                .map(|i| {
+                    let off = base_list + 4 + i * 6;
+                    Ok((
+                        get_u16(data, off)?,
+                        base_list + get_u32(data, off + 2)? as usize,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+        }
+        let layer_list = get_u32(data, offsets::LAYER_LIST)? as usize;
+        if layer_list != 0 {
+            let count = get_u32(data, layer_list)? as usize;
+            colr.layer_paints = (0..count)
+                .map(|i| Ok(layer_list + get_u32(data, layer_list + 4 + i * 4)? as usize))
+                .collect::<Result<Vec<_>>>()?;
+        }
+        let clip_list = get_u32(data, offsets::CLIP_LIST)? as usize;
+        if clip_list != 0 {
+            let count = get_u32(data, clip_list + 1)? as usize;
+            colr.clips = (0..count)
+                .map(|i| {
+                    let off = clip_list + 5 + i * 7;
+                    Ok((
+                        get_u16(data, off)?,
+                        get_u16(data, off + 2)?,
+                        clip_list + get_u24(data, off + 4)? as usize,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+        }
+        colr.var_index_map = match get_u32(data, offsets::VAR_INDEX_MAP)? as usize {
+            0 => None,
+            off => Some(DeltaSetIndexMap::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
+        };
+        colr.store = match get_u32(data, offsets::ITEM_VARIATION_STORE)? as usize {
+            0 => None,
+            off => Some(ItemVariationStore::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
+        };
+        Ok(colr)
+    }
+
+    /// Get commands to paint a color glyph, in font units. `None` if it isn't a color glyph
+    pub(crate) fn paint(&self, glyph: GlyphID, ctx: &PaintContext) -> Option<Vec<PaintCommand>> {
+        let glyph = glyph.0 as u16;
+        let mut commands = Vec::new();
+        if self
+            .base_paints
+            .binary_search_by_key(&glyph, |p| p.0)
+            .is_ok()
+        {
+            self.paint_glyph(glyph, ctx, &mut commands, &mut Vec::new())
+                .ok()?;
+            return Some(commands);
+        }
+        let i = self
+            .base_glyphs
+            .binary_search_by_key(&glyph, |b| b.0)
+            .ok()?;
+        let (_, first, count) = self.base_glyphs[i];
+        for (layer_glyph, palette_index) in self.layers.get(first..first + count)? {
+            commands.push(PaintCommand::PushClip(ctx.outline(*layer_glyph)));
+            commands.push(PaintCommand::Fill(Brush::Solid(
+                ctx.color(*palette_index, 1.0),
+            )));
+            commands.push(PaintCommand::PopClip);
+        }
+        Some(commands)
+    }
+
+    // Paint the graph of a base glyph, within its clip box. Glyphs being painted are tracked to
+    // catch cycles
+    fn paint_glyph(
+        &self,
+        glyph: u16,
+        ctx: &PaintContext,
+        commands: &mut Vec<PaintCommand>,
+        painting: &mut Vec<u16>,
+    ) -> Result<()> {
+        if painting.contains(&glyph) || painting.len() > MAX_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let i = self
+            .base_paints
+            .binary_search_by_key(&glyph, |p| p.0)
+            .map_err(|_| Error::Invalid)?;
+        let clip = self.clip_box(glyph, ctx)?;
+        if let Some(clip) = &clip {
+            commands.push(PaintCommand::PushClip(clip.clone()));
+        }
+        painting.push(glyph);
+        self.paint_node(self.base_paints[i].1, ctx, commands, painting, 0)?;
+        painting.pop();
+        if clip.is_some() {
+            commands.push(PaintCommand::PopClip);
+        }
+        Ok(())
+    }
+
+    fn paint_node(
+        &self,
+        off: usize,
+        ctx: &PaintContext,
+        commands: &mut Vec<PaintCommand>,
+        painting: &mut Vec<u16>,
+        depth: usize,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let data = &self.data[..];
+        let format = *data.get(off).ok_or(Error::Invalid)?;
+        // Children are at 24-bit offsets from the start of the paint
+        let child = |rel: usize| -> Result<usize> { Ok(off + get_u24(data, off + rel)? as usize) };
+        let fword = |rel: usize| -> Result<f32> { Ok(get_i16(data, off + rel)? as f32) };
+        let f2dot14 = |rel: usize| get_f2dot14(data, off + rel);
+        // Variable formats are odd, and have the base index of their deltas after other fields
+        let deltas = |rel: usize, count: usize| -> Result<Vec<f32>> {
+            if format % 2 == 1 {
+                Ok(self.deltas(get_u32(data, off + rel)?, count, ctx.coords))
+            } else {
+                Ok(vec![0.0; count])
+            }
+        };
+        let mut paint_child = |rel: usize, transform: Transform| -> Result<()> {
+            commands.push(PaintCommand::PushTransform(transform));
+            self.paint_node(child(rel)?, ctx, commands, painting, depth + 1)?;
+            commands.push(PaintCommand::PopTransform);
+            Ok(())
+        };
+        match format {
+            formats::COLR_LAYERS => {
+                let count = *data.get(off + 1).ok_or(Error::Invalid)? as usize;
+                let first = get_u32(data, off + 2)? as usize;
+                for i in first..first + count {
+                    let layer = *self.layer_paints.get(i).ok_or(Error::Invalid)?;
+                    self.paint_node(layer, ctx, commands, painting, depth + 1)?;
+                }
+            }
+            formats::SOLID | formats::VAR_SOLID => {
+                let d = deltas(5, 1)?;
+                let color = ctx.color(get_u16(data, off + 1)?, f2dot14(3)? + d[0] / F2DOT14);
+                commands.push(PaintCommand::Fill(Brush::Solid(color)));
+            }
+            formats::LINEAR_GRADIENT | formats::VAR_LINEAR_GRADIENT => {
+                let d = deltas(16, 6)?;
+                let (stops, extend) = self.color_line(child(1)?, format % 2 == 1, ctx)?;
+                commands.push(PaintCommand::Fill(Brush::LinearGradient {
+                    p0: point2(fword(4)? + d[0], fword(6)? + d[1]),
+                    p1: point2(fword(8)? + d[2], fword(10)? + d[3]),
+                    p2: point2(fword(12)? + d[4], fword(14)? + d[5]),
+                    stops,
+                    extend,
+                }));
+            }
+            formats::RADIAL_GRADIENT | formats::VAR_RADIAL_GRADIENT => {
+                let d = deltas(16, 6)?;
+                let (stops, extend) = self.color_line(child(1)?, format % 2 == 1, ctx)?;
+                commands.push(PaintCommand::Fill(Brush::RadialGradient {
+                    c0: point2(fword(4)? + d[0], fword(6)? + d[1]),
+                    r0: get_u16(data, off + 8)? as f32 + d[2],
+                    c1: point2(fword(10)? + d[3], fword(12)? + d[4]),
+                    r1: get_u16(data, off + 14)? as f32 + d[5],
+                    stops,
+                    extend,
+                }));
+            }
+            formats::SWEEP_GRADIENT | formats::VAR_SWEEP_GRADIENT => {
+                let d = deltas(12, 4)?;
+                let (stops, extend) = self.color_line(child(1)?, format % 2 == 1, ctx)?;
+                // Angles are in half turns
+                commands.push(PaintCommand::Fill(Brush::SweepGradient {
+                    center: point2(fword(4)? + d[0], fword(6)? + d[1]),
+                    start_angle: (f2dot14(8)? + d[2] / F2DOT14) * 180.0,
+                    end_angle: (f2dot14(10)? + d[3] / F2DOT14) * 180.0,
+                    stops,
+                    extend,
+                }));
+            }
+            formats::GLYPH => {
+                commands.push(PaintCommand::PushClip(ctx.outline(get_u16(data, off + 4)?)));
+                self.paint_node(child(1)?, ctx, commands, painting, depth + 1)?;
+                commands.push(PaintCommand::PopClip);
+            }
+            formats::COLR_GLYPH => {
+                self.paint_glyph(get_u16(data, off + 1)?, ctx, commands, painting)?;
+            }
+            formats::TRANSFORM | formats::VAR_TRANSFORM => {
+                let t = child(4)?;
+                let fixed =
+                    |i: usize| -> Result<f32> { Ok(get_u32(data, t + i * 4)? as i32 as f32) };
+                let d = if format % 2 == 1 {
+                    self.deltas(get_u32(data, t + 24)?, 6, ctx.coords)
+                } else {
+                    vec![0.0; 6]
+                };
+                let v = |i: usize| -> Result<f32> { Ok((fixed(i)? + d[i]) / FIXED) };
+                let transform = Transform {
+                    xx: v(0)?,
+                    yx: v(1)?,
+                    xy: v(2)?,
+                    yy: v(3)?,
+                    dx: v(4)?,
+                    dy: v(5)?,
+                };
+                paint_child(1, transform)?;
+            }
+            formats::TRANSLATE | formats::VAR_TRANSLATE => {
+                let d = deltas(8, 2)?;
+                paint_child(1, Transform::translate(fword(4)? + d[0], fword(6)? + d[1]))?;
+            }
+            formats::SCALE..=formats::VAR_SCALE_UNIFORM_AROUND_CENTER => {
+                // Scales come in four flavours: uniform or not, and around the origin or not
+                let uniform = format >= formats::SCALE_UNIFORM;
+                let around_center = matches!(
+                    format,
+                    formats::SCALE_AROUND_CENTER
+                        | formats::VAR_SCALE_AROUND_CENTER
+                        | formats::SCALE_UNIFORM_AROUND_CENTER
+                        | formats::VAR_SCALE_UNIFORM_AROUND_CENTER
+                );
+                let num_scales = if uniform { 1 } else { 2 };
+                let num_fields = num_scales + if around_center { 2 } else { 0 };
+                let d = deltas(4 + num_fields * 2, num_fields)?;
+                let sx = f2dot14(4)? + d[0] / F2DOT14;
+                let sy = if uniform {
+                    sx
+                } else {
+                    f2dot14(6)? + d[1] / F2DOT14
+                };
+                let mut transform = Transform::scale(sx, sy);
+                if around_center {
+                    let rel = 4 + num_scales * 2;
+                    let center = point2(
+                        fword(rel)? + d[num_scales],
+                        fword(rel + 2)? + d[num_scales + 1],
+                    );
+                    transform = transform.around(center);
+                }
+                paint_child(1, transform)?;
+            }
+            formats::ROTATE..=formats::VAR_ROTATE_AROUND_CENTER => {
+                let around_center = format >= formats::ROTATE_AROUND_CENTER;
+                let num_fields = if around_center { 3 } else { 1 };
+                let d = deltas(4 + num_fields * 2, num_fields)?;
+                let angle = (f2dot14(4)? + d[0] / F2DOT14) * PI;
+                let (sin, cos) = angle.sin_cos();
+                let mut transform = Transform {
+                    xx: cos,
+                    yx: sin,
+                    xy: -sin,
+                    yy: cos,
+                    dx: 0.0,
+                    dy: 0.0,
+                };
+                if around_center {
+                    transform = transform.around(point2(fword(6)? + d[1], fword(8)? + d[2]));
+                }
+                paint_child(1, transform)?;
+            }
+            formats::SKEW..=formats::VAR_SKEW_AROUND_CENTER => {
+                let around_center = format >= formats::SKEW_AROUND_CENTER;
+                let num_fields = if around_center { 4 } else { 2 };
+                let d = deltas(4 + num_fields * 2, num_fields)?;
+                let x_angle = (f2dot14(4)? + d[0] / F2DOT14) * PI;
+                let y_angle = (f2dot14(6)? + d[1] / F2DOT14) * PI;
+                let mut transform = Transform {
+                    xx: 1.0,
+                    yx: y_angle.tan(),
+                    xy: -x_angle.tan(),
+                    yy: 1.0,
+                    dx: 0.0,
+                    dy: 0.0,
+                };
+                if around_center {
+                    transform = transform.around(point2(fword(8)? + d[2], fword(10)? + d[3]));
+                }
+                paint_child(1, transform)?;
+            }
+            formats::COMPOSITE => {
+                let mode = composite_mode(*data.get(off + 4).ok_or(Error::Invalid)?);
+                // Paint the backdrop and the source in layers of their own, and combine them
+                commands.push(PaintCommand::PushLayer);
+                self.paint_node(child(5)?, ctx, commands, painting, depth + 1)?;
+                commands.push(PaintCommand::PushLayer);
+                self.paint_node(child(1)?, ctx, commands, painting, depth + 1)?;
+                commands.push(PaintCommand::PopLayer(mode));
+                commands.push(PaintCommand::PopLayer(CompositeMode::SrcOver));
+            }
+            // Ignore formats from future versions
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn color_line(
+        &self,
+        off: usize,
+        var: bool,
+        ctx: &PaintContext,
+    ) -> Result<(Vec<ColorStop>, Extend)> {
+        let data = &self.data[..];
+        let extend = match *data.get(off).ok_or(Error::Invalid)? {
+            1 => Extend::Repeat,
+            2 => Extend::Reflect,
+            _ => Extend::Pad,
+        };
+        let count = get_u16(data, off + 1)? as usize;
+        let size = if var { 10 } else { 6 };
+        let mut stops = (0..count)
+            .map(|i| {
+                let off = off + 3 + i * size;
+                let d = if var {
+                    self.deltas(get_u32(data, off + 6)?, 2, ctx.coords)
+                } else {
+                    vec![0.0; 2]
+                };
+                let alpha = get_f2dot14(data, off + 4)? + d[1] / F2DOT14;
+                Ok(ColorStop {
+                    offset: get_f2dot14(data, off)? + d[0] / F2DOT14,
+                    color: ctx.color(get_u16(data, off + 2)?, alpha),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        stops.sort_by(|a, b| {
+            a.offset
+                .partial_cmp(&b.offset)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok((stops, extend))
+    }
+
+    // Get outline of clip box of glyph, if it has one
+    fn clip_box(&self, glyph: u16, ctx: &PaintContext) -> Result<Option<Outline>> {
+        let off = match self
+            .clips
+            .iter()
+            .find(|(start, end, _)| *start <= glyph && glyph <= *end)
+        {
+            Some((_, _, off)) => *off,
+            None => return Ok(None),
+        };
+        let data = &self.data[..];
+        let d = if *data.get(off).ok_or(Error::Invalid)? == 2 {
+            self.deltas(get_u32(data, off + 9)?, 4, ctx.coords)
+        } else {
+            vec![0.0; 4]
+        };
+        let v = |i: usize| -> Result<f32> { Ok(get_i16(data, off + 1 + i * 2)? as f32 + d[i]) };
+        let (min, max) = (point2(v(0)?, v(1)?), point2(v(2)?, v(3)?));
+        let mut outline = Outline::default();
+        outline.move_to(min);
+        outline.line_to(point2(max.x, min.y));
+        outline.line_to(max);
+        outline.line_to(point2(min.x, max.y));
+        Ok(Some(outline))
+    }
+
+    // Get deltas for consecutive fields with variations, in the units of the fields
+    fn deltas(&self, base: u32, count: usize, coords: &[f32]) -> Vec<f32> {
+        let store = match &self.store {
+            Some(store) if base != NO_VARIATIONS => store,
+            _ => return vec![0.0; count],
+        };
+        (0..count as u32)
+            .map(|i| {
+                let index = base.wrapping_add(i);
+                let indices = match &self.var_index_map {
+                    Some(map) => map.get(index),
+                    None => Some(((index >> 16) as u16, index as u16)),
+                };
+                indices
+                    .and_then(|(outer, inner)| store.delta(outer, inner, coords).ok())
+                    .unwrap_or(0.0)
+            })
+            .collect()
+    }
+}
+
+fn composite_mode(mode: u8) -> CompositeMode {
+    match mode {
+        0 => CompositeMode::Clear,
+        1 => CompositeMode::Src,
+        2 => CompositeMode::Dest,
+        4 => CompositeMode::DestOver,
+        5 => CompositeMode::SrcIn,
+        6 => CompositeMode::DestIn,
+        7 => CompositeMode::SrcOut,
+        8 => CompositeMode::DestOut,
+        9 => CompositeMode::SrcAtop,
+        10 => CompositeMode::DestAtop,
+        11 => CompositeMode::Xor,
+        12 => CompositeMode::Plus,
+        13 => CompositeMode::Screen,
+        14 => CompositeMode::Overlay,
+        15 => CompositeMode::Darken,
+        16 => CompositeMode::Lighten,
+        17 => CompositeMode::ColorDodge,
+        18 => CompositeMode::ColorBurn,
+        19 => CompositeMode::HardLight,
+        20 => CompositeMode::SoftLight,
+        21 => CompositeMode::Difference,
+        22 => CompositeMode::Exclusion,
+        23 => CompositeMode::Multiply,
+        24 => CompositeMode::Hue,
+        25 => CompositeMode::Saturation,
+        26 => CompositeMode::Color,
+        27 => CompositeMode::Luminosity,
+        _ => CompositeMode::SrcOver,
+    }
+}
+
+// Palette index for the color text is drawn with
+const FOREGROUND: u16 = 0xffff;
+const NO_VARIATIONS: u32 = 0xffff_ffff;
+// Deltas are in the units of the fields they apply to
+const F2DOT14: f32 = 16384.0;
+const FIXED: f32 = 65536.0;
+// Limit on nesting of paints, against malicious fonts
+const MAX_DEPTH: usize = 64;
+
+mod formats {
+    pub(super) const COLR_LAYERS: u8 = 1;
+    pub(super) const SOLID: u8 = 2;
+    pub(super) const VAR_SOLID: u8 = 3;
+    pub(super) const LINEAR_GRADIENT: u8 = 4;
+    pub(super) const VAR_LINEAR_GRADIENT: u8 = 5;
+    pub(super) const RADIAL_GRADIENT: u8 = 6;
+    pub(super) const VAR_RADIAL_GRADIENT: u8 = 7;
+    pub(super) const SWEEP_GRADIENT: u8 = 8;
+    pub(super) const VAR_SWEEP_GRADIENT: u8 = 9;
+    pub(super) const GLYPH: u8 = 10;
+    pub(super) const COLR_GLYPH: u8 = 11;
+    pub(super) const TRANSFORM: u8 = 12;
+    pub(super) const VAR_TRANSFORM: u8 = 13;
+    pub(super) const TRANSLATE: u8 = 14;
+    pub(super) const VAR_TRANSLATE: u8 = 15;
+    pub(super) const SCALE: u8 = 16;
+    pub(super) const SCALE_AROUND_CENTER: u8 = 18;
+    pub(super) const VAR_SCALE_AROUND_CENTER: u8 = 19;
+    pub(super) const SCALE_UNIFORM: u8 = 20;
+    pub(super) const SCALE_UNIFORM_AROUND_CENTER: u8 = 22;
+    pub(super) const VAR_SCALE_UNIFORM_AROUND_CENTER: u8 = 23;
+    pub(super) const ROTATE: u8 = 24;
+    pub(super) const ROTATE_AROUND_CENTER: u8 = 26;
+    pub(super) const VAR_ROTATE_AROUND_CENTER: u8 = 27;
+    pub(super) const SKEW: u8 = 28;
+    pub(super) const SKEW_AROUND_CENTER: u8 = 30;
+    pub(super) const VAR_SKEW_AROUND_CENTER: u8 = 31;
+    pub(super) const COMPOSITE: u8 = 32;
+}
+
+mod offsets {
+    pub(super) const VERSION: usize = 0;
+    pub(super) const NUM_BASE_GLYPHS: usize = 2;
+    pub(super) const BASE_GLYPHS: usize = 4;
+    pub(super) const LAYERS: usize = 8;
+    pub(super) const NUM_LAYERS: usize = 12;
+    pub(super) const BASE_GLYPH_LIST: usize = 14;
+    pub(super) const LAYER_LIST: usize = 18;
+    pub(super) const CLIP_LIST: usize = 22;
+    pub(super) const VAR_INDEX_MAP: usize = 26;
+    pub(super) const ITEM_VARIATION_STORE: usize = 30;
+}
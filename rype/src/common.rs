@@ -5,24 +5,64 @@ use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
 
 use geom::{size2, vec2, Num, NumCast, Size2D, Vector2D};
 
+use crate::features::GlyphFeatures;
+use crate::shaper::SyllableInfo;
+
 /// Wrapper around glyphs
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub struct GlyphID(pub(crate) u32);
 
+impl GlyphID {
+    /// Glyph with the given index in the font
+    pub fn new(id: u16) -> GlyphID {
+        GlyphID(id as u32)
+    }
+}
+
+impl From<u16> for GlyphID {
+    fn from(id: u16) -> GlyphID {
+        GlyphID::new(id)
+    }
+}
+
 impl fmt::Debug for GlyphID {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+/// Ligature membership of a glyph. Every ligature formed gets a non-zero ID, larger than those of
+/// ligatures already in the text. IDs wrap around to 1 after 65535, after which they may repeat.
+/// Marks that were skipped over while forming a ligature carry its ID, along with the (1-based)
+/// index of the component they followed.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LigatureInfo {
+    pub(crate) id: u16,
+    pub(crate) component: u16,
+    pub(crate) num_components: u16,
+}
+
+/// Glyph information tracked through substitution
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SubstGlyph {
+    pub(crate) glyph: GlyphID,
+    pub(crate) cluster: usize, // Index of first character this glyph was formed from
+    pub(crate) lig: LigatureInfo,
+    pub(crate) features: GlyphFeatures, // Features enabled for this glyph by the shaper
+    pub(crate) syllable: SyllableInfo,
+}
+
 /// Shaped glyph information
 #[derive(Debug)]
 pub(crate) struct GlyphInfo {
     pub(crate) glyph: GlyphID,
+    pub(crate) cluster: usize,
+    pub(crate) lig: LigatureInfo,
+    pub(crate) features: GlyphFeatures,
     pub(crate) bearing: Vector2D<i16>,
     pub(crate) size: Size2D<u16>,
-    pub(crate) offset: Vector2D<i16>,
-    pub(crate) advance: Vector2D<u16>,
+    pub(crate) offset: Vector2D<i32>,
+    pub(crate) advance: Vector2D<i32>,
 }
 
 impl GlyphInfo {
@@ -50,6 +90,7 @@ impl GlyphInfo {
         .cast();
         ScaledGlyphInfo {
             glyph: self.glyph,
+            cluster: self.cluster,
             bearing,
             size,
             offset,
@@ -62,12 +103,29 @@ impl GlyphInfo {
 #[derive(Debug)]
 pub struct ScaledGlyphInfo {
     pub glyph: GlyphID,
+    pub cluster: usize, // Index of the first character this glyph was formed from
     pub bearing: Vector2D<F26p6>,
     pub size: Size2D<F26p6>,
     pub offset: Vector2D<F26p6>,
     pub advance: Vector2D<F26p6>,
 }
 
+/// Font-wide metrics of a scaled face. Vertical positions are relative to the baseline, with y
+/// pointing up
+#[derive(Clone, Copy, Debug)]
+pub struct FaceMetrics {
+    pub ascender: F26p6,
+    pub descender: F26p6, // Usually negative
+    pub line_gap: F26p6,  // Space between the descender of a line and the ascender of the next
+    pub x_height: Option<F26p6>,
+    pub cap_height: Option<F26p6>,
+    pub underline_position: F26p6, // Top of the underline
+    pub underline_thickness: F26p6,
+    pub strikeout_position: F26p6, // Top of the strikeout stroke
+    pub strikeout_thickness: F26p6,
+    pub italic_angle: f32, // Degrees counter-clockwise from vertical, so negative when leaning right
+}
+
 /// Imitiate floating point number with 6 bits of decimal precision
 #[derive(Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord)]
 pub struct F26p6(i32);
@@ -250,7 +308,7 @@ impl F26p6 {
     }
 
     fn int_part(self) -> i32 {
-        (self.0 >> 6) & 0x3ffffff
+        self.0 >> 6
     }
 
     fn frac_part(self) -> i32 {
@@ -53,10 +53,10 @@ impl Coverage {
             }
             Coverage::Format2 { ranges } => {
                 for range in ranges {
-                    if (range.end_glyph_id as u32) < g.0 {
+                    if (range.start_glyph_id as u32) > g.0 {
                         break;
                     }
-                    if (range.start_glyph_id as u32) > g.0 {
+                    if (range.end_glyph_id as u32) < g.0 {
                         continue;
                     }
                     return Some(
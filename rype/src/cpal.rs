@@ -0,0 +1,47 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::paint::Color;
+use crate::types::{get_u16, get_u32};
+
+/// Wrapper around color palette table
+#[derive(Debug)]
+pub(crate) struct Cpal(Vec<Vec<Color>>);
+
+impl Cpal {
+    pub(crate) fn load(data: &[u8]) -> Result<Cpal> {
+        let num_entries = get_u16(data, offsets::NUM_PALETTE_ENTRIES)? as usize;
+        let num_palettes = get_u16(data, offsets::NUM_PALETTES)? as usize;
+        let records_off = get_u32(data, offsets::COLOR_RECORDS)? as usize;
+        let palettes = (0..num_palettes)
+            .map(|i| {
+                let first = get_u16(data, offsets::COLOR_RECORD_INDICES + i * 2)? as usize;
+                (first..first + num_entries)
+                    .map(|r| {
+                        // Colors are stored in BGRA order
+                        let off = records_off + r * 4;
+                        let record = data.get(off..off + 4).ok_or(Error::Invalid)?;
+                        Ok(Color {
+                            r: record[2],
+                            g: record[1],
+                            b: record[0],
+                            a: record[3],
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Cpal(palettes))
+    }
+
+    pub(crate) fn palettes(&self) -> &[Vec<Color>] {
+        &self.0
+    }
+}
+
+mod offsets {
+    pub(super) const NUM_PALETTE_ENTRIES: usize = 2;
+    pub(super) const NUM_PALETTES: usize = 4;
+    pub(super) const COLOR_RECORDS: usize = 8;
+    pub(super) const COLOR_RECORD_INDICES: usize = 12;
+}
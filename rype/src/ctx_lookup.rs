@@ -40,7 +40,9 @@ impl SequenceRuleTable {
         let start = 4 + glyph_count * 2;
         let mut lookup_records = Vec::new();
         for off in (start..start + seq_count * 4).step_by(4) {
-            lookup_records.push(SequenceLookupRecord::load(&data[off..])?);
+            lookup_records.push(SequenceLookupRecord::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?);
         }
         Ok(SequenceRuleTable {
             input_seq,
@@ -71,7 +73,7 @@ impl SequenceContextFormat {
         match get_u16(data, 0)? {
             1 => {
                 let coverage_offset = get_u16(data, 2)? as usize;
-                let coverage = Coverage::load(&data[coverage_offset..])?;
+                let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
                 let rule_set_count = get_u16(data, 4)? as usize;
                 let mut rule_sets = Vec::new();
                 for rule_set_off_off in (6..6 + rule_set_count * 2).step_by(2) {
@@ -79,12 +81,14 @@ impl SequenceContextFormat {
                     if rule_set_off == 0 {
                         rule_sets.push(None);
                     } else {
-                        let data = &data[rule_set_off..];
+                        let data = data.get(rule_set_off..).ok_or(Error::Invalid)?;
                         let rule_count = get_u16(data, 0)? as usize;
                         let mut rules = Vec::new();
                         for rule_off_off in (2..2 + rule_count * 2).step_by(2) {
                             let rule_off = get_u16(data, rule_off_off)? as usize;
-                            rules.push(SequenceRuleTable::load(&data[rule_off..])?);
+                            rules.push(SequenceRuleTable::load(
+                                data.get(rule_off..).ok_or(Error::Invalid)?,
+                            )?);
                         }
                         rule_sets.push(Some(rules));
                     }
@@ -96,9 +100,9 @@ impl SequenceContextFormat {
             }
             2 => {
                 let coverage_offset = get_u16(data, 2)? as usize;
-                let coverage = Coverage::load(&data[coverage_offset..])?;
+                let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
                 let classdef_offset = get_u16(data, 4)? as usize;
-                let classdef = ClassDef::load(&data[classdef_offset..])?;
+                let classdef = ClassDef::load(data.get(classdef_offset..).ok_or(Error::Invalid)?)?;
                 let rule_set_count = get_u16(data, 6)? as usize;
                 let mut rule_sets = Vec::new();
                 for rule_set_off_off in (8..8 + rule_set_count * 2).step_by(2) {
@@ -106,12 +110,14 @@ impl SequenceContextFormat {
                     if rule_set_off == 0 {
                         rule_sets.push(None);
                     } else {
-                        let data = &data[rule_set_off..];
+                        let data = data.get(rule_set_off..).ok_or(Error::Invalid)?;
                         let rule_count = get_u16(data, 0)? as usize;
                         let mut rules = Vec::new();
                         for rule_off_off in (2..2 + rule_count * 2).step_by(2) {
                             let rule_off = get_u16(data, rule_off_off)? as usize;
-                            rules.push(SequenceRuleTable::load(&data[rule_off..])?);
+                            rules.push(SequenceRuleTable::load(
+                                data.get(rule_off..).ok_or(Error::Invalid)?,
+                            )?);
                         }
                         rule_sets.push(Some(rules));
                     }
@@ -128,12 +134,14 @@ impl SequenceContextFormat {
                 let mut coverages = Vec::new();
                 for off in (6..6 + glyph_count * 2).step_by(2) {
                     let cov_off = get_u16(data, off)? as usize;
-                    coverages.push(Coverage::load(&data[cov_off..])?);
+                    coverages.push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
                 }
                 let start = 6 + glyph_count * 2;
                 let mut lookup_records = Vec::new();
                 for off in (start..start + seq_count * 4).step_by(4) {
-                    lookup_records.push(SequenceLookupRecord::load(&data[off..])?);
+                    lookup_records.push(SequenceLookupRecord::load(
+                        data.get(off..).ok_or(Error::Invalid)?,
+                    )?);
                 }
                 Ok(SequenceContextFormat::Format3 {
                     coverages,
@@ -167,7 +175,7 @@ impl SequenceContextFormat {
                                 continue 'outer;
                             }
                         }
-                        return Some((opt.lookup_records.as_ref(), opt.input_seq.len()));
+                        return Some((opt.lookup_records.as_ref(), opt.input_seq.len() + 1));
                     }
                     None
                 }),
@@ -193,7 +201,7 @@ impl SequenceContextFormat {
                                 continue 'outer;
                             }
                         }
-                        return Some((opt.lookup_records.as_ref(), opt.input_seq.len()));
+                        return Some((opt.lookup_records.as_ref(), opt.input_seq.len() + 1));
                     }
                     None
                 }),
@@ -247,7 +255,9 @@ impl ChainedSequenceRuleTable {
         let seq_count = get_u16(data, seq_off)? as usize;
         let mut lookup_records = Vec::new();
         for off in (seq_off + 2..seq_off + 2 + seq_count * 4).step_by(4) {
-            lookup_records.push(SequenceLookupRecord::load(&data[off..])?);
+            lookup_records.push(SequenceLookupRecord::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?);
         }
         Ok(ChainedSequenceRuleTable {
             backtrack_glyphs,
@@ -284,7 +294,7 @@ impl ChainedSequenceContextFormat {
         match get_u16(data, 0)? {
             1 => {
                 let coverage_offset = get_u16(data, 2)? as usize;
-                let coverage = Coverage::load(&data[coverage_offset..])?;
+                let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
                 let rule_set_count = get_u16(data, 4)? as usize;
                 let mut rule_sets = Vec::new();
                 for rule_set_off_off in (6..6 + rule_set_count * 2).step_by(2) {
@@ -292,12 +302,14 @@ impl ChainedSequenceContextFormat {
                     if rule_set_off == 0 {
                         rule_sets.push(None);
                     } else {
-                        let data = &data[rule_set_off..];
+                        let data = data.get(rule_set_off..).ok_or(Error::Invalid)?;
                         let rule_count = get_u16(data, 0)? as usize;
                         let mut rules = Vec::new();
                         for rule_off_off in (2..2 + rule_count * 2).step_by(2) {
                             let rule_off = get_u16(data, rule_off_off)? as usize;
-                            rules.push(ChainedSequenceRuleTable::load(&data[rule_off..])?);
+                            rules.push(ChainedSequenceRuleTable::load(
+                                data.get(rule_off..).ok_or(Error::Invalid)?,
+                            )?);
                         }
                         rule_sets.push(Some(rules));
                     }
@@ -309,13 +321,20 @@ impl ChainedSequenceContextFormat {
             }
             2 => {
                 let coverage_offset = get_u16(data, 2)? as usize;
-                let coverage = Coverage::load(&data[coverage_offset..])?;
+                let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
                 let backtrack_classdef_offset = get_u16(data, 4)? as usize;
-                let backtrack_classdef = ClassDef::load(&data[backtrack_classdef_offset..])?;
+                let backtrack_classdef = ClassDef::load(
+                    data.get(backtrack_classdef_offset..)
+                        .ok_or(Error::Invalid)?,
+                )?;
                 let input_classdef_offset = get_u16(data, 6)? as usize;
-                let input_classdef = ClassDef::load(&data[input_classdef_offset..])?;
+                let input_classdef =
+                    ClassDef::load(data.get(input_classdef_offset..).ok_or(Error::Invalid)?)?;
                 let lookahead_classdef_offset = get_u16(data, 8)? as usize;
-                let lookahead_classdef = ClassDef::load(&data[lookahead_classdef_offset..])?;
+                let lookahead_classdef = ClassDef::load(
+                    data.get(lookahead_classdef_offset..)
+                        .ok_or(Error::Invalid)?,
+                )?;
                 let rule_set_count = get_u16(data, 10)? as usize;
                 let mut rule_sets = Vec::new();
                 for rule_set_off_off in (12..12 + rule_set_count * 2).step_by(2) {
@@ -323,12 +342,14 @@ impl ChainedSequenceContextFormat {
                     if rule_set_off == 0 {
                         rule_sets.push(None);
                     } else {
-                        let data = &data[rule_set_off..];
+                        let data = data.get(rule_set_off..).ok_or(Error::Invalid)?;
                         let rule_count = get_u16(data, 0)? as usize;
                         let mut rules = Vec::new();
                         for rule_off_off in (2..2 + rule_count * 2).step_by(2) {
                             let rule_off = get_u16(data, rule_off_off)? as usize;
-                            rules.push(ChainedSequenceRuleTable::load(&data[rule_off..])?);
+                            rules.push(ChainedSequenceRuleTable::load(
+                                data.get(rule_off..).ok_or(Error::Invalid)?,
+                            )?);
                         }
                         rule_sets.push(Some(rules));
                     }
@@ -346,7 +367,8 @@ impl ChainedSequenceContextFormat {
                 let mut backtrack_coverages = Vec::new();
                 for off in (4..4 + backtrack_glyph_count * 2).step_by(2) {
                     let cov_off = get_u16(data, off)? as usize;
-                    backtrack_coverages.push(Coverage::load(&data[cov_off..])?);
+                    backtrack_coverages
+                        .push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
                 }
 
                 let input_off = 4 + backtrack_glyph_count * 2;
@@ -354,7 +376,8 @@ impl ChainedSequenceContextFormat {
                 let mut input_coverages = Vec::new();
                 for off in (input_off + 2..input_off + 2 + input_glyph_count * 2).step_by(2) {
                     let cov_off = get_u16(data, off)? as usize;
-                    input_coverages.push(Coverage::load(&data[cov_off..])?);
+                    input_coverages
+                        .push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
                 }
 
                 let lookahead_off = input_off + 2 + input_glyph_count * 2;
@@ -364,14 +387,17 @@ impl ChainedSequenceContextFormat {
                     (lookahead_off + 2..lookahead_off + 2 + lookahead_glyph_count * 2).step_by(2)
                 {
                     let cov_off = get_u16(data, off)? as usize;
-                    lookahead_coverages.push(Coverage::load(&data[cov_off..])?);
+                    lookahead_coverages
+                        .push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
                 }
 
                 let seq_off = lookahead_off + 2 + lookahead_glyph_count * 2;
                 let seq_count = get_u16(data, seq_off)? as usize;
                 let mut lookup_records = Vec::new();
                 for off in (seq_off + 2..seq_off + 2 + seq_count * 4).step_by(4) {
-                    lookup_records.push(SequenceLookupRecord::load(&data[off..])?);
+                    lookup_records.push(SequenceLookupRecord::load(
+                        data.get(off..).ok_or(Error::Invalid)?,
+                    )?);
                 }
                 Ok(ChainedSequenceContextFormat::Format3 {
                     backtrack_coverages,
@@ -421,7 +447,7 @@ impl ChainedSequenceContextFormat {
                                 continue 'outer;
                             }
                         }
-                        return Some((opt.lookup_records.as_ref(), opt.input_glyphs.len()));
+                        return Some((opt.lookup_records.as_ref(), opt.input_glyphs.len() + 1));
                     }
                     None
                 }),
@@ -459,7 +485,7 @@ impl ChainedSequenceContextFormat {
                                 continue 'outer;
                             }
                         }
-                        return Some((opt.lookup_records.as_ref(), opt.input_glyphs.len()));
+                        return Some((opt.lookup_records.as_ref(), opt.input_glyphs.len() + 1));
                     }
                     None
                 }),
@@ -0,0 +1,88 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::Size2D;
+
+use crate::error::*;
+use crate::types::get_u16;
+use crate::varstore::ItemVariationStore;
+
+/// Adjustment to a value in GPOS or GDEF, either in pixels at specific sizes, or by variation
+/// deltas
+#[derive(Clone, Debug)]
+pub(crate) enum Device {
+    Hinting { start_size: u16, deltas: Vec<i8> }, // Pixel deltas from the start size onwards
+    Variation { outer: u16, inner: u16 },         // Indices of delta set in the variation store
+}
+
+impl Device {
+    /// Load device table. Unknown formats are ignored
+    pub(crate) fn load(data: &[u8]) -> Result<Option<Device>> {
+        let start_size = get_u16(data, offsets::START_SIZE)?;
+        let end_size = get_u16(data, offsets::END_SIZE)?;
+        let bits = match get_u16(data, offsets::DELTA_FORMAT)? {
+            1 => 2,
+            2 => 4,
+            3 => 8,
+            VARIATION_INDEX => {
+                return Ok(Some(Device::Variation {
+                    outer: start_size,
+                    inner: end_size,
+                }))
+            }
+            _ => return Ok(None),
+        };
+        // Deltas are signed values packed into 16-bit words, most significant bits first
+        let count = (end_size.max(start_size) - start_size) as usize + 1;
+        let per_word = 16 / bits;
+        let deltas = (0..count)
+            .map(|i| {
+                let word = get_u16(data, offsets::DELTA_VALUES + (i / per_word) * 2)?;
+                let shift = 16 - bits * (i % per_word + 1);
+                let value = (word >> shift) as u32 & ((1 << bits) - 1);
+                // Sign-extend
+                Ok(((value << (32 - bits)) as i32 >> (32 - bits)) as i8)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Device::Hinting { start_size, deltas }))
+    }
+
+    /// Get adjustment in font units, given the pixels per em along the adjusted direction
+    pub(crate) fn delta(&self, ctx: &DeviceContext, ppem: u16) -> i32 {
+        match self {
+            Device::Hinting { start_size, deltas } => {
+                if ppem == 0 || ppem < *start_size {
+                    return 0;
+                }
+                match deltas.get((ppem - start_size) as usize) {
+                    Some(d) => (*d as i32 * ctx.units_per_em as i32) / ppem as i32,
+                    None => 0,
+                }
+            }
+            Device::Variation { outer, inner } => match ctx.store {
+                Some(store) => store
+                    .delta(*outer, *inner, ctx.coords)
+                    .map(|d| d.round() as i32)
+                    .unwrap_or(0),
+                None => 0,
+            },
+        }
+    }
+}
+
+/// What device adjustments depend on: the size text is grid-fitted at, and the variation instance
+#[derive(Clone, Copy)]
+pub(crate) struct DeviceContext<'a> {
+    pub(crate) ppem: Size2D<u16>, // Zero when not grid-fitting
+    pub(crate) units_per_em: u16,
+    pub(crate) coords: &'a [f32],
+    pub(crate) store: Option<&'a ItemVariationStore>,
+}
+
+const VARIATION_INDEX: u16 = 0x8000;
+
+mod offsets {
+    pub(super) const START_SIZE: usize = 0;
+    pub(super) const END_SIZE: usize = 2;
+    pub(super) const DELTA_FORMAT: usize = 4;
+    pub(super) const DELTA_VALUES: usize = 6;
+}
@@ -3,7 +3,7 @@
 use crate::features::*;
 
 // Text direction
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Direction {
     LeftToRight,
     RightToLeft,
@@ -20,4 +20,68 @@ impl Direction {
             Direction::BottomToTop => &VERTICAL_FEATURES,
         }
     }
+
+    pub(crate) fn is_horizontal(&self) -> bool {
+        match self {
+            Direction::LeftToRight | Direction::RightToLeft => true,
+            Direction::TopToBottom | Direction::BottomToTop => false,
+        }
+    }
+
+    /// Is visual order the reverse of logical order?
+    pub(crate) fn is_backward(&self) -> bool {
+        match self {
+            Direction::LeftToRight | Direction::TopToBottom => false,
+            Direction::RightToLeft | Direction::BottomToTop => true,
+        }
+    }
+
+    /// Get the character to display for `c` in text of this direction. Only common brackets,
+    /// quotation marks and relations are mirrored
+    pub(crate) fn mirror(&self, c: char) -> char {
+        match self {
+            Direction::RightToLeft => MIRRORED
+                .iter()
+                .find_map(|(a, b)| {
+                    if *a == c {
+                        Some(*b)
+                    } else if *b == c {
+                        Some(*a)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(c),
+            _ => c,
+        }
+    }
 }
+
+// Pairs of characters which are mirror images of each other. This is a subset of the Unicode
+// Bidi_Mirroring_Glyph property, covering ASCII and Latin-1 brackets and quotation marks, and the
+// most common punctuation, mathematical and CJK brackets and relations. Other mirrored characters
+// are displayed unchanged
+static MIRRORED: [(char, char); 22] = [
+    ('(', ')'),
+    ('<', '>'),
+    ('[', ']'),
+    ('{', '}'),
+    ('\u{AB}', '\u{BB}'),
+    ('\u{2039}', '\u{203A}'),
+    ('\u{2045}', '\u{2046}'),
+    ('\u{207D}', '\u{207E}'),
+    ('\u{208D}', '\u{208E}'),
+    ('\u{2208}', '\u{220B}'),
+    ('\u{2264}', '\u{2265}'),
+    ('\u{2282}', '\u{2283}'),
+    ('\u{2286}', '\u{2287}'),
+    ('\u{2329}', '\u{232A}'),
+    ('\u{27E8}', '\u{27E9}'),
+    ('\u{27EA}', '\u{27EB}'),
+    ('\u{2983}', '\u{2984}'),
+    ('\u{3008}', '\u{3009}'),
+    ('\u{300A}', '\u{300B}'),
+    ('\u{300C}', '\u{300D}'),
+    ('\u{3010}', '\u{3011}'),
+    ('\u{FF08}', '\u{FF09}'),
+];
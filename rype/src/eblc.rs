@@ -0,0 +1,372 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{size2, vec2};
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::png;
+use crate::raster::{Bitmap, BitmapFormat};
+use crate::strike::BitmapMetrics;
+use crate::types::{get_u16, get_u32};
+
+/// Wrapper around embedded bitmap location and data tables. Handles both EBLC/EBDT, and CBLC/CBDT
+/// which add color PNG images to the same structure
+#[derive(Debug)]
+pub(crate) struct Eblc {
+    strikes: Vec<Strike>,
+    data: Vec<u8>, // Contents of the EBDT or CBDT table
+}
+
+impl Eblc {
+    pub(crate) fn load(location: &[u8], data: &[u8]) -> Result<Eblc> {
+        let num_sizes = get_u32(location, offsets::NUM_SIZES)? as usize;
+        let strikes = (0..num_sizes)
+            .map(|i| Strike::load(location, offsets::BITMAP_SIZES + i * sizes::BITMAP_SIZE))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Eblc {
+            strikes,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Pixels per em of each strike
+    pub(crate) fn strike_ppems(&self) -> impl Iterator<Item = u16> + '_ {
+        self.strikes.iter().map(|s| s.ppem)
+    }
+
+    /// Whether the strike has color images
+    pub(crate) fn is_color(&self, strike: usize) -> bool {
+        self.strikes[strike].bit_depth == 32
+    }
+
+    /// Get metrics of glyph in strike, in pixels, without decoding its image
+    pub(crate) fn metrics(&self, strike: usize, glyph: GlyphID) -> Option<BitmapMetrics> {
+        let (format, image, index_metrics) = self.strikes[strike].locate(glyph, &self.data)?;
+        image_metrics(format, image, index_metrics).map(|(metrics, _)| metrics)
+    }
+
+    /// Decode bitmap of glyph in strike. Monochrome and grayscale images are decoded into
+    /// coverage, and color images into premultiplied RGBA
+    pub(crate) fn bitmap(&self, strike: usize, glyph: GlyphID) -> Option<Bitmap> {
+        self.bitmap_nested(strike, glyph, 0)
+    }
+
+    fn bitmap_nested(&self, strike: usize, glyph: GlyphID, depth: usize) -> Option<Bitmap> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return None;
+        }
+        let bit_depth = self.strikes[strike].bit_depth;
+        let (format, image, index_metrics) = self.strikes[strike].locate(glyph, &self.data)?;
+        let (metrics, header_len) = image_metrics(format, image, index_metrics)?;
+        let body = image.get(header_len..)?;
+        let mut bitmap = Bitmap {
+            size: metrics.size,
+            bearing: metrics.bearing,
+            format: BitmapFormat::Gray,
+            data: vec![0; (metrics.size.width * metrics.size.height) as usize],
+        };
+        match format {
+            1 | 6 => unpack(&mut bitmap, body, bit_depth, true)?,
+            2 | 5 | 7 => unpack(&mut bitmap, body, bit_depth, false)?,
+            8 | 9 => {
+                // Components are placed relative to the top-left of the composite
+                let body = if format == 8 { body.get(1..)? } else { body };
+                let num_components = get_u16(body, 0).ok()? as usize;
+                for i in 0..num_components {
+                    let at = 2 + i * sizes::COMPONENT;
+                    let component = GlyphID(get_u16(body, at).ok()? as u32);
+                    let (dx, dy) = (*body.get(at + 2)? as i8, *body.get(at + 3)? as i8);
+                    let component = self.bitmap_nested(strike, component, depth + 1)?;
+                    paste(&mut bitmap, &component, dx as i32, dy as i32);
+                }
+            }
+            17..=19 => {
+                let len = get_u32(body, 0).ok()? as usize;
+                let (size, data) = png::decode(body.get(4..4 + len)?).ok()?;
+                bitmap.size = size;
+                bitmap.format = BitmapFormat::Rgba;
+                bitmap.data = data;
+            }
+            _ => return None,
+        }
+        Some(bitmap)
+    }
+}
+
+// Bitmap size record, and the index subtables locating its glyphs
+#[derive(Debug)]
+struct Strike {
+    ppem: u16,
+    bit_depth: u8,
+    ranges: Vec<IndexSubtable>,
+}
+
+impl Strike {
+    fn load(data: &[u8], offset: usize) -> Result<Strike> {
+        let record = data
+            .get(offset..offset + sizes::BITMAP_SIZE)
+            .ok_or(Error::Invalid)?;
+        let array_off = get_u32(record, offsets::INDEX_SUBTABLE_ARRAY)? as usize;
+        let num_subtables = get_u32(record, offsets::NUM_INDEX_SUBTABLES)? as usize;
+        let ranges = (0..num_subtables)
+            .map(|i| {
+                let entry = array_off + i * sizes::INDEX_SUBTABLE_RECORD;
+                let first = get_u16(data, entry + offsets::FIRST_GLYPH)?;
+                let last = get_u16(data, entry + offsets::LAST_GLYPH)?;
+                let subtable_off = get_u32(data, entry + offsets::SUBTABLE_OFFSET)? as usize;
+                IndexSubtable::load(data, array_off + subtable_off, first, last)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Strike {
+            ppem: record[offsets::PPEM_Y] as u16,
+            bit_depth: record[offsets::BIT_DEPTH],
+            ranges,
+        })
+    }
+
+    // Get image format and data of glyph, along with metrics shared by glyphs in the subtable
+    fn locate<'a>(
+        &self,
+        glyph: GlyphID,
+        data: &'a [u8],
+    ) -> Option<(u16, &'a [u8], Option<BitmapMetrics>)> {
+        let glyph = glyph.0 as u16;
+        let range = self
+            .ranges
+            .iter()
+            .find(|r| r.first <= glyph && glyph <= r.last)?;
+        let index = (glyph - range.first) as usize;
+        let (start, end, metrics) = match &range.index {
+            Index::Offsets(offsets) => (*offsets.get(index)?, *offsets.get(index + 1)?, None),
+            Index::Fixed {
+                image_size,
+                metrics,
+            } => (
+                index as u32 * image_size,
+                (index as u32 + 1) * image_size,
+                Some(*metrics),
+            ),
+            Index::Sparse(glyphs) => {
+                let i = glyphs[..glyphs.len() - 1]
+                    .iter()
+                    .position(|(g, _)| *g == glyph)?;
+                (glyphs[i].1, glyphs.get(i + 1)?.1, None)
+            }
+            Index::SparseFixed {
+                image_size,
+                metrics,
+                glyphs,
+            } => {
+                let i = glyphs.binary_search(&glyph).ok()? as u32;
+                (i * image_size, (i + 1) * image_size, Some(*metrics))
+            }
+        };
+        if end <= start {
+            return None;
+        }
+        let (start, end) = (
+            range.image_offset + start as usize,
+            range.image_offset + end as usize,
+        );
+        Some((range.image_format, data.get(start..end)?, metrics))
+    }
+}
+
+// Locations of images for a range of glyphs
+#[derive(Debug)]
+struct IndexSubtable {
+    first: u16,
+    last: u16,
+    image_format: u16,
+    image_offset: usize, // Offset of images in the data table
+    index: Index,
+}
+
+// Offsets of images relative to the subtable's image offset
+#[derive(Debug)]
+enum Index {
+    Offsets(Vec<u32>), // For each glyph in the range, and one past the last
+    Fixed {
+        image_size: u32,
+        metrics: BitmapMetrics,
+    }, // Images of the same size and metrics for each glyph in the range
+    Sparse(Vec<(u16, u32)>), // Glyphs with offsets, and a final offset past the last image
+    SparseFixed {
+        image_size: u32,
+        metrics: BitmapMetrics,
+        glyphs: Vec<u16>,
+    }, // Images of the same size and metrics for the given glyphs
+}
+
+impl IndexSubtable {
+    fn load(data: &[u8], offset: usize, first: u16, last: u16) -> Result<IndexSubtable> {
+        if last < first {
+            return Err(Error::Invalid);
+        }
+        let index_format = get_u16(data, offset + offsets::INDEX_FORMAT)?;
+        let image_format = get_u16(data, offset + offsets::IMAGE_FORMAT)?;
+        let image_offset = get_u32(data, offset + offsets::IMAGE_DATA_OFFSET)? as usize;
+        let body = offset + sizes::INDEX_SUBHEADER;
+        let count = (last - first) as usize + 2;
+        let index = match index_format {
+            1 => Index::Offsets(
+                (0..count)
+                    .map(|i| get_u32(data, body + i * 4))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            2 => Index::Fixed {
+                image_size: get_u32(data, body)?,
+                metrics: big_metrics(data.get(body + 4..).ok_or(Error::Invalid)?)
+                    .ok_or(Error::Invalid)?,
+            },
+            3 => Index::Offsets(
+                (0..count)
+                    .map(|i| get_u16(data, body + i * 2).map(|o| o as u32))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            4 => {
+                let num_glyphs = get_u32(data, body)? as usize;
+                Index::Sparse(
+                    (0..num_glyphs + 1)
+                        .map(|i| {
+                            let at = body + 4 + i * 4;
+                            Ok((get_u16(data, at)?, get_u16(data, at + 2)? as u32))
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                )
+            }
+            5 => {
+                let num_glyphs_off = body + 4 + sizes::BIG_METRICS;
+                let num_glyphs = get_u32(data, num_glyphs_off)? as usize;
+                Index::SparseFixed {
+                    image_size: get_u32(data, body)?,
+                    metrics: big_metrics(data.get(body + 4..).ok_or(Error::Invalid)?)
+                        .ok_or(Error::Invalid)?,
+                    glyphs: (0..num_glyphs)
+                        .map(|i| get_u16(data, num_glyphs_off + 4 + i * 2))
+                        .collect::<Result<Vec<_>>>()?,
+                }
+            }
+            _ => return Err(Error::Invalid),
+        };
+        Ok(IndexSubtable {
+            first,
+            last,
+            image_format,
+            image_offset,
+            index,
+        })
+    }
+}
+
+// Get metrics of image, and the length of the header before its data. Formats without metrics of
+// their own use those from the index subtable
+fn image_metrics(
+    format: u16,
+    image: &[u8],
+    index_metrics: Option<BitmapMetrics>,
+) -> Option<(BitmapMetrics, usize)> {
+    match format {
+        1 | 2 | 8 | 17 => Some((small_metrics(image)?, sizes::SMALL_METRICS)),
+        6 | 7 | 9 | 18 => Some((big_metrics(image)?, sizes::BIG_METRICS)),
+        5 | 19 => Some((index_metrics?, 0)),
+        _ => None,
+    }
+    .and_then(|(metrics, len)| match format {
+        // The size is in the PNG image too, which is what gets decoded
+        17..=19 => {
+            let data_len = get_u32(image, len).ok()? as usize;
+            let size = png::size(image.get(len + 4..len + 4 + data_len)?).ok()?;
+            Some((BitmapMetrics { size, ..metrics }, len))
+        }
+        _ => Some((metrics, len)),
+    })
+}
+
+// Height, width, horizontal bearings and advance
+fn small_metrics(data: &[u8]) -> Option<BitmapMetrics> {
+    let metrics = data.get(..sizes::SMALL_METRICS)?;
+    Some(BitmapMetrics {
+        size: size2(metrics[1] as u32, metrics[0] as u32),
+        bearing: vec2(metrics[2] as i8 as i32, metrics[3] as i8 as i32),
+    })
+}
+
+// Small metrics, followed by vertical bearings and advance. Only horizontal metrics are used
+fn big_metrics(data: &[u8]) -> Option<BitmapMetrics> {
+    data.get(..sizes::BIG_METRICS).and_then(small_metrics)
+}
+
+// Unpack pixels of the given bit depth into coverage. Rows are either padded to whole bytes, or
+// packed together
+fn unpack(bitmap: &mut Bitmap, data: &[u8], bit_depth: u8, byte_aligned: bool) -> Option<()> {
+    let depth = bit_depth as usize;
+    if !matches!(depth, 1 | 2 | 4 | 8) {
+        return None;
+    }
+    let max = (1 << depth) - 1;
+    let (width, height) = (bitmap.size.width as usize, bitmap.size.height as usize);
+    let row_bits = if byte_aligned {
+        (width * depth + 7) / 8 * 8
+    } else {
+        width * depth
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let bit = y * row_bits + x * depth;
+            let byte = *data.get(bit / 8)? as usize;
+            let value = (byte >> (8 - depth - bit % 8)) & max;
+            bitmap.data[y * width + x] = (value * 255 / max) as u8;
+        }
+    }
+    Some(())
+}
+
+// Draw component into a composite bitmap, at the given offset from its top-left
+fn paste(bitmap: &mut Bitmap, component: &Bitmap, dx: i32, dy: i32) {
+    if component.format != bitmap.format {
+        return;
+    }
+    let (width, height) = (bitmap.size.width as i32, bitmap.size.height as i32);
+    for y in 0..component.size.height as i32 {
+        for x in 0..component.size.width as i32 {
+            let (px, py) = (x + dx, y + dy);
+            if px < 0 || py < 0 || px >= width || py >= height {
+                continue;
+            }
+            let src = component.data[(y * component.size.width as i32 + x) as usize];
+            let dst = &mut bitmap.data[(py * width + px) as usize];
+            *dst = (*dst).max(src);
+        }
+    }
+}
+
+// Limit on nesting of composite bitmaps
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+mod offsets {
+    pub(super) const NUM_SIZES: usize = 4;
+    pub(super) const BITMAP_SIZES: usize = 8;
+
+    pub(super) const INDEX_SUBTABLE_ARRAY: usize = 0;
+    pub(super) const NUM_INDEX_SUBTABLES: usize = 8;
+    pub(super) const PPEM_Y: usize = 45;
+    pub(super) const BIT_DEPTH: usize = 46;
+
+    pub(super) const FIRST_GLYPH: usize = 0;
+    pub(super) const LAST_GLYPH: usize = 2;
+    pub(super) const SUBTABLE_OFFSET: usize = 4;
+
+    pub(super) const INDEX_FORMAT: usize = 0;
+    pub(super) const IMAGE_FORMAT: usize = 2;
+    pub(super) const IMAGE_DATA_OFFSET: usize = 4;
+}
+
+mod sizes {
+    pub(super) const BITMAP_SIZE: usize = 48;
+    pub(super) const INDEX_SUBTABLE_RECORD: usize = 8;
+    pub(super) const INDEX_SUBHEADER: usize = 8;
+    pub(super) const SMALL_METRICS: usize = 5;
+    pub(super) const BIG_METRICS: usize = 8;
+    pub(super) const COMPONENT: usize = 4;
+}
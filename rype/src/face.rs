@@ -2,84 +2,514 @@
 
 use std::fmt;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use fnv::FnvHashMap;
-use geom::{size2, vec2, Size2D};
+use geom::{point2, size2, vec2, BBox, NumCast, Point2D, Size2D, Vector2D};
 
+use super::autohint::Autohinter;
+use super::avar::Avar;
+use super::cff::Cff;
 use super::cmap::Cmap;
-use super::common::{GlyphInfo, ScaledGlyphInfo};
+use super::colr::{Colr, PaintContext};
+use super::common::{
+    F26p6, FaceMetrics, GlyphID, GlyphInfo, LigatureInfo, ScaledGlyphInfo, SubstGlyph,
+};
+use super::cpal::Cpal;
+use super::device::DeviceContext;
 use super::direction::Direction;
+use super::eblc::Eblc;
 use super::error::*;
 use super::features::*;
+use super::fvar::{Fvar, NamedInstance, VariationAxis};
 use super::gasp::Gasp;
-use super::gdef::Gdef;
+use super::gdef::{CaretValue, Gdef};
 use super::glyf::Glyf;
 use super::gpos::Gpos;
 use super::gsub::Gsub;
+use super::gvar::Gvar;
 use super::head::Head;
 use super::hhea::Hhea;
+use super::hint::{Hinter, Hinting, Programs};
 use super::hmtx::Hmtx;
+use super::hvar::Hvar;
 use super::kern::Kern;
 use super::loca::Loca;
 use super::maxp::Maxp;
+use super::mvar::Mvar;
+use super::name::{Name, NameID};
+use super::options::ShapingOptions;
 use super::os2::Os2;
+use super::outline::Outline;
+use super::paint::{self, Color, PaintCommand, Transform};
+use super::post::Post;
+use super::raster::{self, Bitmap, RenderMode};
+use super::sbix::Sbix;
+use super::shaper::{Shaper, SyllableInfo};
+use super::strike::{self, BitmapMetrics};
 use super::types::*;
-use super::Script;
+use super::vhea::Vhea;
+use super::vmtx::Vmtx;
+use super::vorg::Vorg;
+use super::{Language, Script};
 
 /// A face that has been scaled
 #[derive(Debug)]
 pub struct ScaledFace {
     scale: Size2D<f32>,
     face_inner: Rc<FaceInner>,
+    coords: Rc<[f32]>, // Normalized variation coordinates
+    hinting: Hinting,
+    hinter: Option<Hinter>, // Interpreter state for TrueType hinting at this size
+    autohinter: Autohinter,
+    face_id: usize,
+}
+
+/// Identifies a face at a size and variation instance, with the hinting that changes its glyphs
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ScaledFaceID {
+    face: usize,
+    scale: (u32, u32), // Bits of the floating-point scale
+    hinting: Hinting,
 }
 
 impl ScaledFace {
+    pub(crate) fn id(&self) -> ScaledFaceID {
+        ScaledFaceID {
+            face: self.face_id,
+            scale: (self.scale.width.to_bits(), self.scale.height.to_bits()),
+            hinting: self.hinting,
+        }
+    }
+
+    /// Set how outlines are grid-fitted
+    pub fn set_hinting(&mut self, hinting: Hinting) {
+        self.hinting = hinting;
+    }
+
+    /// Get how outlines are actually grid-fitted. TrueType instructions are only run at the
+    /// default variation instance, and when the font's programs ran without errors. Otherwise,
+    /// including for CFF faces, outlines are autohinted instead. Nothing is grid-fitted at sizes
+    /// where `gasp` doesn't allow it
+    pub fn hinting(&self) -> Hinting {
+        let ppem = self.face_inner.ppem(self.scale);
+        match self.hinting {
+            Hinting::Full if !self.face_inner.gridfit(ppem) => Hinting::None,
+            Hinting::Full if self.hinter.is_none() || self.coords.iter().any(|c| *c != 0.0) => {
+                Hinting::Light
+            }
+            hinting => hinting,
+        }
+    }
+
+    /// Shape text. Returns the characters in the text, and the shaped glyphs in visual order. Each
+    /// glyph's cluster indexes into the returned characters. Clusters never decrease in logical
+    /// order (so they never increase for right-to-left text), and a glyph formed from multiple
+    /// characters (e.g. a ligature) covers all characters up to the next glyph's cluster in
+    /// logical order. In vertical text, advances point down, and each glyph's offset moves its
+    /// vertical origin (horizontally centered, at the top) onto the pen position.
     pub fn shape<S: AsRef<str>>(
         &self,
         text: &S,
-        script: Script,
-        direction: Direction,
+        options: &ShapingOptions,
     ) -> Result<(Vec<char>, Vec<ScaledGlyphInfo>)> {
+        let (script, language, direction) = (options.script, options.language, options.direction);
+        let shaper = Shaper::for_script(script);
         let codepoints = text.as_ref().chars().collect::<Vec<_>>();
-        let mut glyph_ids = codepoints
+        let mut glyphs = codepoints
             .iter()
-            .map(|cp| self.face_inner.cmap.glyph_id_for_codepoint(*cp as u32))
+            .enumerate()
+            .map(|(i, cp)| SubstGlyph {
+                glyph: (self.face_inner.cmap).glyph_id_for_codepoint(direction.mirror(*cp) as u32),
+                cluster: i,
+                lig: LigatureInfo::default(),
+                features: GlyphFeatures::empty(),
+                syllable: SyllableInfo::default(),
+            })
             .collect::<Vec<_>>();
-        let features = DEFAULT_FEATURES
+        let features = options.feature_values(shaper.features());
+        shaper.substitute(
+            &mut glyphs,
+            &codepoints,
+            &self.face_inner.cmap,
+            self.face_inner.gsub.as_ref(),
+            options,
+            &features,
+        );
+        let mut glyph_infos = glyphs
             .iter()
-            .map(|f| f.tag())
-            .chain(direction.features().iter().map(|f| f.tag()))
-            .collect();
-        if let Some(gsub) = &self.face_inner.gsub {
-            gsub.substitute(&mut glyph_ids, script, &features);
-        }
-        let glyph_infos = glyph_ids.iter().map(|g| {
-            let hor_metrics = self.face_inner.hmtx.get_metrics(*g);
-            let bbox = match &self.face_inner.face_type {
-                FaceType::TTF { glyf, .. } => glyf.glyph_bbox(*g),
-            };
-            GlyphInfo {
-                glyph: *g,
-                size: size2(bbox.max.x - bbox.min.x, bbox.max.y - bbox.min.y).cast(),
-                bearing: vec2(hor_metrics.lsb, bbox.max.y),
-                offset: vec2(0, 0),
-                advance: vec2(hor_metrics.advance_width, 0),
-            }
-        });
-        /*
+            .map(|g| {
+                let hor_metrics = self.face_inner.hmtx.get_metrics(g.glyph);
+                let (bbox, lsb) = match self.bitmap_bbox(g.glyph) {
+                    Some(bbox) => (bbox, bbox.min.x),
+                    None => {
+                        let bbox = self.face_inner.glyph_bbox(g.glyph, &self.coords);
+                        // Variations may move the outline away from the stored left side bearing
+                        if self.coords.iter().any(|c| *c != 0.0) {
+                            (bbox, bbox.min.x)
+                        } else {
+                            (bbox, hor_metrics.lsb)
+                        }
+                    }
+                };
+                GlyphInfo {
+                    glyph: g.glyph,
+                    cluster: g.cluster,
+                    lig: g.lig,
+                    features: g.features,
+                    size: size2(bbox.max.x - bbox.min.x, bbox.max.y - bbox.min.y).cast(),
+                    bearing: vec2(lsb, bbox.max.y),
+                    offset: vec2(0, 0),
+                    advance: vec2(self.face_inner.advance(g.glyph, &self.coords), 0),
+                }
+            })
+            .map(|mut g| {
+                // Glyphs are positioned by their horizontal origin, so move it away from the
+                // vertical origin
+                if !direction.is_horizontal() {
+                    let origin_y = self.face_inner.vertical_origin(g.glyph, &self.coords);
+                    g.offset = vec2(-g.advance.x / 2, -origin_y);
+                    g.advance = vec2(0, -self.face_inner.vertical_advance(g.glyph));
+                }
+                g
+            })
+            .collect::<Vec<_>>();
+        let kern_feature = if direction.is_horizontal() {
+            Features::Kerning.tag()
+        } else {
+            Features::VerticalKerning.tag()
+        };
+        let mut gpos_kerns = false;
         if let Some(gpos) = &self.face_inner.gpos {
-            gpos.apply(&mut glyph_infos, script, &features);
+            gpos_kerns = gpos.has_feature(script, language, kern_feature);
+            gpos.position(
+                &mut glyph_infos,
+                script,
+                language,
+                direction,
+                &features,
+                self.device_context(),
+            );
+        }
+        // Fall back to the legacy kern table if GPOS doesn't do kerning
+        if !gpos_kerns && features.may_enable(kern_feature) {
+            if let Some(kern) = &self.face_inner.kern {
+                kern.apply(
+                    &mut glyph_infos,
+                    direction.is_horizontal(),
+                    &features,
+                    kern_feature,
+                );
+            }
+        }
+        // Glyphs have been in logical order so far
+        if direction.is_backward() {
+            glyph_infos.reverse();
         }
-        */
         // Scale all glyph data
-        let scaled_glyph_infos = glyph_infos.map(|g| g.scale(self.scale)).collect();
+        let scaled_glyph_infos = glyph_infos.iter().map(|g| g.scale(self.scale)).collect();
         Ok((codepoints, scaled_glyph_infos))
     }
+
+    /// Get font-wide metrics, scaled to this size. When hinting, the ascender and descender are
+    /// rounded outwards to whole pixels, and the line gap to the nearest pixel
+    pub fn metrics(&self) -> FaceMetrics {
+        let (inner, coords) = (&self.face_inner, &self.coords[..]);
+        let (hhea, os2) = (&inner.hhea, &inner.os2);
+        // Line metrics are in hhea, unless the font asks for the typographic ones in OS/2
+        let (ascender, descender, line_gap) = if os2.use_typo_metrics() {
+            (os2.typo_ascender, os2.typo_descender, os2.typo_line_gap)
+        } else if hhea.ascender != 0 || hhea.descender != 0 {
+            (hhea.ascender, hhea.descender, hhea.line_gap)
+        } else if os2.typo_ascender != 0 || os2.typo_descender != 0 {
+            (os2.typo_ascender, os2.typo_descender, os2.typo_line_gap)
+        } else {
+            (os2.win_ascent as i16, -(os2.win_descent as i16), 0)
+        };
+        // MVAR only varies the typographic line metrics, which the others usually match
+        let ascender = ascender as f32 + inner.mvar_delta(b"hasc", coords);
+        let descender = descender as f32 + inner.mvar_delta(b"hdsc", coords);
+        let line_gap = line_gap as f32 + inner.mvar_delta(b"hlgp", coords);
+        // Fonts without a post table get an underline as thick as the strikeout, just below the
+        // baseline
+        let (underline_position, underline_thickness) = match &inner.post {
+            Some(post) => (post.underline_position, post.underline_thickness),
+            None => (-os2.strikeout_size, os2.strikeout_size),
+        };
+        let italic_angle = inner.post.as_ref().map(|p| p.italic_angle).unwrap_or(0.0);
+
+        let scale = self.scale.height;
+        let (ascender, descender, line_gap) = match self.hinting {
+            Hinting::None => (ascender * scale, descender * scale, line_gap * scale),
+            _ => (
+                (ascender * scale).ceil(),
+                (descender * scale).floor(),
+                (line_gap * scale).round(),
+            ),
+        };
+        let scaled = |value: f32| -> F26p6 { (value * scale).cast() };
+        FaceMetrics {
+            ascender: ascender.cast(),
+            descender: descender.cast(),
+            line_gap: line_gap.cast(),
+            x_height: inner.x_height(coords).map(scaled),
+            cap_height: inner.cap_height(coords).map(scaled),
+            underline_position: scaled(
+                underline_position as f32 + inner.mvar_delta(b"undo", coords),
+            ),
+            underline_thickness: scaled(
+                underline_thickness as f32 + inner.mvar_delta(b"unds", coords),
+            ),
+            strikeout_position: scaled(
+                os2.strikeout_position as f32 + inner.mvar_delta(b"stro", coords),
+            ),
+            strikeout_thickness: scaled(
+                os2.strikeout_size as f32 + inner.mvar_delta(b"strs", coords),
+            ),
+            italic_angle,
+        }
+    }
+
+    /// Get caret positions between the components of a ligature glyph, from the glyph's origin
+    /// along the direction of text. Device and variation adjustments are applied, and carets at
+    /// outline points follow the grid-fitted outline when hinting
+    pub fn ligature_carets(&self, glyph: GlyphID, direction: Direction) -> Vec<F26p6> {
+        let gdef = match &self.face_inner.gdef {
+            Some(gdef) => gdef,
+            None => return Vec::new(),
+        };
+        let device = self.device_context();
+        let vertical = !direction.is_horizontal();
+        let (scale, ppem) = if vertical {
+            (self.scale.height, device.ppem.height)
+        } else {
+            (self.scale.width, device.ppem.width)
+        };
+        gdef.ligature_carets(glyph)
+            .iter()
+            .map(|caret| {
+                let pos = match caret {
+                    CaretValue::Coord {
+                        coord,
+                        device: caret_device,
+                    } => {
+                        let delta = caret_device
+                            .as_ref()
+                            .map(|d| d.delta(&device, ppem))
+                            .unwrap_or(0);
+                        (*coord as i32 + delta) as f32 * scale
+                    }
+                    CaretValue::Point(index) => self
+                        .outline_point(glyph, *index as usize)
+                        .map(|p| if vertical { p.y } else { p.x })
+                        .unwrap_or(0.0),
+                };
+                pos.cast()
+            })
+            .collect()
+    }
+
+    // Get point of glyph's outline in pixels, grid-fitted if hinting
+    fn outline_point(&self, glyph: GlyphID, index: usize) -> Option<Point2D<f32>> {
+        let (glyf, gvar, programs) = match &self.face_inner.face_type {
+            FaceType::TTF {
+                glyf,
+                gvar,
+                programs,
+                ..
+            } => (glyf, gvar, programs),
+            _ => return None,
+        };
+        match (self.hinting(), &self.hinter) {
+            (Hinting::Full, Some(hinter)) => {
+                glyf.hinted_point(glyph, index, &self.face_inner.hmtx, programs, hinter)
+            }
+            _ => glyf
+                .point(glyph, index, gvar.as_ref(), &self.coords)
+                .map(|p| point2(p.x * self.scale.width, p.y * self.scale.height)),
+        }
+    }
+
+    // Size and variation instance that device tables adjust positions for
+    fn device_context(&self) -> DeviceContext<'_> {
+        DeviceContext {
+            ppem: self.device_ppem(),
+            units_per_em: self.face_inner.head.units_per_em,
+            coords: &self.coords,
+            store: self
+                .face_inner
+                .gdef
+                .as_ref()
+                .and_then(|gdef| gdef.var_store()),
+        }
+    }
+
+    // Pixels per em that device tables adjust positions for. Pixel adjustments only make sense
+    // when grid-fitting
+    fn device_ppem(&self) -> Size2D<u16> {
+        if self.hinting == Hinting::None {
+            return size2(0, 0);
+        }
+        let units_per_em = self.face_inner.head.units_per_em as f32;
+        size2(
+            (self.scale.width * units_per_em).round() as u16,
+            (self.scale.height * units_per_em).round() as u16,
+        )
+    }
+
+    /// Get outline of glyph, scaled to pixels and grid-fitted if hinting is enabled
+    pub fn outline(&self, glyph: GlyphID) -> Option<Outline> {
+        if let Some(outline) = self.hinted_outline(glyph) {
+            return Some(outline);
+        }
+        let outline = self
+            .face_inner
+            .outline(glyph, &self.coords)?
+            .scale(self.scale);
+        match self.hinting() {
+            Hinting::Light => Some(self.autohinter.hint(&outline)),
+            _ => Some(outline),
+        }
+    }
+
+    // Grid-fit outline with TrueType instructions, at the default instance
+    fn hinted_outline(&self, glyph: GlyphID) -> Option<Outline> {
+        let hinter = match (self.hinting(), &self.hinter) {
+            (Hinting::Full, Some(hinter)) => hinter,
+            _ => return None,
+        };
+        match &self.face_inner.face_type {
+            FaceType::TTF { glyf, programs, .. } => {
+                glyf.hinted_outline(glyph, &self.face_inner.hmtx, programs, hinter)
+            }
+            _ => None,
+        }
+    }
+
+    /// Rasterize glyph into a coverage bitmap, with its origin at the given subpixel offset.
+    /// Embedded bitmaps are used instead for color bitmap glyphs, at sizes the font has bitmaps
+    /// drawn for, and for glyphs without outlines. They ignore the offset and render mode, and
+    /// color bitmaps are in premultiplied RGBA
+    pub fn rasterize(
+        &self,
+        glyph: GlyphID,
+        offset: Vector2D<f32>,
+        mode: RenderMode,
+    ) -> Option<Bitmap> {
+        if self.bitmap_strike(glyph).is_some() {
+            return self.bitmap(glyph);
+        }
+        // The gasp table may turn off smoothing across subpixels at some sizes
+        let ppem = self.face_inner.ppem(self.scale);
+        let symmetric_smoothing = match &self.face_inner.face_type {
+            FaceType::TTF {
+                gasp: Some(gasp), ..
+            } => gasp.symmetric_smoothing(ppem),
+            _ => true,
+        };
+        self.outline(glyph)
+            .map(|outline| raster::rasterize(&outline, offset, mode, symmetric_smoothing))
+            .or_else(|| self.bitmap(glyph))
+    }
+
+    /// Get embedded bitmap of glyph from the strike that best matches this size, scaled to this
+    /// size. Monochrome and grayscale bitmaps are decoded into coverage, and color bitmaps into
+    /// premultiplied RGBA
+    pub fn bitmap(&self, glyph: GlyphID) -> Option<Bitmap> {
+        let ppem = self.face_inner.ppem(self.scale);
+        let strike = self.face_inner.strike(glyph, ppem)?;
+        let scale = self.scale.height * self.face_inner.head.units_per_em as f32;
+        strike
+            .bitmap(glyph)
+            .map(|bitmap| strike::resample(&bitmap, scale / strike.ppem() as f32))
+    }
+
+    // Strike of embedded bitmaps that stands in for the glyph's outline at this size. Color
+    // bitmaps are used at any size, and others only at the size they were drawn for
+    fn bitmap_strike(&self, glyph: GlyphID) -> Option<StrikeRef<'_>> {
+        let ppem = self.face_inner.ppem(self.scale);
+        let strike = self.face_inner.strike(glyph, ppem)?;
+        let has_outlines = !matches!(self.face_inner.face_type, FaceType::Bitmap);
+        if strike.is_color() || strike.ppem() == ppem || !has_outlines {
+            Some(strike)
+        } else {
+            None
+        }
+    }
+
+    // Bounding box of glyph in font units, if it is drawn from an embedded bitmap at this size
+    fn bitmap_bbox(&self, glyph: GlyphID) -> Option<BBox<i16>> {
+        let strike = self.bitmap_strike(glyph)?;
+        let metrics = strike.metrics(glyph)?;
+        let units = self.face_inner.head.units_per_em as f32 / strike.ppem() as f32;
+        let to_units = |px: i32| (px as f32 * units).round() as i16;
+        let (bearing, size) = (metrics.bearing, metrics.size.cast::<i32>());
+        Some(BBox::new(
+            point2(to_units(bearing.x), to_units(bearing.y - size.height)),
+            point2(to_units(bearing.x + size.width), to_units(bearing.y)),
+        ))
+    }
+
+    /// Get commands to paint a color glyph with colors from the given palette. The foreground color
+    /// is what text is drawn with. Geometry is in font units, and the first command transforms it
+    /// to pixels. `None` if the glyph isn't a color glyph
+    pub fn paint(
+        &self,
+        glyph: GlyphID,
+        palette: usize,
+        foreground: Color,
+    ) -> Option<Vec<PaintCommand>> {
+        let colr = self.face_inner.colr.as_ref()?;
+        let palette = match &self.face_inner.cpal {
+            Some(cpal) => cpal.palettes().get(palette).map(|p| &p[..]).unwrap_or(&[]),
+            None => &[],
+        };
+        let outline = |glyph| self.face_inner.outline(glyph, &self.coords);
+        let ctx = PaintContext {
+            palette,
+            foreground,
+            coords: &self.coords,
+            outline: &outline,
+        };
+        let commands = colr.paint(glyph, &ctx)?;
+        let mut ret = Vec::with_capacity(commands.len() + 2);
+        let scale = Transform::scale(self.scale.width, self.scale.height);
+        ret.push(PaintCommand::PushTransform(scale));
+        ret.extend(commands);
+        ret.push(PaintCommand::PopTransform);
+        Some(ret)
+    }
+
+    /// Rasterize color glyph into a premultiplied RGBA bitmap, with its origin at the given
+    /// subpixel offset. Glyphs with color embedded bitmaps ignore the offset and palette. `None`
+    /// if the glyph isn't a color glyph
+    pub fn rasterize_color(
+        &self,
+        glyph: GlyphID,
+        offset: Vector2D<f32>,
+        palette: usize,
+        foreground: Color,
+    ) -> Option<Bitmap> {
+        if let Some(commands) = self.paint(glyph, palette, foreground) {
+            return Some(paint::render(&commands, offset));
+        }
+        match self.bitmap_strike(glyph) {
+            Some(strike) if strike.is_color() => self.bitmap(glyph),
+            _ => None,
+        }
+    }
 }
 
 /// A face within an OpenType file
 #[derive(Debug)]
-pub struct Face(Rc<FaceInner>);
+pub struct Face {
+    inner: Rc<FaceInner>,
+    coords: Rc<[f32]>, // Normalized variation coordinates
+    id: usize,
+}
+
+// IDs for faces and their variation instances, to tell them apart in caches
+static NEXT_FACE_ID: AtomicUsize = AtomicUsize::new(0);
 
 impl Face {
     /// Load face at given index from font file
@@ -104,19 +534,175 @@ impl Face {
         }
     }
 
+    /// Get alternate glyphs offered for a glyph by the given feature. Alternate `i` in the returned
+    /// list is selected by setting the feature's value to `i + 1` when shaping.
+    pub fn alternates(
+        &self,
+        glyph: GlyphID,
+        script: Script,
+        language: Language,
+        feature: Features,
+    ) -> Vec<GlyphID> {
+        self.inner
+            .gsub
+            .as_ref()
+            .map(|gsub| gsub.alternates(glyph, script, language, feature.tag()))
+            .unwrap_or_default()
+    }
+
+    /// Get glyph for character from the `cmap` table. `None` if the font has no glyph for it
+    pub fn glyph_index(&self, c: char) -> Option<GlyphID> {
+        match self.inner.cmap.glyph_id_for_codepoint(c as u32) {
+            GlyphID(0) => None,
+            glyph => Some(glyph),
+        }
+    }
+
+    /// Get outline of glyph, in font units
+    pub fn outline(&self, glyph: GlyphID) -> Option<Outline> {
+        self.inner.outline(glyph, &self.coords)
+    }
+
+    /// Get string from the `name` table in the given language, falling back to English and then
+    /// any language
+    pub fn name(&self, id: NameID, language: Language) -> Option<&str> {
+        self.inner.name.as_ref()?.get(id, language)
+    }
+
+    /// Get family name of the face, preferring the typographic family name which groups more
+    /// styles together
+    pub fn family_name(&self, language: Language) -> Option<&str> {
+        self.name(NameID::TYPOGRAPHIC_FAMILY, language)
+            .or_else(|| self.name(NameID::FAMILY, language))
+    }
+
+    /// Get style name of the face within its family (e.g. "Bold Italic"), preferring the
+    /// typographic subfamily name
+    pub fn subfamily_name(&self, language: Language) -> Option<&str> {
+        self.name(NameID::TYPOGRAPHIC_SUBFAMILY, language)
+            .or_else(|| self.name(NameID::SUBFAMILY, language))
+    }
+
+    /// Get color palettes for color glyphs
+    pub fn palettes(&self) -> &[Vec<Color>] {
+        match &self.inner.cpal {
+            Some(cpal) => cpal.palettes(),
+            None => &[],
+        }
+    }
+
+    /// Get variation axes of the face. Empty if the face isn't variable
+    pub fn variation_axes(&self) -> &[VariationAxis] {
+        self.inner
+            .fvar
+            .as_ref()
+            .map(|fvar| &fvar.axes[..])
+            .unwrap_or(&[])
+    }
+
+    /// Get named instances of a variable face
+    pub fn named_instances(&self) -> &[NamedInstance] {
+        self.inner
+            .fvar
+            .as_ref()
+            .map(|fvar| &fvar.instances[..])
+            .unwrap_or(&[])
+    }
+
+    /// Get face at the variation instance given by user coordinates for some axes, e.g. a weight
+    /// of 650 for `wght`. Other axes are at their defaults
+    pub fn with_variations(&self, variations: &[([u8; 4], f32)]) -> Face {
+        let coords = self
+            .variation_axes()
+            .iter()
+            .map(|axis| {
+                variations
+                    .iter()
+                    .rev()
+                    .find(|(tag, _)| *tag == axis.tag)
+                    .map(|(_, v)| *v)
+                    .unwrap_or(axis.default)
+            })
+            .collect::<Vec<_>>();
+        self.with_user_coords(&coords)
+    }
+
+    /// Get face at a named instance
+    pub fn with_named_instance(&self, index: usize) -> Option<Face> {
+        let instance = self.named_instances().get(index)?;
+        Some(self.with_user_coords(&instance.coords))
+    }
+
+    // Get face at user coordinates for each axis, normalizing them and mapping them with avar
+    fn with_user_coords(&self, coords: &[f32]) -> Face {
+        let normalized = self
+            .variation_axes()
+            .iter()
+            .zip(coords)
+            .enumerate()
+            .map(|(i, (axis, v))| {
+                let coord = axis.normalize(*v);
+                match &self.inner.avar {
+                    Some(avar) => avar.map(i, coord),
+                    None => coord,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.with_normalized_coords(&normalized)
+    }
+
+    /// Get face at the variation instance given by normalized coordinates, one for each
+    /// variation axis of the face in the range -1 to 1. Missing coordinates are taken as 0, i.e.
+    /// the default instance
+    pub fn with_normalized_coords(&self, coords: &[f32]) -> Face {
+        // NaN coordinates are taken as the default
+        let coords = coords
+            .iter()
+            .map(|c| if c.is_nan() { 0.0 } else { c.clamp(-1.0, 1.0) })
+            .collect();
+        Face {
+            inner: self.inner.clone(),
+            coords,
+            id: NEXT_FACE_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
     /// Get scaled face
     pub fn scale(&self, point_size: u16, dpi: Size2D<u16>) -> ScaledFace {
         let point_size = point_size as f32;
-        let units_per_em = self.0.head.units_per_em as f32;
+        let units_per_em = self.inner.head.units_per_em as f32;
         let mult = point_size / (72.0 * units_per_em);
         let scale = size2(dpi.width as f32 * mult, dpi.height as f32 * mult);
-        let face_inner = self.0.clone();
-        ScaledFace { scale, face_inner }
+        let face_inner = self.inner.clone();
+        let coords = self.coords.clone();
+        // Run the font and control value programs, if grid-fitting is allowed at this size
+        let ppem = face_inner.ppem(scale);
+        let hinter = match &face_inner.face_type {
+            FaceType::TTF { programs, .. } if face_inner.gridfit(ppem) => {
+                Hinter::new(programs, scale, ppem).ok()
+            }
+            _ => None,
+        };
+        let blues = face_inner.blue_zones(&coords);
+        let autohinter = Autohinter::new(&blues, scale.height, ppem as f32);
+        ScaledFace {
+            scale,
+            face_inner,
+            coords,
+            hinting: Hinting::default(),
+            hinter,
+            autohinter,
+            face_id: self.id,
+        }
     }
 
     /// Initialize face structure
     fn load_face(data: &[u8], offset: usize) -> Result<Face> {
-        FaceInner::load(data, offset).map(|fi| Face(Rc::new(fi)))
+        FaceInner::load(data, offset).map(|fi| Face {
+            inner: Rc::new(fi),
+            coords: Rc::new([]),
+            id: NEXT_FACE_ID.fetch_add(1, Ordering::Relaxed),
+        })
     }
 }
 
@@ -133,6 +719,19 @@ pub(crate) struct FaceInner {
     gpos: Option<Gpos>,
     kern: Option<Kern>,
     gdef: Option<Rc<Gdef>>,
+    fvar: Option<Fvar>,
+    avar: Option<Avar>,
+    hvar: Option<Hvar>,
+    mvar: Option<Mvar>,
+    colr: Option<Colr>,
+    cpal: Option<Cpal>,
+    eblc: Option<Eblc>,
+    cblc: Option<Eblc>,
+    sbix: Option<Sbix>,
+    name: Option<Name>,
+    post: Option<Post>,
+    vmtx: Option<Vmtx>,
+    vorg: Option<Vorg>,
 }
 
 impl FaceInner {
@@ -172,6 +771,14 @@ impl FaceInner {
             .and_then(|data| {
                 Hmtx::load(data, maxp.num_glyphs as usize, hhea.num_h_metrics as usize)
             })?;
+        // Optional tables that fail to load are ignored, rather than failing the whole face
+        let optional = |tag: &[u8; 4]| tables.get(&Tag::from(tag)).copied();
+        let vmtx = match (optional(b"vhea"), optional(b"vmtx")) {
+            (Some(vhea), Some(vmtx)) => Vhea::load(vhea).ok().and_then(|vhea| {
+                Vmtx::load(vmtx, maxp.num_glyphs as usize, vhea.num_v_metrics as usize).ok()
+            }),
+            _ => None,
+        };
         let cmap = tables
             .get(&Tag::from(b"cmap"))
             .ok_or(Error::Invalid)
@@ -182,8 +789,10 @@ impl FaceInner {
             .and_then(|data| Os2::load(data))?;
 
         const OTTO: Tag = Tag::from(b"OTTO");
+        const TRUE: Tag = Tag::from(b"true");
+        let has_table = |tag| tables.contains_key(&Tag::from(tag));
         let face_type = match sfnt_version {
-            Tag(0x00010000) => {
+            Tag(0x00010000) | TRUE if has_table(b"glyf") => {
                 let loca = tables
                     .get(&Tag::from(b"loca"))
                     .ok_or(Error::Invalid)
@@ -194,27 +803,63 @@ impl FaceInner {
                     .get(&Tag::from(b"glyf"))
                     .ok_or(Error::Invalid)
                     .and_then(|data| Glyf::load(data, &loca))?;
-                let gasp = tables
-                    .get(&Tag::from(b"gasp"))
-                    .map(|d| Gasp::load(d).expect("failed to load gasp"));
-                FaceType::TTF { gasp, glyf }
+                let gasp = optional(b"gasp").and_then(|data| Gasp::load(data).ok());
+                let programs = Programs::load(
+                    tables.get(&Tag::from(b"fpgm")).copied(),
+                    tables.get(&Tag::from(b"prep")).copied(),
+                    tables.get(&Tag::from(b"cvt ")).copied(),
+                    &maxp,
+                )?;
+                let gvar = optional(b"gvar").and_then(|data| Gvar::load(data).ok());
+                FaceType::TTF {
+                    gasp,
+                    glyf,
+                    programs,
+                    gvar,
+                }
+            }
+            OTTO => {
+                let cff = match tables.get(&Tag::from(b"CFF2")) {
+                    Some(data) => Cff::load_cff2(data)?,
+                    None => tables
+                        .get(&Tag::from(b"CFF "))
+                        .ok_or(Error::Invalid)
+                        .and_then(|data| Cff::load(data))?,
+                };
+                FaceType::CFF { cff }
+            }
+            // Bitmap-only fonts have no outlines
+            Tag(0x00010000) | TRUE
+                if has_table(b"EBLC") || has_table(b"CBLC") || has_table(b"sbix") =>
+            {
+                FaceType::Bitmap
             }
-            OTTO => unimplemented!(), // OTTO = CFF
             _ => return Err(Error::Invalid),
         };
 
-        let gdef = tables
-            .get(&Tag::from(b"GDEF"))
-            .map(|d| Rc::new(Gdef::load(d).expect("failed to load GDEF")));
-        let gsub = tables
-            .get(&Tag::from(b"GSUB"))
-            .map(|d| Gsub::load(d, gdef.clone()).expect("failed to load GSUB"));
-        let gpos = tables
-            .get(&Tag::from(b"GPOS"))
-            .map(|d| Gpos::load(d, gdef.clone()).expect("failed to load GPOS"));
-        let kern = tables
-            .get(&Tag::from(b"kern"))
-            .map(|d| Kern::load(d).expect("failed to load kern"));
+        let gdef = optional(b"GDEF")
+            .and_then(|data| Gdef::load(data).ok())
+            .map(Rc::new);
+        let gsub = optional(b"GSUB").and_then(|data| Gsub::load(data, gdef.clone()).ok());
+        let gpos = optional(b"GPOS").and_then(|data| Gpos::load(data, gdef.clone()).ok());
+        let kern = optional(b"kern").and_then(|data| Kern::load(data).ok());
+        let fvar = optional(b"fvar").and_then(|data| Fvar::load(data).ok());
+        let avar = optional(b"avar").and_then(|data| Avar::load(data).ok());
+        let hvar = optional(b"HVAR").and_then(|data| Hvar::load(data).ok());
+        let mvar = optional(b"MVAR").and_then(|data| Mvar::load(data).ok());
+        let colr = optional(b"COLR").and_then(|data| Colr::load(data).ok());
+        let cpal = optional(b"CPAL").and_then(|data| Cpal::load(data).ok());
+        let load_bitmaps = |location, data| match (optional(location), optional(data)) {
+            (Some(location), Some(data)) => Eblc::load(location, data).ok(),
+            _ => None,
+        };
+        let eblc = load_bitmaps(b"EBLC", b"EBDT");
+        let cblc = load_bitmaps(b"CBLC", b"CBDT");
+        let sbix =
+            optional(b"sbix").and_then(|data| Sbix::load(data, maxp.num_glyphs as usize).ok());
+        let name = optional(b"name").and_then(|data| Name::load(data).ok());
+        let post = optional(b"post").and_then(|data| Post::load(data).ok());
+        let vorg = optional(b"VORG").and_then(|data| Vorg::load(data).ok());
 
         Ok(FaceInner {
             tables: tables.keys().map(|t| *t).collect::<Vec<_>>(),
@@ -229,10 +874,215 @@ impl FaceInner {
             kern,
             gdef,
             os2,
+            fvar,
+            avar,
+            hvar,
+            mvar,
+            colr,
+            cpal,
+            eblc,
+            cblc,
+            sbix,
+            name,
+            post,
+            vmtx,
+            vorg,
         })
     }
 }
 
+impl FaceInner {
+    // Pixels per em at the given scale
+    fn ppem(&self, scale: Size2D<f32>) -> u16 {
+        (scale.height * self.head.units_per_em as f32).round() as u16
+    }
+
+    // Does the gasp table allow grid-fitting at the size?
+    fn gridfit(&self, ppem: u16) -> bool {
+        match &self.face_type {
+            FaceType::TTF {
+                gasp: Some(gasp), ..
+            } => gasp.gridfit(ppem),
+            _ => true,
+        }
+    }
+
+    // Heights that glyphs align to, in font units: the baseline, the x-height and the cap height
+    fn blue_zones(&self, coords: &[f32]) -> Vec<f32> {
+        let mut blues = vec![0.0];
+        blues.extend(self.x_height(coords));
+        blues.extend(self.cap_height(coords));
+        blues
+    }
+
+    // Height of lowercase letters in font units, at the given variation coordinates
+    fn x_height(&self, coords: &[f32]) -> Option<f32> {
+        self.measure_height(self.os2.x_height, 'x', coords)
+            .map(|h| h + self.mvar_delta(b"xhgt", coords))
+    }
+
+    // Height of uppercase letters in font units, at the given variation coordinates
+    fn cap_height(&self, coords: &[f32]) -> Option<f32> {
+        self.measure_height(self.os2.cap_height, 'H', coords)
+            .map(|h| h + self.mvar_delta(b"cpht", coords))
+    }
+
+    // Height from the OS/2 table, or measured from the top of a character if it's missing
+    fn measure_height(&self, height: Option<i16>, c: char, coords: &[f32]) -> Option<f32> {
+        match height.filter(|h| *h > 0) {
+            Some(h) => Some(h as f32),
+            None => {
+                let glyph = self.cmap.glyph_id_for_codepoint(c as u32);
+                let bbox = self.glyph_bbox(glyph, coords);
+                Some(bbox.max.y as f32).filter(|_| glyph.0 != 0 && bbox.max.y > 0)
+            }
+        }
+    }
+
+    // Change in a font-wide metric in font units, at the given variation coordinates
+    fn mvar_delta(&self, tag: &[u8; 4], coords: &[f32]) -> f32 {
+        match &self.mvar {
+            Some(mvar) => mvar.delta(Tag::from(tag), coords),
+            None => 0.0,
+        }
+    }
+
+    // Get advance width of glyph in font units, at the given variation coordinates
+    fn advance(&self, glyph: GlyphID, coords: &[f32]) -> i32 {
+        let advance = self.hmtx.get_metrics(glyph).advance_width as i32;
+        if coords.iter().all(|c| *c == 0.0) {
+            return advance;
+        }
+        let delta = match (&self.hvar, &self.face_type) {
+            (Some(hvar), _) => hvar.advance_delta(glyph, coords),
+            (
+                None,
+                FaceType::TTF {
+                    glyf,
+                    gvar: Some(gvar),
+                    ..
+                },
+            ) => glyf.advance_delta(glyph, gvar, coords),
+            _ => 0.0,
+        };
+        advance + delta.round() as i32
+    }
+
+    // Get advance height of glyph in font units, for vertical text. Fonts without vertical metrics
+    // get the height of a line
+    fn vertical_advance(&self, glyph: GlyphID) -> i32 {
+        match &self.vmtx {
+            Some(vmtx) => vmtx.get_metrics(glyph).advance_height as i32,
+            None => {
+                let (ascender, descender) = self.vertical_extents();
+                ascender - descender
+            }
+        }
+    }
+
+    // Get height of the vertical origin of glyph in font units, at the given variation
+    // coordinates. It's the top of the glyph's advance, where the pen is in vertical text
+    fn vertical_origin(&self, glyph: GlyphID, coords: &[f32]) -> i32 {
+        if let Some(vorg) = &self.vorg {
+            return vorg.origin_y(glyph) as i32;
+        }
+        match &self.vmtx {
+            Some(vmtx) => {
+                vmtx.get_metrics(glyph).tsb as i32 + self.glyph_bbox(glyph, coords).max.y as i32
+            }
+            None => self.vertical_extents().0,
+        }
+    }
+
+    // Ascender and descender that vertical metrics are synthesized from, when the font has none
+    fn vertical_extents(&self) -> (i32, i32) {
+        let os2 = &self.os2;
+        if os2.typo_ascender != 0 || os2.typo_descender != 0 {
+            (os2.typo_ascender as i32, os2.typo_descender as i32)
+        } else {
+            (self.hhea.ascender as i32, self.hhea.descender as i32)
+        }
+    }
+
+    fn outline(&self, glyph: GlyphID, coords: &[f32]) -> Option<Outline> {
+        match &self.face_type {
+            FaceType::TTF { glyf, gvar, .. } => glyf.outline(glyph, gvar.as_ref(), coords),
+            FaceType::CFF { cff } => cff.outline(glyph, coords),
+            FaceType::Bitmap => None,
+        }
+    }
+
+    fn glyph_bbox(&self, glyph: GlyphID, coords: &[f32]) -> BBox<i16> {
+        match &self.face_type {
+            // Variations may move points outside the stored bounding box
+            FaceType::TTF {
+                glyf,
+                gvar: Some(_),
+                ..
+            } if coords.iter().any(|c| *c != 0.0) => self
+                .outline(glyph, coords)
+                .map(|outline| outline.control_bbox())
+                .unwrap_or_else(|| glyf.glyph_bbox(glyph)),
+            FaceType::TTF { glyf, .. } => glyf.glyph_bbox(glyph),
+            FaceType::CFF { cff } => cff.glyph_bbox(glyph, coords),
+            FaceType::Bitmap => BBox::new(point2(0, 0), point2(0, 0)),
+        }
+    }
+
+    // Strike to draw glyph from at the given pixels per em, if it has an embedded bitmap there.
+    // Color bitmaps are preferred
+    fn strike(&self, glyph: GlyphID, ppem: u16) -> Option<StrikeRef<'_>> {
+        let sbix = self.sbix.iter().filter_map(|sbix| {
+            let index = strike::best_strike(sbix.strike_ppems(), ppem)?;
+            let ppem = sbix.strike_ppems().nth(index)?;
+            Some(StrikeRef::Sbix(sbix, index, ppem))
+        });
+        let eblc = self.cblc.iter().chain(&self.eblc).filter_map(|eblc| {
+            let index = strike::best_strike(eblc.strike_ppems(), ppem)?;
+            let ppem = eblc.strike_ppems().nth(index)?;
+            Some(StrikeRef::Eblc(eblc, index, ppem))
+        });
+        sbix.chain(eblc)
+            .find(|strike| strike.metrics(glyph).is_some())
+    }
+}
+
+// A strike of embedded bitmaps, with its pixels per em
+#[derive(Clone, Copy)]
+enum StrikeRef<'a> {
+    Sbix(&'a Sbix, usize, u16),
+    Eblc(&'a Eblc, usize, u16),
+}
+
+impl<'a> StrikeRef<'a> {
+    fn ppem(&self) -> u16 {
+        match self {
+            StrikeRef::Sbix(_, _, ppem) | StrikeRef::Eblc(_, _, ppem) => *ppem,
+        }
+    }
+
+    fn is_color(&self) -> bool {
+        match self {
+            StrikeRef::Sbix(..) => true,
+            StrikeRef::Eblc(eblc, index, _) => eblc.is_color(*index),
+        }
+    }
+
+    fn metrics(&self, glyph: GlyphID) -> Option<BitmapMetrics> {
+        match self {
+            StrikeRef::Sbix(sbix, index, _) => sbix.metrics(*index, glyph),
+            StrikeRef::Eblc(eblc, index, _) => eblc.metrics(*index, glyph),
+        }
+    }
+
+    fn bitmap(&self, glyph: GlyphID) -> Option<Bitmap> {
+        match self {
+            StrikeRef::Sbix(sbix, index, _) => sbix.bitmap(*index, glyph),
+            StrikeRef::Eblc(eblc, index, _) => eblc.bitmap(*index, glyph),
+        }
+    }
+}
+
 impl fmt::Debug for FaceInner {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Face")
@@ -248,13 +1098,36 @@ impl fmt::Debug for FaceInner {
             .field("GPOS", &self.gpos)
             .field("kern", &self.kern)
             .field("GDEF", &self.gdef)
+            .field("fvar", &self.fvar)
+            .field("avar", &self.avar)
+            .field("HVAR", &self.hvar)
+            .field("MVAR", &self.mvar)
+            .field("COLR", &self.colr)
+            .field("CPAL", &self.cpal)
+            .field("EBLC", &self.eblc)
+            .field("CBLC", &self.cblc)
+            .field("sbix", &self.sbix)
+            .field("name", &self.name)
+            .field("post", &self.post)
+            .field("vmtx", &self.vmtx)
+            .field("VORG", &self.vorg)
             .finish()
     }
 }
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum FaceType {
-    TTF { gasp: Option<Gasp>, glyf: Glyf },
+    TTF {
+        gasp: Option<Gasp>,
+        glyf: Glyf,
+        programs: Programs,
+        gvar: Option<Gvar>,
+    },
+    CFF {
+        cff: Cff,
+    }, // Either CFF or CFF2
+    Bitmap, // Glyphs are only in embedded bitmaps
 }
 
 mod offsets {
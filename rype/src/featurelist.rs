@@ -16,7 +16,7 @@ impl FeatureList {
             let tag = get_tag(data, record_off)?;
             let table_off = get_u16(data, record_off + 4)? as usize;
 
-            let data = &data[table_off..];
+            let data = data.get(table_off..).ok_or(Error::Invalid)?;
             let mut lookup_list_indices = Vec::new();
             let lookup_count = get_u16(data, 2)? as usize;
             for lookup_off in (4..4 + lookup_count * 2).step_by(2) {
@@ -4,7 +4,7 @@ use crate::types::Tag;
 
 macro_rules! features {
     ($pt:vis $name:ident { $( $feat:ident $stag:expr ),* } ) => {
-        #[derive(Debug)]
+        #[derive(Clone, Copy, Debug)]
         $pt enum $name {
             $( $feat ),*
         }
@@ -284,4 +284,50 @@ pub(crate) static HORIZONTAL_FEATURES: [Features; 7] = [
     Features::RequiredContextualAlternates,
 ];
 
-pub(crate) static VERTICAL_FEATURES: [Features; 1] = [Features::VerticalAlternates];
+pub(crate) static VERTICAL_FEATURES: [Features; 2] =
+    [Features::VerticalAlternates, Features::VerticalKerning];
+
+bitflags! {
+    /// Features which shapers enable for individual glyphs, rather than for the whole text
+    pub(crate) struct GlyphFeatures : u32 {
+        const ISOL = 0x0001;
+        const INIT = 0x0002;
+        const MEDI = 0x0004;
+        const MED2 = 0x0008;
+        const FINA = 0x0010;
+        const FIN2 = 0x0020;
+        const FIN3 = 0x0040;
+        const RPHF = 0x0080;
+        const HALF = 0x0100;
+        const PREF = 0x0200;
+        const BLWF = 0x0400;
+        const ABVF = 0x0800;
+        const PSTF = 0x1000;
+    }
+}
+
+static GLYPH_FEATURES: [(Features, GlyphFeatures); 13] = [
+    (Features::IsolatedForms, GlyphFeatures::ISOL),
+    (Features::InitialForms, GlyphFeatures::INIT),
+    (Features::MedialForms, GlyphFeatures::MEDI),
+    (Features::MedialForms2, GlyphFeatures::MED2),
+    (Features::TerminalForms, GlyphFeatures::FINA),
+    (Features::TerminalForm2, GlyphFeatures::FIN2),
+    (Features::TerminalForm3, GlyphFeatures::FIN3),
+    (Features::RephForm, GlyphFeatures::RPHF),
+    (Features::HalfForms, GlyphFeatures::HALF),
+    (Features::PreBaseForms, GlyphFeatures::PREF),
+    (Features::BelowBaseForms, GlyphFeatures::BLWF),
+    (Features::AboveBaseForms, GlyphFeatures::ABVF),
+    (Features::PostBaseForms, GlyphFeatures::PSTF),
+];
+
+impl GlyphFeatures {
+    /// Get flag for feature, if it is only enabled for individual glyphs
+    pub(crate) fn for_tag(tag: Tag) -> Option<GlyphFeatures> {
+        GLYPH_FEATURES
+            .iter()
+            .find(|(f, _)| f.tag() == tag)
+            .map(|(_, flag)| *flag)
+    }
+}
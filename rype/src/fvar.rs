@@ -0,0 +1,107 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::types::{get_u16, get_u32};
+
+/// Axis along which a variable face varies
+#[derive(Clone, Debug)]
+pub struct VariationAxis {
+    pub tag: [u8; 4], // e.g. `wght` for weight
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+    pub hidden: bool, // Should this be hidden in user interfaces?
+    pub name_id: u16, // Name of the axis in the `name` table
+}
+
+impl VariationAxis {
+    // Normalize user coordinate to the range -1 to 1, with the default at 0
+    pub(crate) fn normalize(&self, value: f32) -> f32 {
+        let value = value.max(self.min).min(self.max);
+        if value < self.default && self.default > self.min {
+            (value - self.default) / (self.default - self.min)
+        } else if value > self.default && self.max > self.default {
+            (value - self.default) / (self.max - self.default)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Named instance of a variable face, e.g. "Semibold"
+#[derive(Clone, Debug)]
+pub struct NamedInstance {
+    pub subfamily_name_id: u16,
+    pub postscript_name_id: Option<u16>,
+    pub coords: Vec<f32>, // User coordinate for each axis
+}
+
+/// Wrapper around font variations table
+#[derive(Debug)]
+pub(crate) struct Fvar {
+    pub(crate) axes: Vec<VariationAxis>,
+    pub(crate) instances: Vec<NamedInstance>,
+}
+
+impl Fvar {
+    pub(crate) fn load(data: &[u8]) -> Result<Fvar> {
+        let axes_off = get_u16(data, offsets::AXES_ARRAY)? as usize;
+        let axis_count = get_u16(data, offsets::AXIS_COUNT)? as usize;
+        let axis_size = get_u16(data, offsets::AXIS_SIZE)? as usize;
+        let instance_count = get_u16(data, offsets::INSTANCE_COUNT)? as usize;
+        let instance_size = get_u16(data, offsets::INSTANCE_SIZE)? as usize;
+        let fixed = |off| get_u32(data, off).map(|v| v as i32 as f32 / 65536.0);
+        let mut axes = Vec::with_capacity(axis_count);
+        for i in 0..axis_count {
+            let off = axes_off + i * axis_size;
+            axes.push(VariationAxis {
+                tag: get_u32(data, off)?.to_be_bytes(),
+                min: fixed(off + offsets::AXIS_MIN)?,
+                default: fixed(off + offsets::AXIS_DEFAULT)?,
+                max: fixed(off + offsets::AXIS_MAX)?,
+                hidden: get_u16(data, off + offsets::AXIS_FLAGS)? & HIDDEN_AXIS != 0,
+                name_id: get_u16(data, off + offsets::AXIS_NAME_ID)?,
+            });
+        }
+        let mut instances = Vec::with_capacity(instance_count);
+        for i in 0..instance_count {
+            let off = axes_off + axis_count * axis_size + i * instance_size;
+            let coords = (0..axis_count)
+                .map(|a| fixed(off + offsets::INSTANCE_COORDS + a * 4))
+                .collect::<Result<Vec<_>>>()?;
+            // The PostScript name ID is optional
+            let postscript_name_id = if instance_size >= axis_count * 4 + 6 {
+                Some(get_u16(
+                    data,
+                    off + offsets::INSTANCE_COORDS + axis_count * 4,
+                )?)
+            } else {
+                None
+            };
+            instances.push(NamedInstance {
+                subfamily_name_id: get_u16(data, off)?,
+                postscript_name_id,
+                coords,
+            });
+        }
+        Ok(Fvar { axes, instances })
+    }
+}
+
+const HIDDEN_AXIS: u16 = 0x0001;
+
+mod offsets {
+    pub(super) const AXES_ARRAY: usize = 4;
+    pub(super) const AXIS_COUNT: usize = 8;
+    pub(super) const AXIS_SIZE: usize = 10;
+    pub(super) const INSTANCE_COUNT: usize = 12;
+    pub(super) const INSTANCE_SIZE: usize = 14;
+
+    pub(super) const AXIS_MIN: usize = 4;
+    pub(super) const AXIS_DEFAULT: usize = 8;
+    pub(super) const AXIS_MAX: usize = 12;
+    pub(super) const AXIS_FLAGS: usize = 16;
+    pub(super) const AXIS_NAME_ID: usize = 18;
+
+    pub(super) const INSTANCE_COORDS: usize = 4;
+}
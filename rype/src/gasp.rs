@@ -5,29 +5,54 @@ use crate::types::get_u16;
 
 /// Wrapper around grid-fitting and scan-conversion procedure table
 #[derive(Debug)]
-pub(crate) struct Gasp(Vec<GaspRangeRecord>);
+pub(crate) struct Gasp {
+    version: u16,
+    ranges: Vec<GaspRangeRecord>,
+}
 
 impl Gasp {
     pub(crate) fn load(data: &[u8]) -> Result<Gasp> {
+        let version = get_u16(data, offsets::VERSION)?;
         let num_ranges = get_u16(data, offsets::NUM_RANGES)? as usize;
-        let mut records = Vec::new();
+        let mut ranges = Vec::new();
         for off in (0..num_ranges * sizes::RECORD).step_by(sizes::RECORD) {
             let max_ppem = get_u16(data, off + offsets::RANGES)?;
             let behavior = get_u16(data, off + offsets::RANGES + 2)?;
             let behavior = GaspBehavior::from_bits_truncate(behavior);
-            records.push(GaspRangeRecord { max_ppem, behavior });
+            ranges.push(GaspRangeRecord { max_ppem, behavior });
         }
-        Ok(Gasp(records))
+        Ok(Gasp { version, ranges })
     }
 
     pub(crate) fn get_for_ppem(&self, ppem: u16) -> Option<GaspRangeRecord> {
-        for rec in &self.0 {
+        for rec in &self.ranges {
             if rec.max_ppem >= ppem {
                 return Some(*rec);
             }
         }
         None
     }
+
+    /// Should outlines be grid-fitted at this size?
+    pub(crate) fn gridfit(&self, ppem: u16) -> bool {
+        self.get_for_ppem(ppem)
+            .map(|rec| {
+                rec.behavior
+                    .intersects(GaspBehavior::GRIDFIT | GaspBehavior::SYMMETRIC_GRIDFIT)
+            })
+            .unwrap_or(true)
+    }
+
+    /// Should subpixel rendering smooth across the subpixel axis at this size? Only version 1
+    /// tables have this flag
+    pub(crate) fn symmetric_smoothing(&self, ppem: u16) -> bool {
+        if self.version < 1 {
+            return true;
+        }
+        self.get_for_ppem(ppem)
+            .map(|rec| rec.behavior.contains(GaspBehavior::SYMMETRIC_SMOOTHING))
+            .unwrap_or(true)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -39,13 +64,14 @@ pub(crate) struct GaspRangeRecord {
 bitflags! {
     pub(crate) struct GaspBehavior: u16 {
         const GRIDFIT             = 0x0001; // Use gridfitting
-        const DO_GRAY             = 0x0001; // Use grayscale rendering
-        const SYMMETRIC_GRIDFIT   = 0x0001; // Use gridfitting with ClearType symmetric smoothing
-        const SYMMETRIC_SMOOTHING = 0x0001; // Smoothing along multiple axes with ClearType
+        const DO_GRAY             = 0x0002; // Use grayscale rendering
+        const SYMMETRIC_GRIDFIT   = 0x0004; // Use gridfitting with ClearType symmetric smoothing
+        const SYMMETRIC_SMOOTHING = 0x0008; // Smoothing along multiple axes with ClearType
     }
 }
 
 mod offsets {
+    pub(super) const VERSION: usize = 0;
     pub(super) const NUM_RANGES: usize = 2;
     pub(super) const RANGES: usize = 4;
 }
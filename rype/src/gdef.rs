@@ -3,8 +3,10 @@
 use crate::classdef::ClassDef;
 use crate::common::GlyphID;
 use crate::coverage::Coverage;
+use crate::device::Device;
 use crate::error::*;
 use crate::types::{get_i16, get_u16, get_u32};
+use crate::varstore::ItemVariationStore;
 
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum GlyphClass {
@@ -29,12 +31,12 @@ struct AttachmentPoints {
 impl AttachmentPoints {
     fn load(data: &[u8]) -> Result<AttachmentPoints> {
         let coverage_off = get_u16(data, 0)? as usize;
-        let coverage = Coverage::load(&data[coverage_off..])?;
+        let coverage = Coverage::load(data.get(coverage_off..).ok_or(Error::Invalid)?)?;
         let glyph_count = get_u16(data, 2)? as usize;
         let mut contour_points = Vec::new();
         for off in (4..4 + glyph_count * 2).step_by(2) {
             let attach_off = get_u16(data, off)? as usize;
-            let data = &data[attach_off..];
+            let data = data.get(attach_off..).ok_or(Error::Invalid)?;
             let point_count = get_u16(data, 0)? as usize;
             let mut point_indices = Vec::new();
             for off in (2..2 + point_count * 2).step_by(2) {
@@ -49,11 +51,11 @@ impl AttachmentPoints {
     }
 }
 
+/// Position of a caret between ligature components, along the direction of text
 #[derive(Debug)]
-enum CaretValue {
-    Format1 { coord: i16 },
-    Format2 { index: u16 },
-    Format3 { coord: i16, /* TODO device table */ },
+pub(crate) enum CaretValue {
+    Coord { coord: i16, device: Option<Device> },
+    Point(u16), // Index of the glyph's outline point the caret is at
 }
 
 impl CaretValue {
@@ -61,16 +63,19 @@ impl CaretValue {
         match get_u16(data, 0)? {
             1 => {
                 let coord = get_i16(data, 2)?;
-                Ok(CaretValue::Format1 { coord })
-            }
-            2 => {
-                let index = get_u16(data, 2)?;
-                Ok(CaretValue::Format2 { index })
+                Ok(CaretValue::Coord {
+                    coord,
+                    device: None,
+                })
             }
+            2 => Ok(CaretValue::Point(get_u16(data, 2)?)),
             3 => {
                 let coord = get_i16(data, 2)?;
-                // TODO: Device table
-                Ok(CaretValue::Format3 { coord })
+                let device = match get_u16(data, 4)? as usize {
+                    0 => None,
+                    off => Device::load(data.get(off..).ok_or(Error::Invalid)?)?,
+                };
+                Ok(CaretValue::Coord { coord, device })
             }
             _ => Err(Error::Invalid),
         }
@@ -86,17 +91,19 @@ struct LigatureCarets {
 impl LigatureCarets {
     fn load(data: &[u8]) -> Result<LigatureCarets> {
         let coverage_off = get_u16(data, 0)? as usize;
-        let coverage = Coverage::load(&data[coverage_off..])?;
+        let coverage = Coverage::load(data.get(coverage_off..).ok_or(Error::Invalid)?)?;
         let glyph_count = get_u16(data, 2)? as usize;
         let mut ligatures = Vec::new();
         for off in (4..4 + glyph_count * 2).step_by(2) {
             let tab_off = get_u16(data, off)? as usize;
-            let data = &data[tab_off..];
+            let data = data.get(tab_off..).ok_or(Error::Invalid)?;
             let caret_count = get_u16(data, 0)? as usize;
             let mut carets = Vec::new();
             for off in (2..2 + caret_count * 2).step_by(2) {
                 let caret_off = get_u16(data, off)? as usize;
-                carets.push(CaretValue::load(&data[caret_off..])?);
+                carets.push(CaretValue::load(
+                    data.get(caret_off..).ok_or(Error::Invalid)?,
+                )?);
             }
             ligatures.push(carets);
         }
@@ -119,7 +126,7 @@ impl MarkGlyphSets {
         let mut coverages = Vec::new();
         for off in (4..4 + count * 4).step_by(4) {
             let cov_off = get_u32(data, off)? as usize;
-            coverages.push(Coverage::load(&data[cov_off..])?);
+            coverages.push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
         }
         Ok(MarkGlyphSets(coverages))
     }
@@ -132,7 +139,7 @@ pub(crate) struct Gdef {
     ligature_carets: Option<LigatureCarets>,
     mark_attachment_class_def: Option<MarkAttachmentClassDef>,
     mark_glyph_sets: Option<MarkGlyphSets>,
-    // TODO: Item variation store
+    var_store: Option<ItemVariationStore>,
 }
 
 impl Gdef {
@@ -140,24 +147,42 @@ impl Gdef {
         let minor_version = get_u16(data, 2)?;
         let glyph_class_def = match get_u16(data, 4)? as usize {
             0 => None,
-            off => Some(GlyphClassDef(ClassDef::load(&data[off..])?)),
+            off => Some(GlyphClassDef(ClassDef::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?)),
         };
         let attachment_points = match get_u16(data, 6)? as usize {
             0 => None,
-            off => Some(AttachmentPoints::load(&data[off..])?),
+            off => Some(AttachmentPoints::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
         };
         let ligature_carets = match get_u16(data, 8)? as usize {
             0 => None,
-            off => Some(LigatureCarets::load(&data[off..])?),
+            off => Some(LigatureCarets::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
         };
         let mark_attachment_class_def = match get_u16(data, 10)? as usize {
             0 => None,
-            off => Some(MarkAttachmentClassDef(ClassDef::load(&data[off..])?)),
+            off => Some(MarkAttachmentClassDef(ClassDef::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?)),
         };
         let mark_glyph_sets = if minor_version >= 2 {
             match get_u16(data, 12)? as usize {
                 0 => None,
-                off => Some(MarkGlyphSets::load(&data[off..])?),
+                off => Some(MarkGlyphSets::load(data.get(off..).ok_or(Error::Invalid)?)?),
+            }
+        } else {
+            None
+        };
+        let var_store = if minor_version >= 3 {
+            match get_u32(data, 14)? as usize {
+                0 => None,
+                off => Some(ItemVariationStore::load(
+                    data.get(off..).ok_or(Error::Invalid)?,
+                )?),
             }
         } else {
             None
@@ -168,9 +193,27 @@ impl Gdef {
             ligature_carets,
             mark_attachment_class_def,
             mark_glyph_sets,
+            var_store,
         })
     }
 
+    /// Get carets between the components of a ligature glyph
+    pub(crate) fn ligature_carets(&self, glyph: GlyphID) -> &[CaretValue] {
+        self.ligature_carets
+            .as_ref()
+            .and_then(|lc| {
+                let ci = lc.coverage.for_glyph(glyph)?;
+                lc.caret_values.get(ci)
+            })
+            .map(|carets| &carets[..])
+            .unwrap_or(&[])
+    }
+
+    /// Get store of deltas for device tables in variable fonts
+    pub(crate) fn var_store(&self) -> Option<&ItemVariationStore> {
+        self.var_store.as_ref()
+    }
+
     pub(crate) fn glyph_class(&self, glyph: GlyphID) -> Option<GlyphClass> {
         self.glyph_class_def
             .as_ref()
@@ -1,10 +1,14 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
-use geom::{bbox, point2, BBox};
+use geom::{bbox, point2, vec2, BBox, Point2D, Vector2D};
 
 use crate::common::GlyphID;
 use crate::error::*;
+use crate::gvar::Gvar;
+use crate::hint::{Hinter, Programs};
+use crate::hmtx::Hmtx;
 use crate::loca::Loca;
+use crate::outline::Outline;
 use crate::types::{get_i16, get_u16};
 
 #[derive(Debug)]
@@ -29,6 +33,425 @@ impl Glyf {
             .map(|g| g.bbox)
             .unwrap_or_else(|| bbox(point2(0, 0), point2(0, 0)))
     }
+
+    /// Get outline of glyph in font units, at the given normalized variation coordinates
+    pub(crate) fn outline(
+        &self,
+        glyph: GlyphID,
+        gvar: Option<&Gvar>,
+        coords: &[f32],
+    ) -> Option<Outline> {
+        let gvar = gvar.filter(|_| coords.iter().any(|c| *c != 0.0));
+        let mut points = self.points(glyph, gvar, coords, 0).ok()?;
+        // The first phantom point is the origin, so keep it at zero
+        if let Some(gvar) = gvar {
+            let (origin, _) = self.phantom_deltas(glyph, gvar, coords);
+            for p in &mut points.coords {
+                p.x -= origin;
+            }
+        }
+        Some(points.outline())
+    }
+
+    /// Get point of glyph's outline in font units, at the given normalized variation coordinates
+    pub(crate) fn point(
+        &self,
+        glyph: GlyphID,
+        index: usize,
+        gvar: Option<&Gvar>,
+        coords: &[f32],
+    ) -> Option<Point2D<f32>> {
+        let gvar = gvar.filter(|_| coords.iter().any(|c| *c != 0.0));
+        let point = *self
+            .points(glyph, gvar, coords, 0)
+            .ok()?
+            .coords
+            .get(index)?;
+        match gvar {
+            Some(gvar) => Some(point - vec2(self.phantom_deltas(glyph, gvar, coords).0, 0.0)),
+            None => Some(point),
+        }
+    }
+
+    /// Get change in advance width at the given normalized variation coordinates, from the
+    /// deltas of the phantom points
+    pub(crate) fn advance_delta(&self, glyph: GlyphID, gvar: &Gvar, coords: &[f32]) -> f32 {
+        let (left, right) = self.phantom_deltas(glyph, gvar, coords);
+        right - left
+    }
+
+    // Get horizontal deltas of the left and right phantom points
+    fn phantom_deltas(&self, glyph: GlyphID, gvar: &Gvar, coords: &[f32]) -> (f32, f32) {
+        let num_points = match self.0.get(glyph.0 as usize) {
+            Some(Some(g)) => match &g.typ {
+                GlyphTyp::Simple { num_points, .. } => *num_points as usize,
+                GlyphTyp::Composite(data) => match Component::load_all(data) {
+                    Ok(components) => components.len(),
+                    Err(_) => return (0.0, 0.0),
+                },
+            },
+            _ => 0,
+        };
+        match gvar.deltas(glyph, coords, num_points + 4, None) {
+            Ok(deltas) => (deltas[num_points].x, deltas[num_points + 1].x),
+            Err(_) => (0.0, 0.0),
+        }
+    }
+
+    /// Get outline of glyph, scaled to pixels and grid-fitted with the glyph's instructions
+    pub(crate) fn hinted_outline(
+        &self,
+        glyph: GlyphID,
+        hmtx: &Hmtx,
+        programs: &Programs,
+        hinter: &Hinter,
+    ) -> Option<Outline> {
+        let hinted = HintedGlyf {
+            glyf: self,
+            hmtx,
+            programs,
+            hinter,
+        };
+        hinted.points(glyph, 0).ok().map(|p| p.outline())
+    }
+
+    /// Get point of glyph's outline, scaled to pixels and grid-fitted with the glyph's instructions
+    pub(crate) fn hinted_point(
+        &self,
+        glyph: GlyphID,
+        index: usize,
+        hmtx: &Hmtx,
+        programs: &Programs,
+        hinter: &Hinter,
+    ) -> Option<Point2D<f32>> {
+        let hinted = HintedGlyf {
+            glyf: self,
+            hmtx,
+            programs,
+            hinter,
+        };
+        hinted.points(glyph, 0).ok()?.coords.get(index).copied()
+    }
+
+    // Get points of glyph, resolving components for composite glyphs, and applying variation
+    // deltas if given
+    fn points(
+        &self,
+        glyph_id: GlyphID,
+        gvar: Option<&Gvar>,
+        coords: &[f32],
+        depth: usize,
+    ) -> Result<Points> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let glyph = match self.0.get(glyph_id.0 as usize).ok_or(Error::Invalid)? {
+            Some(glyph) => glyph,
+            None => return Ok(Points::default()),
+        };
+        let data = match &glyph.typ {
+            GlyphTyp::Simple { .. } => {
+                let mut points = glyph.typ.simple_points()?;
+                if let Some(gvar) = gvar {
+                    let num_points = points.coords.len();
+                    let outline = (&points.coords[..], &points.contour_ends[..]);
+                    let deltas = gvar.deltas(glyph_id, coords, num_points + 4, Some(outline))?;
+                    for (p, d) in points.coords.iter_mut().zip(deltas) {
+                        *p += d;
+                    }
+                }
+                return Ok(points);
+            }
+            GlyphTyp::Composite(data) => data,
+        };
+        let components = Component::load_all(data)?;
+        // Composite glyphs have a delta for the offset of each component
+        let deltas = match gvar {
+            Some(gvar) => gvar.deltas(glyph_id, coords, components.len() + 4, None)?,
+            None => vec![vec2(0.0, 0.0); components.len()],
+        };
+        let mut points = Points::default();
+        for (component, delta) in components.into_iter().zip(deltas) {
+            let mut child = self.points(component.glyph, gvar, coords, depth + 1)?;
+            child.transform(component.transform);
+            let offset = match component.offset {
+                ComponentOffset::Vector(v) => {
+                    let v = v + delta;
+                    if component
+                        .flags
+                        .contains(CompositeFlags::SCALED_COMPONENT_OFF)
+                        && !component
+                            .flags
+                            .contains(CompositeFlags::UNSCALED_COMPONENT_OFF)
+                    {
+                        let [a, b, c, d] = component.transform;
+                        vec2(a * v.x + c * v.y, b * v.x + d * v.y)
+                    } else {
+                        v
+                    }
+                }
+                // Align point in the component with a point in the glyph so far
+                ComponentOffset::Points(parent, child_point) => {
+                    let parent = *points.coords.get(parent).ok_or(Error::Invalid)?;
+                    let child_point = *child.coords.get(child_point).ok_or(Error::Invalid)?;
+                    vec2(parent.x - child_point.x, parent.y - child_point.y)
+                }
+            };
+            for p in &mut child.coords {
+                *p += offset;
+            }
+            points.append(child);
+        }
+        Ok(points)
+    }
+}
+
+const MAX_COMPONENT_DEPTH: usize = 16;
+
+// Glyphs, with what's needed to grid-fit them at a size
+struct HintedGlyf<'a> {
+    glyf: &'a Glyf,
+    hmtx: &'a Hmtx,
+    programs: &'a Programs,
+    hinter: &'a Hinter,
+}
+
+impl<'a> HintedGlyf<'a> {
+    // Get grid-fitted points of glyph in pixels, followed by the four phantom points
+    fn points(&self, glyph_id: GlyphID, depth: usize) -> Result<Points> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let scale = self.hinter.scale();
+        let glyph = self.glyf.0.get(glyph_id.0 as usize).ok_or(Error::Invalid)?;
+        let mut points = match glyph.as_ref().map(|g| &g.typ) {
+            None => Points::default(),
+            Some(typ @ GlyphTyp::Simple { .. }) => {
+                let mut points = typ.simple_points()?;
+                for p in &mut points.coords {
+                    *p = point2(p.x * scale.width, p.y * scale.height);
+                }
+                points
+            }
+            // Components are scaled and grid-fitted on their own first
+            Some(GlyphTyp::Composite(data)) => self.composite_points(data, depth)?,
+        };
+        // Phantom points, for the horizontal origin and advance
+        let metrics = self.hmtx.get_metrics(glyph_id);
+        let xmin = glyph.as_ref().map(|g| g.bbox.min.x).unwrap_or(0);
+        let origin = (xmin as f32 - metrics.lsb as f32) * scale.width;
+        let advance = metrics.advance_width as f32 * scale.width;
+        points.coords.extend(&[
+            point2(origin, 0.0),
+            point2(origin + advance, 0.0),
+            point2(0.0, 0.0),
+            point2(0.0, 0.0),
+        ]);
+        points.on_curve.extend(&[true; 4]);
+        let instructions = glyph.as_ref().map(|g| &g.instructions[..]).unwrap_or(&[]);
+        self.hinter.hint(
+            self.programs,
+            &mut points.coords,
+            &points.on_curve,
+            &points.contour_ends,
+            instructions,
+        );
+        Ok(points)
+    }
+
+    // Get points of components in pixels
+    fn composite_points(&self, data: &[u8], depth: usize) -> Result<Points> {
+        let scale = self.hinter.scale();
+        let mut points = Points::default();
+        for component in Component::load_all(data)? {
+            let mut child = self.points(component.glyph, depth + 1)?;
+            let len = child.coords.len() - 4;
+            child.coords.truncate(len);
+            child.on_curve.truncate(len);
+            child.transform(component.transform);
+            let offset = match component.offset {
+                ComponentOffset::Vector(v) => {
+                    let v = if component
+                        .flags
+                        .contains(CompositeFlags::SCALED_COMPONENT_OFF)
+                        && !component
+                            .flags
+                            .contains(CompositeFlags::UNSCALED_COMPONENT_OFF)
+                    {
+                        let [a, b, c, d] = component.transform;
+                        vec2(a * v.x + c * v.y, b * v.x + d * v.y)
+                    } else {
+                        v
+                    };
+                    let v = vec2(v.x * scale.width, v.y * scale.height);
+                    if component.flags.contains(CompositeFlags::ROUND_XY_TO_GRID) {
+                        vec2(v.x.round(), v.y.round())
+                    } else {
+                        v
+                    }
+                }
+                ComponentOffset::Points(parent, child_point) => {
+                    let parent = *points.coords.get(parent).ok_or(Error::Invalid)?;
+                    let child_point = *child.coords.get(child_point).ok_or(Error::Invalid)?;
+                    vec2(parent.x - child_point.x, parent.y - child_point.y)
+                }
+            };
+            for p in &mut child.coords {
+                *p += offset;
+            }
+            points.append(child);
+        }
+        Ok(points)
+    }
+}
+
+// Points of a glyph, with contours ending at the given (inclusive) indices
+#[derive(Debug, Default)]
+struct Points {
+    coords: Vec<Point2D<f32>>,
+    on_curve: Vec<bool>,
+    contour_ends: Vec<usize>,
+}
+
+impl Points {
+    fn append(&mut self, other: Points) {
+        let base = self.coords.len();
+        self.coords.extend(other.coords);
+        self.on_curve.extend(other.on_curve);
+        self.contour_ends
+            .extend(other.contour_ends.iter().map(|e| e + base));
+    }
+
+    // Apply 2x2 transformation matrix
+    fn transform(&mut self, [a, b, c, d]: [f32; 4]) {
+        for p in &mut self.coords {
+            *p = point2(a * p.x + c * p.y, b * p.x + d * p.y);
+        }
+    }
+
+    // Convert to outline. Two consecutive off-curve points have an implied on-curve point
+    // midway between them
+    fn outline(&self) -> Outline {
+        let mid = |a: Point2D<f32>, b: Point2D<f32>| point2((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+        let mut outline = Outline::default();
+        let mut start = 0;
+        for end in &self.contour_ends {
+            let (pts, on) = (&self.coords[start..=*end], &self.on_curve[start..=*end]);
+            start = end + 1;
+            let n = pts.len();
+            // Start at an on-curve point
+            let (first, indices) = if on[0] {
+                (pts[0], 1..n)
+            } else if on[n - 1] {
+                (pts[n - 1], 0..n - 1)
+            } else {
+                (mid(pts[0], pts[n - 1]), 0..n)
+            };
+            outline.move_to(first);
+            let mut control = None;
+            for i in indices {
+                if on[i] {
+                    match control.take() {
+                        Some(c) => outline.quad_to(c, pts[i]),
+                        None => outline.line_to(pts[i]),
+                    }
+                } else {
+                    if let Some(c) = control {
+                        outline.quad_to(c, mid(c, pts[i]));
+                    }
+                    control = Some(pts[i]);
+                }
+            }
+            match control {
+                Some(c) => outline.quad_to(c, first),
+                None => outline.line_to(first),
+            }
+        }
+        outline
+    }
+}
+
+#[derive(Debug)]
+enum ComponentOffset {
+    Vector(Vector2D<f32>),
+    Points(usize, usize), // Point in glyph so far, point in component
+}
+
+#[derive(Debug)]
+struct Component {
+    glyph: GlyphID,
+    flags: CompositeFlags,
+    offset: ComponentOffset,
+    transform: [f32; 4],
+}
+
+impl Component {
+    fn load_all(data: &[u8]) -> Result<Vec<Component>> {
+        let f2dot14 = |off| get_i16(data, off).map(|v| v as f32 / 16384.0);
+        let mut components = Vec::new();
+        let mut off = 0;
+        loop {
+            let flags = CompositeFlags::from_bits_truncate(get_u16(data, off)?);
+            let glyph = GlyphID(get_u16(data, off + 2)? as u32);
+            off += 4;
+            let (arg1, arg2) = if flags.contains(CompositeFlags::ARGS_ARE_WORDS) {
+                off += 4;
+                if flags.contains(CompositeFlags::ARGS_ARE_XY_VALUES) {
+                    (
+                        get_i16(data, off - 4)? as i32,
+                        get_i16(data, off - 2)? as i32,
+                    )
+                } else {
+                    (
+                        get_u16(data, off - 4)? as i32,
+                        get_u16(data, off - 2)? as i32,
+                    )
+                }
+            } else {
+                off += 2;
+                if data.len() < off {
+                    return Err(Error::Invalid);
+                }
+                if flags.contains(CompositeFlags::ARGS_ARE_XY_VALUES) {
+                    (data[off - 2] as i8 as i32, data[off - 1] as i8 as i32)
+                } else {
+                    (data[off - 2] as i32, data[off - 1] as i32)
+                }
+            };
+            let offset = if flags.contains(CompositeFlags::ARGS_ARE_XY_VALUES) {
+                ComponentOffset::Vector(vec2(arg1 as f32, arg2 as f32))
+            } else {
+                ComponentOffset::Points(arg1 as usize, arg2 as usize)
+            };
+            let transform = if flags.contains(CompositeFlags::HAVE_SCALE) {
+                off += 2;
+                let scale = f2dot14(off - 2)?;
+                [scale, 0.0, 0.0, scale]
+            } else if flags.contains(CompositeFlags::HAVE_XY_SCALE) {
+                off += 4;
+                [f2dot14(off - 4)?, 0.0, 0.0, f2dot14(off - 2)?]
+            } else if flags.contains(CompositeFlags::HAVE_TWO_BY_TWO) {
+                off += 8;
+                [
+                    f2dot14(off - 8)?,
+                    f2dot14(off - 6)?,
+                    f2dot14(off - 4)?,
+                    f2dot14(off - 2)?,
+                ]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            components.push(Component {
+                glyph,
+                flags,
+                offset,
+                transform,
+            });
+            if !flags.contains(CompositeFlags::MORE_COMPONENTS) {
+                return Ok(components);
+            }
+        }
+    }
 }
 
 bitflags! {
@@ -62,6 +485,7 @@ bitflags! {
 #[derive(Debug)]
 enum GlyphTyp {
     Simple {
+        contour_ends: Vec<u16>,
         num_points: u16,
         data: Vec<u8>,
         flags_len: u16,
@@ -72,23 +496,38 @@ enum GlyphTyp {
 }
 
 impl GlyphTyp {
-    fn load_simple(data: &[u8], num_contours: usize) -> Result<GlyphTyp> {
+    // Load simple glyph, and its instructions
+    fn load_simple(data: &[u8], num_contours: usize) -> Result<(GlyphTyp, Vec<u8>)> {
         if num_contours == 0 {
-            return Ok(GlyphTyp::Simple {
+            let typ = GlyphTyp::Simple {
+                contour_ends: vec![],
                 num_points: 0,
                 flags_len: 0,
                 x_len: 0,
                 y_len: 0,
                 data: vec![],
-            });
+            };
+            return Ok((typ, vec![]));
         }
         let mut contour_ends = Vec::new();
         for off in (0..num_contours * 2).step_by(2) {
             contour_ends.push(get_u16(data, off)?);
         }
-        let num_points = *contour_ends.last().unwrap() + 1;
+        // Contours end at strictly increasing point indices
+        if contour_ends.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::Invalid);
+        }
+        let num_points = contour_ends
+            .last()
+            .unwrap()
+            .checked_add(1)
+            .ok_or(Error::Invalid)?;
         let insn_len = get_u16(data, num_contours * 2)? as usize;
         let flags_off = num_contours * 2 + 2 + insn_len;
+        if data.len() < flags_off {
+            return Err(Error::Invalid);
+        }
+        let instructions = data[num_contours * 2 + 2..flags_off].to_vec();
         let (mut flags_len, mut x_len, mut y_len, mut repeat_count) = (0, 0, 0, 0);
         let mut flags = Vec::new();
         let mut cur_flag = SimpleFlags::empty();
@@ -96,11 +535,12 @@ impl GlyphTyp {
             if repeat_count > 0 {
                 repeat_count -= 1;
             } else {
-                cur_flag = SimpleFlags::from_bits_truncate(data[flags_off + flags_len]);
+                let flag = *data.get(flags_off + flags_len).ok_or(Error::Invalid)?;
+                cur_flag = SimpleFlags::from_bits_truncate(flag);
                 flags_len += 1;
                 flags.push(cur_flag);
                 if cur_flag.contains(SimpleFlags::REPEAT_FLAG) {
-                    repeat_count = data[flags_off + flags_len];
+                    repeat_count = *data.get(flags_off + flags_len).ok_or(Error::Invalid)?;
                     flags_len += 1;
                 }
             }
@@ -116,19 +556,106 @@ impl GlyphTyp {
             }
         }
         let data_len = flags_len + x_len + y_len;
-        Ok(GlyphTyp::Simple {
+        let typ = GlyphTyp::Simple {
+            contour_ends,
             num_points,
             flags_len: flags_len as u16,
             x_len: x_len as u16,
             y_len: y_len as u16,
             data: data[flags_off..flags_off + data_len].to_vec(),
+        };
+        Ok((typ, instructions))
+    }
+
+    // Decode points of simple glyph
+    fn simple_points(&self) -> Result<Points> {
+        let (contour_ends, num_points, data, flags_len, x_len, y_len) = match self {
+            GlyphTyp::Simple {
+                contour_ends,
+                num_points,
+                data,
+                flags_len,
+                x_len,
+                y_len,
+            } => (
+                contour_ends,
+                *num_points as usize,
+                data,
+                *flags_len as usize,
+                *x_len as usize,
+                *y_len as usize,
+            ),
+            GlyphTyp::Composite(_) => return Err(Error::Invalid),
+        };
+        if data.len() < flags_len + x_len + y_len {
+            return Err(Error::Invalid);
+        }
+        let mut flags = Vec::with_capacity(num_points);
+        let mut off = 0;
+        while flags.len() < num_points {
+            let flag = SimpleFlags::from_bits_truncate(*data.get(off).ok_or(Error::Invalid)?);
+            off += 1;
+            let mut count = 1;
+            if flag.contains(SimpleFlags::REPEAT_FLAG) {
+                count += *data.get(off).ok_or(Error::Invalid)? as usize;
+                off += 1;
+            }
+            flags.extend(std::iter::repeat(flag).take(count));
+        }
+        flags.truncate(num_points);
+        // Coordinates are stored as deltas from the previous point
+        let decode = |off: &mut usize,
+                      short: SimpleFlags,
+                      same_or_positive: SimpleFlags|
+         -> Result<Vec<f32>> {
+            let mut value = 0;
+            let mut ret = Vec::with_capacity(num_points);
+            for flag in &flags {
+                if flag.contains(short) {
+                    let delta = *data.get(*off).ok_or(Error::Invalid)? as i32;
+                    *off += 1;
+                    value += if flag.contains(same_or_positive) {
+                        delta
+                    } else {
+                        -delta
+                    };
+                } else if !flag.contains(same_or_positive) {
+                    value += get_i16(data, *off)? as i32;
+                    *off += 2;
+                }
+                ret.push(value as f32);
+            }
+            Ok(ret)
+        };
+        let mut x_off = flags_len;
+        let mut y_off = flags_len + x_len;
+        let xs = decode(
+            &mut x_off,
+            SimpleFlags::X_SHORT_VECTOR,
+            SimpleFlags::X_SAME_OR_POSITIVE_SHORT,
+        )?;
+        let ys = decode(
+            &mut y_off,
+            SimpleFlags::Y_SHORT_VECTOR,
+            SimpleFlags::Y_SAME_OR_POSITIVE_SHORT,
+        )?;
+        Ok(Points {
+            coords: xs.iter().zip(&ys).map(|(x, y)| point2(*x, *y)).collect(),
+            on_curve: flags
+                .iter()
+                .map(|f| f.contains(SimpleFlags::ON_CURVE_POINT))
+                .collect(),
+            contour_ends: contour_ends.iter().map(|e| *e as usize).collect(),
         })
     }
 
-    fn load_composite(data: &[u8]) -> Result<GlyphTyp> {
+    // Load composite glyph, and its instructions
+    fn load_composite(data: &[u8]) -> Result<(GlyphTyp, Vec<u8>)> {
         let mut off = 0;
+        let mut have_instructions = false;
         loop {
             let flags = CompositeFlags::from_bits_truncate(get_u16(data, off)?);
+            have_instructions |= flags.contains(CompositeFlags::HAVE_INSTR);
             off += 4;
             if flags.contains(CompositeFlags::ARGS_ARE_WORDS) {
                 off += 4;
@@ -136,17 +663,25 @@ impl GlyphTyp {
                 off += 2;
             };
             if flags.contains(CompositeFlags::HAVE_SCALE) {
-                off += 1;
-            } else if flags.contains(CompositeFlags::HAVE_XY_SCALE) {
                 off += 2;
-            } else if flags.contains(CompositeFlags::HAVE_TWO_BY_TWO) {
+            } else if flags.contains(CompositeFlags::HAVE_XY_SCALE) {
                 off += 4;
+            } else if flags.contains(CompositeFlags::HAVE_TWO_BY_TWO) {
+                off += 8;
             }
             if !flags.contains(CompositeFlags::MORE_COMPONENTS) {
                 break;
             }
         }
-        Ok(GlyphTyp::Composite(data[..off].to_vec()))
+        let instructions = if have_instructions {
+            let len = get_u16(data, off)? as usize;
+            data.get(off + 2..off + 2 + len)
+                .ok_or(Error::Invalid)?
+                .to_vec()
+        } else {
+            vec![]
+        };
+        Ok((GlyphTyp::Composite(data[..off].to_vec()), instructions))
     }
 }
 
@@ -154,6 +689,7 @@ impl GlyphTyp {
 struct Glyph {
     bbox: BBox<i16>,
     typ: GlyphTyp,
+    instructions: Vec<u8>, // TrueType instructions for grid-fitting
 }
 
 impl Glyph {
@@ -163,13 +699,14 @@ impl Glyph {
         let ymin = get_i16(data, 4)?;
         let xmax = get_i16(data, 6)?;
         let ymax = get_i16(data, 8)?;
-        let typ = if num_contours < 0 {
+        let (typ, instructions) = if num_contours < 0 {
             GlyphTyp::load_composite(&data[10..])?
         } else {
             GlyphTyp::load_simple(&data[10..], num_contours as usize)?
         };
         Ok(Glyph {
             typ,
+            instructions,
             bbox: bbox(point2(xmin, ymin), point2(xmax, ymax)),
         })
     }
@@ -1,9 +1,10 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 use fnv::FnvHashMap;
-use geom::{point2, Point2D};
+use geom::{point2, vec2, Point2D};
 
 use crate::classdef::ClassDef;
 use crate::common::{GlyphID, GlyphInfo};
@@ -11,14 +12,19 @@ use crate::coverage::Coverage;
 use crate::ctx_lookup::{
     ChainedSequenceContextFormat, SequenceContextFormat, SequenceLookupRecord,
 };
+use crate::device::{Device, DeviceContext};
+use crate::direction::Direction;
 use crate::error::*;
 use crate::featurelist::FeatureList;
-use crate::gdef::Gdef;
-use crate::lookuplist::{GlyphData, LookupList, LookupSubtable};
+use crate::features::GlyphFeatures;
+use crate::gdef::{Gdef, GlyphClass};
+use crate::lookuplist::{GlyphData, LookupContext, LookupList, LookupSubtable, RunContext};
+use crate::options::FeatureValues;
 use crate::scriptlist::ScriptList;
-use crate::types::{get_i16, get_u16, get_u32};
+use crate::types::{get_i16, get_u16, get_u32, Tag};
+use crate::{Language, Script};
 
-/// Wrapper around glyph substitution table
+/// Wrapper around glyph positioning table
 #[derive(Debug)]
 pub(crate) struct Gpos {
     scriptlist: ScriptList,
@@ -33,9 +39,9 @@ impl Gpos {
         let scriptlist_off = get_u16(data, 4)? as usize;
         let featurelist_off = get_u16(data, 6)? as usize;
         let lookuplist_off = get_u16(data, 8)? as usize;
-        let scriptlist = ScriptList::load(&data[scriptlist_off..])?;
-        let featurelist = FeatureList::load(&data[featurelist_off..])?;
-        let lookuplist = LookupList::load(&data[lookuplist_off..])?;
+        let scriptlist = ScriptList::load(data.get(scriptlist_off..).ok_or(Error::Invalid)?)?;
+        let featurelist = FeatureList::load(data.get(featurelist_off..).ok_or(Error::Invalid)?)?;
+        let lookuplist = LookupList::load(data.get(lookuplist_off..).ok_or(Error::Invalid)?)?;
         Ok(Gpos {
             scriptlist,
             featurelist,
@@ -43,6 +49,50 @@ impl Gpos {
             gdef,
         })
     }
+
+    /// Does the table have the given feature for the script?
+    pub(crate) fn has_feature(&self, script: Script, language: Language, feature: Tag) -> bool {
+        self.scriptlist
+            .feature_indices(script, language)
+            .iter()
+            .any(|i| self.featurelist[*i as usize].0 == feature)
+    }
+
+    pub(crate) fn position(
+        &self,
+        glyphs: &mut Vec<GlyphInfo>,
+        script: Script,
+        language: Language,
+        direction: Direction,
+        feature_values: &FeatureValues,
+        device: DeviceContext,
+    ) {
+        // Get feature indices
+        let feature_indices = self.scriptlist.feature_indices(script, language);
+        let features = feature_indices
+            .iter()
+            .map(|i| &self.featurelist[*i as usize])
+            .filter(|(tag, _)| feature_values.may_enable(*tag));
+        // Select lookups, along with the features they're a part of
+        let mut lookups = BTreeMap::<u16, Vec<Tag>>::new();
+        for (tag, indices) in features {
+            for idx in indices {
+                lookups.entry(*idx).or_default().push(*tag);
+            }
+        }
+
+        let run = RunContext {
+            gdef: self.gdef.as_ref().map(|g| g.as_ref()),
+            device: Some(device),
+            direction,
+            feature_values,
+        };
+        // Apply all lookups
+        for (idx, tags) in lookups {
+            let lookup = &self.lookuplist[idx as usize];
+            lookup.apply(glyphs, &self.lookuplist, &run, &tags);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -72,7 +122,8 @@ enum AnchorTable {
     },
     Format3 {
         coord: Point2D<i16>,
-        // TODO: Device tables
+        x_device: Option<Device>,
+        y_device: Option<Device>,
     },
 }
 
@@ -86,15 +137,31 @@ impl AnchorTable {
                 coord: point2(get_i16(data, 2)?, get_i16(data, 4)?),
                 anchor_point: get_u16(data, 6)?,
             }),
-            3 => {
-                Ok(AnchorTable::Format3 {
-                    coord: point2(get_i16(data, 2)?, get_i16(data, 4)?),
-                    // TODO: Device tables
-                })
-            }
+            3 => Ok(AnchorTable::Format3 {
+                coord: point2(get_i16(data, 2)?, get_i16(data, 4)?),
+                x_device: load_device(data, 6)?,
+                y_device: load_device(data, 8)?,
+            }),
             _ => Err(Error::Invalid),
         }
     }
+
+    fn coord(&self, ctx: &LookupContext) -> Point2D<i32> {
+        match self {
+            AnchorTable::Format1 { coord } => coord.cast(),
+            // Positioning is done in font units, before the glyphs are scaled or hinted, so the
+            // contour point is deliberately ignored in favour of the design coordinates
+            AnchorTable::Format2 { coord, .. } => coord.cast(),
+            AnchorTable::Format3 {
+                coord,
+                x_device,
+                y_device,
+            } => point2(
+                coord.x as i32 + device_delta(x_device, ctx, false),
+                coord.y as i32 + device_delta(y_device, ctx, true),
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -110,7 +177,7 @@ impl MarkRecord {
         for off in (2..2 + count * 4).step_by(4) {
             let class = get_u16(data, off)?;
             let anchor_off = get_u16(data, off + 2)? as usize;
-            let anchor = AnchorTable::load(&data[anchor_off..])?;
+            let anchor = AnchorTable::load(data.get(anchor_off..).ok_or(Error::Invalid)?)?;
             ret.push(MarkRecord { class, anchor });
         }
         Ok(ret)
@@ -126,6 +193,7 @@ enum Subtable {
     PairAdjustment {
         coverage: Coverage,
         format: PairFormat,
+        value_format2: ValueFormat,
     },
     CursiveAttachment {
         coverage: Coverage,
@@ -157,16 +225,25 @@ impl GlyphData for GlyphInfo {
     fn glyph(&self) -> GlyphID {
         self.glyph
     }
+
+    fn cluster(&self) -> usize {
+        self.cluster
+    }
+
+    fn features(&self) -> GlyphFeatures {
+        self.features
+    }
+
+    fn ligature_id(&self) -> u16 {
+        self.lig.id
+    }
 }
 
 impl LookupSubtable for Subtable {
     type GlyphData = GlyphInfo;
 
     fn is_recursive(lookup_type: u16) -> bool {
-        match lookup_type {
-            7 | 8 => true,
-            _ => false,
-        }
+        matches!(lookup_type, 7 | 8)
     }
 
     fn load(data: &[u8], lookup_type: u16) -> Result<Subtable> {
@@ -184,8 +261,162 @@ impl LookupSubtable for Subtable {
         }
     }
 
-    fn apply(&self, glyph_seq: &mut Vec<GlyphInfo>, idx: usize) -> Option<usize> {
-        unimplemented!()
+    fn apply(
+        &self,
+        glyph_seq: &mut Vec<GlyphInfo>,
+        idx: usize,
+        ctx: &LookupContext,
+    ) -> Option<usize> {
+        let glyph = glyph_seq[idx].glyph;
+        match self {
+            Subtable::SingleAdjustment { coverage, format } => {
+                let ci = coverage.for_glyph(glyph)?;
+                let record = match format {
+                    SingleFormat::Format1(record) => record,
+                    SingleFormat::Format2(records) => records.get(ci)?,
+                };
+                record.apply(&mut glyph_seq[idx], ctx);
+                Some(1)
+            }
+            Subtable::PairAdjustment {
+                coverage,
+                format,
+                value_format2,
+            } => {
+                let ci = coverage.for_glyph(glyph)?;
+                let next_idx = ctx.next(glyph_seq, idx)?;
+                let next_glyph = glyph_seq[next_idx].glyph;
+                let (rec1, rec2) = match format {
+                    PairFormat::Format1(pair_sets) => {
+                        pair_sets.get(ci)?.get(&(next_glyph.0 as u16))?
+                    }
+                    PairFormat::Format2 {
+                        class1,
+                        class2,
+                        records,
+                    } => {
+                        let c1 = class1.glyph_class(glyph).unwrap_or(0) as usize;
+                        let c2 = class2.glyph_class(next_glyph).unwrap_or(0) as usize;
+                        records.get(c1).and_then(|r| r.get(c2))?
+                    }
+                };
+                rec1.apply(&mut glyph_seq[idx], ctx);
+                rec2.apply(&mut glyph_seq[next_idx], ctx);
+                // If the second glyph was positioned, we skip it. Otherwise it could be the first
+                // glyph of the next pair
+                if value_format2.is_empty() {
+                    Some(next_idx - idx)
+                } else {
+                    Some(next_idx - idx + 1)
+                }
+            }
+            Subtable::CursiveAttachment { coverage, records } => {
+                let ci = coverage.for_glyph(glyph)?;
+                let exit = records.get(ci)?.1.as_ref()?.coord(ctx);
+                let next_idx = ctx.next(glyph_seq, idx)?;
+                let next_ci = coverage.for_glyph(glyph_seq[next_idx].glyph)?;
+                let entry = records.get(next_ci)?.0.as_ref()?.coord(ctx);
+                // Move the pen so that the exit point of this glyph meets the entry point of the
+                // next glyph. For right-to-left text, the next glyph is to the left
+                if ctx.direction.is_backward() {
+                    let cur = &mut glyph_seq[idx];
+                    let delta = exit.x + cur.offset.x;
+                    cur.advance.x -= delta;
+                    cur.offset.x -= delta;
+                    let next = &mut glyph_seq[next_idx];
+                    next.advance.x = entry.x + next.offset.x;
+                } else {
+                    let cur = &mut glyph_seq[idx];
+                    cur.advance.x = exit.x + cur.offset.x;
+                    let next = &mut glyph_seq[next_idx];
+                    let delta = entry.x + next.offset.x;
+                    next.advance.x -= delta;
+                    next.offset.x -= delta;
+                }
+                // The lookup flag decides which glyph is moved vertically to align anchors
+                if ctx.right_to_left() {
+                    let next_offset_y = glyph_seq[next_idx].offset.y;
+                    glyph_seq[idx].offset.y = next_offset_y + entry.y - exit.y;
+                } else {
+                    let cur_offset_y = glyph_seq[idx].offset.y;
+                    glyph_seq[next_idx].offset.y = cur_offset_y + exit.y - entry.y;
+                }
+                Some(1)
+            }
+            Subtable::MarkToBaseAttachment {
+                mark_coverage,
+                base_coverage,
+                mark_array,
+                base_array,
+            } => {
+                let mark_ci = mark_coverage.for_glyph(glyph)?;
+                // The base is the closest preceding glyph which isn't a mark
+                let base_idx = (0..idx)
+                    .rev()
+                    .find(|i| ctx.glyph_class(glyph_seq[*i].glyph) != Some(GlyphClass::Mark))?;
+                let base_ci = base_coverage.for_glyph(glyph_seq[base_idx].glyph)?;
+                let mark = mark_array.get(mark_ci)?;
+                let base_anchor = base_array
+                    .get(base_ci)?
+                    .get(mark.class as usize)?
+                    .as_ref()?;
+                attach_mark(glyph_seq, ctx, idx, base_idx, &mark.anchor, base_anchor);
+                Some(1)
+            }
+            Subtable::MarkToLigatureAttachment {
+                mark_coverage,
+                ligature_coverage,
+                mark_array,
+                ligature_array,
+            } => {
+                let mark_ci = mark_coverage.for_glyph(glyph)?;
+                let lig_idx = (0..idx)
+                    .rev()
+                    .find(|i| ctx.glyph_class(glyph_seq[*i].glyph) != Some(GlyphClass::Mark))?;
+                let lig_ci = ligature_coverage.for_glyph(glyph_seq[lig_idx].glyph)?;
+                let mark = mark_array.get(mark_ci)?;
+                // Attach to the component the mark followed when the ligature was formed, and
+                // the last component otherwise
+                let components = ligature_array.get(lig_ci)?;
+                let (mark_lig, lig) = (glyph_seq[idx].lig, glyph_seq[lig_idx].lig);
+                let component =
+                    if mark_lig.id != 0 && mark_lig.id == lig.id && mark_lig.component > 0 {
+                        components.get(mark_lig.component as usize - 1)
+                    } else {
+                        None
+                    };
+                let component = component.or_else(|| components.last())?;
+                let lig_anchor = component.get(mark.class as usize)?.as_ref()?;
+                attach_mark(glyph_seq, ctx, idx, lig_idx, &mark.anchor, lig_anchor);
+                Some(1)
+            }
+            Subtable::MarkToMarkAttachment {
+                mark1_coverage,
+                mark2_coverage,
+                mark1_array,
+                mark2_array,
+            } => {
+                let mark1_ci = mark1_coverage.for_glyph(glyph)?;
+                let mark2_idx = ctx.prev(glyph_seq, idx)?;
+                let mark2_glyph = glyph_seq[mark2_idx].glyph;
+                if ctx
+                    .glyph_class(mark2_glyph)
+                    .map(|c| c != GlyphClass::Mark)
+                    .unwrap_or(false)
+                {
+                    return None;
+                }
+                let mark2_ci = mark2_coverage.for_glyph(mark2_glyph)?;
+                let mark1 = mark1_array.get(mark1_ci)?;
+                let mark2_anchor = mark2_array
+                    .get(mark2_ci)?
+                    .get(mark1.class as usize)?
+                    .as_ref()?;
+                attach_mark(glyph_seq, ctx, idx, mark2_idx, &mark1.anchor, mark2_anchor);
+                Some(1)
+            }
+            Subtable::Context(_) | Subtable::ChainedContext(_) => None,
+        }
     }
 
     fn apply_recursive(
@@ -193,7 +424,62 @@ impl LookupSubtable for Subtable {
         glyph_seq: &[GlyphInfo],
         cur_idx: usize,
     ) -> Option<(&[SequenceLookupRecord], usize)> {
-        unimplemented!()
+        match self {
+            Subtable::Context(fmt) => fmt.apply(glyph_seq, cur_idx),
+            Subtable::ChainedContext(fmt) => fmt.apply(glyph_seq, cur_idx),
+            _ => None,
+        }
+    }
+}
+
+// Position mark so that its anchor coincides with the anchor on the glyph it attaches to
+fn attach_mark(
+    glyph_seq: &mut [GlyphInfo],
+    ctx: &LookupContext,
+    mark_idx: usize,
+    base_idx: usize,
+    mark_anchor: &AnchorTable,
+    base_anchor: &AnchorTable,
+) {
+    let (mark_anchor, base_anchor) = (mark_anchor.coord(ctx), base_anchor.coord(ctx));
+    let base_offset = glyph_seq[base_idx].offset;
+    // Offsets are relative to the mark's own pen position, so undo the advances in between. Glyphs
+    // are later reversed for backward text, which puts the mark before the base
+    let advance = if ctx.direction.is_backward() {
+        glyph_seq[base_idx + 1..=mark_idx]
+            .iter()
+            .fold(vec2(0, 0), |a, g| a - g.advance)
+    } else {
+        glyph_seq[base_idx..mark_idx]
+            .iter()
+            .fold(vec2(0, 0), |a, g| a + g.advance)
+    };
+    glyph_seq[mark_idx].offset = vec2(
+        base_offset.x + base_anchor.x - mark_anchor.x - advance.x,
+        base_offset.y + base_anchor.y - mark_anchor.y - advance.y,
+    );
+}
+
+// Load device table at the offset stored at `off`, if any
+fn load_device(data: &[u8], off: usize) -> Result<Option<Device>> {
+    match get_u16(data, off)? as usize {
+        0 => Ok(None),
+        device_off => Device::load(data.get(device_off..).ok_or(Error::Invalid)?),
+    }
+}
+
+// Get adjustment in font units from device table, for the horizontal or vertical direction
+fn device_delta(device: &Option<Device>, ctx: &LookupContext, vertical: bool) -> i32 {
+    match (device, &ctx.device) {
+        (Some(device), Some(dctx)) => {
+            let ppem = if vertical {
+                dctx.ppem.height
+            } else {
+                dctx.ppem.width
+            };
+            device.delta(dctx, ppem)
+        }
+        _ => 0,
     }
 }
 
@@ -203,7 +489,7 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         let value_format = ValueFormat::from_bits_truncate(data[5]);
         let format = match get_u16(data, 0)? {
             1 => SingleFormat::Format1(ValueRecord::load(data, 6, value_format)?),
@@ -230,7 +516,7 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         let value_format1 = ValueFormat::from_bits_truncate(data[5]);
         let value_format2 = ValueFormat::from_bits_truncate(data[7]);
         let (size1, size2) = (value_format1.record_size(), value_format2.record_size());
@@ -240,7 +526,7 @@ impl Subtable {
                 let mut pair_sets = Vec::new();
                 for off in (10..10 + pair_set_count * 2).step_by(2) {
                     let pair_set_off = get_u16(data, off)? as usize;
-                    let data = &data[pair_set_off..];
+                    let data = data.get(pair_set_off..).ok_or(Error::Invalid)?;
                     let pair_val_count = get_u16(data, 0)? as usize;
                     let size = 2 + size1 + size2;
                     let mut records = FnvHashMap::default();
@@ -256,9 +542,9 @@ impl Subtable {
             }
             2 => {
                 let class1_off = get_u16(data, 8)? as usize;
-                let class1 = ClassDef::load(&data[class1_off..])?;
+                let class1 = ClassDef::load(data.get(class1_off..).ok_or(Error::Invalid)?)?;
                 let class2_off = get_u16(data, 10)? as usize;
-                let class2 = ClassDef::load(&data[class2_off..])?;
+                let class2 = ClassDef::load(data.get(class2_off..).ok_or(Error::Invalid)?)?;
                 let class1_count = get_u16(data, 12)? as usize;
                 let class2_count = get_u16(data, 14)? as usize;
                 let class2_size = size1 + size2;
@@ -289,7 +575,11 @@ impl Subtable {
             }
             _ => return Err(Error::Invalid),
         };
-        Ok(Subtable::PairAdjustment { coverage, format })
+        Ok(Subtable::PairAdjustment {
+            coverage,
+            format,
+            value_format2,
+        })
     }
 
     fn load_cursive_attachment(data: &[u8]) -> Result<Subtable> {
@@ -297,7 +587,7 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         let count = get_u16(data, 4)? as usize;
         let mut records = Vec::new();
         for off in (6..6 + count * 4).step_by(4) {
@@ -305,13 +595,13 @@ impl Subtable {
             let anchor1 = if off1 == 0 {
                 None
             } else {
-                Some(AnchorTable::load(&data[off1..])?)
+                Some(AnchorTable::load(data.get(off1..).ok_or(Error::Invalid)?)?)
             };
             let off2 = get_u16(data, off + 2)? as usize;
             let anchor2 = if off2 == 0 {
                 None
             } else {
-                Some(AnchorTable::load(&data[off2..])?)
+                Some(AnchorTable::load(data.get(off2..).ok_or(Error::Invalid)?)?)
             };
             records.push((anchor1, anchor2));
         }
@@ -323,14 +613,16 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let mark_coverage_offset = get_u16(data, 2)? as usize;
-        let mark_coverage = Coverage::load(&data[mark_coverage_offset..])?;
+        let mark_coverage =
+            Coverage::load(data.get(mark_coverage_offset..).ok_or(Error::Invalid)?)?;
         let base_coverage_offset = get_u16(data, 4)? as usize;
-        let base_coverage = Coverage::load(&data[base_coverage_offset..])?;
+        let base_coverage =
+            Coverage::load(data.get(base_coverage_offset..).ok_or(Error::Invalid)?)?;
         let mark_class_count = get_u16(data, 6)? as usize;
         let mark_offset = get_u16(data, 8)? as usize;
-        let mark_array = MarkRecord::load_array(&data[mark_offset..])?;
+        let mark_array = MarkRecord::load_array(data.get(mark_offset..).ok_or(Error::Invalid)?)?;
         let base_off = get_u16(data, 10)? as usize;
-        let data = &data[base_off..];
+        let data = data.get(base_off..).ok_or(Error::Invalid)?;
         let record_count = get_u16(data, 0)? as usize;
         let record_size = mark_class_count * 2;
         let mut base_array = Vec::new();
@@ -341,7 +633,9 @@ impl Subtable {
                 if anchor_off == 0 {
                     anchors.push(None);
                 } else {
-                    anchors.push(Some(AnchorTable::load(&data[anchor_off..])?));
+                    anchors.push(Some(AnchorTable::load(
+                        data.get(anchor_off..).ok_or(Error::Invalid)?,
+                    )?));
                 }
             }
             base_array.push(anchors);
@@ -359,21 +653,23 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let mark_coverage_offset = get_u16(data, 2)? as usize;
-        let mark_coverage = Coverage::load(&data[mark_coverage_offset..])?;
+        let mark_coverage =
+            Coverage::load(data.get(mark_coverage_offset..).ok_or(Error::Invalid)?)?;
         let ligature_coverage_offset = get_u16(data, 4)? as usize;
-        let ligature_coverage = Coverage::load(&data[ligature_coverage_offset..])?;
+        let ligature_coverage =
+            Coverage::load(data.get(ligature_coverage_offset..).ok_or(Error::Invalid)?)?;
         let mark_class_count = get_u16(data, 6)? as usize;
         let mark_offset = get_u16(data, 8)? as usize;
-        let mark_array = MarkRecord::load_array(&data[mark_offset..])?;
+        let mark_array = MarkRecord::load_array(data.get(mark_offset..).ok_or(Error::Invalid)?)?;
 
         let ligature_arr_off = get_u16(data, 10)? as usize;
-        let data = &data[ligature_arr_off..];
+        let data = data.get(ligature_arr_off..).ok_or(Error::Invalid)?;
         let ligature_count = get_u16(data, 0)? as usize;
         let mut ligature_array = Vec::new();
 
         for off in (2..2 + ligature_count * 2).step_by(2) {
             let ligature_off = get_u16(data, off)? as usize;
-            let data = &data[ligature_off..];
+            let data = data.get(ligature_off..).ok_or(Error::Invalid)?;
 
             let mut components = Vec::new();
             let component_count = get_u16(data, 0)? as usize;
@@ -385,7 +681,9 @@ impl Subtable {
                     if anchor_off == 0 {
                         anchors.push(None);
                     } else {
-                        anchors.push(Some(AnchorTable::load(&data[anchor_off..])?));
+                        anchors.push(Some(AnchorTable::load(
+                            data.get(anchor_off..).ok_or(Error::Invalid)?,
+                        )?));
                     }
                 }
                 components.push(anchors);
@@ -405,14 +703,16 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let mark1_coverage_offset = get_u16(data, 2)? as usize;
-        let mark1_coverage = Coverage::load(&data[mark1_coverage_offset..])?;
+        let mark1_coverage =
+            Coverage::load(data.get(mark1_coverage_offset..).ok_or(Error::Invalid)?)?;
         let mark2_coverage_offset = get_u16(data, 4)? as usize;
-        let mark2_coverage = Coverage::load(&data[mark2_coverage_offset..])?;
+        let mark2_coverage =
+            Coverage::load(data.get(mark2_coverage_offset..).ok_or(Error::Invalid)?)?;
         let mark1_class_count = get_u16(data, 6)? as usize;
         let mark1_offset = get_u16(data, 8)? as usize;
-        let mark1_array = MarkRecord::load_array(&data[mark1_offset..])?;
+        let mark1_array = MarkRecord::load_array(data.get(mark1_offset..).ok_or(Error::Invalid)?)?;
         let mark2_off = get_u16(data, 10)? as usize;
-        let data = &data[mark2_off..];
+        let data = data.get(mark2_off..).ok_or(Error::Invalid)?;
         let record_count = get_u16(data, 0)? as usize;
         let record_size = mark1_class_count * 2;
         let mut mark2_array = Vec::new();
@@ -423,7 +723,9 @@ impl Subtable {
                 if anchor_off == 0 {
                     anchors.push(None);
                 } else {
-                    anchors.push(Some(AnchorTable::load(&data[anchor_off..])?));
+                    anchors.push(Some(AnchorTable::load(
+                        data.get(anchor_off..).ok_or(Error::Invalid)?,
+                    )?));
                 }
             }
             mark2_array.push(anchors);
@@ -437,11 +739,11 @@ impl Subtable {
     }
 
     fn load_contextual_position(data: &[u8]) -> Result<Subtable> {
-        SequenceContextFormat::load(data).map(|f| Subtable::Context(f))
+        SequenceContextFormat::load(data).map(Subtable::Context)
     }
 
     fn load_chained_contextual_positioning(data: &[u8]) -> Result<Subtable> {
-        ChainedSequenceContextFormat::load(data).map(|f| Subtable::ChainedContext(f))
+        ChainedSequenceContextFormat::load(data).map(Subtable::ChainedContext)
     }
 
     fn load_extension_positioning(data: &[u8]) -> Result<Subtable> {
@@ -450,7 +752,7 @@ impl Subtable {
         }
         let typ = get_u16(data, 2)?;
         let offset = get_u32(data, 4)? as usize;
-        Subtable::load(&data[offset..], typ)
+        Subtable::load(data.get(offset..).ok_or(Error::Invalid)?, typ)
     }
 }
 
@@ -486,29 +788,163 @@ struct ValueRecord {
     y_placement: i16,
     x_advance: i16,
     y_advance: i16,
-    // TODO: Device
+    x_placement_device: Option<Device>,
+    y_placement_device: Option<Device>,
+    x_advance_device: Option<Device>,
+    y_advance_device: Option<Device>,
 }
 
 impl ValueRecord {
-    fn load(data: &[u8], mut offset: usize, format: ValueFormat) -> Result<ValueRecord> {
-        let mut get = |flag| -> Result<i16> {
+    // Device table offsets are relative to the start of `data`
+    fn load(data: &[u8], offset: usize, format: ValueFormat) -> Result<ValueRecord> {
+        let mut off = offset;
+        // Fields are present in the order of their flags
+        let mut get = |flag| {
             if format.contains(flag) {
-                let ret = get_i16(data, offset)?;
-                offset += 2;
-                Ok(ret)
+                off += 2;
+                Some(off - 2)
             } else {
-                Ok(0)
+                None
             }
         };
-        let x_placement = get(ValueFormat::X_PLACEMENT)?;
-        let y_placement = get(ValueFormat::Y_PLACEMENT)?;
-        let x_advance = get(ValueFormat::X_ADVANCE)?;
-        let y_advance = get(ValueFormat::Y_ADVANCE)?;
+        let value = |off: Option<usize>| -> Result<i16> {
+            off.map(|off| get_i16(data, off)).unwrap_or(Ok(0))
+        };
+        let device = |off: Option<usize>| -> Result<Option<Device>> {
+            off.map(|off| load_device(data, off)).unwrap_or(Ok(None))
+        };
+        let x_placement = value(get(ValueFormat::X_PLACEMENT))?;
+        let y_placement = value(get(ValueFormat::Y_PLACEMENT))?;
+        let x_advance = value(get(ValueFormat::X_ADVANCE))?;
+        let y_advance = value(get(ValueFormat::Y_ADVANCE))?;
+        let x_placement_device = device(get(ValueFormat::X_PLACEMENT_DEVICE))?;
+        let y_placement_device = device(get(ValueFormat::Y_PLACEMENT_DEVICE))?;
+        let x_advance_device = device(get(ValueFormat::X_ADVANCE_DEVICE))?;
+        let y_advance_device = device(get(ValueFormat::Y_ADVANCE_DEVICE))?;
         Ok(ValueRecord {
             x_placement,
             y_placement,
             x_advance,
             y_advance,
+            x_placement_device,
+            y_placement_device,
+            x_advance_device,
+            y_advance_device,
         })
     }
+
+    fn apply(&self, glyph: &mut GlyphInfo, ctx: &LookupContext) {
+        glyph.offset.x +=
+            self.x_placement as i32 + device_delta(&self.x_placement_device, ctx, false);
+        glyph.offset.y +=
+            self.y_placement as i32 + device_delta(&self.y_placement_device, ctx, true);
+        glyph.advance.x += self.x_advance as i32 + device_delta(&self.x_advance_device, ctx, false);
+        let y_advance = self.y_advance as i32 + device_delta(&self.y_advance_device, ctx, true);
+        // Y advances in fonts grow along vertical text, which runs down
+        if ctx.direction.is_horizontal() {
+            glyph.advance.y += y_advance;
+        } else {
+            glyph.advance.y -= y_advance;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geom::size2;
+
+    use super::*;
+    use crate::common::LigatureInfo;
+
+    // Build a GPOS table with a single lookup, enabled by a single feature for the default script
+    fn gpos(feature: &[u8; 4], lookup_type: u16, subtable: &[u16]) -> Gpos {
+        let mut data = Vec::new();
+        let mut push = |values: &[u16]| {
+            for v in values {
+                data.extend_from_slice(&v.to_be_bytes());
+            }
+        };
+        push(&[1, 0, 10, 30, 44]);
+        // Script list, with only the default language system
+        push(&[1, 0x4446, 0x4c54, 8, 4, 0, 0, 0xffff, 1, 0]);
+        // Feature list
+        let (tag0, tag1) = (
+            u16::from_be_bytes([feature[0], feature[1]]),
+            u16::from_be_bytes([feature[2], feature[3]]),
+        );
+        push(&[1, tag0, tag1, 8, 0, 1, 0]);
+        // Lookup list
+        push(&[1, 4, lookup_type, 0, 1, 8]);
+        push(subtable);
+        Gpos::load(&data, None).unwrap()
+    }
+
+    fn position(gpos: &Gpos, feature: &[u8; 4], glyphs: &[(u32, i32)]) -> Vec<GlyphInfo> {
+        let mut glyphs = glyphs
+            .iter()
+            .enumerate()
+            .map(|(i, (glyph, advance))| GlyphInfo {
+                glyph: GlyphID(*glyph),
+                cluster: i,
+                lig: LigatureInfo::default(),
+                features: GlyphFeatures::empty(),
+                bearing: vec2(0, 0),
+                size: size2(0, 0),
+                offset: vec2(0, 0),
+                advance: vec2(*advance, 0),
+            })
+            .collect::<Vec<_>>();
+        let device = DeviceContext {
+            ppem: size2(0, 0),
+            units_per_em: 1000,
+            coords: &[],
+            store: None,
+        };
+        gpos.position(
+            &mut glyphs,
+            Script::Default,
+            Language::Default,
+            Direction::LeftToRight,
+            &FeatureValues::single(Tag::from(feature)),
+            device,
+        );
+        glyphs
+    }
+
+    #[test]
+    fn pair_kerning() {
+        // Glyph 1 followed by glyph 2 has its advance reduced by 50
+        let subtable = [1, 12, 0x0004, 0, 1, 18, 1, 1, 1, 1, 2, -50i16 as u16];
+        let gpos = gpos(b"kern", 2, &subtable);
+        let glyphs = position(&gpos, b"kern", &[(1, 500), (2, 500), (1, 500)]);
+        let advances = glyphs.iter().map(|g| g.advance.x).collect::<Vec<_>>();
+        assert_eq!(advances, [450, 500, 500]);
+    }
+
+    // Mark-to-base subtable attaching glyph 3 to glyph 1, with the given mark class
+    fn mark_to_base(class: u16) -> Vec<u16> {
+        vec![
+            1, 12, 18, 1, 24, 36, // Header
+            1, 1, 3, // Mark coverage
+            1, 1, 1, // Base coverage
+            1, class, 6, 1, 100, 200, // Mark array, with its anchor
+            1, 4, 1, 300, 500, // Base array, with its anchor
+        ]
+    }
+
+    #[test]
+    fn mark_attachment() {
+        let gpos = gpos(b"mark", 4, &mark_to_base(0));
+        let glyphs = position(&gpos, b"mark", &[(1, 600), (3, 0)]);
+        // The mark's anchor meets the base's, undoing the base's advance
+        assert_eq!(glyphs[1].offset, vec2(300 - 100 - 600, 500 - 200));
+        assert_eq!(glyphs[0].offset, vec2(0, 0));
+    }
+
+    #[test]
+    fn mark_class_out_of_range() {
+        let gpos = gpos(b"mark", 4, &mark_to_base(1));
+        let glyphs = position(&gpos, b"mark", &[(1, 600), (3, 0)]);
+        assert_eq!(glyphs[1].offset, vec2(0, 0));
+    }
 }
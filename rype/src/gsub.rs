@@ -1,21 +1,24 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
-use fnv::FnvHashSet;
-
-use crate::common::GlyphID;
+use crate::common::{GlyphID, LigatureInfo, SubstGlyph};
 use crate::coverage::Coverage;
 use crate::ctx_lookup::{
     ChainedSequenceContextFormat, SequenceContextFormat, SequenceLookupRecord,
 };
+use crate::direction::Direction;
 use crate::error::*;
 use crate::featurelist::FeatureList;
+use crate::features::GlyphFeatures;
 use crate::gdef::Gdef;
-use crate::lookuplist::{GlyphData, LookupList, LookupSubtable};
+use crate::lookuplist::{GlyphData, LookupContext, LookupList, LookupSubtable, RunContext};
+use crate::options::FeatureValues;
 use crate::scriptlist::ScriptList;
+use crate::shaper::SyllableInfo;
 use crate::types::{get_i16, get_u16, get_u32, Tag};
-use crate::Script;
+use crate::{Language, Script};
 
 /// Wrapper around glyph substitution table
 #[derive(Debug)]
@@ -32,9 +35,9 @@ impl Gsub {
         let scriptlist_off = get_u16(data, 4)? as usize;
         let featurelist_off = get_u16(data, 6)? as usize;
         let lookuplist_off = get_u16(data, 8)? as usize;
-        let scriptlist = ScriptList::load(&data[scriptlist_off..])?;
-        let featurelist = FeatureList::load(&data[featurelist_off..])?;
-        let lookuplist = LookupList::load(&data[lookuplist_off..])?;
+        let scriptlist = ScriptList::load(data.get(scriptlist_off..).ok_or(Error::Invalid)?)?;
+        let featurelist = FeatureList::load(data.get(featurelist_off..).ok_or(Error::Invalid)?)?;
+        let lookuplist = LookupList::load(data.get(lookuplist_off..).ok_or(Error::Invalid)?)?;
         Ok(Gsub {
             scriptlist,
             featurelist,
@@ -43,28 +46,96 @@ impl Gsub {
         })
     }
 
+    /// Get alternates offered for glyph by feature
+    pub(crate) fn alternates(
+        &self,
+        glyph: GlyphID,
+        script: Script,
+        language: Language,
+        feature: Tag,
+    ) -> Vec<GlyphID> {
+        let feature_indices = self.scriptlist.feature_indices(script, language);
+        let lookups = feature_indices
+            .iter()
+            .map(|i| &self.featurelist[*i as usize])
+            .filter(|(tag, _)| *tag == feature)
+            .flat_map(|(_, is)| is);
+        for idx in lookups {
+            for subtable in self.lookuplist[*idx as usize].subtables() {
+                if let Subtable::Alternate {
+                    coverage,
+                    alternate_sets,
+                } = subtable
+                {
+                    if let Some(ci) = coverage.for_glyph(glyph) {
+                        return alternate_sets[ci]
+                            .iter()
+                            .map(|g| GlyphID(*g as u32))
+                            .collect();
+                    }
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    /// Would the feature substitute anything in the given sequence of glyphs?
+    pub(crate) fn would_substitute(
+        &self,
+        glyphs: &[GlyphID],
+        script: Script,
+        language: Language,
+        direction: Direction,
+        feature: Tag,
+    ) -> bool {
+        let mut glyph_seq = glyphs
+            .iter()
+            .enumerate()
+            .map(|(i, glyph)| SubstGlyph {
+                glyph: *glyph,
+                cluster: i,
+                lig: LigatureInfo::default(),
+                features: GlyphFeatures::all(),
+                syllable: SyllableInfo::default(),
+            })
+            .collect::<Vec<_>>();
+        let feature_values = FeatureValues::single(feature);
+        self.substitute(&mut glyph_seq, script, language, direction, &feature_values);
+        glyph_seq.len() != glyphs.len() || glyph_seq.iter().zip(glyphs).any(|(a, b)| a.glyph != *b)
+    }
+
     pub(crate) fn substitute(
         &self,
-        glyphs: &mut Vec<GlyphID>,
+        glyphs: &mut Vec<SubstGlyph>,
         script: Script,
-        enabled_features: &FnvHashSet<Tag>,
+        language: Language,
+        direction: Direction,
+        feature_values: &FeatureValues,
     ) {
         // Get feature indices
-        let feature_indices = self.scriptlist.feature_indices(script);
+        let feature_indices = self.scriptlist.feature_indices(script, language);
         let features = feature_indices
             .iter()
             .map(|i| &self.featurelist[*i as usize])
-            .filter(|(tag, _)| enabled_features.contains(tag));
-        // Select lookups
-        let mut lookuplist_indices = features.flat_map(|(_, is)| is).collect::<Vec<_>>();
-        lookuplist_indices.sort();
-        lookuplist_indices.dedup();
+            .filter(|(tag, _)| feature_values.may_enable(*tag));
+        // Select lookups, along with the features they're a part of
+        let mut lookups = BTreeMap::<u16, Vec<Tag>>::new();
+        for (tag, indices) in features {
+            for idx in indices {
+                lookups.entry(*idx).or_default().push(*tag);
+            }
+        }
 
-        let gdef_ref = self.gdef.as_ref().map(|g| g.as_ref());
+        let run = RunContext {
+            gdef: self.gdef.as_ref().map(|g| g.as_ref()),
+            device: None,
+            direction,
+            feature_values,
+        };
         // Apply all lookups
-        for idx in lookuplist_indices {
-            let lookup = &self.lookuplist[*idx as usize];
-            lookup.apply(glyphs, gdef_ref, &self.lookuplist);
+        for (idx, tags) in lookups {
+            let lookup = &self.lookuplist[idx as usize];
+            lookup.apply(glyphs, &self.lookuplist, &run, &tags);
         }
     }
 }
@@ -84,7 +155,10 @@ struct LigatureTable {
 impl LigatureTable {
     fn load(data: &[u8]) -> Result<LigatureTable> {
         let ligature_glyph = get_u16(data, 0)?;
-        let count = get_u16(data, 2)? as usize;
+        // Component count includes the first glyph, which is matched by coverage
+        let count = (get_u16(data, 2)? as usize)
+            .checked_sub(1)
+            .ok_or(Error::Invalid)?;
         let mut component_glyphs = Vec::new();
         for off in (4..4 + count * 2).step_by(2) {
             component_glyphs.push(get_u16(data, off)?);
@@ -124,20 +198,29 @@ enum Subtable {
     },
 }
 
-impl GlyphData for GlyphID {
+impl GlyphData for SubstGlyph {
     fn glyph(&self) -> GlyphID {
-        *self
+        self.glyph
+    }
+
+    fn cluster(&self) -> usize {
+        self.cluster
+    }
+
+    fn features(&self) -> GlyphFeatures {
+        self.features
+    }
+
+    fn ligature_id(&self) -> u16 {
+        self.lig.id
     }
 }
 
 impl LookupSubtable for Subtable {
-    type GlyphData = GlyphID;
+    type GlyphData = SubstGlyph;
 
     fn is_recursive(lookup_type: u16) -> bool {
-        match lookup_type {
-            5 | 6 => true,
-            _ => false,
-        }
+        matches!(lookup_type, 5 | 6)
     }
 
     fn load(data: &[u8], lookup_type: u16) -> Result<Subtable> {
@@ -154,19 +237,22 @@ impl LookupSubtable for Subtable {
         }
     }
 
-    fn apply(&self, glyph_seq: &mut Vec<GlyphID>, idx: usize) -> Option<usize> {
-        let glyph = glyph_seq[idx];
-        let next_idx = idx + 1;
-        let rest = &glyph_seq[next_idx..];
+    fn apply(
+        &self,
+        glyph_seq: &mut Vec<SubstGlyph>,
+        idx: usize,
+        ctx: &LookupContext,
+    ) -> Option<usize> {
+        let glyph = glyph_seq[idx].glyph;
         match self {
             Subtable::Single { coverage, format } => {
                 coverage.for_glyph(glyph).map(|ci| match format {
                     SingleFormat::Format1 { delta } => {
-                        glyph_seq[idx] = GlyphID((glyph.0 as i32 + *delta as i32) as u32);
+                        glyph_seq[idx].glyph = GlyphID((glyph.0 as i32 + *delta as i32) as u32);
                         1
                     }
                     SingleFormat::Format2 { subst } => {
-                        glyph_seq[idx] = GlyphID(subst[ci] as u32);
+                        glyph_seq[idx].glyph = GlyphID(subst[ci] as u32);
                         1
                     }
                 })
@@ -175,37 +261,50 @@ impl LookupSubtable for Subtable {
                 coverage,
                 sequences,
             } => coverage.for_glyph(glyph).map(|ci| {
-                glyph_seq.splice(idx..=idx, sequences[ci].iter().map(|x| GlyphID(*x as u32)));
+                let orig = glyph_seq[idx];
+                glyph_seq.splice(
+                    idx..=idx,
+                    sequences[ci].iter().map(|x| SubstGlyph {
+                        glyph: GlyphID(*x as u32),
+                        ..orig
+                    }),
+                );
                 sequences[ci].len()
             }),
             Subtable::Alternate {
                 coverage,
                 alternate_sets,
             } => {
-                // TODO: Do something here?
-                None
+                // Feature value selects alternate, starting from 1
+                let ci = coverage.for_glyph(glyph)?;
+                let alternate = ctx.feature_value.checked_sub(1)? as usize;
+                let alternate = alternate_sets[ci].get(alternate)?;
+                glyph_seq[idx].glyph = GlyphID(*alternate as u32);
+                Some(1)
             }
             Subtable::Ligature {
                 coverage,
                 ligature_sets,
             } => {
-                if let Some(ci) = coverage.for_glyph(glyph) {
-                    'outer: for option in &ligature_sets[ci] {
-                        if rest.len() < option.component_glyphs.len() {
-                            continue;
-                        }
-                        for (g, tgt) in rest.iter().zip(&option.component_glyphs) {
-                            if g.0 != *tgt as u32 {
-                                continue 'outer;
-                            }
+                let ci = coverage.for_glyph(glyph)?;
+                'outer: for option in &ligature_sets[ci] {
+                    // Find components, skipping over glyphs ignored by the lookup
+                    let mut indices = vec![idx];
+                    for tgt in &option.component_glyphs {
+                        match ctx.next(glyph_seq, *indices.last().unwrap()) {
+                            Some(i) if glyph_seq[i].glyph.0 == *tgt as u32 => indices.push(i),
+                            _ => continue 'outer,
                         }
-                        glyph_seq[idx] = GlyphID(option.ligature_glyph as u32);
-                        return Some(1);
                     }
-                    None
-                } else {
-                    None
+                    form_ligature(
+                        glyph_seq,
+                        &indices,
+                        option.ligature_glyph,
+                        ctx.ligature_id(),
+                    );
+                    return Some(1);
                 }
+                None
             }
             Subtable::Context(_) | Subtable::ChainedContext(_) => None,
             Subtable::ReverseChainedContextSingle {
@@ -214,29 +313,19 @@ impl LookupSubtable for Subtable {
                 lookahead_coverages,
                 subst_glyphs,
             } => {
+                let rest = &glyph_seq[idx + 1..];
                 if backtrack_coverages.len() > idx || lookahead_coverages.len() > rest.len() {
-                    None
-                } else {
-                    if let Some(ci) = coverage.for_glyph(glyph) {
-                        for i in 0..backtrack_coverages.len() {
-                            if backtrack_coverages[i]
-                                .for_glyph(glyph_seq[idx - i - 1])
-                                .is_none()
-                            {
-                                return None;
-                            }
-                        }
-                        for i in 0..lookahead_coverages.len() {
-                            if lookahead_coverages[i].for_glyph(rest[i]).is_none() {
-                                return None;
-                            }
-                        }
-                        glyph_seq[idx] = GlyphID(subst_glyphs[ci] as u32);
-                        Some(1)
-                    } else {
-                        None
-                    }
+                    return None;
+                }
+                let ci = coverage.for_glyph(glyph)?;
+                for (i, coverage) in backtrack_coverages.iter().enumerate() {
+                    coverage.for_glyph(glyph_seq[idx - i - 1].glyph)?;
+                }
+                for (coverage, next) in lookahead_coverages.iter().zip(rest) {
+                    coverage.for_glyph(next.glyph)?;
                 }
+                glyph_seq[idx].glyph = GlyphID(*subst_glyphs.get(ci)? as u32);
+                Some(1)
             }
         }
     }
@@ -254,10 +343,46 @@ impl LookupSubtable for Subtable {
     }
 }
 
+// Replace the glyphs at the given (increasing) indices with a ligature. Glyphs in between which
+// were skipped over stay after the ligature, and remember which component they followed. They
+// also join the ligature's cluster, so that clusters stay contiguous.
+fn form_ligature(glyph_seq: &mut Vec<SubstGlyph>, indices: &[usize], ligature_glyph: u16, id: u16) {
+    let first = indices[0];
+    let last = *indices.last().unwrap();
+    let cluster = indices.iter().map(|i| glyph_seq[*i].cluster).min().unwrap();
+    let mut component = 0;
+    for (i, glyph) in glyph_seq[first..=last].iter_mut().enumerate() {
+        if indices.contains(&(first + i)) {
+            component += 1;
+        } else {
+            glyph.cluster = cluster;
+            glyph.lig = LigatureInfo {
+                id,
+                component,
+                num_components: 0,
+            };
+        }
+    }
+    glyph_seq[first] = SubstGlyph {
+        glyph: GlyphID(ligature_glyph as u32),
+        cluster,
+        lig: LigatureInfo {
+            id,
+            component: 0,
+            num_components: indices.len() as u16,
+        },
+        features: glyph_seq[first].features,
+        syllable: glyph_seq[first].syllable,
+    };
+    for i in indices[1..].iter().rev() {
+        glyph_seq.remove(*i);
+    }
+}
+
 impl Subtable {
     fn load_single(data: &[u8]) -> Result<Subtable> {
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         let format = match get_u16(data, 0)? {
             1 => SingleFormat::Format1 {
                 delta: get_i16(data, 4)?,
@@ -277,7 +402,7 @@ impl Subtable {
 
     fn load_multiple(data: &[u8]) -> Result<Subtable> {
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         if get_u16(data, 0)? != 1 {
             return Err(Error::Invalid);
         }
@@ -285,7 +410,7 @@ impl Subtable {
         let seq_tab_count = get_u16(data, 4)? as usize;
         for seq_tab_off_off in (6..6 + seq_tab_count * 2).step_by(2) {
             let seq_tab_off = get_u16(data, seq_tab_off_off)? as usize;
-            let data = &data[seq_tab_off..];
+            let data = data.get(seq_tab_off..).ok_or(Error::Invalid)?;
             let glyph_count = get_u16(data, 0)? as usize;
             let mut glyphs = Vec::new();
             for off in (2..2 + glyph_count * 2).step_by(2) {
@@ -301,7 +426,7 @@ impl Subtable {
 
     fn load_alternate(data: &[u8]) -> Result<Subtable> {
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         if get_u16(data, 0)? != 1 {
             return Err(Error::Invalid);
         }
@@ -309,7 +434,7 @@ impl Subtable {
         let alt_set_count = get_u16(data, 4)? as usize;
         for alt_set_off_off in (6..6 + alt_set_count * 2).step_by(2) {
             let alt_set_off = get_u16(data, alt_set_off_off)? as usize;
-            let data = &data[alt_set_off..];
+            let data = data.get(alt_set_off..).ok_or(Error::Invalid)?;
             let glyph_count = get_u16(data, 0)? as usize;
             let mut glyphs = Vec::new();
             for off in (2..2 + glyph_count * 2).step_by(2) {
@@ -325,7 +450,7 @@ impl Subtable {
 
     fn load_ligature(data: &[u8]) -> Result<Subtable> {
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
         if get_u16(data, 0)? != 1 {
             return Err(Error::Invalid);
         }
@@ -334,12 +459,14 @@ impl Subtable {
         for lig_set_off_off in (6..6 + lig_set_count * 2).step_by(2) {
             let lig_set_off = get_u16(data, lig_set_off_off)? as usize;
 
-            let data = &data[lig_set_off..];
+            let data = data.get(lig_set_off..).ok_or(Error::Invalid)?;
             let lig_tab_count = get_u16(data, 0)? as usize;
             let mut lig_tables = Vec::new();
             for lig_tab_off_off in (2..2 + lig_tab_count * 2).step_by(2) {
                 let lig_tab_off = get_u16(data, lig_tab_off_off)? as usize;
-                lig_tables.push(LigatureTable::load(&data[lig_tab_off..])?);
+                lig_tables.push(LigatureTable::load(
+                    data.get(lig_tab_off..).ok_or(Error::Invalid)?,
+                )?);
             }
             ligature_sets.push(lig_tables);
         }
@@ -350,11 +477,11 @@ impl Subtable {
     }
 
     fn load_context(data: &[u8]) -> Result<Subtable> {
-        SequenceContextFormat::load(data).map(|f| Subtable::Context(f))
+        SequenceContextFormat::load(data).map(Subtable::Context)
     }
 
     fn load_chained_context(data: &[u8]) -> Result<Subtable> {
-        ChainedSequenceContextFormat::load(data).map(|f| Subtable::ChainedContext(f))
+        ChainedSequenceContextFormat::load(data).map(Subtable::ChainedContext)
     }
 
     fn load_extension_substitution(data: &[u8]) -> Result<Subtable> {
@@ -363,7 +490,7 @@ impl Subtable {
         }
         let typ = get_u16(data, 2)?;
         let offset = get_u32(data, 4)? as usize;
-        Subtable::load(&data[offset..], typ)
+        Subtable::load(data.get(offset..).ok_or(Error::Invalid)?, typ)
     }
 
     fn load_reverse_chained_context_single(data: &[u8]) -> Result<Subtable> {
@@ -371,13 +498,13 @@ impl Subtable {
             return Err(Error::Invalid);
         }
         let coverage_offset = get_u16(data, 2)? as usize;
-        let coverage = Coverage::load(&data[coverage_offset..])?;
+        let coverage = Coverage::load(data.get(coverage_offset..).ok_or(Error::Invalid)?)?;
 
         let backtrack_glyph_count = get_u16(data, 4)? as usize;
         let mut backtrack_coverages = Vec::new();
         for off in (6..6 + backtrack_glyph_count * 2).step_by(2) {
             let cov_off = get_u16(data, off)? as usize;
-            backtrack_coverages.push(Coverage::load(&data[cov_off..])?);
+            backtrack_coverages.push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
         }
 
         let lookahead_off = 6 + backtrack_glyph_count * 2;
@@ -385,7 +512,7 @@ impl Subtable {
         let mut lookahead_coverages = Vec::new();
         for off in (lookahead_off + 2..lookahead_off + 2 + lookahead_glyph_count * 2).step_by(2) {
             let cov_off = get_u16(data, off)? as usize;
-            lookahead_coverages.push(Coverage::load(&data[cov_off..])?);
+            lookahead_coverages.push(Coverage::load(data.get(cov_off..).ok_or(Error::Invalid)?)?);
         }
 
         let glyph_off = lookahead_off + 2 + lookahead_glyph_count * 2;
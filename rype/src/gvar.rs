@@ -0,0 +1,310 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{vec2, Point2D, Vector2D};
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::types::{get_i16, get_u16, get_u32};
+use crate::varstore::{get_f2dot14, RegionAxis};
+
+/// Wrapper around glyph variations table, which has deltas for points of glyf outlines
+#[derive(Debug)]
+pub(crate) struct Gvar {
+    axis_count: usize,
+    shared_tuples: Vec<Vec<f32>>, // Peak coordinates shared between glyphs
+    offsets: Vec<usize>,          // Range of variation data for each glyph
+    data: Vec<u8>,
+}
+
+impl Gvar {
+    pub(crate) fn load(data: &[u8]) -> Result<Gvar> {
+        let axis_count = get_u16(data, offsets::AXIS_COUNT)? as usize;
+        let shared_tuple_count = get_u16(data, offsets::SHARED_TUPLE_COUNT)? as usize;
+        let shared_tuples_off = get_u32(data, offsets::SHARED_TUPLES)? as usize;
+        let glyph_count = get_u16(data, offsets::GLYPH_COUNT)? as usize;
+        let long_offsets = get_u16(data, offsets::FLAGS)? & LONG_OFFSETS != 0;
+        let data_off = get_u32(data, offsets::GLYPH_VARIATION_DATA)? as usize;
+        let shared_tuples = (0..shared_tuple_count)
+            .map(|i| {
+                (0..axis_count)
+                    .map(|a| get_f2dot14(data, shared_tuples_off + (i * axis_count + a) * 2))
+                    .collect()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let offsets = (0..=glyph_count)
+            .map(|i| {
+                let off = if long_offsets {
+                    get_u32(data, offsets::GLYPH_OFFSETS + i * 4)? as usize
+                } else {
+                    get_u16(data, offsets::GLYPH_OFFSETS + i * 2)? as usize * 2
+                };
+                Ok(data_off + off)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Gvar {
+            axis_count,
+            shared_tuples,
+            offsets,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Get deltas for all points of a glyph, including the four phantom points, at the given
+    /// normalized coordinates. For simple glyphs, the original points and contour ends are used to
+    /// infer deltas for points without explicit deltas
+    pub(crate) fn deltas(
+        &self,
+        glyph: GlyphID,
+        coords: &[f32],
+        num_points: usize,
+        outline: Option<(&[Point2D<f32>], &[usize])>,
+    ) -> Result<Vec<Vector2D<f32>>> {
+        let mut deltas = vec![vec2(0.0, 0.0); num_points];
+        let i = glyph.0 as usize;
+        let (start, end) = match (self.offsets.get(i), self.offsets.get(i + 1)) {
+            (Some(start), Some(end)) if start < end => (*start, *end),
+            _ => return Ok(deltas),
+        };
+        let data = self.data.get(start..end).ok_or(Error::Invalid)?;
+        let count = get_u16(data, offsets::TUPLE_VARIATION_COUNT)?;
+        let mut serialized = get_u16(data, offsets::DATA_OFFSET)? as usize;
+        let shared_points = if count & SHARED_POINT_NUMBERS != 0 {
+            Some(read_points(data, &mut serialized)?)
+        } else {
+            None
+        };
+        let mut header = offsets::TUPLE_HEADERS;
+        for _ in 0..count & COUNT_MASK {
+            let size = get_u16(data, header)? as usize;
+            let index = get_u16(data, header + 2)?;
+            header += 4;
+            let peak = if index & EMBEDDED_PEAK_TUPLE != 0 {
+                header += self.axis_count * 2;
+                self.read_tuple(data, header - self.axis_count * 2)?
+            } else {
+                let i = (index & TUPLE_INDEX_MASK) as usize;
+                self.shared_tuples.get(i).ok_or(Error::Invalid)?.clone()
+            };
+            let intermediate = if index & INTERMEDIATE_REGION != 0 {
+                header += self.axis_count * 4;
+                let start = self.read_tuple(data, header - self.axis_count * 4)?;
+                let end = self.read_tuple(data, header - self.axis_count * 2)?;
+                Some((start, end))
+            } else {
+                None
+            };
+            let tuple_data = serialized;
+            serialized += size;
+            // Scale deltas by how much the coordinates are in this tuple's region
+            let scalar = peak
+                .iter()
+                .enumerate()
+                .map(|(a, peak)| {
+                    let (start, end) = match &intermediate {
+                        Some((start, end)) => (start[a], end[a]),
+                        None => (peak.min(0.0), peak.max(0.0)),
+                    };
+                    let coord = coords.get(a).copied().unwrap_or(0.0);
+                    RegionAxis {
+                        start,
+                        peak: *peak,
+                        end,
+                    }
+                    .scalar(coord)
+                })
+                .product::<f32>();
+            if scalar == 0.0 {
+                continue;
+            }
+            let mut off = tuple_data;
+            let points = if index & PRIVATE_POINT_NUMBERS != 0 {
+                read_points(data, &mut off)?
+            } else {
+                shared_points.clone().unwrap_or(None)
+            };
+            let count = points.as_ref().map(|p| p.len()).unwrap_or(num_points);
+            let xs = read_deltas(data, &mut off, count)?;
+            let ys = read_deltas(data, &mut off, count)?;
+            let tuple_deltas = xs.iter().zip(&ys).map(|(x, y)| vec2(*x as f32, *y as f32));
+            match points {
+                None => {
+                    for (d, t) in deltas.iter_mut().zip(tuple_deltas) {
+                        *d += vec2(t.x * scalar, t.y * scalar);
+                    }
+                }
+                Some(points) => {
+                    let mut explicit = vec![None; num_points];
+                    for (p, t) in points.iter().zip(tuple_deltas) {
+                        if let Some(e) = explicit.get_mut(*p as usize) {
+                            *e = Some(t);
+                        }
+                    }
+                    if let Some((coords, contour_ends)) = outline {
+                        infer_deltas(&mut explicit, coords, contour_ends);
+                    }
+                    for (d, e) in deltas.iter_mut().zip(explicit) {
+                        if let Some(t) = e {
+                            *d += vec2(t.x * scalar, t.y * scalar);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(deltas)
+    }
+
+    fn read_tuple(&self, data: &[u8], off: usize) -> Result<Vec<f32>> {
+        (0..self.axis_count)
+            .map(|a| get_f2dot14(data, off + a * 2))
+            .collect()
+    }
+}
+
+// Read packed point numbers. `None` means all points
+fn read_points(data: &[u8], off: &mut usize) -> Result<Option<Vec<u16>>> {
+    let byte = |off: &mut usize| -> Result<u8> {
+        let b = *data.get(*off).ok_or(Error::Invalid)?;
+        *off += 1;
+        Ok(b)
+    };
+    let first = byte(off)? as usize;
+    let count = if first == 0 {
+        return Ok(None);
+    } else if first & 0x80 != 0 {
+        ((first & 0x7f) << 8) | byte(off)? as usize
+    } else {
+        first
+    };
+    let mut points = Vec::with_capacity(count);
+    let mut point = 0u16;
+    while points.len() < count {
+        let control = byte(off)?;
+        for _ in 0..(control & POINT_RUN_COUNT_MASK) + 1 {
+            let delta = if control & POINTS_ARE_WORDS != 0 {
+                *off += 2;
+                get_u16(data, *off - 2)?
+            } else {
+                byte(off)? as u16
+            };
+            point = point.wrapping_add(delta);
+            points.push(point);
+        }
+    }
+    points.truncate(count);
+    Ok(Some(points))
+}
+
+// Read packed deltas
+fn read_deltas(data: &[u8], off: &mut usize, count: usize) -> Result<Vec<i16>> {
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count {
+        let control = *data.get(*off).ok_or(Error::Invalid)?;
+        *off += 1;
+        let run = (control & DELTA_RUN_COUNT_MASK) as usize + 1;
+        for _ in 0..run {
+            deltas.push(if control & DELTAS_ARE_ZERO != 0 {
+                0
+            } else if control & DELTAS_ARE_WORDS != 0 {
+                *off += 2;
+                get_i16(data, *off - 2)?
+            } else {
+                *off += 1;
+                *data.get(*off - 1).ok_or(Error::Invalid)? as i8 as i16
+            });
+        }
+    }
+    deltas.truncate(count);
+    Ok(deltas)
+}
+
+// Infer deltas for points without explicit deltas, by interpolating between the nearest points
+// with deltas in the same contour
+fn infer_deltas(
+    deltas: &mut [Option<Vector2D<f32>>],
+    coords: &[Point2D<f32>],
+    contour_ends: &[usize],
+) {
+    let mut start = 0;
+    for &end in contour_ends {
+        if end >= deltas.len() || end >= coords.len() || end < start {
+            return;
+        }
+        let explicit = (start..=end)
+            .filter(|p| deltas[*p].is_some())
+            .collect::<Vec<_>>();
+        if explicit.is_empty() || explicit.len() == end + 1 - start {
+            start = end + 1;
+            continue;
+        }
+        let len = end + 1 - start;
+        let mut inferred = Vec::new();
+        for (i, &p1) in explicit.iter().enumerate() {
+            let p2 = explicit[(i + 1) % explicit.len()];
+            let (d1, d2) = (deltas[p1].unwrap(), deltas[p2].unwrap());
+            let mut p = start + (p1 - start + 1) % len;
+            while p != p2 {
+                let x = interpolate(coords[p1].x, coords[p2].x, d1.x, d2.x, coords[p].x);
+                let y = interpolate(coords[p1].y, coords[p2].y, d1.y, d2.y, coords[p].y);
+                inferred.push((p, vec2(x, y)));
+                p = start + (p - start + 1) % len;
+            }
+        }
+        for (p, d) in inferred {
+            deltas[p] = Some(d);
+        }
+        start = end + 1;
+    }
+}
+
+// Interpolate delta along one axis, given the positions and deltas of two reference points
+fn interpolate(a: f32, b: f32, da: f32, db: f32, v: f32) -> f32 {
+    let ((a, da), (b, db)) = if a <= b {
+        ((a, da), (b, db))
+    } else {
+        ((b, db), (a, da))
+    };
+    if a == b {
+        if da == db {
+            da
+        } else {
+            0.0
+        }
+    } else if v <= a {
+        da
+    } else if v >= b {
+        db
+    } else {
+        da + (v - a) * (db - da) / (b - a)
+    }
+}
+
+const LONG_OFFSETS: u16 = 0x0001;
+
+const SHARED_POINT_NUMBERS: u16 = 0x8000;
+const COUNT_MASK: u16 = 0x0fff;
+
+const EMBEDDED_PEAK_TUPLE: u16 = 0x8000;
+const INTERMEDIATE_REGION: u16 = 0x4000;
+const PRIVATE_POINT_NUMBERS: u16 = 0x2000;
+const TUPLE_INDEX_MASK: u16 = 0x0fff;
+
+const POINTS_ARE_WORDS: u8 = 0x80;
+const POINT_RUN_COUNT_MASK: u8 = 0x7f;
+
+const DELTAS_ARE_ZERO: u8 = 0x80;
+const DELTAS_ARE_WORDS: u8 = 0x40;
+const DELTA_RUN_COUNT_MASK: u8 = 0x3f;
+
+mod offsets {
+    pub(super) const AXIS_COUNT: usize = 4;
+    pub(super) const SHARED_TUPLE_COUNT: usize = 6;
+    pub(super) const SHARED_TUPLES: usize = 8;
+    pub(super) const GLYPH_COUNT: usize = 12;
+    pub(super) const FLAGS: usize = 14;
+    pub(super) const GLYPH_VARIATION_DATA: usize = 16;
+    pub(super) const GLYPH_OFFSETS: usize = 20;
+
+    pub(super) const TUPLE_VARIATION_COUNT: usize = 0;
+    pub(super) const DATA_OFFSET: usize = 2;
+    pub(super) const TUPLE_HEADERS: usize = 4;
+}
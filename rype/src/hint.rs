@@ -0,0 +1,1546 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{point2, vec2, Point2D, Size2D, Vector2D};
+
+use crate::error::*;
+use crate::maxp::Maxp;
+use crate::types::get_i16;
+
+/// How outlines are grid-fitted
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Hinting {
+    /// Don't grid-fit
+    None,
+    /// Align horizontal edges to the pixel grid and to the x-height and cap height, only moving
+    /// points vertically. Works for any outline, including CFF and unhinted TrueType
+    Light,
+    /// Run TrueType instructions, at sizes where the `gasp` table allows grid-fitting. Falls back
+    /// to `Light` where there are no instructions to run
+    Full,
+}
+
+impl Default for Hinting {
+    fn default() -> Hinting {
+        Hinting::Full
+    }
+}
+
+/// Font-wide TrueType programs, and the control value table
+#[derive(Debug)]
+pub(crate) struct Programs {
+    fpgm: Vec<u8>,
+    prep: Vec<u8>,
+    cvt: Vec<i16>,
+    max_twilight: usize,
+    max_storage: usize,
+    max_function_defs: usize,
+    max_stack: usize,
+}
+
+impl Programs {
+    pub(crate) fn load(
+        fpgm: Option<&[u8]>,
+        prep: Option<&[u8]>,
+        cvt: Option<&[u8]>,
+        maxp: &Maxp,
+    ) -> Result<Programs> {
+        let cvt = match cvt {
+            Some(data) => (0..data.len() / 2)
+                .map(|i| get_i16(data, i * 2))
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        Ok(Programs {
+            fpgm: fpgm.map(|d| d.to_vec()).unwrap_or_default(),
+            prep: prep.map(|d| d.to_vec()).unwrap_or_default(),
+            cvt,
+            max_twilight: maxp.max_twilight as usize,
+            max_storage: maxp.max_storage as usize,
+            max_function_defs: maxp.max_function_defs as usize,
+            max_stack: maxp.max_stack as usize,
+        })
+    }
+}
+
+/// State of the TrueType interpreter for a face at a particular size, after running the font
+/// program and the control value program
+#[derive(Debug)]
+pub(crate) struct Hinter {
+    scale: Size2D<f32>, // Font units to pixels
+    ppem: u16,
+    state: State,
+}
+
+impl Hinter {
+    pub(crate) fn new(programs: &Programs, scale: Size2D<f32>, ppem: u16) -> Result<Hinter> {
+        // Control values are scaled along the axis with the larger scale
+        let cvt_scale = scale.width.max(scale.height);
+        let state = State {
+            functions: vec![None; programs.max_function_defs],
+            idefs: vec![None; 256],
+            cvt: programs
+                .cvt
+                .iter()
+                .map(|v| to_f26p6(*v as f32 * cvt_scale))
+                .collect(),
+            storage: vec![0; programs.max_storage],
+            twilight: Zone::new(
+                vec![point2(0, 0); programs.max_twilight],
+                vec![false; programs.max_twilight],
+                vec![],
+            ),
+            gs: GraphicsState::default(),
+        };
+        let mut exec = Exec::new(programs, &[], state, Zone::default(), cvt_scale, ppem);
+        exec.run_program(Program::Font)?;
+        exec.state.gs = GraphicsState::default();
+        exec.run_program(Program::ControlValue)?;
+        Ok(Hinter {
+            scale,
+            ppem,
+            state: exec.state,
+        })
+    }
+
+    pub(crate) fn scale(&self) -> Size2D<f32> {
+        self.scale
+    }
+
+    /// Grid-fit points, in pixels, using the glyph's instructions. The last four points are the
+    /// phantom points
+    pub(crate) fn hint(
+        &self,
+        programs: &Programs,
+        coords: &mut [Point2D<f32>],
+        on_curve: &[bool],
+        contour_ends: &[usize],
+        instructions: &[u8],
+    ) {
+        if self.state.gs.instruct_control & INHIBIT_GRID_FIT != 0 {
+            return;
+        }
+        let mut points = coords
+            .iter()
+            .map(|p| point2(to_f26p6(p.x), to_f26p6(p.y)))
+            .collect::<Vec<_>>();
+        // Phantom points start on the grid
+        let num_points = points.len();
+        for p in points.iter_mut().skip(num_points.saturating_sub(4)) {
+            *p = point2(round_to_grid(p.x), round_to_grid(p.y));
+        }
+        let zone = Zone::new(points, on_curve.to_vec(), contour_ends.to_vec());
+        let mut state = self.state.clone();
+        if state.gs.instruct_control & IGNORE_CVT_PROGRAM != 0 {
+            state.gs = GraphicsState::default();
+        }
+        let cvt_scale = self.scale.width.max(self.scale.height);
+        let mut exec = Exec::new(programs, instructions, state, zone, cvt_scale, self.ppem);
+        // Glyph programs with errors are common enough, so keep whatever was done before the
+        // error, like other rasterizers do
+        let _ = exec.run_program(Program::Glyph);
+        // Keep the glyph origin at the first phantom point
+        let zone = exec.glyph;
+        let origin = zone
+            .cur
+            .get(num_points.wrapping_sub(4))
+            .map(|p| p.x)
+            .unwrap_or(0);
+        for (c, p) in coords.iter_mut().zip(&zone.cur) {
+            *c = point2((p.x - origin) as f32 / 64.0, p.y as f32 / 64.0);
+        }
+    }
+}
+
+// Instruct control flags, set by the control value program
+const INHIBIT_GRID_FIT: u8 = 0x1;
+const IGNORE_CVT_PROGRAM: u8 = 0x2;
+
+// Limits to protect against runaway programs
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+const MAX_CALL_DEPTH: usize = 64;
+
+// Interpreter state which persists between programs
+#[derive(Clone, Debug)]
+struct State {
+    functions: Vec<Option<Function>>,
+    idefs: Vec<Option<Function>>, // Instruction definitions for each opcode
+    cvt: Vec<i32>,
+    storage: Vec<i32>,
+    twilight: Zone,
+    gs: GraphicsState,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Program {
+    Font,
+    ControlValue,
+    Glyph,
+}
+
+// Function or instruction definition
+#[derive(Clone, Copy, Debug)]
+struct Function {
+    program: Program,
+    start: usize,
+    end: usize, // Index of the ENDF instruction
+}
+
+bitflags! {
+    struct Touched: u8 {
+        const X = 0x1;
+        const Y = 0x2;
+    }
+}
+
+// Points which instructions act on, in 26.6 fixed point
+#[derive(Clone, Debug, Default)]
+struct Zone {
+    org: Vec<Point2D<i32>>, // Original, scaled positions
+    cur: Vec<Point2D<i32>>, // Grid-fitted positions
+    touched: Vec<Touched>,
+    on_curve: Vec<bool>,
+    contour_ends: Vec<usize>,
+}
+
+impl Zone {
+    fn new(points: Vec<Point2D<i32>>, on_curve: Vec<bool>, contour_ends: Vec<usize>) -> Zone {
+        Zone {
+            org: points.clone(),
+            touched: vec![Touched::empty(); points.len()],
+            cur: points,
+            on_curve,
+            contour_ends,
+        }
+    }
+
+    // Number of points excluding phantom points
+    fn num_outline_points(&self) -> usize {
+        self.contour_ends
+            .last()
+            .map(|e| e + 1)
+            .unwrap_or_else(|| self.cur.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum RoundState {
+    ToHalfGrid,
+    ToGrid,
+    ToDoubleGrid,
+    DownToGrid,
+    UpToGrid,
+    Off,
+    Super {
+        period: i32,
+        phase: i32,
+        threshold: i32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct GraphicsState {
+    auto_flip: bool,
+    control_value_cutin: i32,
+    delta_base: i32,
+    delta_shift: i32,
+    dual_vector: Vector2D<i32>, // Unit vectors in 2.14 fixed point
+    freedom_vector: Vector2D<i32>,
+    projection_vector: Vector2D<i32>,
+    instruct_control: u8,
+    loop_count: i32,
+    min_distance: i32,
+    round_state: RoundState,
+    rp: [usize; 3],
+    single_width_cutin: i32,
+    single_width_value: i32,
+    zp: [usize; 3],
+}
+
+impl Default for GraphicsState {
+    fn default() -> GraphicsState {
+        GraphicsState {
+            auto_flip: true,
+            control_value_cutin: 68,
+            delta_base: 9,
+            delta_shift: 3,
+            dual_vector: vec2(ONE, 0),
+            freedom_vector: vec2(ONE, 0),
+            projection_vector: vec2(ONE, 0),
+            instruct_control: 0,
+            loop_count: 1,
+            min_distance: 64,
+            round_state: RoundState::ToGrid,
+            rp: [0; 3],
+            single_width_cutin: 0,
+            single_width_value: 0,
+            zp: [1; 3],
+        }
+    }
+}
+
+// 1.0 in 2.14 fixed point
+const ONE: i32 = 0x4000;
+
+// Execution context for a single program
+struct Exec<'a> {
+    programs: &'a Programs,
+    instructions: &'a [u8],
+    state: State,
+    glyph: Zone,
+    stack: Vec<i32>,
+    cvt_scale: f32,
+    ppem: u16,
+    count: usize, // Instructions executed so far
+}
+
+impl<'a> Exec<'a> {
+    fn new(
+        programs: &'a Programs,
+        instructions: &'a [u8],
+        state: State,
+        glyph: Zone,
+        cvt_scale: f32,
+        ppem: u16,
+    ) -> Exec<'a> {
+        Exec {
+            programs,
+            instructions,
+            state,
+            glyph,
+            stack: Vec::new(),
+            cvt_scale,
+            ppem,
+            count: 0,
+        }
+    }
+
+    fn code(&self, program: Program) -> &'a [u8] {
+        match program {
+            Program::Font => &self.programs.fpgm,
+            Program::ControlValue => &self.programs.prep,
+            Program::Glyph => self.instructions,
+        }
+    }
+
+    fn run_program(&mut self, program: Program) -> Result<()> {
+        // Each program starts with some of the graphics state reset
+        let gs = &mut self.state.gs;
+        gs.zp = [1; 3];
+        gs.projection_vector = vec2(ONE, 0);
+        gs.freedom_vector = vec2(ONE, 0);
+        gs.dual_vector = vec2(ONE, 0);
+        gs.round_state = RoundState::ToGrid;
+        gs.loop_count = 1;
+        self.stack.clear();
+        let len = self.code(program).len();
+        self.run(program, 0, len, 0)
+    }
+
+    fn run(&mut self, program: Program, start: usize, end: usize, depth: usize) -> Result<()> {
+        if depth > MAX_CALL_DEPTH {
+            return Err(Error::Invalid);
+        }
+        let code = self.code(program);
+        let mut ip = start;
+        while ip < end {
+            self.count += 1;
+            if self.count > MAX_INSTRUCTIONS {
+                return Err(Error::Invalid);
+            }
+            let op = code[ip];
+            let mut next = ip + instruction_len(code, ip)?;
+            match op {
+                // SVTCA, SPVTCA, SFVTCA
+                0x00..=0x05 => {
+                    let axis = if op & 1 == 0 {
+                        vec2(0, ONE)
+                    } else {
+                        vec2(ONE, 0)
+                    };
+                    let gs = &mut self.state.gs;
+                    if op < 0x04 {
+                        gs.projection_vector = axis;
+                        gs.dual_vector = axis;
+                    }
+                    if !(0x02..0x04).contains(&op) {
+                        gs.freedom_vector = axis;
+                    }
+                }
+                // SPVTL, SFVTL
+                0x06..=0x09 => {
+                    let (v, _) = self.line_vectors(op & 1 == 1)?;
+                    if op < 0x08 {
+                        self.state.gs.projection_vector = v;
+                        self.state.gs.dual_vector = v;
+                    } else {
+                        self.state.gs.freedom_vector = v;
+                    }
+                }
+                // SPVFS, SFVFS
+                0x0a | 0x0b => {
+                    let y = self.pop()?;
+                    let x = self.pop()?;
+                    let v = normalize(x as f64, y as f64);
+                    if op == 0x0a {
+                        self.state.gs.projection_vector = v;
+                        self.state.gs.dual_vector = v;
+                    } else {
+                        self.state.gs.freedom_vector = v;
+                    }
+                }
+                // GPV, GFV
+                0x0c | 0x0d => {
+                    let v = if op == 0x0c {
+                        self.state.gs.projection_vector
+                    } else {
+                        self.state.gs.freedom_vector
+                    };
+                    self.push(v.x)?;
+                    self.push(v.y)?;
+                }
+                // SFVTPV
+                0x0e => self.state.gs.freedom_vector = self.state.gs.projection_vector,
+                // ISECT
+                0x0f => self.isect()?,
+                // SRP0, SRP1, SRP2
+                0x10..=0x12 => self.state.gs.rp[(op - 0x10) as usize] = self.pop()? as usize,
+                // SZP0, SZP1, SZP2
+                0x13..=0x15 => self.state.gs.zp[(op - 0x13) as usize] = self.pop_zone()?,
+                // SZPS
+                0x16 => self.state.gs.zp = [self.pop_zone()?; 3],
+                // SLOOP
+                0x17 => {
+                    let n = self.pop()?;
+                    if n < 0 {
+                        return Err(Error::Invalid);
+                    }
+                    self.state.gs.loop_count = n;
+                }
+                0x18 => self.state.gs.round_state = RoundState::ToGrid,
+                0x19 => self.state.gs.round_state = RoundState::ToHalfGrid,
+                // SMD
+                0x1a => self.state.gs.min_distance = self.pop()?,
+                // ELSE, reached at the end of an IF branch
+                0x1b => next = skip_branch(code, ip, end, false)?,
+                // JMPR
+                0x1c => next = jump(ip, self.pop()?)?,
+                // SCVTCI
+                0x1d => self.state.gs.control_value_cutin = self.pop()?,
+                // SSWCI
+                0x1e => self.state.gs.single_width_cutin = self.pop()?,
+                // SSW
+                0x1f => {
+                    let value = self.pop()?;
+                    self.state.gs.single_width_value = self.scale_funits(value);
+                }
+                // DUP
+                0x20 => {
+                    let v = *self.stack.last().ok_or(Error::Invalid)?;
+                    self.push(v)?;
+                }
+                // POP
+                0x21 => {
+                    self.pop()?;
+                }
+                // CLEAR
+                0x22 => self.stack.clear(),
+                // SWAP
+                0x23 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(a)?;
+                }
+                // DEPTH
+                0x24 => self.push(self.stack.len() as i32)?,
+                // CINDEX
+                0x25 => {
+                    let k = self.pop()? as usize;
+                    let i = self.stack.len().checked_sub(k).ok_or(Error::Invalid)?;
+                    let v = *self.stack.get(i).ok_or(Error::Invalid)?;
+                    self.push(v)?;
+                }
+                // MINDEX
+                0x26 => {
+                    let k = self.pop()? as usize;
+                    let i = self.stack.len().checked_sub(k).ok_or(Error::Invalid)?;
+                    if i >= self.stack.len() {
+                        return Err(Error::Invalid);
+                    }
+                    let v = self.stack.remove(i);
+                    self.push(v)?;
+                }
+                // ALIGNPTS
+                0x27 => {
+                    let p2 = self.pop()? as usize;
+                    let p1 = self.pop()? as usize;
+                    let [z0, z1, _] = self.state.gs.zp;
+                    let d = self.project(self.cur(z0, p2)?, self.cur(z1, p1)?) / 2;
+                    self.move_point(z1, p1, d, true)?;
+                    self.move_point(z0, p2, -d, true)?;
+                }
+                // UTP
+                0x29 => {
+                    let p = self.pop()? as usize;
+                    let fv = self.state.gs.freedom_vector;
+                    let touched = self.zone_mut(self.state.gs.zp[0]).touched.get_mut(p);
+                    let touched = touched.ok_or(Error::Invalid)?;
+                    if fv.x != 0 {
+                        touched.remove(Touched::X);
+                    }
+                    if fv.y != 0 {
+                        touched.remove(Touched::Y);
+                    }
+                }
+                // LOOPCALL, CALL
+                0x2a | 0x2b => {
+                    let f = self.pop()? as usize;
+                    let count = if op == 0x2a { self.pop()? } else { 1 };
+                    let function = self
+                        .state
+                        .functions
+                        .get(f)
+                        .copied()
+                        .flatten()
+                        .ok_or(Error::Invalid)?;
+                    for _ in 0..count {
+                        self.run(function.program, function.start, function.end, depth + 1)?;
+                    }
+                }
+                // FDEF
+                0x2c => {
+                    let f = self.pop()? as usize;
+                    let endf = find_endf(code, next, end)?;
+                    // Function numbers are limited by maxp
+                    let function = self.state.functions.get_mut(f).ok_or(Error::Invalid)?;
+                    *function = Some(Function {
+                        program,
+                        start: next,
+                        end: endf,
+                    });
+                    next = endf + 1;
+                }
+                // ENDF
+                0x2d => return Ok(()),
+                // MDAP
+                0x2e | 0x2f => {
+                    let p = self.pop()? as usize;
+                    let z0 = self.state.gs.zp[0];
+                    let d = if op == 0x2f {
+                        let d = self.project(self.cur(z0, p)?, point2(0, 0));
+                        self.round(d).wrapping_sub(d)
+                    } else {
+                        0
+                    };
+                    self.move_point(z0, p, d, true)?;
+                    self.state.gs.rp[0] = p;
+                    self.state.gs.rp[1] = p;
+                }
+                // IUP
+                0x30 | 0x31 => self.iup(op == 0x31),
+                // SHP
+                0x32 | 0x33 => {
+                    let (dx, dy) = self.displacement(op & 1 == 1)?;
+                    let z2 = self.state.gs.zp[2];
+                    for _ in 0..self.take_loop() {
+                        let p = self.pop()? as usize;
+                        self.shift_point(z2, p, dx, dy, true)?;
+                    }
+                }
+                // SHC
+                0x34 | 0x35 => {
+                    let (dx, dy) = self.displacement(op & 1 == 1)?;
+                    let c = self.pop()? as usize;
+                    let z2 = self.state.gs.zp[2];
+                    let zone = self.zone(z2);
+                    let end = *zone.contour_ends.get(c).ok_or(Error::Invalid)?;
+                    let start = if c == 0 {
+                        0
+                    } else {
+                        zone.contour_ends[c - 1] + 1
+                    };
+                    let (rz, rp) = self.reference(op & 1 == 1);
+                    for p in start..=end {
+                        if rz != z2 || rp != p {
+                            self.shift_point(z2, p, dx, dy, true)?;
+                        }
+                    }
+                }
+                // SHZ
+                0x36 | 0x37 => {
+                    let (dx, dy) = self.displacement(op & 1 == 1)?;
+                    let z = self.pop_zone()?;
+                    let (rz, rp) = self.reference(op & 1 == 1);
+                    for p in 0..self.zone(z).num_outline_points() {
+                        if rz != z || rp != p {
+                            self.shift_point(z, p, dx, dy, false)?;
+                        }
+                    }
+                }
+                // SHPIX
+                0x38 => {
+                    let d = self.pop()?;
+                    let fv = self.state.gs.freedom_vector;
+                    let (dx, dy) = (mul_f2dot14(d, fv.x), mul_f2dot14(d, fv.y));
+                    let z2 = self.state.gs.zp[2];
+                    for _ in 0..self.take_loop() {
+                        let p = self.pop()? as usize;
+                        self.shift_point(z2, p, dx, dy, true)?;
+                    }
+                }
+                // IP
+                0x39 => self.ip()?,
+                // MSIRP
+                0x3a | 0x3b => {
+                    let d = self.pop()?;
+                    let p = self.pop()? as usize;
+                    let [z0, z1, _] = self.state.gs.zp;
+                    let rp0 = self.state.gs.rp[0];
+                    if z1 == 0 {
+                        let org = self.org(z0, rp0)?;
+                        let zone = self.zone_mut(z1);
+                        *zone.org.get_mut(p).ok_or(Error::Invalid)? = org;
+                        self.move_original(z1, p, d)?;
+                        let zone = self.zone_mut(z1);
+                        zone.cur[p] = zone.org[p];
+                    }
+                    let cur_dist = self.project(self.cur(z1, p)?, self.cur(z0, rp0)?);
+                    self.move_point(z1, p, d.wrapping_sub(cur_dist), true)?;
+                    self.state.gs.rp[1] = rp0;
+                    self.state.gs.rp[2] = p;
+                    if op == 0x3b {
+                        self.state.gs.rp[0] = p;
+                    }
+                }
+                // ALIGNRP
+                0x3c => {
+                    let [z0, z1, _] = self.state.gs.zp;
+                    let rp0 = self.state.gs.rp[0];
+                    for _ in 0..self.take_loop() {
+                        let p = self.pop()? as usize;
+                        let d = self.project(self.cur(z1, p)?, self.cur(z0, rp0)?);
+                        self.move_point(z1, p, -d, true)?;
+                    }
+                }
+                0x3d => self.state.gs.round_state = RoundState::ToDoubleGrid,
+                // MIAP
+                0x3e | 0x3f => {
+                    let cvt = self.pop()? as usize;
+                    let p = self.pop()? as usize;
+                    let z0 = self.state.gs.zp[0];
+                    let mut d = *self.state.cvt.get(cvt).ok_or(Error::Invalid)?;
+                    if z0 == 0 {
+                        let fv = self.state.gs.freedom_vector;
+                        let org = point2(mul_f2dot14(d, fv.x), mul_f2dot14(d, fv.y));
+                        let zone = self.zone_mut(z0);
+                        *zone.org.get_mut(p).ok_or(Error::Invalid)? = org;
+                        zone.cur[p] = org;
+                    }
+                    let org_dist = self.project(self.cur(z0, p)?, point2(0, 0));
+                    if op == 0x3f {
+                        if d.wrapping_sub(org_dist).wrapping_abs()
+                            > self.state.gs.control_value_cutin
+                        {
+                            d = org_dist;
+                        }
+                        d = self.round(d);
+                    }
+                    self.move_point(z0, p, d.wrapping_sub(org_dist), true)?;
+                    self.state.gs.rp[0] = p;
+                    self.state.gs.rp[1] = p;
+                }
+                // NPUSHB, NPUSHW, PUSHB, PUSHW
+                0x40 | 0x41 | 0xb0..=0xbf => {
+                    let (count, start, words) = match op {
+                        0x40 => (code[ip + 1] as usize, ip + 2, false),
+                        0x41 => (code[ip + 1] as usize, ip + 2, true),
+                        0xb0..=0xb7 => ((op - 0xaf) as usize, ip + 1, false),
+                        _ => ((op - 0xb7) as usize, ip + 1, true),
+                    };
+                    for i in 0..count {
+                        let v = if words {
+                            get_i16(code, start + i * 2)? as i32
+                        } else {
+                            code[start + i] as i32
+                        };
+                        self.push(v)?;
+                    }
+                }
+                // WS
+                0x42 => {
+                    let v = self.pop()?;
+                    let i = self.pop()? as usize;
+                    // Storage locations are limited by maxp
+                    *self.state.storage.get_mut(i).ok_or(Error::Invalid)? = v;
+                }
+                // RS
+                0x43 => {
+                    let i = self.pop()? as usize;
+                    let v = *self.state.storage.get(i).ok_or(Error::Invalid)?;
+                    self.push(v)?;
+                }
+                // WCVTP, WCVTF
+                0x44 | 0x70 => {
+                    let mut v = self.pop()?;
+                    let i = self.pop()? as usize;
+                    if op == 0x70 {
+                        v = self.scale_funits(v);
+                    }
+                    *self.state.cvt.get_mut(i).ok_or(Error::Invalid)? = v;
+                }
+                // RCVT
+                0x45 => {
+                    let i = self.pop()? as usize;
+                    let v = *self.state.cvt.get(i).ok_or(Error::Invalid)?;
+                    self.push(v)?;
+                }
+                // GC
+                0x46 | 0x47 => {
+                    let p = self.pop()? as usize;
+                    let z2 = self.state.gs.zp[2];
+                    let v = if op == 0x46 {
+                        self.project(self.cur(z2, p)?, point2(0, 0))
+                    } else {
+                        self.dual_project(self.org(z2, p)?, point2(0, 0))
+                    };
+                    self.push(v)?;
+                }
+                // SCFS
+                0x48 => {
+                    let v = self.pop()?;
+                    let p = self.pop()? as usize;
+                    let z2 = self.state.gs.zp[2];
+                    let d = self.project(self.cur(z2, p)?, point2(0, 0));
+                    self.move_point(z2, p, v.wrapping_sub(d), true)?;
+                    if z2 == 0 {
+                        let zone = self.zone_mut(z2);
+                        zone.org[p] = zone.cur[p];
+                    }
+                }
+                // MD
+                0x49 | 0x4a => {
+                    let k = self.pop()? as usize;
+                    let l = self.pop()? as usize;
+                    let [z0, z1, _] = self.state.gs.zp;
+                    let d = if op == 0x49 {
+                        self.project(self.cur(z0, l)?, self.cur(z1, k)?)
+                    } else {
+                        self.dual_project(self.org(z0, l)?, self.org(z1, k)?)
+                    };
+                    self.push(d)?;
+                }
+                // MPPEM, MPS
+                0x4b | 0x4c => self.push(self.ppem as i32)?,
+                0x4d => self.state.gs.auto_flip = true,
+                0x4e => self.state.gs.auto_flip = false,
+                // DEBUG
+                0x4f => {
+                    self.pop()?;
+                }
+                // LT, LTEQ, GT, GTEQ, EQ, NEQ
+                0x50..=0x55 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let v = match op {
+                        0x50 => a < b,
+                        0x51 => a <= b,
+                        0x52 => a > b,
+                        0x53 => a >= b,
+                        0x54 => a == b,
+                        _ => a != b,
+                    };
+                    self.push(v as i32)?;
+                }
+                // ODD, EVEN
+                0x56 | 0x57 => {
+                    let v = self.pop()?;
+                    let odd = self.round(v) & 127 == 64;
+                    self.push((odd == (op == 0x56)) as i32)?;
+                }
+                // IF
+                0x58 => {
+                    if self.pop()? == 0 {
+                        next = skip_branch(code, ip, end, true)?;
+                    }
+                }
+                // EIF
+                0x59 => {}
+                // AND, OR
+                0x5a | 0x5b => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let v = if op == 0x5a {
+                        a != 0 && b != 0
+                    } else {
+                        a != 0 || b != 0
+                    };
+                    self.push(v as i32)?;
+                }
+                // NOT
+                0x5c => {
+                    let v = self.pop()?;
+                    self.push((v == 0) as i32)?;
+                }
+                // DELTAP1, DELTAP2, DELTAP3, DELTAC1, DELTAC2, DELTAC3
+                0x5d | 0x71..=0x75 => {
+                    let base = match op {
+                        0x5d | 0x73 => 0,
+                        0x71 | 0x74 => 16,
+                        _ => 32,
+                    };
+                    let n = self.pop()?;
+                    for _ in 0..n {
+                        let target = self.pop()? as usize;
+                        let arg = self.pop()?;
+                        let ppem = self
+                            .state
+                            .gs
+                            .delta_base
+                            .wrapping_add(base + ((arg >> 4) & 0xf));
+                        if ppem != self.ppem as i32 {
+                            continue;
+                        }
+                        let mut step = (arg & 0xf) - 8;
+                        if step >= 0 {
+                            step += 1;
+                        }
+                        let d = step * 64 / (1 << self.state.gs.delta_shift);
+                        if op == 0x5d || op == 0x71 || op == 0x72 {
+                            self.move_point(self.state.gs.zp[0], target, d, true)?;
+                        } else {
+                            let cvt = self.state.cvt.get_mut(target).ok_or(Error::Invalid)?;
+                            *cvt = cvt.wrapping_add(d);
+                        }
+                    }
+                }
+                // SDB, SDS
+                0x5e => self.state.gs.delta_base = self.pop()?,
+                0x5f => self.state.gs.delta_shift = self.pop()?.clamp(0, 6),
+                // ADD, SUB, DIV, MUL
+                0x60..=0x63 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let v = match op {
+                        0x60 => a.wrapping_add(b),
+                        0x61 => a.wrapping_sub(b),
+                        0x62 => {
+                            if b == 0 {
+                                return Err(Error::Invalid);
+                            }
+                            (a as i64 * 64 / b as i64) as i32
+                        }
+                        _ => (a as i64 * b as i64 / 64) as i32,
+                    };
+                    self.push(v)?;
+                }
+                // ABS, NEG, FLOOR, CEILING
+                0x64..=0x67 => {
+                    let v = self.pop()?;
+                    let v = match op {
+                        0x64 => v.wrapping_abs(),
+                        0x65 => v.wrapping_neg(),
+                        0x66 => v & !63,
+                        _ => v.wrapping_add(63) & !63,
+                    };
+                    self.push(v)?;
+                }
+                // ROUND
+                0x68..=0x6b => {
+                    let v = self.pop()?;
+                    self.push(self.round(v))?;
+                }
+                // NROUND, which only compensates for engine characteristics
+                0x6c..=0x6f => {}
+                // SROUND, S45ROUND
+                0x76 | 0x77 => {
+                    let n = self.pop()?;
+                    self.state.gs.round_state = super_round(n, op == 0x77);
+                }
+                // JROT, JROF
+                0x78 | 0x79 => {
+                    let b = self.pop()?;
+                    let offset = self.pop()?;
+                    if (b != 0) == (op == 0x78) {
+                        next = jump(ip, offset)?;
+                    }
+                }
+                0x7a => self.state.gs.round_state = RoundState::Off,
+                0x7c => self.state.gs.round_state = RoundState::UpToGrid,
+                0x7d => self.state.gs.round_state = RoundState::DownToGrid,
+                // SANGW, AA, SCANCTRL, SCANTYPE have no effect on outlines
+                0x7e | 0x7f | 0x85 | 0x8d => {
+                    self.pop()?;
+                }
+                // FLIPPT
+                0x80 => {
+                    let z0 = self.state.gs.zp[0];
+                    for _ in 0..self.take_loop() {
+                        let p = self.pop()? as usize;
+                        let on_curve = self.zone_mut(z0).on_curve.get_mut(p);
+                        let on_curve = on_curve.ok_or(Error::Invalid)?;
+                        *on_curve = !*on_curve;
+                    }
+                }
+                // FLIPRGON, FLIPRGOFF
+                0x81 | 0x82 => {
+                    let hi = self.pop()? as usize;
+                    let lo = self.pop()? as usize;
+                    let z0 = self.state.gs.zp[0];
+                    let on_curve = self.zone_mut(z0).on_curve.get_mut(lo..=hi);
+                    for p in on_curve.ok_or(Error::Invalid)? {
+                        *p = op == 0x81;
+                    }
+                }
+                // SDPVTL
+                0x86 | 0x87 => {
+                    let (cur, org) = self.line_vectors(op & 1 == 1)?;
+                    self.state.gs.projection_vector = cur;
+                    self.state.gs.dual_vector = org;
+                }
+                // GETINFO
+                0x88 => {
+                    let selector = self.pop()?;
+                    let mut v = 0;
+                    if selector & 1 != 0 {
+                        v |= 35; // Interpreter version
+                    }
+                    if selector & 32 != 0 {
+                        v |= 1 << 12; // Grayscale rendering
+                    }
+                    self.push(v)?;
+                }
+                // IDEF
+                0x89 => {
+                    let opcode = self.pop()? as usize;
+                    let endf = find_endf(code, next, end)?;
+                    *self.state.idefs.get_mut(opcode).ok_or(Error::Invalid)? = Some(Function {
+                        program,
+                        start: next,
+                        end: endf,
+                    });
+                    next = endf + 1;
+                }
+                // ROLL
+                0x8a => {
+                    let c = self.pop()?;
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(c)?;
+                    self.push(a)?;
+                }
+                // MAX, MIN
+                0x8b | 0x8c => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(if op == 0x8b { a.max(b) } else { a.min(b) })?;
+                }
+                // INSTCTRL
+                0x8e => {
+                    let selector = self.pop()?;
+                    let value = self.pop()?;
+                    if program == Program::ControlValue && (1..=3).contains(&selector) {
+                        let flag = 1 << (selector - 1);
+                        self.state.gs.instruct_control &= !flag;
+                        if value != 0 {
+                            self.state.gs.instruct_control |= flag;
+                        }
+                    }
+                }
+                // MDRP
+                0xc0..=0xdf => self.mdrp(op)?,
+                // MIRP
+                0xe0..=0xff => self.mirp(op)?,
+                _ => {
+                    let idef = self.state.idefs[op as usize].ok_or(Error::Invalid)?;
+                    self.run(idef.program, idef.start, idef.end, depth + 1)?;
+                }
+            }
+            ip = next;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, v: i32) -> Result<()> {
+        // Fonts often underestimate their stack use
+        if self.stack.len() >= self.programs.max_stack + 256 {
+            return Err(Error::Invalid);
+        }
+        self.stack.push(v);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32> {
+        self.stack.pop().ok_or(Error::Invalid)
+    }
+
+    fn pop_zone(&mut self) -> Result<usize> {
+        match self.pop()? {
+            z @ 0..=1 => Ok(z as usize),
+            _ => Err(Error::Invalid),
+        }
+    }
+
+    // Get loop count for the next instruction, and reset it
+    fn take_loop(&mut self) -> i32 {
+        std::mem::replace(&mut self.state.gs.loop_count, 1)
+    }
+
+    fn zone(&self, z: usize) -> &Zone {
+        if z == 0 {
+            &self.state.twilight
+        } else {
+            &self.glyph
+        }
+    }
+
+    fn zone_mut(&mut self, z: usize) -> &mut Zone {
+        if z == 0 {
+            &mut self.state.twilight
+        } else {
+            &mut self.glyph
+        }
+    }
+
+    fn cur(&self, z: usize, p: usize) -> Result<Point2D<i32>> {
+        self.zone(z).cur.get(p).copied().ok_or(Error::Invalid)
+    }
+
+    fn org(&self, z: usize, p: usize) -> Result<Point2D<i32>> {
+        self.zone(z).org.get(p).copied().ok_or(Error::Invalid)
+    }
+
+    fn scale_funits(&self, v: i32) -> i32 {
+        to_f26p6(v as f32 * self.cvt_scale)
+    }
+
+    // Distance from b to a along the projection vector
+    fn project(&self, a: Point2D<i32>, b: Point2D<i32>) -> i32 {
+        let d = vec2(a.x.wrapping_sub(b.x), a.y.wrapping_sub(b.y));
+        dot(d, self.state.gs.projection_vector)
+    }
+
+    // Distance from b to a along the dual projection vector
+    fn dual_project(&self, a: Point2D<i32>, b: Point2D<i32>) -> i32 {
+        let d = vec2(a.x.wrapping_sub(b.x), a.y.wrapping_sub(b.y));
+        dot(d, self.state.gs.dual_vector)
+    }
+
+    // Dot product of freedom and projection vectors, which converts distances along the
+    // projection vector to distances along the freedom vector
+    fn f_dot_p(&self) -> i32 {
+        let d = dot(
+            self.state.gs.freedom_vector,
+            self.state.gs.projection_vector,
+        );
+        if d.abs() < 0x400 {
+            ONE
+        } else {
+            d
+        }
+    }
+
+    // Move point along the freedom vector, so that its projection changes by the distance
+    fn move_point(&mut self, z: usize, p: usize, d: i32, touch: bool) -> Result<()> {
+        let fv = self.state.gs.freedom_vector;
+        let f_dot_p = self.f_dot_p();
+        let (dx, dy) = (mul_div(d, fv.x, f_dot_p), mul_div(d, fv.y, f_dot_p));
+        self.shift_point(z, p, dx, dy, touch)
+    }
+
+    fn move_original(&mut self, z: usize, p: usize, d: i32) -> Result<()> {
+        let fv = self.state.gs.freedom_vector;
+        let f_dot_p = self.f_dot_p();
+        let org = self.zone_mut(z).org.get_mut(p).ok_or(Error::Invalid)?;
+        org.x = org.x.wrapping_add(mul_div(d, fv.x, f_dot_p));
+        org.y = org.y.wrapping_add(mul_div(d, fv.y, f_dot_p));
+        Ok(())
+    }
+
+    fn shift_point(&mut self, z: usize, p: usize, dx: i32, dy: i32, touch: bool) -> Result<()> {
+        let fv = self.state.gs.freedom_vector;
+        let zone = self.zone_mut(z);
+        let cur = zone.cur.get_mut(p).ok_or(Error::Invalid)?;
+        cur.x = cur.x.wrapping_add(dx);
+        cur.y = cur.y.wrapping_add(dy);
+        if touch {
+            if fv.x != 0 {
+                zone.touched[p].insert(Touched::X);
+            }
+            if fv.y != 0 {
+                zone.touched[p].insert(Touched::Y);
+            }
+        }
+        Ok(())
+    }
+
+    // Reference point used by SHP, SHC and SHZ
+    fn reference(&self, use_rp1: bool) -> (usize, usize) {
+        let gs = &self.state.gs;
+        if use_rp1 {
+            (gs.zp[0], gs.rp[1])
+        } else {
+            (gs.zp[1], gs.rp[2])
+        }
+    }
+
+    // How far the reference point has moved, along the freedom vector
+    fn displacement(&self, use_rp1: bool) -> Result<(i32, i32)> {
+        let (z, p) = self.reference(use_rp1);
+        let d = self.project(self.cur(z, p)?, self.org(z, p)?);
+        let fv = self.state.gs.freedom_vector;
+        let f_dot_p = self.f_dot_p();
+        Ok((mul_div(d, fv.x, f_dot_p), mul_div(d, fv.y, f_dot_p)))
+    }
+
+    // Pop two points and get the (possibly perpendicular) unit vectors of the line between them,
+    // using current and original positions
+    fn line_vectors(&mut self, perpendicular: bool) -> Result<(Vector2D<i32>, Vector2D<i32>)> {
+        let p2 = self.pop()? as usize;
+        let p1 = self.pop()? as usize;
+        let [_, z1, z2] = self.state.gs.zp;
+        let cur = line_to_vector(self.cur(z1, p1)?, self.cur(z2, p2)?, perpendicular);
+        let org = line_to_vector(self.org(z1, p1)?, self.org(z2, p2)?, perpendicular);
+        Ok((cur, org))
+    }
+
+    fn round(&self, d: i32) -> i32 {
+        // Distances near the limits of i32 wrap around, as they would in other interpreters
+        let round = |v: i32, f: &dyn Fn(i32) -> i32| {
+            if v >= 0 {
+                f(v).max(0)
+            } else {
+                f(v.wrapping_neg()).max(0).wrapping_neg()
+            }
+        };
+        match self.state.gs.round_state {
+            RoundState::ToHalfGrid => round(d, &|v| (v & !63).wrapping_add(32)),
+            RoundState::ToGrid => round(d, &|v| v.wrapping_add(32) & !63),
+            RoundState::ToDoubleGrid => round(d, &|v| v.wrapping_add(16) & !31),
+            RoundState::DownToGrid => round(d, &|v| v & !63),
+            RoundState::UpToGrid => round(d, &|v| v.wrapping_add(63) & !63),
+            RoundState::Off => d,
+            RoundState::Super {
+                period,
+                phase,
+                threshold,
+            } => {
+                let r = |v: i32| {
+                    let v = v.wrapping_sub(phase).wrapping_add(threshold);
+                    (v.div_euclid(period) * period).wrapping_add(phase)
+                };
+                if d >= 0 {
+                    let v = r(d);
+                    if v < 0 {
+                        phase
+                    } else {
+                        v
+                    }
+                } else {
+                    let v = r(d.wrapping_neg()).wrapping_neg();
+                    if v > 0 {
+                        -phase
+                    } else {
+                        v
+                    }
+                }
+            }
+        }
+    }
+
+    // Move point to the intersection of two lines
+    fn isect(&mut self) -> Result<()> {
+        let b1 = self.pop()? as usize;
+        let b0 = self.pop()? as usize;
+        let a1 = self.pop()? as usize;
+        let a0 = self.pop()? as usize;
+        let p = self.pop()? as usize;
+        let [z0, z1, z2] = self.state.gs.zp;
+        let to_f = |p: Point2D<i32>| point2(p.x as f64, p.y as f64);
+        let (a0, a1) = (to_f(self.cur(z1, a0)?), to_f(self.cur(z1, a1)?));
+        let (b0, b1) = (to_f(self.cur(z0, b0)?), to_f(self.cur(z0, b1)?));
+        let (da, db) = (
+            vec2(a1.x - a0.x, a1.y - a0.y),
+            vec2(b1.x - b0.x, b1.y - b0.y),
+        );
+        let denom = da.x * db.y - da.y * db.x;
+        let point = if denom.abs() < 1e-6 {
+            // Parallel lines, so use the middle of all the points
+            point2(
+                (a0.x + a1.x + b0.x + b1.x) / 4.0,
+                (a0.y + a1.y + b0.y + b1.y) / 4.0,
+            )
+        } else {
+            let t = ((b0.x - a0.x) * db.y - (b0.y - a0.y) * db.x) / denom;
+            point2(a0.x + da.x * t, a0.y + da.y * t)
+        };
+        let zone = self.zone_mut(z2);
+        *zone.cur.get_mut(p).ok_or(Error::Invalid)? =
+            point2(point.x.round() as i32, point.y.round() as i32);
+        zone.touched[p].insert(Touched::X | Touched::Y);
+        Ok(())
+    }
+
+    // Interpolate untouched points in the glyph zone along one axis
+    fn iup(&mut self, x_axis: bool) {
+        let (flag, get): (_, fn(&Point2D<i32>) -> i32) = if x_axis {
+            (Touched::X, |p| p.x)
+        } else {
+            (Touched::Y, |p| p.y)
+        };
+        let zone = &mut self.glyph;
+        let mut start = 0;
+        for &end in &zone.contour_ends {
+            if end >= zone.cur.len() || end < start {
+                break;
+            }
+            let touched = (start..=end)
+                .filter(|p| zone.touched[*p].contains(flag))
+                .collect::<Vec<_>>();
+            if touched.is_empty() {
+                start = end + 1;
+                continue;
+            }
+            let len = end + 1 - start;
+            for (i, &t1) in touched.iter().enumerate() {
+                let t2 = touched[(i + 1) % touched.len()];
+                // Untouched points between t1 and t2, wrapping around the contour
+                let count = (t2 + len - t1 - 1) % len;
+                let count = if touched.len() == 1 { len - 1 } else { count };
+                let (mut o1, mut o2) = (get(&zone.org[t1]), get(&zone.org[t2]));
+                let (mut c1, mut c2) = (get(&zone.cur[t1]), get(&zone.cur[t2]));
+                if o1 > o2 {
+                    std::mem::swap(&mut o1, &mut o2);
+                    std::mem::swap(&mut c1, &mut c2);
+                }
+                let (d1, d2) = (c1.wrapping_sub(o1), c2.wrapping_sub(o2));
+                for k in 0..count {
+                    let p = start + (t1 - start + 1 + k) % len;
+                    let o = get(&zone.org[p]);
+                    let v = if o <= o1 {
+                        o.wrapping_add(d1)
+                    } else if o >= o2 {
+                        o.wrapping_add(d2)
+                    } else {
+                        let (o, o1, o2) = (o as i64, o1 as i64, o2 as i64);
+                        let d = (o - o1) * (c2 as i64 - c1 as i64) / (o2 - o1);
+                        c1.wrapping_add(d as i32)
+                    };
+                    if x_axis {
+                        zone.cur[p].x = v;
+                    } else {
+                        zone.cur[p].y = v;
+                    }
+                }
+            }
+            start = end + 1;
+        }
+    }
+
+    // Interpolate points relative to reference points 1 and 2
+    fn ip(&mut self) -> Result<()> {
+        let [z0, z1, z2] = self.state.gs.zp;
+        let [_, rp1, rp2] = self.state.gs.rp;
+        let (org_base, cur_base) = (self.org(z0, rp1)?, self.cur(z0, rp1)?);
+        let old_range = self.dual_project(self.org(z1, rp2)?, org_base);
+        let cur_range = self.project(self.cur(z1, rp2)?, cur_base);
+        for _ in 0..self.take_loop() {
+            let p = self.pop()? as usize;
+            let org_dist = self.dual_project(self.org(z2, p)?, org_base);
+            let cur_dist = self.project(self.cur(z2, p)?, cur_base);
+            let new_dist = if org_dist == 0 || old_range == 0 {
+                org_dist
+            } else {
+                (org_dist as i64 * cur_range as i64 / old_range as i64) as i32
+            };
+            self.move_point(z2, p, new_dist.wrapping_sub(cur_dist), true)?;
+        }
+        Ok(())
+    }
+
+    // Keep a distance from the minimum distance, if the flag is set
+    fn apply_min_distance(&self, op: u8, org_dist: i32, dist: i32) -> i32 {
+        let min = self.state.gs.min_distance;
+        if op & 0x08 == 0 {
+            dist
+        } else if org_dist >= 0 {
+            dist.max(min)
+        } else {
+            dist.min(-min)
+        }
+    }
+
+    // Apply single width cut-in to a distance
+    fn single_width(&self, d: i32) -> i32 {
+        let gs = &self.state.gs;
+        let diff = d.wrapping_abs().wrapping_sub(gs.single_width_value);
+        if diff.wrapping_abs() < gs.single_width_cutin {
+            if d >= 0 {
+                gs.single_width_value
+            } else {
+                -gs.single_width_value
+            }
+        } else {
+            d
+        }
+    }
+
+    // Move direct relative point
+    fn mdrp(&mut self, op: u8) -> Result<()> {
+        let p = self.pop()? as usize;
+        let [z0, z1, _] = self.state.gs.zp;
+        let rp0 = self.state.gs.rp[0];
+        let org_dist = self.single_width(self.dual_project(self.org(z1, p)?, self.org(z0, rp0)?));
+        let dist = if op & 0x04 != 0 {
+            self.round(org_dist)
+        } else {
+            org_dist
+        };
+        let dist = self.apply_min_distance(op, org_dist, dist);
+        let cur_dist = self.project(self.cur(z1, p)?, self.cur(z0, rp0)?);
+        self.move_point(z1, p, dist.wrapping_sub(cur_dist), true)?;
+        self.set_reference_points(op, p);
+        Ok(())
+    }
+
+    // Move indirect relative point
+    fn mirp(&mut self, op: u8) -> Result<()> {
+        let cvt = self.pop()?;
+        let p = self.pop()? as usize;
+        let [z0, z1, _] = self.state.gs.zp;
+        let rp0 = self.state.gs.rp[0];
+        let cvt_dist = if cvt == -1 {
+            0
+        } else {
+            *self.state.cvt.get(cvt as usize).ok_or(Error::Invalid)?
+        };
+        let mut cvt_dist = self.single_width(cvt_dist);
+        if z1 == 0 {
+            let fv = self.state.gs.freedom_vector;
+            let org = self.org(z0, rp0)?;
+            let org = point2(
+                org.x.wrapping_add(mul_f2dot14(cvt_dist, fv.x)),
+                org.y.wrapping_add(mul_f2dot14(cvt_dist, fv.y)),
+            );
+            let zone = self.zone_mut(z1);
+            *zone.org.get_mut(p).ok_or(Error::Invalid)? = org;
+            zone.cur[p] = org;
+        }
+        let org_dist = self.dual_project(self.org(z1, p)?, self.org(z0, rp0)?);
+        let cur_dist = self.project(self.cur(z1, p)?, self.cur(z0, rp0)?);
+        if self.state.gs.auto_flip && (org_dist ^ cvt_dist) < 0 {
+            cvt_dist = cvt_dist.wrapping_neg();
+        }
+        let dist = if op & 0x04 != 0 {
+            if z0 == z1
+                && cvt_dist.wrapping_sub(org_dist).wrapping_abs()
+                    > self.state.gs.control_value_cutin
+            {
+                cvt_dist = org_dist;
+            }
+            self.round(cvt_dist)
+        } else {
+            cvt_dist
+        };
+        let dist = self.apply_min_distance(op, org_dist, dist);
+        self.move_point(z1, p, dist.wrapping_sub(cur_dist), true)?;
+        self.set_reference_points(op, p);
+        Ok(())
+    }
+
+    fn set_reference_points(&mut self, op: u8, p: usize) {
+        let gs = &mut self.state.gs;
+        gs.rp[1] = gs.rp[0];
+        gs.rp[2] = p;
+        if op & 0x10 != 0 {
+            gs.rp[0] = p;
+        }
+    }
+}
+
+// Length of instruction, including inline data
+fn instruction_len(code: &[u8], ip: usize) -> Result<usize> {
+    let len = match code[ip] {
+        0x40 => 2 + *code.get(ip + 1).ok_or(Error::Invalid)? as usize,
+        0x41 => 2 + *code.get(ip + 1).ok_or(Error::Invalid)? as usize * 2,
+        op @ 0xb0..=0xb7 => 1 + (op - 0xaf) as usize,
+        op @ 0xb8..=0xbf => 1 + (op - 0xb7) as usize * 2,
+        _ => 1,
+    };
+    if ip + len > code.len() {
+        return Err(Error::Invalid);
+    }
+    Ok(len)
+}
+
+// Get position after the matching ELSE (if `to_else` is set) or EIF
+fn skip_branch(code: &[u8], ip: usize, end: usize, to_else: bool) -> Result<usize> {
+    let mut depth = 0;
+    let mut ip = ip + 1;
+    while ip < end {
+        match code[ip] {
+            0x58 => depth += 1,
+            0x1b if depth == 0 && to_else => return Ok(ip + 1),
+            0x59 if depth == 0 => return Ok(ip + 1),
+            0x59 => depth -= 1,
+            _ => {}
+        }
+        ip += instruction_len(code, ip)?;
+    }
+    Err(Error::Invalid)
+}
+
+// Get position of the ENDF which ends a definition
+fn find_endf(code: &[u8], mut ip: usize, end: usize) -> Result<usize> {
+    while ip < end {
+        if code[ip] == 0x2d {
+            return Ok(ip);
+        }
+        ip += instruction_len(code, ip)?;
+    }
+    Err(Error::Invalid)
+}
+
+fn jump(ip: usize, offset: i32) -> Result<usize> {
+    let target = ip as i64 + offset as i64;
+    if target < 0 || offset == 0 {
+        Err(Error::Invalid)
+    } else {
+        Ok(target as usize)
+    }
+}
+
+fn super_round(n: i32, diagonal: bool) -> RoundState {
+    let base = if diagonal { 45 } else { 64 };
+    let period = match (n >> 6) & 3 {
+        0 => base / 2,
+        2 => base * 2,
+        _ => base,
+    };
+    let phase = match (n >> 4) & 3 {
+        0 => 0,
+        1 => period / 4,
+        2 => period / 2,
+        _ => period * 3 / 4,
+    };
+    let threshold = match n & 0xf {
+        0 => period - 1,
+        t => (t - 4) * period / 8,
+    };
+    RoundState::Super {
+        period,
+        phase,
+        threshold,
+    }
+}
+
+// Unit vector in 2.14 fixed point, along the line from b to a
+fn line_to_vector(a: Point2D<i32>, b: Point2D<i32>, perpendicular: bool) -> Vector2D<i32> {
+    let (x, y) = ((a.x - b.x) as f64, (a.y - b.y) as f64);
+    if perpendicular {
+        normalize(-y, x)
+    } else {
+        normalize(x, y)
+    }
+}
+
+fn normalize(x: f64, y: f64) -> Vector2D<i32> {
+    let len = x.hypot(y);
+    if len == 0.0 {
+        return vec2(ONE, 0);
+    }
+    let one = ONE as f64;
+    vec2(
+        (x / len * one).round() as i32,
+        (y / len * one).round() as i32,
+    )
+}
+
+// Dot product of a 26.6 vector with a 2.14 unit vector
+fn dot(v: Vector2D<i32>, u: Vector2D<i32>) -> i32 {
+    ((v.x as i64 * u.x as i64 + v.y as i64 * u.y as i64 + 0x2000) >> 14) as i32
+}
+
+fn mul_f2dot14(d: i32, v: i32) -> i32 {
+    ((d as i64 * v as i64 + 0x2000) >> 14) as i32
+}
+
+fn mul_div(a: i32, b: i32, c: i32) -> i32 {
+    (a as i64 * b as i64 / c as i64) as i32
+}
+
+fn to_f26p6(v: f32) -> i32 {
+    (v * 64.0).round() as i32
+}
+
+fn round_to_grid(v: i32) -> i32 {
+    v.wrapping_add(32) & !63
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run glyph program on the given points, in 26.6 fixed point. Returns the stack and the
+    // grid-fitted points
+    fn run(code: &[u8], points: &[Point2D<i32>]) -> (Vec<i32>, Vec<Point2D<i32>>) {
+        let programs = Programs {
+            fpgm: Vec::new(),
+            prep: Vec::new(),
+            cvt: Vec::new(),
+            max_twilight: 0,
+            max_storage: 0,
+            max_function_defs: 0,
+            max_stack: 16,
+        };
+        let state = State {
+            functions: Vec::new(),
+            idefs: vec![None; 256],
+            cvt: Vec::new(),
+            storage: Vec::new(),
+            twilight: Zone::default(),
+            gs: GraphicsState::default(),
+        };
+        let zone = Zone::new(points.to_vec(), vec![true; points.len()], Vec::new());
+        let mut exec = Exec::new(&programs, code, state, zone, 1.0, 12);
+        exec.run_program(Program::Glyph).unwrap();
+        (exec.stack, exec.glyph.cur)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let code = [
+            0xb8, 0xff, 0x9c, 0x64, // PUSHW -100, ABS
+            0xb0, 100, 0x65, // PUSHB 100, NEG
+            0xb0, 65, 0x66, // PUSHB 65, FLOOR
+            0xb0, 65, 0x67, // PUSHB 65, CEILING
+            0xb0, 95, 0x68, // PUSHB 95, ROUND
+            0xb8, 0xff, 0xa1, 0x68, // PUSHW -95, ROUND
+        ];
+        let (stack, _) = run(&code, &[]);
+        assert_eq!(stack, [100, -100, 64, 128, 64, -64]);
+    }
+
+    #[test]
+    fn arithmetic_wraps_around() {
+        // i32::MIN, from 2^24 * 2^13 / 64. Its absolute value and negation can't be represented
+        let min = [0xb9, 0x80, 0x00, 0x80, 0x00, 0x63, 0xb8, 0x20, 0x00, 0x63];
+        let mut code = Vec::new();
+        for op in &[0x64, 0x65, 0x67, 0x68] {
+            code.extend_from_slice(&min);
+            code.push(*op);
+        }
+        let (stack, _) = run(&code, &[]);
+        assert_eq!(stack, [i32::MIN, i32::MIN, i32::MIN, 0]);
+    }
+
+    #[test]
+    fn move_points() {
+        // PUSHB 0, MDAP[round] rounds the first point to the grid along the x-axis. PUSHB 1 64,
+        // SHPIX then moves the second point by a pixel
+        let code = [0xb0, 0, 0x2f, 0xb1, 1, 64, 0x38];
+        let points = [point2(40, 40), point2(i32::MAX - 10, 0)];
+        let (_, points) = run(&code, &points);
+        assert_eq!(points, [point2(64, 40), point2(i32::MIN + 53, 0)]);
+    }
+}
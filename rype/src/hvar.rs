@@ -0,0 +1,44 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::types::get_u32;
+use crate::varstore::{DeltaSetIndexMap, ItemVariationStore};
+
+/// Wrapper around horizontal metrics variations table
+#[derive(Debug)]
+pub(crate) struct Hvar {
+    store: ItemVariationStore,
+    advance_map: Option<DeltaSetIndexMap>, // Without a map, glyph IDs are the inner indices
+}
+
+impl Hvar {
+    pub(crate) fn load(data: &[u8]) -> Result<Hvar> {
+        let store_off = get_u32(data, offsets::ITEM_VARIATION_STORE)? as usize;
+        let store = ItemVariationStore::load(data.get(store_off..).ok_or(Error::Invalid)?)?;
+        let advance_map = match get_u32(data, offsets::ADVANCE_WIDTH_MAPPING)? as usize {
+            0 => None,
+            off => Some(DeltaSetIndexMap::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
+        };
+        Ok(Hvar { store, advance_map })
+    }
+
+    /// Get change in advance width, in font units
+    pub(crate) fn advance_delta(&self, glyph: GlyphID, coords: &[f32]) -> f32 {
+        let (outer, inner) = match &self.advance_map {
+            Some(map) => match map.get(glyph.0) {
+                Some(indices) => indices,
+                None => return 0.0,
+            },
+            None => (0, glyph.0 as u16),
+        };
+        self.store.delta(outer, inner, coords).unwrap_or(0.0)
+    }
+}
+
+mod offsets {
+    pub(super) const ITEM_VARIATION_STORE: usize = 4;
+    pub(super) const ADVANCE_WIDTH_MAPPING: usize = 8;
+}
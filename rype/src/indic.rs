@@ -0,0 +1,519 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::cmap::Cmap;
+use crate::common::{GlyphID, SubstGlyph};
+use crate::features::{Features, GlyphFeatures};
+use crate::gsub::Gsub;
+use crate::options::{FeatureValues, ShapingOptions};
+use crate::shaper::{self, Category, Position};
+use Category::{Consonant, Halant, Matra, Modifier, Nukta, Placeholder, PreMatra, Ra, Vowel};
+
+/// Features applied by the Indic shaper, on top of the defaults
+pub(crate) static INDIC_FEATURES: [Features; 16] = [
+    Features::NuktaForms,
+    Features::Akhand,
+    Features::RephForm,
+    Features::RakarForms,
+    Features::PreBaseForms,
+    Features::BelowBaseForms,
+    Features::AboveBaseForms,
+    Features::HalfForms,
+    Features::PostBaseForms,
+    Features::VattuVariants,
+    Features::ConjunctForms,
+    Features::PreBaseSubstitutions,
+    Features::AboveBaseSubstitutions,
+    Features::BelowBaseSubstitutions,
+    Features::PostBaseSubstitutions,
+    Features::HalantForms,
+];
+
+// Features which form basic shapes. These are applied one at a time, in this order, before the
+// final reordering. Everything else is applied afterwards.
+static BASIC_FEATURES: [Features; 13] = [
+    Features::LocalizedForms,
+    Features::GlyphCompositionDecomposition,
+    Features::NuktaForms,
+    Features::Akhand,
+    Features::RephForm,
+    Features::RakarForms,
+    Features::PreBaseForms,
+    Features::BelowBaseForms,
+    Features::AboveBaseForms,
+    Features::HalfForms,
+    Features::PostBaseForms,
+    Features::VattuVariants,
+    Features::ConjunctForms,
+];
+
+/// Split text into syllables, reorder them, and substitute glyphs in stages
+pub(crate) fn substitute(
+    glyphs: &mut Vec<SubstGlyph>,
+    codepoints: &[char],
+    cmap: &Cmap,
+    gsub: Option<&Gsub>,
+    options: &ShapingOptions,
+    features: &FeatureValues,
+) {
+    // Split matras are decomposed, so that the part displayed before the base can be reordered
+    shaper::decompose(glyphs, codepoints, cmap, &SPLIT_MATRAS, category);
+    shaper::segment(glyphs, cmap, is_mark, syllable_end);
+    shaper::for_each_syllable(glyphs, |syllable| initial_reorder(syllable, gsub, options));
+
+    let basic_features = BASIC_FEATURES.iter().map(|f| f.tag()).collect::<Vec<_>>();
+    for tag in &basic_features {
+        shaper::substitute(glyphs, gsub, options, &features.only(&[*tag]));
+    }
+    shaper::for_each_syllable(glyphs, final_reorder);
+    shaper::substitute(glyphs, gsub, options, &features.except(&basic_features));
+}
+
+// Find the end of the syllable starting at the given index
+fn syllable_end(glyphs: &[SubstGlyph], start: usize) -> usize {
+    let cat = |i: usize| {
+        glyphs
+            .get(i)
+            .map(|g| g.syllable.category)
+            .unwrap_or(Category::Other)
+    };
+    let mut i = start;
+    match cat(i) {
+        Consonant | Ra | Placeholder => loop {
+            // Consonants joined by halants, optionally followed by a joiner
+            i += 1;
+            if cat(i) == Nukta {
+                i += 1;
+            }
+            if cat(i) != Halant {
+                break;
+            }
+            let mut j = i + 1;
+            if cat(j) == Category::Zwj || cat(j) == Category::Zwnj {
+                j += 1;
+            }
+            if cat(j) != Consonant && cat(j) != Ra {
+                return j; // Syllable ends with a halant
+            }
+            i = j;
+        },
+        Vowel => i += 1,
+        Matra | PreMatra | Halant | Nukta | Modifier => {}
+        _ => return start + 1,
+    }
+    while is_mark(cat(i)) {
+        i += 1;
+    }
+    i.max(start + 1)
+}
+
+// Find the base, move pre-base matras to the start, and enable features for glyphs depending on
+// their position relative to the base
+fn initial_reorder(syllable: &mut [SubstGlyph], gsub: Option<&Gsub>, options: &ShapingOptions) {
+    let len = syllable.len();
+    let cats = syllable
+        .iter()
+        .map(|g| g.syllable.category)
+        .collect::<Vec<_>>();
+    let would_substitute = |glyphs: &[GlyphID], feature: Features| {
+        gsub.map(|gsub| {
+            gsub.would_substitute(
+                glyphs,
+                options.script,
+                options.language,
+                options.direction,
+                feature.tag(),
+            )
+        })
+        .unwrap_or(false)
+    };
+    // Ra and halant at the start of the syllable form a reph, if there's a base after them
+    let has_reph = len > 2
+        && cats[0] == Ra
+        && cats[1] == Halant
+        && is_base(cats[2])
+        && would_substitute(&[syllable[0].glyph, syllable[1].glyph], Features::RephForm);
+    let limit = if has_reph { 2 } else { 0 };
+    // The base is the last consonant which doesn't take a below-base or post-base form
+    let halant = cats
+        .iter()
+        .position(|c| *c == Halant)
+        .map(|i| syllable[i].glyph);
+    let mut base = limit.min(len - 1);
+    let mut seen_below = false;
+    for i in (limit..len).rev() {
+        if is_base(cats[i]) {
+            let glyph = syllable[i].glyph;
+            let forms = |feature: Features| {
+                halant
+                    .map(|h| {
+                        would_substitute(&[glyph, h], feature)
+                            || would_substitute(&[h, glyph], feature)
+                    })
+                    .unwrap_or(false)
+            };
+            let below = forms(Features::BelowBaseForms);
+            let post = !below && forms(Features::PostBaseForms);
+            base = i;
+            if !below && (!post || seen_below) {
+                break;
+            }
+            seen_below |= below;
+        } else if i > 0 && cats[i] == Category::Zwj && cats[i - 1] == Halant {
+            break; // Explicit half form before the joiner
+        }
+    }
+    // All glyphs in the syllable form one cluster
+    let cluster = syllable.iter().map(|g| g.cluster).min().unwrap_or(0);
+    for (i, glyph) in syllable.iter_mut().enumerate() {
+        let (position, features) = if i < limit {
+            (Position::Reph, GlyphFeatures::RPHF)
+        } else if cats[i] == PreMatra {
+            (Position::PreMatra, GlyphFeatures::empty())
+        } else if i < base {
+            (Position::PreBase, GlyphFeatures::HALF)
+        } else if i == base {
+            (Position::Base, GlyphFeatures::empty())
+        } else {
+            (
+                Position::PostBase,
+                GlyphFeatures::PREF
+                    | GlyphFeatures::BLWF
+                    | GlyphFeatures::ABVF
+                    | GlyphFeatures::PSTF,
+            )
+        };
+        glyph.cluster = cluster;
+        glyph.features |= features;
+        glyph.syllable.position = position;
+    }
+    syllable[limit..].sort_by_key(|g| g.syllable.position != Position::PreMatra);
+}
+
+// Settle pre-base matras and reph, now that basic forms have been substituted
+fn final_reorder(syllable: &mut [SubstGlyph]) {
+    let len = syllable.len();
+    // Ligatures take the position of their first component, so a conjunct of half forms and the
+    // base appears to be before the base
+    let mut base = syllable
+        .iter()
+        .position(|g| g.syllable.position >= Position::Base)
+        .unwrap_or(len);
+    if base > 0 && (base == len || syllable[base].syllable.position > Position::Base) {
+        base -= 1;
+    }
+    // Pre-base matras go after the last halant before the base which didn't form a half form
+    let first = syllable
+        .iter()
+        .position(|g| g.syllable.position != Position::Reph)
+        .unwrap_or(len);
+    let matras_end = first
+        + syllable[first..]
+            .iter()
+            .take_while(|g| g.syllable.position == Position::PreMatra)
+            .count();
+    if matras_end > first && matras_end < base {
+        if let Some(h) = (matras_end..base)
+            .rev()
+            .find(|i| syllable[*i].syllable.category == Halant)
+        {
+            syllable[first..=h].rotate_left(matras_end - first);
+        }
+    }
+    // If Ra and halant formed a single reph glyph, it goes after the base and post-base
+    // consonants, but before matras and modifiers
+    let reph_formed = len > 1
+        && syllable[0].syllable.position == Position::Reph
+        && syllable[1].syllable.position != Position::Reph;
+    if reph_formed {
+        let target = (base + 1..len)
+            .find(|i| matches!(syllable[*i].syllable.category, Matra | Modifier))
+            .unwrap_or(len);
+        syllable[..target].rotate_left(1);
+    }
+}
+
+fn is_base(category: Category) -> bool {
+    matches!(category, Consonant | Ra | Vowel | Placeholder)
+}
+
+fn is_mark(category: Category) -> bool {
+    matches!(category, Matra | PreMatra | Halant | Nukta | Modifier)
+}
+
+fn category(c: char) -> Category {
+    shaper::category(&CATEGORIES, c)
+}
+
+// Matras which are displayed partly before and partly after the base
+static SPLIT_MATRAS: [(char, [char; 2]); 11] = [
+    ('\u{09CB}', ['\u{09C7}', '\u{09BE}']),
+    ('\u{09CC}', ['\u{09C7}', '\u{09D7}']),
+    ('\u{0B48}', ['\u{0B47}', '\u{0B56}']),
+    ('\u{0B4B}', ['\u{0B47}', '\u{0B3E}']),
+    ('\u{0B4C}', ['\u{0B47}', '\u{0B57}']),
+    ('\u{0BCA}', ['\u{0BC6}', '\u{0BBE}']),
+    ('\u{0BCB}', ['\u{0BC7}', '\u{0BBE}']),
+    ('\u{0BCC}', ['\u{0BC6}', '\u{0BD7}']),
+    ('\u{0D4A}', ['\u{0D46}', '\u{0D3E}']),
+    ('\u{0D4B}', ['\u{0D47}', '\u{0D3E}']),
+    ('\u{0D4C}', ['\u{0D46}', '\u{0D57}']),
+];
+
+// Categories for ranges of characters in the Devanagari to Malayalam blocks
+static CATEGORIES: [(u32, u32, Category); 180] = [
+    (0x0900, 0x0903, Modifier),
+    (0x0904, 0x0914, Vowel),
+    (0x0915, 0x092F, Consonant),
+    (0x0930, 0x0930, Ra),
+    (0x0931, 0x0939, Consonant),
+    (0x093A, 0x093B, Matra),
+    (0x093C, 0x093C, Nukta),
+    (0x093E, 0x093E, Matra),
+    (0x093F, 0x093F, PreMatra),
+    (0x0940, 0x094C, Matra),
+    (0x094D, 0x094D, Halant),
+    (0x094E, 0x094E, PreMatra),
+    (0x094F, 0x094F, Matra),
+    (0x0951, 0x0954, Modifier),
+    (0x0955, 0x0957, Matra),
+    (0x0958, 0x095F, Consonant),
+    (0x0960, 0x0961, Vowel),
+    (0x0962, 0x0963, Matra),
+    (0x0972, 0x0977, Vowel),
+    (0x0978, 0x097C, Consonant),
+    (0x097D, 0x097D, Vowel),
+    (0x097E, 0x097F, Consonant),
+    (0x0981, 0x0983, Modifier),
+    (0x0985, 0x098C, Vowel),
+    (0x098F, 0x0990, Vowel),
+    (0x0993, 0x0994, Vowel),
+    (0x0995, 0x09A8, Consonant),
+    (0x09AA, 0x09AF, Consonant),
+    (0x09B0, 0x09B0, Ra),
+    (0x09B2, 0x09B2, Consonant),
+    (0x09B6, 0x09B9, Consonant),
+    (0x09BC, 0x09BC, Nukta),
+    (0x09BE, 0x09BE, Matra),
+    (0x09BF, 0x09BF, PreMatra),
+    (0x09C0, 0x09C4, Matra),
+    (0x09C7, 0x09C8, PreMatra),
+    (0x09CB, 0x09CC, Matra),
+    (0x09CD, 0x09CD, Halant),
+    (0x09CE, 0x09CE, Consonant),
+    (0x09D7, 0x09D7, Modifier),
+    (0x09DC, 0x09DD, Consonant),
+    (0x09DF, 0x09DF, Consonant),
+    (0x09E0, 0x09E1, Vowel),
+    (0x09E2, 0x09E3, Matra),
+    (0x09F0, 0x09F0, Ra),
+    (0x09F1, 0x09F1, Consonant),
+    (0x09FE, 0x09FE, Modifier),
+    (0x0A01, 0x0A03, Modifier),
+    (0x0A05, 0x0A0A, Vowel),
+    (0x0A0F, 0x0A10, Vowel),
+    (0x0A13, 0x0A14, Vowel),
+    (0x0A15, 0x0A28, Consonant),
+    (0x0A2A, 0x0A2F, Consonant),
+    (0x0A30, 0x0A30, Ra),
+    (0x0A32, 0x0A33, Consonant),
+    (0x0A35, 0x0A36, Consonant),
+    (0x0A38, 0x0A39, Consonant),
+    (0x0A3C, 0x0A3C, Nukta),
+    (0x0A3E, 0x0A3E, Matra),
+    (0x0A3F, 0x0A3F, PreMatra),
+    (0x0A40, 0x0A42, Matra),
+    (0x0A47, 0x0A48, Matra),
+    (0x0A4B, 0x0A4C, Matra),
+    (0x0A4D, 0x0A4D, Halant),
+    (0x0A51, 0x0A51, Modifier),
+    (0x0A59, 0x0A5C, Consonant),
+    (0x0A5E, 0x0A5E, Consonant),
+    (0x0A70, 0x0A71, Modifier),
+    (0x0A75, 0x0A75, Modifier),
+    (0x0A81, 0x0A83, Modifier),
+    (0x0A85, 0x0A8C, Vowel),
+    (0x0A8F, 0x0A90, Vowel),
+    (0x0A93, 0x0A94, Vowel),
+    (0x0A95, 0x0AA8, Consonant),
+    (0x0AAA, 0x0AAF, Consonant),
+    (0x0AB0, 0x0AB0, Ra),
+    (0x0AB2, 0x0AB3, Consonant),
+    (0x0AB5, 0x0AB9, Consonant),
+    (0x0ABC, 0x0ABC, Nukta),
+    (0x0ABE, 0x0ABE, Matra),
+    (0x0ABF, 0x0ABF, PreMatra),
+    (0x0AC0, 0x0AC5, Matra),
+    (0x0AC7, 0x0AC9, Matra),
+    (0x0ACB, 0x0ACC, Matra),
+    (0x0ACD, 0x0ACD, Halant),
+    (0x0AE0, 0x0AE1, Vowel),
+    (0x0AE2, 0x0AE3, Matra),
+    (0x0AF9, 0x0AF9, Consonant),
+    (0x0AFA, 0x0AFF, Modifier),
+    (0x0B01, 0x0B03, Modifier),
+    (0x0B05, 0x0B0C, Vowel),
+    (0x0B0F, 0x0B10, Vowel),
+    (0x0B13, 0x0B14, Vowel),
+    (0x0B15, 0x0B28, Consonant),
+    (0x0B2A, 0x0B2F, Consonant),
+    (0x0B30, 0x0B30, Ra),
+    (0x0B32, 0x0B33, Consonant),
+    (0x0B35, 0x0B39, Consonant),
+    (0x0B3C, 0x0B3C, Nukta),
+    (0x0B3E, 0x0B44, Matra),
+    (0x0B47, 0x0B47, PreMatra),
+    (0x0B48, 0x0B48, Matra),
+    (0x0B4B, 0x0B4C, Matra),
+    (0x0B4D, 0x0B4D, Halant),
+    (0x0B55, 0x0B57, Modifier),
+    (0x0B5C, 0x0B5D, Consonant),
+    (0x0B5F, 0x0B5F, Consonant),
+    (0x0B60, 0x0B61, Vowel),
+    (0x0B62, 0x0B63, Matra),
+    (0x0B71, 0x0B71, Consonant),
+    (0x0B82, 0x0B82, Modifier),
+    (0x0B85, 0x0B8A, Vowel),
+    (0x0B8E, 0x0B90, Vowel),
+    (0x0B92, 0x0B94, Vowel),
+    (0x0B95, 0x0B95, Consonant),
+    (0x0B99, 0x0B9A, Consonant),
+    (0x0B9C, 0x0B9C, Consonant),
+    (0x0B9E, 0x0B9F, Consonant),
+    (0x0BA3, 0x0BA4, Consonant),
+    (0x0BA8, 0x0BAA, Consonant),
+    (0x0BAE, 0x0BAF, Consonant),
+    (0x0BB0, 0x0BB0, Ra),
+    (0x0BB1, 0x0BB9, Consonant),
+    (0x0BBE, 0x0BC2, Matra),
+    (0x0BC6, 0x0BC8, PreMatra),
+    (0x0BCA, 0x0BCC, Matra),
+    (0x0BCD, 0x0BCD, Halant),
+    (0x0BD7, 0x0BD7, Modifier),
+    (0x0C00, 0x0C04, Modifier),
+    (0x0C05, 0x0C0C, Vowel),
+    (0x0C0E, 0x0C10, Vowel),
+    (0x0C12, 0x0C14, Vowel),
+    (0x0C15, 0x0C28, Consonant),
+    (0x0C2A, 0x0C2F, Consonant),
+    (0x0C30, 0x0C30, Ra),
+    (0x0C31, 0x0C39, Consonant),
+    (0x0C3C, 0x0C3C, Nukta),
+    (0x0C3E, 0x0C44, Matra),
+    (0x0C46, 0x0C48, Matra),
+    (0x0C4A, 0x0C4C, Matra),
+    (0x0C4D, 0x0C4D, Halant),
+    (0x0C55, 0x0C56, Modifier),
+    (0x0C58, 0x0C5A, Consonant),
+    (0x0C5D, 0x0C5D, Consonant),
+    (0x0C60, 0x0C61, Vowel),
+    (0x0C62, 0x0C63, Matra),
+    (0x0C81, 0x0C83, Modifier),
+    (0x0C85, 0x0C8C, Vowel),
+    (0x0C8E, 0x0C90, Vowel),
+    (0x0C92, 0x0C94, Vowel),
+    (0x0C95, 0x0CA8, Consonant),
+    (0x0CAA, 0x0CAF, Consonant),
+    (0x0CB0, 0x0CB0, Ra),
+    (0x0CB1, 0x0CB3, Consonant),
+    (0x0CB5, 0x0CB9, Consonant),
+    (0x0CBC, 0x0CBC, Nukta),
+    (0x0CBE, 0x0CC4, Matra),
+    (0x0CC6, 0x0CC8, Matra),
+    (0x0CCA, 0x0CCC, Matra),
+    (0x0CCD, 0x0CCD, Halant),
+    (0x0CD5, 0x0CD6, Modifier),
+    (0x0CDD, 0x0CDE, Consonant),
+    (0x0CE0, 0x0CE1, Vowel),
+    (0x0CE2, 0x0CE3, Matra),
+    (0x0D00, 0x0D03, Modifier),
+    (0x0D05, 0x0D0C, Vowel),
+    (0x0D0E, 0x0D10, Vowel),
+    (0x0D12, 0x0D14, Vowel),
+    (0x0D15, 0x0D2F, Consonant),
+    (0x0D30, 0x0D30, Ra),
+    (0x0D31, 0x0D3A, Consonant),
+    (0x0D3B, 0x0D3C, Modifier),
+    (0x0D3E, 0x0D44, Matra),
+    (0x0D46, 0x0D48, PreMatra),
+    (0x0D4A, 0x0D4C, Matra),
+    (0x0D4D, 0x0D4D, Halant),
+    (0x0D57, 0x0D57, Modifier),
+    (0x0D5F, 0x0D5F, Consonant),
+    (0x0D60, 0x0D61, Vowel),
+    (0x0D62, 0x0D63, Matra),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::LigatureInfo;
+    use crate::direction::Direction;
+    use crate::shaper::SyllableInfo;
+    use crate::Script;
+
+    // One glyph per character, numbered in order
+    fn syllable(text: &str) -> Vec<SubstGlyph> {
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| SubstGlyph {
+                glyph: GlyphID(i as u32 + 1),
+                cluster: i,
+                lig: LigatureInfo::default(),
+                features: GlyphFeatures::empty(),
+                syllable: SyllableInfo {
+                    index: 0,
+                    category: category(c),
+                    position: Position::Base,
+                },
+            })
+            .collect()
+    }
+
+    fn glyphs(syllable: &[SubstGlyph]) -> Vec<u32> {
+        syllable.iter().map(|g| g.glyph.0).collect()
+    }
+
+    #[test]
+    fn pre_base_matra() {
+        // KA, I
+        let mut syl = syllable("\u{0915}\u{093F}");
+        let options = ShapingOptions::new(Script::Devanagari, Direction::LeftToRight);
+        initial_reorder(&mut syl, None, &options);
+        assert_eq!(glyphs(&syl), [2, 1]);
+        assert!(syl.iter().all(|g| g.cluster == 0));
+        final_reorder(&mut syl);
+        assert_eq!(glyphs(&syl), [2, 1]);
+    }
+
+    #[test]
+    fn pre_base_matra_after_halant() {
+        // KA, VIRAMA, SSA, I. Without a half form for KA, the matra goes just before the base
+        let mut syl = syllable("\u{0915}\u{094D}\u{0937}\u{093F}");
+        let options = ShapingOptions::new(Script::Devanagari, Direction::LeftToRight);
+        initial_reorder(&mut syl, None, &options);
+        assert_eq!(glyphs(&syl), [4, 1, 2, 3]);
+        let positions = syl.iter().map(|g| g.syllable.position).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                Position::PreMatra,
+                Position::PreBase,
+                Position::PreBase,
+                Position::Base
+            ]
+        );
+        assert!(syl[1].features.contains(GlyphFeatures::HALF));
+        final_reorder(&mut syl);
+        assert_eq!(glyphs(&syl), [1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn reph() {
+        // A reph formed from RA and VIRAMA, followed by KA and AA
+        let mut syl = syllable("\u{0930}\u{0915}\u{093E}");
+        syl[0].syllable.position = Position::Reph;
+        syl[2].syllable.position = Position::PostBase;
+        final_reorder(&mut syl);
+        assert_eq!(glyphs(&syl), [2, 1, 3]);
+    }
+}
@@ -0,0 +1,313 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+
+/// Decompress zlib stream into at most `max_len` bytes. The checksum is not verified
+pub(crate) fn zlib_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf as u16, *flg as u16),
+        _ => return Err(Error::Invalid),
+    };
+    // Only deflate without a preset dictionary is used
+    if cmf & 0x0f != 8 || flg & 0x20 != 0 || ((cmf << 8) | flg) % 31 != 0 {
+        return Err(Error::Invalid);
+    }
+    inflate(&data[2..], max_len)
+}
+
+/// Decompress raw deflate stream into at most `max_len` bytes
+pub(crate) fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => reader.stored(&mut out, max_len)?,
+            1 => {
+                let (lits, dists) = fixed_tables();
+                reader.codes(&mut out, max_len, &lits, &dists)?;
+            }
+            2 => {
+                let (lits, dists) = reader.dynamic_tables()?;
+                reader.codes(&mut out, max_len, &lits, &dists)?;
+            }
+            _ => return Err(Error::Invalid),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+// Canonical Huffman code, as the number of codes of each length and the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0; MAX_BITS + 1];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        // Reject over-subscribed codes. Incomplete codes are allowed, and fail on unused codes
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(Error::Invalid);
+            }
+        }
+        let mut offsets = [0; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+// Code lengths for the fixed Huffman tables
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (i, len) in lengths.iter_mut().enumerate() {
+        *len = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (
+        Huffman::new(&lengths).expect("invalid fixed literal table"),
+        Huffman::new(&[5; 30]).expect("invalid fixed distance table"),
+    )
+}
+
+// Reads bits from the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        let mut ret = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or(Error::Invalid)?;
+            ret |= ((byte as u32 >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(ret)
+    }
+
+    // Huffman codes are packed starting with the most significant bit
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &huffman.counts[1..] {
+            code |= self.bits(1)? as i32;
+            let count = *count as i32;
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Invalid)
+    }
+
+    fn stored(&mut self, out: &mut Vec<u8>, max_len: usize) -> Result<()> {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        let header = self
+            .data
+            .get(self.pos..self.pos + 4)
+            .ok_or(Error::Invalid)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        let nlen = u16::from_le_bytes([header[2], header[3]]);
+        if len != !nlen {
+            return Err(Error::Invalid);
+        }
+        if out.len() + len as usize > max_len {
+            return Err(Error::Invalid);
+        }
+        let start = self.pos + 4;
+        let bytes = self
+            .data
+            .get(start..start + len as usize)
+            .ok_or(Error::Invalid)?;
+        out.extend_from_slice(bytes);
+        self.pos = start + len as usize;
+        Ok(())
+    }
+
+    fn dynamic_tables(&mut self) -> Result<(Huffman, Huffman)> {
+        let num_lits = self.bits(5)? as usize + 257;
+        let num_dists = self.bits(5)? as usize + 1;
+        let num_code_lengths = self.bits(4)? as usize + 4;
+        if num_lits > 286 || num_dists > 30 {
+            return Err(Error::Invalid);
+        }
+        let mut code_lengths = [0; 19];
+        for i in &CODE_LENGTH_ORDER[..num_code_lengths] {
+            code_lengths[*i] = self.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+        let mut lengths = Vec::with_capacity(num_lits + num_dists);
+        while lengths.len() < num_lits + num_dists {
+            let (len, repeat) = match self.decode(&code_lengths)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => (
+                    *lengths.last().ok_or(Error::Invalid)?,
+                    3 + self.bits(2)? as usize,
+                ),
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if lengths.len() + repeat > num_lits + num_dists {
+                return Err(Error::Invalid);
+            }
+            lengths.extend(std::iter::repeat(len).take(repeat));
+        }
+        // There has to be a code for the end of the block
+        if lengths[256] == 0 {
+            return Err(Error::Invalid);
+        }
+        Ok((
+            Huffman::new(&lengths[..num_lits])?,
+            Huffman::new(&lengths[num_lits..])?,
+        ))
+    }
+
+    fn codes(
+        &mut self,
+        out: &mut Vec<u8>,
+        max_len: usize,
+        lits: &Huffman,
+        dists: &Huffman,
+    ) -> Result<()> {
+        loop {
+            let symbol = self.decode(lits)? as usize;
+            if symbol < 256 {
+                if out.len() == max_len {
+                    return Err(Error::Invalid);
+                }
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(Error::Invalid);
+            }
+            let len = LENGTH_BASE[symbol] as usize + self.bits(LENGTH_EXTRA[symbol])? as usize;
+            let symbol = self.decode(dists)? as usize;
+            if symbol >= DIST_BASE.len() {
+                return Err(Error::Invalid);
+            }
+            let dist = DIST_BASE[symbol] as usize + self.bits(DIST_EXTRA[symbol])? as usize;
+            if dist > out.len() || out.len() + len > max_len {
+                return Err(Error::Invalid);
+            }
+            // The copy may overlap with what it produces
+            let start = out.len() - dist;
+            for i in 0..len {
+                out.push(out[start + i]);
+            }
+        }
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+const DIST_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_block() {
+        let data = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn fixed_block() {
+        let data = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+        assert_eq!(inflate(&data, 12).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        let data = [
+            0x05, 0xc1, 0x01, 0x01, 0x00, 0x00, 0x00, 0x80, 0x90, 0xad, 0xfa, 0x3f, 0x22, 0x10,
+            0x42, 0x08, 0x0d,
+        ];
+        assert_eq!(inflate(&data, 20).unwrap(), b"aaab".repeat(5));
+    }
+
+    #[test]
+    fn zlib_stream() {
+        let data = [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(zlib_decompress(&data, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn over_subscribed_code() {
+        // Four code length codes of length 1
+        let data = [0x05, 0x00, 0x92, 0x04];
+        assert!(inflate(&data, 100).is_err());
+    }
+
+    #[test]
+    fn output_limit() {
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert!(inflate(&stored, 2).is_err());
+        let fixed = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+        assert!(inflate(&fixed, 11).is_err());
+    }
+}
@@ -2,13 +2,78 @@
 
 use fnv::FnvHashMap;
 
+use crate::common::{GlyphID, GlyphInfo};
 use crate::error::*;
-use crate::types::{get_i16, get_u16};
+use crate::options::FeatureValues;
+use crate::types::{get_i16, get_u16, Tag};
+
+/// Class table for format 2 subtables. Class values are byte offsets into the subtable
+#[derive(Debug)]
+struct ClassTable {
+    first_glyph: u16,
+    classes: Vec<u16>,
+}
+
+impl ClassTable {
+    fn load(data: &[u8]) -> Result<ClassTable> {
+        let first_glyph = get_u16(data, 0)?;
+        let num_glyphs = get_u16(data, 2)? as usize;
+        let mut classes = Vec::new();
+        for off in (4..4 + num_glyphs * 2).step_by(2) {
+            classes.push(get_u16(data, off)?);
+        }
+        Ok(ClassTable {
+            first_glyph,
+            classes,
+        })
+    }
+
+    fn class(&self, glyph: GlyphID) -> Option<usize> {
+        if glyph.0 < self.first_glyph as u32 {
+            None
+        } else {
+            let idx = (glyph.0 - self.first_glyph as u32) as usize;
+            self.classes.get(idx).map(|c| *c as usize)
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Format {
+    Format0(FnvHashMap<(u16, u16), i16>),
+    Format2 {
+        left: ClassTable,
+        right: ClassTable,
+        array_offset: usize,
+        data: Vec<u8>, // Raw subtable, since values are addressed by byte offset
+    },
+}
 
 #[derive(Debug)]
 struct Table {
     coverage: Coverage,
-    map: FnvHashMap<(u16, u16), i16>,
+    format: Format,
+}
+
+impl Table {
+    fn value(&self, left: GlyphID, right: GlyphID) -> Option<i16> {
+        match &self.format {
+            Format::Format0(map) => map.get(&(left.0 as u16, right.0 as u16)).copied(),
+            Format::Format2 {
+                left: left_classes,
+                right: right_classes,
+                array_offset,
+                data,
+            } => {
+                let off = left_classes.class(left)? + right_classes.class(right)?;
+                if off < *array_offset {
+                    None
+                } else {
+                    get_i16(data, off).ok()
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,25 +89,96 @@ impl Kern {
                 return Err(Error::Invalid);
             }
             let length = get_u16(data, off + 2)? as usize;
-            let coverage = Coverage::from_bits_truncate(data[5]);
-            let format = data[4];
-            if format != 0 {
-                // FIXME: format 2 unsupported
-                off += length;
+            let format = data[off + 4];
+            let coverage = Coverage::from_bits_truncate(data[off + 5]);
+            let format = match format {
+                0 => {
+                    let npairs = get_u16(data, off + 6)? as usize;
+                    let mut map = FnvHashMap::default();
+                    for rec_off in (off + 14..off + 14 + npairs * 6).step_by(6) {
+                        let left = get_u16(data, rec_off)?;
+                        let right = get_u16(data, rec_off + 2)?;
+                        let value = get_i16(data, rec_off + 4)?;
+                        map.insert((left, right), value);
+                    }
+                    Format::Format0(map)
+                }
+                2 => {
+                    if data.len() < off + length {
+                        return Err(Error::Invalid);
+                    }
+                    let data = &data[off..off + length];
+                    let left_off = get_u16(data, 8)? as usize;
+                    let right_off = get_u16(data, 10)? as usize;
+                    let array_offset = get_u16(data, 12)? as usize;
+                    Format::Format2 {
+                        left: ClassTable::load(data.get(left_off..).ok_or(Error::Invalid)?)?,
+                        right: ClassTable::load(data.get(right_off..).ok_or(Error::Invalid)?)?,
+                        array_offset,
+                        data: data.to_vec(),
+                    }
+                }
+                _ => {
+                    off += length;
+                    continue;
+                }
+            };
+            tables.push(Table { coverage, format });
+            off += length;
+        }
+        Ok(Kern(tables))
+    }
+
+    /// Apply kerning between adjacent glyphs, where the feature is enabled for the first glyph
+    pub(crate) fn apply(
+        &self,
+        glyphs: &mut [GlyphInfo],
+        horizontal: bool,
+        feature_values: &FeatureValues,
+        feature: Tag,
+    ) {
+        for i in 1..glyphs.len() {
+            if feature_values.value(&[feature], glyphs[i - 1].cluster, glyphs[i - 1].features) == 0
+            {
                 continue;
             }
-            let npairs = get_u16(data, off + 6)? as usize;
-            let mut map = FnvHashMap::default();
-            for rec_off in (off + 14..off + 14 + npairs * 6).step_by(6) {
-                let left = get_u16(data, rec_off)?;
-                let right = get_u16(data, rec_off + 2)?;
-                let value = get_i16(data, rec_off + 4)?;
-                map.insert((left, right), value);
+            let (left, right) = (glyphs[i - 1].glyph, glyphs[i].glyph);
+            let (mut along, mut across) = (0, 0);
+            for table in &self.0 {
+                if table.coverage.contains(Coverage::HORIZONTAL) != horizontal {
+                    continue;
+                }
+                let value = match table.value(left, right) {
+                    Some(value) => value as i32,
+                    None => continue,
+                };
+                let accum = if table.coverage.contains(Coverage::CROSS_STREAM) {
+                    &mut across
+                } else {
+                    &mut along
+                };
+                if table.coverage.contains(Coverage::MINIMUM) {
+                    // Value limits how far kerning accumulated so far may go
+                    *accum = if value < 0 {
+                        (*accum).max(value)
+                    } else {
+                        (*accum).min(value)
+                    };
+                } else if table.coverage.contains(Coverage::OVERRIDE) {
+                    *accum = value;
+                } else {
+                    *accum += value;
+                }
+            }
+            if horizontal {
+                glyphs[i - 1].advance.x += along;
+                glyphs[i].offset.y += across;
+            } else {
+                // Vertical text runs down
+                glyphs[i - 1].advance.y -= along;
+                glyphs[i].offset.x += across;
             }
-            tables.push(Table { coverage, map });
-            off += length;
         }
-        Ok(Kern(tables))
     }
 }
 
@@ -0,0 +1,381 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::types::Tag;
+
+macro_rules! languages {
+    ($pt:vis $name:ident { $( $feat:ident $stag:expr ),* } ) => {
+        #[derive(Clone, Copy, Debug)]
+        $pt enum $name {
+            $( $feat ),*
+        }
+
+        impl $name {
+            pub(crate) const fn tag(&self) -> Tag {
+                match self {
+                    $( $name::$feat => Tag::from($stag) ),*
+                }
+            }
+        }
+    };
+}
+
+languages!(pub Language {
+    Afrikaans                        b"AFK ",
+    Albanian                         b"SQI ",
+    Amharic                          b"AMH ",
+    Arabic                           b"ARA ",
+    Armenian                         b"HYE ",
+    Assamese                         b"ASM ",
+    Azerbaijani                      b"AZE ",
+    Bashkir                          b"BSH ",
+    Basque                           b"EUQ ",
+    Belarusian                       b"BEL ",
+    Bengali                          b"BEN ",
+    Bosnian                          b"BOS ",
+    Breton                           b"BRE ",
+    Bulgarian                        b"BGR ",
+    Burmese                          b"BRM ",
+    Catalan                          b"CAT ",
+    Cherokee                         b"CHR ",
+    ChineseHongKong                  b"ZHH ",
+    ChinesePhonetic                  b"ZHP ",
+    ChineseSimplified                b"ZHS ",
+    ChineseTraditional               b"ZHT ",
+    Chuvash                          b"CHU ",
+    CrimeanTatar                     b"CRT ",
+    Croatian                         b"HRV ",
+    Czech                            b"CSY ",
+    Danish                           b"DAN ",
+    Default                          b"dflt",
+    Dutch                            b"NLD ",
+    Dzongkha                         b"DZN ",
+    English                          b"ENG ",
+    Esperanto                        b"NTO ",
+    Estonian                         b"ETI ",
+    Faroese                          b"FOS ",
+    Farsi                            b"FAR ",
+    Finnish                          b"FIN ",
+    French                           b"FRA ",
+    Frisian                          b"FRI ",
+    Gagauz                           b"GAG ",
+    Galician                         b"GAL ",
+    Georgian                         b"KAT ",
+    German                           b"DEU ",
+    Greek                            b"ELL ",
+    Gujarati                         b"GUJ ",
+    Hausa                            b"HAU ",
+    Hebrew                           b"IWR ",
+    Hindi                            b"HIN ",
+    Hungarian                        b"HUN ",
+    Icelandic                        b"ISL ",
+    Igbo                             b"IBO ",
+    InariSami                        b"ISM ",
+    Indonesian                       b"IND ",
+    Irish                            b"IRI ",
+    Italian                          b"ITA ",
+    Japanese                         b"JAN ",
+    Javanese                         b"JAV ",
+    Kannada                          b"KAN ",
+    Kashmiri                         b"KSH ",
+    Kazakh                           b"KAZ ",
+    Khmer                            b"KHM ",
+    Kirghiz                          b"KIR ",
+    Konkani                          b"KOK ",
+    Korean                           b"KOR ",
+    Kurdish                          b"KUR ",
+    Lao                              b"LAO ",
+    Latin                            b"LAT ",
+    Latvian                          b"LVI ",
+    Lithuanian                       b"LTH ",
+    LuleSami                         b"LSM ",
+    Macedonian                       b"MKD ",
+    Maithili                         b"MTH ",
+    Malay                            b"MLY ",
+    Malayalam                        b"MAL ",
+    MalayalamReformed                b"MLR ",
+    Maltese                          b"MTS ",
+    Manipuri                         b"MNI ",
+    Maori                            b"MRI ",
+    Marathi                          b"MAR ",
+    Moldavian                        b"MOL ",
+    Mongolian                        b"MNG ",
+    Nepali                           b"NEP ",
+    NorthernSami                     b"NSM ",
+    Norwegian                        b"NOR ",
+    NorwegianNynorsk                 b"NYN ",
+    Odia                             b"ORI ",
+    Pashto                           b"PAS ",
+    Polish                           b"PLK ",
+    Portuguese                       b"PTG ",
+    Punjabi                          b"PAN ",
+    Romanian                         b"ROM ",
+    Romansh                          b"RMS ",
+    Russian                          b"RUS ",
+    Sanskrit                         b"SAN ",
+    Serbian                          b"SRB ",
+    Sindhi                           b"SND ",
+    Sinhala                          b"SNH ",
+    SkoltSami                        b"SKS ",
+    Slovak                           b"SKY ",
+    Slovenian                        b"SLV ",
+    Somali                           b"SML ",
+    SouthernSami                     b"SSM ",
+    Spanish                          b"ESP ",
+    Swahili                          b"SWK ",
+    Swedish                          b"SVE ",
+    Syriac                           b"SYR ",
+    Tagalog                          b"TGL ",
+    Tajik                            b"TAJ ",
+    Tamil                            b"TAM ",
+    Tatar                            b"TAT ",
+    Telugu                           b"TEL ",
+    Thai                             b"THA ",
+    Tibetan                          b"TIB ",
+    Tigrinya                         b"TGY ",
+    Turkish                          b"TRK ",
+    Turkmen                          b"TKM ",
+    Uyghur                           b"UYG ",
+    Ukrainian                        b"UKR ",
+    Urdu                             b"URD ",
+    Uzbek                            b"UZB ",
+    Vietnamese                       b"VIT ",
+    Welsh                            b"WEL ",
+    Yiddish                          b"JII ",
+    Yoruba                           b"YBA ",
+    Zulu                             b"ZUL "
+} );
+
+impl Language {
+    // Windows language ID (LCID) of names in this language in the `name` table
+    pub(crate) fn windows_id(&self) -> Option<u16> {
+        match self {
+            Language::Afrikaans => Some(0x0436),
+            Language::Albanian => Some(0x041c),
+            Language::Amharic => Some(0x045e),
+            Language::Arabic => Some(0x0401),
+            Language::Armenian => Some(0x042b),
+            Language::Assamese => Some(0x044d),
+            Language::Azerbaijani => Some(0x042c),
+            Language::Bashkir => Some(0x046d),
+            Language::Basque => Some(0x042d),
+            Language::Belarusian => Some(0x0423),
+            Language::Bengali => Some(0x0445),
+            Language::Bosnian => Some(0x141a),
+            Language::Breton => Some(0x047e),
+            Language::Bulgarian => Some(0x0402),
+            Language::Burmese => Some(0x0455),
+            Language::Catalan => Some(0x0403),
+            Language::Cherokee => Some(0x045c),
+            Language::ChineseHongKong => Some(0x0c04),
+            Language::ChineseSimplified => Some(0x0804),
+            Language::ChineseTraditional => Some(0x0404),
+            Language::Croatian => Some(0x041a),
+            Language::Czech => Some(0x0405),
+            Language::Danish => Some(0x0406),
+            Language::Dutch => Some(0x0413),
+            Language::English => Some(0x0409),
+            Language::Estonian => Some(0x0425),
+            Language::Faroese => Some(0x0438),
+            Language::Farsi => Some(0x0429),
+            Language::Finnish => Some(0x040b),
+            Language::French => Some(0x040c),
+            Language::Frisian => Some(0x0462),
+            Language::Galician => Some(0x0456),
+            Language::Georgian => Some(0x0437),
+            Language::German => Some(0x0407),
+            Language::Greek => Some(0x0408),
+            Language::Gujarati => Some(0x0447),
+            Language::Hausa => Some(0x0468),
+            Language::Hebrew => Some(0x040d),
+            Language::Hindi => Some(0x0439),
+            Language::Hungarian => Some(0x040e),
+            Language::Icelandic => Some(0x040f),
+            Language::Igbo => Some(0x0470),
+            Language::InariSami => Some(0x243b),
+            Language::Indonesian => Some(0x0421),
+            Language::Irish => Some(0x083c),
+            Language::Italian => Some(0x0410),
+            Language::Japanese => Some(0x0411),
+            Language::Kannada => Some(0x044b),
+            Language::Kashmiri => Some(0x0460),
+            Language::Kazakh => Some(0x043f),
+            Language::Khmer => Some(0x0453),
+            Language::Kirghiz => Some(0x0440),
+            Language::Konkani => Some(0x0457),
+            Language::Korean => Some(0x0412),
+            Language::Kurdish => Some(0x0492),
+            Language::Lao => Some(0x0454),
+            Language::Latvian => Some(0x0426),
+            Language::Lithuanian => Some(0x0427),
+            Language::LuleSami => Some(0x103b),
+            Language::Macedonian => Some(0x042f),
+            Language::Malay => Some(0x043e),
+            Language::Malayalam => Some(0x044c),
+            Language::MalayalamReformed => Some(0x044c),
+            Language::Maltese => Some(0x043a),
+            Language::Manipuri => Some(0x0458),
+            Language::Maori => Some(0x0481),
+            Language::Marathi => Some(0x044e),
+            Language::Moldavian => Some(0x0818),
+            Language::Mongolian => Some(0x0450),
+            Language::Nepali => Some(0x0461),
+            Language::NorthernSami => Some(0x043b),
+            Language::Norwegian => Some(0x0414),
+            Language::NorwegianNynorsk => Some(0x0814),
+            Language::Odia => Some(0x0448),
+            Language::Pashto => Some(0x0463),
+            Language::Polish => Some(0x0415),
+            Language::Portuguese => Some(0x0816),
+            Language::Punjabi => Some(0x0446),
+            Language::Romanian => Some(0x0418),
+            Language::Romansh => Some(0x0417),
+            Language::Russian => Some(0x0419),
+            Language::Sanskrit => Some(0x044f),
+            Language::Serbian => Some(0x0c1a),
+            Language::Sindhi => Some(0x0459),
+            Language::Sinhala => Some(0x045b),
+            Language::SkoltSami => Some(0x203b),
+            Language::Slovak => Some(0x041b),
+            Language::Slovenian => Some(0x0424),
+            Language::Somali => Some(0x0477),
+            Language::SouthernSami => Some(0x183b),
+            Language::Spanish => Some(0x0c0a),
+            Language::Swahili => Some(0x0441),
+            Language::Swedish => Some(0x041d),
+            Language::Syriac => Some(0x045a),
+            Language::Tagalog => Some(0x0464),
+            Language::Tajik => Some(0x0428),
+            Language::Tamil => Some(0x0449),
+            Language::Tatar => Some(0x0444),
+            Language::Telugu => Some(0x044a),
+            Language::Thai => Some(0x041e),
+            Language::Tibetan => Some(0x0451),
+            Language::Tigrinya => Some(0x0473),
+            Language::Turkish => Some(0x041f),
+            Language::Turkmen => Some(0x0442),
+            Language::Uyghur => Some(0x0480),
+            Language::Ukrainian => Some(0x0422),
+            Language::Urdu => Some(0x0420),
+            Language::Uzbek => Some(0x0443),
+            Language::Vietnamese => Some(0x042a),
+            Language::Welsh => Some(0x0452),
+            Language::Yiddish => Some(0x043d),
+            Language::Yoruba => Some(0x046a),
+            Language::Zulu => Some(0x0435),
+            Language::Default => Some(0x0409), // English (United States)
+            _ => None,
+        }
+    }
+
+    // Macintosh language code of names in this language in the `name` table
+    pub(crate) fn mac_id(&self) -> Option<u16> {
+        match self {
+            Language::English => Some(0),
+            Language::French => Some(1),
+            Language::German => Some(2),
+            Language::Italian => Some(3),
+            Language::Dutch => Some(4),
+            Language::Swedish => Some(5),
+            Language::Spanish => Some(6),
+            Language::Danish => Some(7),
+            Language::Portuguese => Some(8),
+            Language::Norwegian => Some(9),
+            Language::Hebrew => Some(10),
+            Language::Japanese => Some(11),
+            Language::Arabic => Some(12),
+            Language::Finnish => Some(13),
+            Language::Greek => Some(14),
+            Language::Icelandic => Some(15),
+            Language::Maltese => Some(16),
+            Language::Turkish => Some(17),
+            Language::Croatian => Some(18),
+            Language::ChineseTraditional => Some(19),
+            Language::Urdu => Some(20),
+            Language::Hindi => Some(21),
+            Language::Thai => Some(22),
+            Language::Korean => Some(23),
+            Language::Lithuanian => Some(24),
+            Language::Polish => Some(25),
+            Language::Hungarian => Some(26),
+            Language::Estonian => Some(27),
+            Language::Latvian => Some(28),
+            Language::NorthernSami => Some(29),
+            Language::Faroese => Some(30),
+            Language::Farsi => Some(31),
+            Language::Russian => Some(32),
+            Language::ChineseSimplified => Some(33),
+            Language::Irish => Some(35),
+            Language::Albanian => Some(36),
+            Language::Romanian => Some(37),
+            Language::Czech => Some(38),
+            Language::Slovak => Some(39),
+            Language::Slovenian => Some(40),
+            Language::Yiddish => Some(41),
+            Language::Serbian => Some(42),
+            Language::Macedonian => Some(43),
+            Language::Bulgarian => Some(44),
+            Language::Ukrainian => Some(45),
+            Language::Belarusian => Some(46),
+            Language::Uzbek => Some(47),
+            Language::Kazakh => Some(48),
+            Language::Azerbaijani => Some(49),
+            Language::Armenian => Some(51),
+            Language::Georgian => Some(52),
+            Language::Moldavian => Some(53),
+            Language::Kirghiz => Some(54),
+            Language::Tajik => Some(55),
+            Language::Turkmen => Some(56),
+            Language::Mongolian => Some(57),
+            Language::Pashto => Some(59),
+            Language::Kurdish => Some(60),
+            Language::Kashmiri => Some(61),
+            Language::Sindhi => Some(62),
+            Language::Tibetan => Some(63),
+            Language::Nepali => Some(64),
+            Language::Sanskrit => Some(65),
+            Language::Marathi => Some(66),
+            Language::Bengali => Some(67),
+            Language::Assamese => Some(68),
+            Language::Gujarati => Some(69),
+            Language::Punjabi => Some(70),
+            Language::Odia => Some(71),
+            Language::Malayalam => Some(72),
+            Language::MalayalamReformed => Some(72),
+            Language::Kannada => Some(73),
+            Language::Tamil => Some(74),
+            Language::Telugu => Some(75),
+            Language::Sinhala => Some(76),
+            Language::Burmese => Some(77),
+            Language::Khmer => Some(78),
+            Language::Lao => Some(79),
+            Language::Vietnamese => Some(80),
+            Language::Indonesian => Some(81),
+            Language::Tagalog => Some(82),
+            Language::Malay => Some(83),
+            Language::Amharic => Some(85),
+            Language::Tigrinya => Some(86),
+            Language::Somali => Some(88),
+            Language::Swahili => Some(89),
+            Language::Esperanto => Some(94),
+            Language::Welsh => Some(128),
+            Language::Basque => Some(129),
+            Language::Catalan => Some(130),
+            Language::Latin => Some(131),
+            Language::Tatar => Some(135),
+            Language::Uyghur => Some(136),
+            Language::Dzongkha => Some(137),
+            Language::Javanese => Some(138),
+            Language::Galician => Some(140),
+            Language::Afrikaans => Some(141),
+            Language::Breton => Some(142),
+            Language::Default => Some(0), // English
+            _ => None,
+        }
+    }
+}
+
+impl Default for Language {
+    fn default() -> Language {
+        Language::Default
+    }
+}
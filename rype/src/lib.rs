@@ -3,36 +3,77 @@
 #[macro_use]
 extern crate bitflags;
 
+mod arabic;
+mod autohint;
+mod avar;
+mod cache;
+mod cff;
 mod classdef;
 mod cmap;
+mod colr;
 mod common;
 mod coverage;
+mod cpal;
 mod ctx_lookup;
+mod device;
 mod direction;
+mod eblc;
 mod error;
 mod face;
 mod featurelist;
 mod features;
+mod fvar;
 mod gasp;
 mod gdef;
 mod glyf;
 mod gpos;
 mod gsub;
+mod gvar;
 mod head;
 mod hhea;
+mod hint;
 mod hmtx;
+mod hvar;
+mod indic;
+mod inflate;
 mod kern;
+mod language;
 mod loca;
 mod lookuplist;
 mod maxp;
+mod mvar;
+mod name;
+mod options;
 mod os2;
+mod outline;
+mod paint;
+mod png;
+mod post;
+mod raster;
+mod sbix;
 mod script;
 mod scriptlist;
+mod shaper;
+mod strike;
 mod types;
+mod universal;
+mod varstore;
+mod vhea;
+mod vmtx;
+mod vorg;
 
-pub use common::ScaledGlyphInfo;
+pub use cache::{AtlasPage, CachedGlyph, DirtyRegion, GlyphCache};
+pub use common::{FaceMetrics, GlyphID, ScaledGlyphInfo};
 pub use direction::Direction;
 pub use error::*;
 pub use face::Face;
 pub use features::Features;
+pub use fvar::{NamedInstance, VariationAxis};
+pub use hint::Hinting;
+pub use language::Language;
+pub use name::NameID;
+pub use options::ShapingOptions;
+pub use outline::{Outline, Segment};
+pub use paint::{Brush, Color, ColorStop, CompositeMode, Extend, PaintCommand, Transform};
+pub use raster::{Bitmap, BitmapFormat, LcdFilter, RenderMode, SubpixelOrder};
 pub use script::Script;
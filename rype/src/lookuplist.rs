@@ -1,12 +1,17 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
+use std::cell::Cell;
 use std::ops::Index;
 
 use crate::common::GlyphID;
 use crate::ctx_lookup::SequenceLookupRecord;
+use crate::device::DeviceContext;
+use crate::direction::Direction;
 use crate::error::*;
+use crate::features::GlyphFeatures;
 use crate::gdef::{Gdef, GlyphClass};
-use crate::types::get_u16;
+use crate::options::FeatureValues;
+use crate::types::{get_u16, Tag};
 
 #[derive(Debug)]
 pub(crate) struct LookupList<T: LookupSubtable>(Vec<LookupTable<T>>);
@@ -17,7 +22,9 @@ impl<T: LookupSubtable> LookupList<T> {
         let mut tables = Vec::new();
         for off in (2..2 + count * 2).step_by(2) {
             let offset = get_u16(data, off)? as usize;
-            tables.push(LookupTable::load(&data[offset..])?);
+            tables.push(LookupTable::load(
+                data.get(offset..).ok_or(Error::Invalid)?,
+            )?);
         }
         Ok(LookupList(tables))
     }
@@ -52,7 +59,10 @@ impl<T: LookupSubtable> LookupTable<T> {
         let count = get_u16(data, 4)? as usize;
         for off in (6..6 + count * 2).step_by(2) {
             let offset = get_u16(data, off)? as usize;
-            subtables.push(T::load(&data[offset..], lookup_type)?);
+            subtables.push(T::load(
+                data.get(offset..).ok_or(Error::Invalid)?,
+                lookup_type,
+            )?);
         }
         let mark_filtering_set = if lookup_flag.contains(LookupFlag::USE_MARK_FILTERING_SET) {
             Some(get_u16(data, 6 + count * 2)?)
@@ -68,17 +78,26 @@ impl<T: LookupSubtable> LookupTable<T> {
         })
     }
 
+    /// Apply lookup to glyphs for which any of the given features are enabled
     pub(crate) fn apply(
         &self,
         glyphs: &mut Vec<T::GlyphData>,
-        gdef: Option<&Gdef>,
         lookups: &LookupList<T>,
+        run: &RunContext,
+        features: &[Tag],
     ) {
+        // Ligatures formed by this lookup are numbered after those formed earlier
+        let last_ligature_id = glyphs.iter().map(|g| g.ligature_id()).max().unwrap_or(0);
+        let next_ligature_id = Cell::new(last_ligature_id.checked_add(1).unwrap_or(1));
+        let mut ctx = self.context(run.gdef, run.device, run.direction, &next_ligature_id, 0);
         // Go over all current glyphs
         let mut i = 0;
         while i < glyphs.len() {
             let g = glyphs[i].glyph();
-            if !self.is_applicable_for_glyph(g, gdef) {
+            ctx.feature_value =
+                run.feature_values
+                    .value(features, glyphs[i].cluster(), glyphs[i].features());
+            if ctx.skip(g) || ctx.feature_value == 0 {
                 i += 1;
                 continue;
             }
@@ -86,21 +105,13 @@ impl<T: LookupSubtable> LookupTable<T> {
             // Is this a recursive lookup?
             let mut applied = false;
             if T::is_recursive(self.lookup_type) {
-                for subtable in &self.subtables {
-                    if let Some((records, len)) = subtable.apply_recursive(glyphs, i) {
-                        for record in records {
-                            let lookup = &lookups[record.lookup_list_index as usize];
-                            let idx = i + record.sequence_index as usize;
-                            assert!(idx < glyphs.len(), "index out of bounds");
-                            lookup.apply_recursive(glyphs, idx, gdef);
-                        }
-                        applied = true;
-                        i += len;
-                    }
+                if let Some(len) = self.apply_nested(glyphs, i, lookups, &ctx, 0) {
+                    applied = true;
+                    i += len;
                 }
             } else {
                 for subtable in &self.subtables {
-                    if let Some(len) = subtable.apply(glyphs, i) {
+                    if let Some(len) = subtable.apply(glyphs, i, &ctx) {
                         i += len;
                         applied = true;
                         break;
@@ -113,25 +124,143 @@ impl<T: LookupSubtable> LookupTable<T> {
         }
     }
 
-    fn apply_recursive(&self, glyphs: &mut Vec<T::GlyphData>, idx: usize, gdef: Option<&Gdef>) {
+    // Apply the nested lookups of the first subtable whose context matches at the index, and get
+    // the length of the matched sequence
+    fn apply_nested(
+        &self,
+        glyphs: &mut Vec<T::GlyphData>,
+        idx: usize,
+        lookups: &LookupList<T>,
+        ctx: &LookupContext,
+        depth: usize,
+    ) -> Option<usize> {
+        for subtable in &self.subtables {
+            if let Some((records, len)) = subtable.apply_recursive(glyphs, idx) {
+                let num_glyphs = glyphs.len();
+                for record in records {
+                    let lookup = match lookups.0.get(record.lookup_list_index as usize) {
+                        Some(lookup) => lookup,
+                        None => continue,
+                    };
+                    let idx = idx + record.sequence_index as usize;
+                    // Earlier records may have formed ligatures, leaving fewer glyphs
+                    if idx >= glyphs.len() {
+                        continue;
+                    }
+                    lookup.apply_recursive(glyphs, idx, lookups, ctx, depth + 1);
+                }
+                // The matched sequence shrinks or grows along with the glyphs
+                return Some((len + glyphs.len()).saturating_sub(num_glyphs).max(1));
+            }
+        }
+        None
+    }
+
+    fn apply_recursive(
+        &self,
+        glyphs: &mut Vec<T::GlyphData>,
+        idx: usize,
+        lookups: &LookupList<T>,
+        parent: &LookupContext,
+        depth: usize,
+    ) {
+        // Contextual lookups may be nested, possibly in a cycle
+        if depth > MAX_NESTING {
+            return;
+        }
+        let ctx = self.context(
+            parent.gdef,
+            parent.device,
+            parent.direction,
+            parent.next_ligature_id,
+            parent.feature_value,
+        );
         let g = glyphs[idx].glyph();
-        if !self.is_applicable_for_glyph(g, gdef) {
+        if ctx.skip(g) {
             return;
         }
         // Is this a recursive lookup?
         if T::is_recursive(self.lookup_type) {
-            panic!("We should not get here");
+            self.apply_nested(glyphs, idx, lookups, &ctx, depth);
         } else {
             for subtable in &self.subtables {
-                if subtable.apply(glyphs, idx).is_some() {
+                if subtable.apply(glyphs, idx, &ctx).is_some() {
                     return;
                 }
             }
         }
     }
 
-    fn is_applicable_for_glyph(&self, glyph: GlyphID, gdef: Option<&Gdef>) -> bool {
-        let glyph_class = gdef.and_then(|gdef| gdef.glyph_class(glyph));
+    pub(crate) fn subtables(&self) -> &[T] {
+        &self.subtables
+    }
+
+    fn context<'a>(
+        &self,
+        gdef: Option<&'a Gdef>,
+        device: Option<DeviceContext<'a>>,
+        direction: Direction,
+        next_ligature_id: &'a Cell<u16>,
+        feature_value: u32,
+    ) -> LookupContext<'a> {
+        LookupContext {
+            direction,
+            next_ligature_id,
+            lookup_flag: self.lookup_flag,
+            mark_attachment_type_mask: self.mark_attachment_type_mask,
+            mark_filtering_set: self.mark_filtering_set,
+            gdef,
+            device,
+            feature_value,
+        }
+    }
+}
+
+/// State shared by all lookups applied to a run of glyphs
+pub(crate) struct RunContext<'a> {
+    pub(crate) gdef: Option<&'a Gdef>,
+    /// Size and variation instance for device adjustments, when positioning
+    pub(crate) device: Option<DeviceContext<'a>>,
+    pub(crate) direction: Direction,
+    pub(crate) feature_values: &'a FeatureValues,
+}
+
+/// Lookup state made available to subtables while they're being applied
+pub(crate) struct LookupContext<'a> {
+    /// Direction of the text. Glyphs are always in logical order while lookups are applied
+    pub(crate) direction: Direction,
+    lookup_flag: LookupFlag,
+    mark_attachment_type_mask: u8,
+    mark_filtering_set: Option<u16>,
+    gdef: Option<&'a Gdef>,
+    /// Size and variation instance for device adjustments, when positioning
+    pub(crate) device: Option<DeviceContext<'a>>,
+    next_ligature_id: &'a Cell<u16>,
+    /// Value of the feature being applied, for the current glyph. Selects alternates
+    pub(crate) feature_value: u32,
+}
+
+impl<'a> LookupContext<'a> {
+    /// Does the lookup flag cursive attachments as right-to-left?
+    pub(crate) fn right_to_left(&self) -> bool {
+        self.lookup_flag.contains(LookupFlag::RIGHT_TO_LEFT)
+    }
+
+    /// Get a new ID for a ligature
+    pub(crate) fn ligature_id(&self) -> u16 {
+        let id = self.next_ligature_id.get();
+        self.next_ligature_id.set(id.checked_add(1).unwrap_or(1));
+        id
+    }
+
+    /// Get GDEF class for glyph, if any
+    pub(crate) fn glyph_class(&self, glyph: GlyphID) -> Option<GlyphClass> {
+        self.gdef.and_then(|gdef| gdef.glyph_class(glyph))
+    }
+
+    /// Should this glyph be skipped over, as per the lookup flags?
+    pub(crate) fn skip(&self, glyph: GlyphID) -> bool {
+        let glyph_class = self.glyph_class(glyph);
         // Check lookup applicability for glyph class
         if self.lookup_flag.contains(LookupFlag::IGNORE_BASE_GLYPHS)
             && glyph_class == Some(GlyphClass::Base)
@@ -140,37 +269,49 @@ impl<T: LookupSubtable> LookupTable<T> {
             || self.lookup_flag.contains(LookupFlag::IGNORE_MARKS)
                 && glyph_class == Some(GlyphClass::Mark)
         {
-            return false;
+            return true;
         }
         // If glyph is a mark, apply mark filtering if required
         if glyph_class == Some(GlyphClass::Mark) {
-            if self.mark_attachment_type_mask != 0 {
-                if let Some(mark_attachment) =
-                    gdef.and_then(|gdef| gdef.mark_attachment_class(glyph))
-                {
-                    if mark_attachment == self.mark_attachment_type_mask as u32 {
-                        return false;
-                    }
-                }
+            // Marks without an attachment class don't match any type
+            if self.mark_attachment_type_mask != 0
+                && self.gdef.and_then(|gdef| gdef.mark_attachment_class(glyph))
+                    != Some(self.mark_attachment_type_mask as u32)
+            {
+                return true;
             }
             if self
                 .lookup_flag
                 .contains(LookupFlag::USE_MARK_FILTERING_SET)
             {
                 if let Some(idx) = self.mark_filtering_set {
-                    if !gdef
+                    if !self
+                        .gdef
                         .map(|gdef| gdef.glyph_in_mark_set(idx as usize, glyph))
                         .unwrap_or(true)
                     {
-                        return false;
+                        return true;
                     }
                 }
             }
         }
-        true
+        false
+    }
+
+    /// Index of the next glyph after `idx` which isn't skipped
+    pub(crate) fn next<G: GlyphData>(&self, glyphs: &[G], idx: usize) -> Option<usize> {
+        (idx + 1..glyphs.len()).find(|i| !self.skip(glyphs[*i].glyph()))
+    }
+
+    /// Index of the previous glyph before `idx` which isn't skipped
+    pub(crate) fn prev<G: GlyphData>(&self, glyphs: &[G], idx: usize) -> Option<usize> {
+        (0..idx).rev().find(|i| !self.skip(glyphs[*i].glyph()))
     }
 }
 
+// Limit on how deeply contextual lookups can be nested
+const MAX_NESTING: usize = 6;
+
 bitflags! {
     pub(crate) struct LookupFlag : u8 {
         const RIGHT_TO_LEFT          = 0x01;
@@ -183,6 +324,9 @@ bitflags! {
 
 pub(crate) trait GlyphData: std::fmt::Debug {
     fn glyph(&self) -> GlyphID;
+    fn cluster(&self) -> usize;
+    fn features(&self) -> GlyphFeatures;
+    fn ligature_id(&self) -> u16;
 }
 
 pub(crate) trait LookupSubtable: Sized + std::fmt::Debug {
@@ -190,7 +334,12 @@ pub(crate) trait LookupSubtable: Sized + std::fmt::Debug {
 
     fn is_recursive(lookup_type: u16) -> bool;
     fn load(data: &[u8], lookup_type: u16) -> Result<Self>;
-    fn apply(&self, glyph_seq: &mut Vec<Self::GlyphData>, cur_idx: usize) -> Option<usize>;
+    fn apply(
+        &self,
+        glyph_seq: &mut Vec<Self::GlyphData>,
+        cur_idx: usize,
+        ctx: &LookupContext,
+    ) -> Option<usize>;
     fn apply_recursive(
         &self,
         glyph_seq: &[Self::GlyphData],
@@ -1,21 +1,46 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
 use crate::error::Result;
-use crate::types::get_u16;
+use crate::types::{get_u16, get_u32};
 
 /// Wrapper around Maximum Profile table
 #[derive(Debug)]
 pub(crate) struct Maxp {
-    pub(crate) num_glyphs: u16, // Number of glyphs in the font
+    pub(crate) num_glyphs: u16,   // Number of glyphs in the font
+    pub(crate) max_twilight: u16, // Points in the twilight zone
+    pub(crate) max_storage: u16,  // Storage area locations
+    pub(crate) max_function_defs: u16,
+    pub(crate) max_stack: u16, // Stack depth of TrueType programs
 }
 
 impl Maxp {
     pub(crate) fn load(data: &[u8]) -> Result<Maxp> {
         let num_glyphs = get_u16(data, offsets::NUM_GLYPHS)?;
-        Ok(Maxp { num_glyphs })
+        // Only version 1.0, used with TrueType outlines, has the limits for instructions
+        if get_u32(data, offsets::VERSION)? < 0x00010000 {
+            return Ok(Maxp {
+                num_glyphs,
+                max_twilight: 0,
+                max_storage: 0,
+                max_function_defs: 0,
+                max_stack: 0,
+            });
+        }
+        Ok(Maxp {
+            num_glyphs,
+            max_twilight: get_u16(data, offsets::MAX_TWILIGHT)?,
+            max_storage: get_u16(data, offsets::MAX_STORAGE)?,
+            max_function_defs: get_u16(data, offsets::MAX_FUNCTION_DEFS)?,
+            max_stack: get_u16(data, offsets::MAX_STACK)?,
+        })
     }
 }
 
 mod offsets {
+    pub(super) const VERSION: usize = 0;
     pub(super) const NUM_GLYPHS: usize = 4;
+    pub(super) const MAX_TWILIGHT: usize = 16;
+    pub(super) const MAX_STORAGE: usize = 18;
+    pub(super) const MAX_FUNCTION_DEFS: usize = 20;
+    pub(super) const MAX_STACK: usize = 24;
 }
@@ -0,0 +1,56 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::types::{get_tag, get_u16, Tag};
+use crate::varstore::ItemVariationStore;
+
+/// Wrapper around metrics variations table, which varies face-wide metrics like the x-height
+#[derive(Debug)]
+pub(crate) struct Mvar {
+    store: Option<ItemVariationStore>,
+    records: Vec<(Tag, u16, u16)>, // Metric tag, outer and inner delta set indices
+}
+
+impl Mvar {
+    pub(crate) fn load(data: &[u8]) -> Result<Mvar> {
+        let record_size = get_u16(data, offsets::VALUE_RECORD_SIZE)? as usize;
+        let record_count = get_u16(data, offsets::VALUE_RECORD_COUNT)? as usize;
+        let store = match get_u16(data, offsets::ITEM_VARIATION_STORE)? as usize {
+            0 => None,
+            off => Some(ItemVariationStore::load(
+                data.get(off..).ok_or(Error::Invalid)?,
+            )?),
+        };
+        let records = (0..record_count)
+            .map(|i| {
+                let off = offsets::VALUE_RECORDS + i * record_size;
+                Ok((
+                    get_tag(data, off)?,
+                    get_u16(data, off + 4)?,
+                    get_u16(data, off + 6)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Mvar { store, records })
+    }
+
+    /// Get change in metric, in font units
+    pub(crate) fn delta(&self, tag: Tag, coords: &[f32]) -> f32 {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return 0.0,
+        };
+        self.records
+            .iter()
+            .find(|(t, _, _)| *t == tag)
+            .and_then(|(_, outer, inner)| store.delta(*outer, *inner, coords).ok())
+            .unwrap_or(0.0)
+    }
+}
+
+mod offsets {
+    pub(super) const VALUE_RECORD_SIZE: usize = 6;
+    pub(super) const VALUE_RECORD_COUNT: usize = 8;
+    pub(super) const ITEM_VARIATION_STORE: usize = 10;
+    pub(super) const VALUE_RECORDS: usize = 12;
+}
@@ -0,0 +1,264 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::types::get_u16;
+use crate::Language;
+
+/// Identifies a string in the `name` table. Name IDs from 256 onwards are font-specific, e.g. for
+/// variation axes and named instances
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NameID(pub u16);
+
+impl NameID {
+    pub const COPYRIGHT: NameID = NameID(0);
+    pub const FAMILY: NameID = NameID(1);
+    pub const SUBFAMILY: NameID = NameID(2);
+    pub const UNIQUE_ID: NameID = NameID(3);
+    pub const FULL_NAME: NameID = NameID(4);
+    pub const VERSION: NameID = NameID(5);
+    pub const POSTSCRIPT_NAME: NameID = NameID(6);
+    pub const TRADEMARK: NameID = NameID(7);
+    pub const MANUFACTURER: NameID = NameID(8);
+    pub const DESIGNER: NameID = NameID(9);
+    pub const DESCRIPTION: NameID = NameID(10);
+    pub const VENDOR_URL: NameID = NameID(11);
+    pub const DESIGNER_URL: NameID = NameID(12);
+    pub const LICENSE: NameID = NameID(13);
+    pub const LICENSE_URL: NameID = NameID(14);
+    /// Family name for more than the four styles the family name allows (regular, italic, bold,
+    /// bold italic)
+    pub const TYPOGRAPHIC_FAMILY: NameID = NameID(16);
+    pub const TYPOGRAPHIC_SUBFAMILY: NameID = NameID(17);
+    pub const SAMPLE_TEXT: NameID = NameID(19);
+    pub const VARIATIONS_POSTSCRIPT_PREFIX: NameID = NameID(25);
+}
+
+/// Wrapper around naming table. Only records with encodings we can decode are kept
+#[derive(Debug)]
+pub(crate) struct Name(Vec<NameRecord>);
+
+#[derive(Debug)]
+struct NameRecord {
+    platform: u16,
+    language: u16,
+    name_id: u16,
+    string: String,
+}
+
+impl Name {
+    pub(crate) fn load(data: &[u8]) -> Result<Name> {
+        let count = get_u16(data, offsets::COUNT)? as usize;
+        let storage = get_u16(data, offsets::STORAGE_OFFSET)? as usize;
+        let mut records = Vec::with_capacity(count);
+        for i in 0..count {
+            let record = offsets::NAME_RECORDS + i * sizes::NAME_RECORD;
+            let platform = get_u16(data, record + offsets::PLATFORM_ID)?;
+            let encoding = get_u16(data, record + offsets::ENCODING_ID)?;
+            let len = get_u16(data, record + offsets::LENGTH)? as usize;
+            let start = storage + get_u16(data, record + offsets::STRING_OFFSET)? as usize;
+            // Skip records pointing outside the table rather than losing every name
+            let bytes = match data.get(start..start + len) {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            let string = match (platform, encoding) {
+                // Windows symbol, Unicode BMP and full Unicode encodings are all UTF-16
+                (platform::UNICODE, _)
+                | (platform::WINDOWS, 0)
+                | (platform::WINDOWS, 1)
+                | (platform::WINDOWS, 10) => decode_utf16be(bytes),
+                (platform::MACINTOSH, 0) => decode_mac_roman(bytes),
+                _ => continue,
+            };
+            records.push(NameRecord {
+                platform,
+                language: get_u16(data, record + offsets::LANGUAGE_ID)?,
+                name_id: get_u16(data, record + offsets::NAME_ID)?,
+                string,
+            });
+        }
+        Ok(Name(records))
+    }
+
+    /// Get string in the given language, falling back to English, and then any language.
+    /// Windows records are preferred over Unicode ones, which are preferred over Macintosh ones
+    pub(crate) fn get(&self, name_id: NameID, language: Language) -> Option<&str> {
+        let (windows, mac) = (language.windows_id(), language.mac_id());
+        let rank = |record: &NameRecord| {
+            let language_rank = match record.platform {
+                platform::WINDOWS if Some(record.language) == windows => 3,
+                // Same primary language, in a different region or script
+                platform::WINDOWS
+                    if windows.map(|w| w & PRIMARY_LANGUAGE_MASK)
+                        == Some(record.language & PRIMARY_LANGUAGE_MASK) =>
+                {
+                    2
+                }
+                platform::MACINTOSH if Some(record.language) == mac => 3,
+                platform::WINDOWS if record.language & PRIMARY_LANGUAGE_MASK == ENGLISH => 1,
+                platform::MACINTOSH if record.language == 0 => 1,
+                _ => 0,
+            };
+            let platform_rank = match record.platform {
+                platform::WINDOWS => 2,
+                platform::UNICODE => 1,
+                _ => 0,
+            };
+            (language_rank, platform_rank)
+        };
+        self.0
+            .iter()
+            .filter(|r| r.name_id == name_id.0)
+            .max_by_key(|r| rank(r))
+            .map(|r| r.string.as_str())
+    }
+}
+
+fn decode_utf16be(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]));
+    std::char::decode_utf16(units)
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn decode_mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            0..=0x7f => *b as char,
+            _ => MAC_ROMAN[*b as usize - 0x80],
+        })
+        .collect()
+}
+
+// Windows language IDs have the primary language in the low bits, and the region above it
+const PRIMARY_LANGUAGE_MASK: u16 = 0x3ff;
+const ENGLISH: u16 = 0x09;
+
+mod platform {
+    pub(super) const UNICODE: u16 = 0;
+    pub(super) const MACINTOSH: u16 = 1;
+    pub(super) const WINDOWS: u16 = 3;
+}
+
+// Characters for bytes 0x80 to 0xff in the Mac OS Roman encoding
+const MAC_ROMAN: [char; 128] = [
+    '\u{00c4}', '\u{00c5}', '\u{00c7}', '\u{00c9}', '\u{00d1}', '\u{00d6}', '\u{00dc}', '\u{00e1}',
+    '\u{00e0}', '\u{00e2}', '\u{00e4}', '\u{00e3}', '\u{00e5}', '\u{00e7}', '\u{00e9}', '\u{00e8}',
+    '\u{00ea}', '\u{00eb}', '\u{00ed}', '\u{00ec}', '\u{00ee}', '\u{00ef}', '\u{00f1}', '\u{00f3}',
+    '\u{00f2}', '\u{00f4}', '\u{00f6}', '\u{00f5}', '\u{00fa}', '\u{00f9}', '\u{00fb}', '\u{00fc}',
+    '\u{2020}', '\u{00b0}', '\u{00a2}', '\u{00a3}', '\u{00a7}', '\u{2022}', '\u{00b6}', '\u{00df}',
+    '\u{00ae}', '\u{00a9}', '\u{2122}', '\u{00b4}', '\u{00a8}', '\u{2260}', '\u{00c6}', '\u{00d8}',
+    '\u{221e}', '\u{00b1}', '\u{2264}', '\u{2265}', '\u{00a5}', '\u{00b5}', '\u{2202}', '\u{2211}',
+    '\u{220f}', '\u{03c0}', '\u{222b}', '\u{00aa}', '\u{00ba}', '\u{03a9}', '\u{00e6}', '\u{00f8}',
+    '\u{00bf}', '\u{00a1}', '\u{00ac}', '\u{221a}', '\u{0192}', '\u{2248}', '\u{2206}', '\u{00ab}',
+    '\u{00bb}', '\u{2026}', '\u{00a0}', '\u{00c0}', '\u{00c3}', '\u{00d5}', '\u{0152}', '\u{0153}',
+    '\u{2013}', '\u{2014}', '\u{201c}', '\u{201d}', '\u{2018}', '\u{2019}', '\u{00f7}', '\u{25ca}',
+    '\u{00ff}', '\u{0178}', '\u{2044}', '\u{20ac}', '\u{2039}', '\u{203a}', '\u{fb01}', '\u{fb02}',
+    '\u{2021}', '\u{00b7}', '\u{201a}', '\u{201e}', '\u{2030}', '\u{00c2}', '\u{00ca}', '\u{00c1}',
+    '\u{00cb}', '\u{00c8}', '\u{00cd}', '\u{00ce}', '\u{00cf}', '\u{00cc}', '\u{00d3}', '\u{00d4}',
+    '\u{f8ff}', '\u{00d2}', '\u{00da}', '\u{00db}', '\u{00d9}', '\u{0131}', '\u{02c6}', '\u{02dc}',
+    '\u{00af}', '\u{02d8}', '\u{02d9}', '\u{02da}', '\u{00b8}', '\u{02dd}', '\u{02db}', '\u{02c7}',
+];
+
+mod offsets {
+    pub(super) const COUNT: usize = 2;
+    pub(super) const STORAGE_OFFSET: usize = 4;
+    pub(super) const NAME_RECORDS: usize = 6;
+
+    pub(super) const PLATFORM_ID: usize = 0;
+    pub(super) const ENCODING_ID: usize = 2;
+    pub(super) const LANGUAGE_ID: usize = 4;
+    pub(super) const NAME_ID: usize = 6;
+    pub(super) const LENGTH: usize = 8;
+    pub(super) const STRING_OFFSET: usize = 10;
+}
+
+mod sizes {
+    pub(super) const NAME_RECORD: usize = 12;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a name table from (platform, encoding, language, name ID, string bytes) records
+    fn table(records: &[(u16, u16, u16, u16, &[u8])]) -> Vec<u8> {
+        let storage = offsets::NAME_RECORDS + records.len() * sizes::NAME_RECORD;
+        let mut data = Vec::new();
+        for v in [0, records.len() as u16, storage as u16] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        let mut strings = Vec::new();
+        for (platform, encoding, language, name_id, bytes) in records {
+            let fields = [
+                *platform,
+                *encoding,
+                *language,
+                *name_id,
+                bytes.len() as u16,
+                strings.len() as u16,
+            ];
+            for v in fields {
+                data.extend_from_slice(&v.to_be_bytes());
+            }
+            strings.extend_from_slice(bytes);
+        }
+        data.extend_from_slice(&strings);
+        data
+    }
+
+    #[test]
+    fn decode() {
+        let utf16 = [0x00, 0x41, 0x00, 0xe9, 0xd8, 0x3d, 0xde, 0x00, 0xdc, 0x00];
+        let data = table(&[
+            (platform::WINDOWS, 1, 0x0409, 1, &utf16),
+            (platform::MACINTOSH, 0, 0, 2, b"Caf\x8e \xa5"),
+        ]);
+        let name = Name::load(&data).unwrap();
+        assert_eq!(
+            name.get(NameID::FAMILY, Language::English),
+            Some("A\u{e9}\u{1f600}\u{fffd}")
+        );
+        assert_eq!(
+            name.get(NameID::SUBFAMILY, Language::English),
+            Some("Caf\u{e9} \u{2022}")
+        );
+    }
+
+    #[test]
+    fn language_fallback() {
+        let data = table(&[
+            (platform::MACINTOSH, 0, 2, 1, b"Mac German"),
+            (platform::WINDOWS, 1, 0x0407, 1, &[0, b'D']),
+            (platform::WINDOWS, 1, 0x0c0c, 1, &[0, b'F']),
+            (platform::WINDOWS, 1, 0x0809, 1, &[0, b'E']),
+            (platform::WINDOWS, 1, 0x0411, 2, &[0, b'J']),
+        ]);
+        let name = Name::load(&data).unwrap();
+        // Exact language, preferring Windows records
+        assert_eq!(name.get(NameID::FAMILY, Language::German), Some("D"));
+        // Same language in another region
+        assert_eq!(name.get(NameID::FAMILY, Language::French), Some("F"));
+        // English, and then any language
+        assert_eq!(name.get(NameID::FAMILY, Language::Dutch), Some("E"));
+        assert_eq!(name.get(NameID::SUBFAMILY, Language::Dutch), Some("J"));
+        assert_eq!(name.get(NameID::FULL_NAME, Language::Dutch), None);
+    }
+
+    #[test]
+    fn out_of_range_string() {
+        let mut data = table(&[
+            (platform::WINDOWS, 1, 0x0409, 1, &[0, b'A']),
+            (platform::WINDOWS, 1, 0x0409, 2, &[0, b'B']),
+        ]);
+        // Point the first record's string past the end of the table
+        let at = offsets::NAME_RECORDS + offsets::STRING_OFFSET;
+        data[at..at + 2].copy_from_slice(&0x100u16.to_be_bytes());
+        let name = Name::load(&data).unwrap();
+        assert_eq!(name.get(NameID::FAMILY, Language::English), None);
+        assert_eq!(name.get(NameID::SUBFAMILY, Language::English), Some("B"));
+    }
+}
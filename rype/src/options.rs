@@ -0,0 +1,151 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::ops::Range;
+
+use crate::direction::Direction;
+use crate::features::*;
+use crate::types::Tag;
+use crate::{Language, Script};
+
+#[derive(Clone, Debug)]
+struct FeatureSetting {
+    tag: Tag,
+    value: u32,
+    range: Option<Range<usize>>,
+}
+
+/// Options to control shaping
+#[derive(Clone, Debug)]
+pub struct ShapingOptions {
+    pub(crate) script: Script,
+    pub(crate) language: Language,
+    pub(crate) direction: Direction,
+    features: Vec<FeatureSetting>,
+}
+
+impl ShapingOptions {
+    /// Shape with default features for script and direction
+    pub fn new(script: Script, direction: Direction) -> ShapingOptions {
+        ShapingOptions {
+            script,
+            language: Language::Default,
+            direction,
+            features: Vec::new(),
+        }
+    }
+
+    /// Shape with language-specific forms. Fonts without them fall back to the defaults
+    pub fn language(&mut self, language: Language) -> &mut ShapingOptions {
+        self.language = language;
+        self
+    }
+
+    /// Enable feature for the whole text
+    pub fn enable(&mut self, feature: Features) -> &mut ShapingOptions {
+        self.set(feature, 1, None)
+    }
+
+    /// Disable feature for the whole text
+    pub fn disable(&mut self, feature: Features) -> &mut ShapingOptions {
+        self.set(feature, 0, None)
+    }
+
+    /// Set value of feature for a range of characters, or the whole text if range is `None`. A
+    /// value of 0 disables the feature, 1 enables it, and larger values select alternate glyphs
+    /// for features that offer them. Later settings take precedence over earlier ones.
+    pub fn set(
+        &mut self,
+        feature: Features,
+        value: u32,
+        range: Option<Range<usize>>,
+    ) -> &mut ShapingOptions {
+        self.features.push(FeatureSetting {
+            tag: feature.tag(),
+            value,
+            range,
+        });
+        self
+    }
+
+    /// Resolve feature values, with defaults for the direction and the given shaper features
+    pub(crate) fn feature_values(&self, shaper_features: &[Features]) -> FeatureValues {
+        let defaults = DEFAULT_FEATURES
+            .iter()
+            .chain(self.direction.features())
+            .chain(shaper_features)
+            .map(|f| FeatureSetting {
+                tag: f.tag(),
+                value: 1,
+                range: None,
+            });
+        FeatureValues(defaults.chain(self.features.iter().cloned()).collect())
+    }
+}
+
+/// Feature values resolved for text being shaped
+pub(crate) struct FeatureValues(Vec<FeatureSetting>);
+
+impl FeatureValues {
+    /// Only the given feature, enabled for the whole text
+    pub(crate) fn single(tag: Tag) -> FeatureValues {
+        FeatureValues(vec![FeatureSetting {
+            tag,
+            value: 1,
+            range: None,
+        }])
+    }
+
+    /// Keep settings only for the given features
+    pub(crate) fn only(&self, tags: &[Tag]) -> FeatureValues {
+        FeatureValues(
+            self.0
+                .iter()
+                .filter(|s| tags.contains(&s.tag))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Keep settings for all but the given features
+    pub(crate) fn except(&self, tags: &[Tag]) -> FeatureValues {
+        FeatureValues(
+            self.0
+                .iter()
+                .filter(|s| !tags.contains(&s.tag))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Could the feature be enabled for any part of the text?
+    pub(crate) fn may_enable(&self, tag: Tag) -> bool {
+        self.0.iter().any(|s| s.tag == tag && s.value > 0)
+    }
+
+    /// Value for the glyph formed from the character at given index, taking the largest of the
+    /// given features' values. Features enabled per glyph are 0 unless set in `glyph_features`
+    pub(crate) fn value(&self, tags: &[Tag], cluster: usize, glyph_features: GlyphFeatures) -> u32 {
+        tags.iter()
+            .filter(|tag| {
+                GlyphFeatures::for_tag(**tag)
+                    .map(|f| glyph_features.contains(f))
+                    .unwrap_or(true)
+            })
+            .map(|tag| {
+                self.0
+                    .iter()
+                    .rev()
+                    .find(|s| {
+                        s.tag == *tag
+                            && s.range
+                                .as_ref()
+                                .map(|r| r.contains(&cluster))
+                                .unwrap_or(true)
+                    })
+                    .map(|s| s.value)
+                    .unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
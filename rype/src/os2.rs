@@ -45,20 +45,20 @@ pub(crate) struct Os2 {
     superscript_y_size: i16,
     superscript_x_offset: i16,
     superscript_y_offset: i16,
-    strikeout_size: i16,
-    strikeout_position: i16,
+    pub(crate) strikeout_size: i16,
+    pub(crate) strikeout_position: i16,
     family_class: i16,
     panose: [u8; 10],
     unicode_range: UnicodeRange,
     arch_vend_id: Tag,
     selection: Selection,
-    typo_ascender: i16,
-    type_descender: i16,
-    typo_line_gap: i16,
-    win_ascent: u16,
-    win_descent: u16,
-    x_height: Option<i16>,
-    cap_height: Option<i16>,
+    pub(crate) typo_ascender: i16,
+    pub(crate) typo_descender: i16,
+    pub(crate) typo_line_gap: i16,
+    pub(crate) win_ascent: u16,
+    pub(crate) win_descent: u16,
+    pub(crate) x_height: Option<i16>,
+    pub(crate) cap_height: Option<i16>,
     default_char: Option<u16>,
     break_char: Option<u16>,
     max_context: Option<u16>,
@@ -95,7 +95,7 @@ impl Os2 {
         let arch_vend_id = get_tag(data, 58)?;
         let selection = Selection::from_bits_truncate(get_u16(data, 62)?);
         let typo_ascender = get_i16(data, 68)?;
-        let type_descender = get_i16(data, 70)?;
+        let typo_descender = get_i16(data, 70)?;
         let typo_line_gap = get_i16(data, 72)?;
         let win_ascent = get_u16(data, 74)?;
         let win_descent = get_u16(data, 76)?;
@@ -136,7 +136,7 @@ impl Os2 {
             arch_vend_id,
             selection,
             typo_ascender,
-            type_descender,
+            typo_descender,
             typo_line_gap,
             win_ascent,
             win_descent,
@@ -149,4 +149,10 @@ impl Os2 {
             upper_optical_point_size,
         })
     }
+
+    /// Whether the typographic ascender, descender and line gap should be used for line layout,
+    /// instead of those in the hhea table
+    pub(crate) fn use_typo_metrics(&self) -> bool {
+        self.selection.contains(Selection::USE_TYPO_METRICS)
+    }
 }
@@ -0,0 +1,104 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{bbox, point2, BBox, Point2D, Size2D, Vector2D};
+
+use crate::raster::{self, Bitmap, RenderMode};
+
+/// Segment of a glyph outline. Each contour starts with a `MoveTo`
+#[derive(Clone, Copy, Debug)]
+pub enum Segment {
+    MoveTo(Point2D<f32>),
+    LineTo(Point2D<f32>),
+    QuadTo(Point2D<f32>, Point2D<f32>), // Control point, end point
+    CubicTo(Point2D<f32>, Point2D<f32>, Point2D<f32>), // Control points, end point
+}
+
+/// Outline of a glyph, as a sequence of closed contours. The y-axis points up
+#[derive(Clone, Debug, Default)]
+pub struct Outline(Vec<Segment>);
+
+impl Outline {
+    /// Get segments making up the outline. Contours are implicitly closed before each `MoveTo`,
+    /// and at the end of the outline
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    pub(crate) fn move_to(&mut self, p: Point2D<f32>) {
+        self.0.push(Segment::MoveTo(p));
+    }
+
+    pub(crate) fn line_to(&mut self, p: Point2D<f32>) {
+        self.0.push(Segment::LineTo(p));
+    }
+
+    pub(crate) fn quad_to(&mut self, c: Point2D<f32>, p: Point2D<f32>) {
+        self.0.push(Segment::QuadTo(c, p));
+    }
+
+    pub(crate) fn cubic_to(&mut self, c1: Point2D<f32>, c2: Point2D<f32>, p: Point2D<f32>) {
+        self.0.push(Segment::CubicTo(c1, c2, p));
+    }
+
+    /// Append contours of another outline, moved by the given offset
+    pub(crate) fn append(&mut self, other: &Outline, offset: Vector2D<f32>) {
+        self.0
+            .extend(other.0.iter().map(|seg| seg.map_points(|p| p + offset)));
+    }
+
+    pub(crate) fn scale(&self, scale: Size2D<f32>) -> Outline {
+        self.map_points(|p| point2(p.x * scale.width, p.y * scale.height))
+    }
+
+    /// Apply a function to all points, including control points
+    pub(crate) fn map_points<F: Fn(Point2D<f32>) -> Point2D<f32>>(&self, f: F) -> Outline {
+        Outline(self.0.iter().map(|seg| seg.map_points(&f)).collect())
+    }
+
+    /// Rasterize outline into a coverage bitmap, after moving it by the given (subpixel) offset
+    pub fn rasterize(&self, offset: Vector2D<f32>, mode: RenderMode) -> Bitmap {
+        raster::rasterize(self, offset, mode, true)
+    }
+
+    /// Get bounding box of all points, including control points, rounded outwards
+    pub(crate) fn control_bbox(&self) -> BBox<i16> {
+        match self.control_bounds() {
+            Some((min, max)) => bbox(
+                point2(min.x.floor() as i16, min.y.floor() as i16),
+                point2(max.x.ceil() as i16, max.y.ceil() as i16),
+            ),
+            None => bbox(point2(0, 0), point2(0, 0)),
+        }
+    }
+
+    /// Get minimum and maximum of all points, including control points
+    pub(crate) fn control_bounds(&self) -> Option<(Point2D<f32>, Point2D<f32>)> {
+        let mut points = self.0.iter().flat_map(|seg| seg.points());
+        let first = points.next()?;
+        Some(points.fold((first, first), |(min, max), p| {
+            (
+                point2(min.x.min(p.x), min.y.min(p.y)),
+                point2(max.x.max(p.x), max.y.max(p.y)),
+            )
+        }))
+    }
+}
+
+impl Segment {
+    fn map_points<F: Fn(Point2D<f32>) -> Point2D<f32>>(&self, f: F) -> Segment {
+        match self {
+            Segment::MoveTo(p) => Segment::MoveTo(f(*p)),
+            Segment::LineTo(p) => Segment::LineTo(f(*p)),
+            Segment::QuadTo(c, p) => Segment::QuadTo(f(*c), f(*p)),
+            Segment::CubicTo(c1, c2, p) => Segment::CubicTo(f(*c1), f(*c2), f(*p)),
+        }
+    }
+
+    fn points(&self) -> Vec<Point2D<f32>> {
+        match self {
+            Segment::MoveTo(p) | Segment::LineTo(p) => vec![*p],
+            Segment::QuadTo(c, p) => vec![*c, *p],
+            Segment::CubicTo(c1, c2, p) => vec![*c1, *c2, *p],
+        }
+    }
+}
@@ -0,0 +1,697 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{point2, size2, vec2, Point2D, Size2D, Vector2D};
+
+use crate::outline::Outline;
+use crate::raster::{self, Bitmap, BitmapFormat};
+
+/// Color with straight (not premultiplied) alpha
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    pub const TRANSPARENT: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+
+    /// Multiply alpha by a factor between 0 and 1
+    pub(crate) fn with_alpha(self, alpha: f32) -> Color {
+        let a = (self.a as f32 * alpha.clamp(0.0, 1.0) + 0.5) as u8;
+        Color { a, ..self }
+    }
+
+    // Premultiplied components between 0 and 1
+    fn premultiplied(self) -> [f32; 4] {
+        let a = self.a as f32 / 255.0;
+        [
+            self.r as f32 / 255.0 * a,
+            self.g as f32 / 255.0 * a,
+            self.b as f32 / 255.0 * a,
+            a,
+        ]
+    }
+}
+
+/// Affine transform, mapping `(x, y)` to `(xx * x + xy * y + dx, yx * x + yy * y + dy)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub xx: f32,
+    pub yx: f32,
+    pub xy: f32,
+    pub yy: f32,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        xx: 1.0,
+        yx: 0.0,
+        xy: 0.0,
+        yy: 1.0,
+        dx: 0.0,
+        dy: 0.0,
+    };
+
+    pub(crate) fn translate(dx: f32, dy: f32) -> Transform {
+        Transform {
+            dx,
+            dy,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub(crate) fn scale(sx: f32, sy: f32) -> Transform {
+        Transform {
+            xx: sx,
+            yy: sy,
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// Transform that applies `other`, and then this
+    pub(crate) fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            xx: self.xx * other.xx + self.xy * other.yx,
+            yx: self.yx * other.xx + self.yy * other.yx,
+            xy: self.xx * other.xy + self.xy * other.yy,
+            yy: self.yx * other.xy + self.yy * other.yy,
+            dx: self.xx * other.dx + self.xy * other.dy + self.dx,
+            dy: self.yx * other.dx + self.yy * other.dy + self.dy,
+        }
+    }
+
+    /// Move transform so that it keeps the given point fixed
+    pub(crate) fn around(&self, center: Point2D<f32>) -> Transform {
+        Transform::translate(center.x, center.y)
+            .then(self)
+            .then(&Transform::translate(-center.x, -center.y))
+    }
+
+    pub(crate) fn apply(&self, p: Point2D<f32>) -> Point2D<f32> {
+        point2(
+            self.xx * p.x + self.xy * p.y + self.dx,
+            self.yx * p.x + self.yy * p.y + self.dy,
+        )
+    }
+
+    fn invert(&self) -> Option<Transform> {
+        let det = self.xx * self.yy - self.xy * self.yx;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let (xx, yx, xy, yy) = (self.yy / det, -self.yx / det, -self.xy / det, self.xx / det);
+        Some(Transform {
+            xx,
+            yx,
+            xy,
+            yy,
+            dx: -(xx * self.dx + xy * self.dy),
+            dy: -(yx * self.dx + yy * self.dy),
+        })
+    }
+}
+
+/// What gradients do outside the range of their color stops
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Extend {
+    Pad,     // Use the color of the nearest end
+    Repeat,  // Repeat the stops
+    Reflect, // Repeat the stops, mirroring every other repetition
+}
+
+/// Color at a position along a gradient
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorStop {
+    pub offset: f32,
+    pub color: Color,
+}
+
+/// How a region is filled
+#[derive(Clone, Debug, PartialEq)]
+pub enum Brush {
+    Solid(Color),
+    /// Colors vary along the line from `p0` to `p1`. Lines of equal color are parallel to the line
+    /// from `p0` to `p2`
+    LinearGradient {
+        p0: Point2D<f32>,
+        p1: Point2D<f32>,
+        p2: Point2D<f32>,
+        stops: Vec<ColorStop>,
+        extend: Extend,
+    },
+    /// Colors vary along circles interpolated between the start and end circles
+    RadialGradient {
+        c0: Point2D<f32>,
+        r0: f32,
+        c1: Point2D<f32>,
+        r1: f32,
+        stops: Vec<ColorStop>,
+        extend: Extend,
+    },
+    /// Colors vary with the counter-clockwise angle around the center, in degrees
+    SweepGradient {
+        center: Point2D<f32>,
+        start_angle: f32,
+        end_angle: f32,
+        stops: Vec<ColorStop>,
+        extend: Extend,
+    },
+}
+
+/// How a layer is combined with what's below it
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CompositeMode {
+    Clear,
+    Src,
+    Dest,
+    SrcOver,
+    DestOver,
+    SrcIn,
+    DestIn,
+    SrcOut,
+    DestOut,
+    SrcAtop,
+    DestAtop,
+    Xor,
+    Plus,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Multiply,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+/// Command for painting a color glyph. Commands are executed in order, and pushes are matched by
+/// pops. The y-axis points up
+#[derive(Clone, Debug)]
+pub enum PaintCommand {
+    /// Transform geometry of following commands, after transforms pushed so far
+    PushTransform(Transform),
+    PopTransform,
+    /// Clip following fills to the outline, intersected with clips pushed so far
+    PushClip(Outline),
+    PopClip,
+    /// Paint the clipped region over what's been painted so far
+    Fill(Brush),
+    /// Paint following commands into a new transparent layer
+    PushLayer,
+    /// Combine the layer with the one below it
+    PopLayer(CompositeMode),
+}
+
+/// Render paint commands into a premultiplied RGBA bitmap, moved by the given (subpixel) offset
+pub(crate) fn render(commands: &[PaintCommand], offset: Vector2D<f32>) -> Bitmap {
+    let base = Transform::translate(offset.x, offset.y);
+    let empty = Bitmap {
+        size: size2(0, 0),
+        bearing: vec2(0, 0),
+        format: BitmapFormat::Rgba,
+        data: Vec::new(),
+    };
+    let (min, max) = match bounds(commands, &base) {
+        Some(bounds) => bounds,
+        None => return empty,
+    };
+    let left = min.x.floor() as i32;
+    let top = max.y.ceil() as i32;
+    let size = size2(
+        (max.x.ceil() as i32 - left).max(1) as u32,
+        (top - min.y.floor() as i32).max(1) as u32,
+    );
+    let len = (size.width * size.height) as usize;
+    let mut canvas = Canvas {
+        left,
+        top,
+        size,
+        transforms: vec![base],
+        clips: Vec::new(),
+        layers: vec![vec![[0.0; 4]; len]],
+    };
+    for command in commands {
+        canvas.execute(command);
+    }
+    let data = canvas.layers[0]
+        .iter()
+        .flat_map(|p| p.iter().map(|c| (c.clamp(0.0, 1.0) * 255.0 + 0.5) as u8))
+        .collect();
+    Bitmap {
+        size,
+        bearing: vec2(left, top),
+        format: BitmapFormat::Rgba,
+        data,
+    }
+}
+
+// Get bounds of everything filled, in pixels
+fn bounds(commands: &[PaintCommand], base: &Transform) -> Option<(Point2D<f32>, Point2D<f32>)> {
+    let mut transforms = vec![*base];
+    let mut clips: Vec<Option<(Point2D<f32>, Point2D<f32>)>> = Vec::new();
+    let mut ret: Option<(Point2D<f32>, Point2D<f32>)> = None;
+    for command in commands {
+        match command {
+            PaintCommand::PushTransform(t) => {
+                let last = *transforms.last().unwrap();
+                transforms.push(last.then(t));
+            }
+            PaintCommand::PopTransform => {
+                if transforms.len() > 1 {
+                    transforms.pop();
+                }
+            }
+            PaintCommand::PushClip(outline) => {
+                let t = transforms.last().unwrap();
+                let bounds = outline.map_points(|p| t.apply(p)).control_bounds();
+                // Intersect with the enclosing clip
+                let bounds = match (clips.last(), bounds) {
+                    (Some(None), _) | (_, None) => None,
+                    (Some(Some((min0, max0))), Some((min1, max1))) => {
+                        let min = point2(min0.x.max(min1.x), min0.y.max(min1.y));
+                        let max = point2(max0.x.min(max1.x), max0.y.min(max1.y));
+                        Some((min, max)).filter(|(min, max)| min.x < max.x && min.y < max.y)
+                    }
+                    (None, bounds) => bounds,
+                };
+                clips.push(bounds);
+            }
+            PaintCommand::PopClip => {
+                clips.pop();
+            }
+            // Unclipped fills have no bounds of their own
+            PaintCommand::Fill(_) => {
+                if let Some(Some((min, max))) = clips.last() {
+                    ret = Some(match ret {
+                        Some((rmin, rmax)) => (
+                            point2(rmin.x.min(min.x), rmin.y.min(min.y)),
+                            point2(rmax.x.max(max.x), rmax.y.max(max.y)),
+                        ),
+                        None => (*min, *max),
+                    });
+                }
+            }
+            PaintCommand::PushLayer | PaintCommand::PopLayer(_) => {}
+        }
+    }
+    ret
+}
+
+// Pixels being painted on, with premultiplied colors
+struct Canvas {
+    left: i32,
+    top: i32,
+    size: Size2D<u32>,
+    transforms: Vec<Transform>, // Combined transforms, the last being current
+    clips: Vec<Vec<f32>>,       // Combined clip coverage, the last being current
+    layers: Vec<Vec<[f32; 4]>>,
+}
+
+impl Canvas {
+    fn execute(&mut self, command: &PaintCommand) {
+        match command {
+            PaintCommand::PushTransform(t) => {
+                let last = *self.transforms.last().unwrap();
+                self.transforms.push(last.then(t));
+            }
+            PaintCommand::PopTransform => {
+                if self.transforms.len() > 1 {
+                    self.transforms.pop();
+                }
+            }
+            PaintCommand::PushClip(outline) => {
+                let t = self.transforms.last().unwrap();
+                let outline = outline.map_points(|p| t.apply(p));
+                let mut coverage = raster::coverage(&outline, self.left, self.top, self.size);
+                if let Some(clip) = self.clips.last() {
+                    for (c, prev) in coverage.iter_mut().zip(clip) {
+                        *c *= prev;
+                    }
+                }
+                self.clips.push(coverage);
+            }
+            PaintCommand::PopClip => {
+                self.clips.pop();
+            }
+            PaintCommand::Fill(brush) => self.fill(brush),
+            PaintCommand::PushLayer => {
+                let layer = vec![[0.0; 4]; (self.size.width * self.size.height) as usize];
+                self.layers.push(layer);
+            }
+            PaintCommand::PopLayer(mode) => {
+                if self.layers.len() > 1 {
+                    let src = self.layers.pop().unwrap();
+                    let dst = self.layers.last_mut().unwrap();
+                    for (d, s) in dst.iter_mut().zip(src) {
+                        *d = composite(s, *d, *mode);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill(&mut self, brush: &Brush) {
+        // Fills are bounded by clips
+        let clip = match self.clips.last() {
+            Some(clip) => clip,
+            None => return,
+        };
+        let inverse = match self.transforms.last().unwrap().invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let layer = self.layers.last_mut().unwrap();
+        for (i, (pixel, coverage)) in layer.iter_mut().zip(clip).enumerate() {
+            if *coverage <= 0.0 {
+                continue;
+            }
+            let width = self.size.width as usize;
+            let (x, y) = (i % width, i / width);
+            let p = point2(
+                self.left as f32 + x as f32 + 0.5,
+                self.top as f32 - y as f32 - 0.5,
+            );
+            let color = brush.color_at(inverse.apply(p));
+            let src = [
+                color[0] * coverage,
+                color[1] * coverage,
+                color[2] * coverage,
+                color[3] * coverage,
+            ];
+            *pixel = composite(src, *pixel, CompositeMode::SrcOver);
+        }
+    }
+}
+
+impl Brush {
+    // Get premultiplied color at a point
+    fn color_at(&self, p: Point2D<f32>) -> [f32; 4] {
+        match self {
+            Brush::Solid(color) => color.premultiplied(),
+            Brush::LinearGradient {
+                p0,
+                p1,
+                p2,
+                stops,
+                extend,
+            } => {
+                // Rotate the line from p0 to p1 so that it's perpendicular to the line from p0 to
+                // p2
+                let normal = vec2(p0.y - p2.y, p2.x - p0.x);
+                let d1 = vec2(p1.x - p0.x, p1.y - p0.y);
+                let len = normal.x * normal.x + normal.y * normal.y;
+                let dir = if len == 0.0 {
+                    d1
+                } else {
+                    let k = (d1.x * normal.x + d1.y * normal.y) / len;
+                    vec2(normal.x * k, normal.y * k)
+                };
+                let len = dir.x * dir.x + dir.y * dir.y;
+                if len == 0.0 {
+                    return [0.0; 4];
+                }
+                let t = ((p.x - p0.x) * dir.x + (p.y - p0.y) * dir.y) / len;
+                color_line(stops, *extend, t)
+            }
+            Brush::RadialGradient {
+                c0,
+                r0,
+                c1,
+                r1,
+                stops,
+                extend,
+            } => match radial_position(p, *c0, *r0, *c1, *r1) {
+                Some(t) => color_line(stops, *extend, t),
+                None => [0.0; 4],
+            },
+            Brush::SweepGradient {
+                center,
+                start_angle,
+                end_angle,
+                stops,
+                extend,
+            } => {
+                let angle = (p.y - center.y).atan2(p.x - center.x).to_degrees();
+                let angle = if angle < 0.0 { angle + 360.0 } else { angle };
+                let t = if end_angle == start_angle {
+                    if angle < *start_angle {
+                        0.0
+                    } else {
+                        1.0
+                    }
+                } else {
+                    (angle - start_angle) / (end_angle - start_angle)
+                };
+                color_line(stops, *extend, t)
+            }
+        }
+    }
+}
+
+// Get the largest position along a two-point conical gradient, with a non-negative radius,
+// where the circle passes through the point
+fn radial_position(
+    p: Point2D<f32>,
+    c0: Point2D<f32>,
+    r0: f32,
+    c1: Point2D<f32>,
+    r1: f32,
+) -> Option<f32> {
+    let (cdx, cdy, dr) = (c1.x - c0.x, c1.y - c0.y, r1 - r0);
+    let (pdx, pdy) = (p.x - c0.x, p.y - c0.y);
+    let a = cdx * cdx + cdy * cdy - dr * dr;
+    let b = pdx * cdx + pdy * cdy + r0 * dr;
+    let c = pdx * pdx + pdy * pdy - r0 * r0;
+    let valid = |t: f32| r0 + t * dr >= 0.0;
+    if a.abs() < 1e-6 {
+        if b == 0.0 {
+            return None;
+        }
+        Some(c / (2.0 * b)).filter(|t| valid(*t))
+    } else {
+        let disc = b * b - a * c;
+        if disc < 0.0 {
+            return None;
+        }
+        let (t1, t2) = ((b + disc.sqrt()) / a, (b - disc.sqrt()) / a);
+        let (t1, t2) = if t1 > t2 { (t1, t2) } else { (t2, t1) };
+        if valid(t1) {
+            Some(t1)
+        } else if valid(t2) {
+            Some(t2)
+        } else {
+            None
+        }
+    }
+}
+
+// Get premultiplied color at a position along the color stops
+fn color_line(stops: &[ColorStop], extend: Extend, t: f32) -> [f32; 4] {
+    let (first, last) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return [0.0; 4],
+    };
+    let period = last.offset - first.offset;
+    let t = if period <= 0.0 {
+        t
+    } else {
+        match extend {
+            Extend::Pad => t,
+            Extend::Repeat => first.offset + (t - first.offset).rem_euclid(period),
+            Extend::Reflect => {
+                let u = (t - first.offset).rem_euclid(2.0 * period);
+                first.offset + if u > period { 2.0 * period - u } else { u }
+            }
+        }
+    };
+    if t <= first.offset {
+        return first.color.premultiplied();
+    }
+    for pair in stops.windows(2) {
+        let (s0, s1) = (&pair[0], &pair[1]);
+        if t <= s1.offset {
+            if s1.offset <= s0.offset {
+                return s1.color.premultiplied();
+            }
+            let k = (t - s0.offset) / (s1.offset - s0.offset);
+            let (c0, c1) = (s0.color.premultiplied(), s1.color.premultiplied());
+            return [
+                c0[0] + (c1[0] - c0[0]) * k,
+                c0[1] + (c1[1] - c0[1]) * k,
+                c0[2] + (c1[2] - c0[2]) * k,
+                c0[3] + (c1[3] - c0[3]) * k,
+            ];
+        }
+    }
+    last.color.premultiplied()
+}
+
+// Combine premultiplied source and destination colors
+fn composite(src: [f32; 4], dst: [f32; 4], mode: CompositeMode) -> [f32; 4] {
+    let (sa, da) = (src[3], dst[3]);
+    // Porter-Duff operators weight source and destination by factors
+    let porter_duff = |fs: f32, fd: f32| {
+        [
+            src[0] * fs + dst[0] * fd,
+            src[1] * fs + dst[1] * fd,
+            src[2] * fs + dst[2] * fd,
+            (sa * fs + da * fd).min(1.0),
+        ]
+    };
+    match mode {
+        CompositeMode::Clear => return [0.0; 4],
+        CompositeMode::Src => return src,
+        CompositeMode::Dest => return dst,
+        CompositeMode::SrcOver => return porter_duff(1.0, 1.0 - sa),
+        CompositeMode::DestOver => return porter_duff(1.0 - da, 1.0),
+        CompositeMode::SrcIn => return porter_duff(da, 0.0),
+        CompositeMode::DestIn => return porter_duff(0.0, sa),
+        CompositeMode::SrcOut => return porter_duff(1.0 - da, 0.0),
+        CompositeMode::DestOut => return porter_duff(0.0, 1.0 - sa),
+        CompositeMode::SrcAtop => return porter_duff(da, 1.0 - sa),
+        CompositeMode::DestAtop => return porter_duff(1.0 - da, sa),
+        CompositeMode::Xor => return porter_duff(1.0 - da, 1.0 - sa),
+        CompositeMode::Plus => {
+            let p = porter_duff(1.0, 1.0);
+            return [p[0].min(1.0), p[1].min(1.0), p[2].min(1.0), p[3]];
+        }
+        _ => {}
+    }
+    // Blend modes mix unpremultiplied colors where both are present
+    if sa == 0.0 {
+        return dst;
+    }
+    if da == 0.0 {
+        return src;
+    }
+    let cs = [src[0] / sa, src[1] / sa, src[2] / sa];
+    let cd = [dst[0] / da, dst[1] / da, dst[2] / da];
+    let blended = match mode {
+        CompositeMode::Hue => set_lum(set_sat(cs, sat(cd)), lum(cd)),
+        CompositeMode::Saturation => set_lum(set_sat(cd, sat(cs)), lum(cd)),
+        CompositeMode::Color => set_lum(cs, lum(cd)),
+        CompositeMode::Luminosity => set_lum(cd, lum(cs)),
+        _ => [
+            blend(cs[0], cd[0], mode),
+            blend(cs[1], cd[1], mode),
+            blend(cs[2], cd[2], mode),
+        ],
+    };
+    let mix = |i: usize| src[i] * (1.0 - da) + dst[i] * (1.0 - sa) + sa * da * blended[i];
+    [mix(0), mix(1), mix(2), sa + da - sa * da]
+}
+
+// Blend unpremultiplied source and destination color components
+fn blend(s: f32, d: f32, mode: CompositeMode) -> f32 {
+    let hard_light = |s: f32, d: f32| {
+        if s <= 0.5 {
+            d * 2.0 * s
+        } else {
+            let s = 2.0 * s - 1.0;
+            d + s - d * s
+        }
+    };
+    match mode {
+        CompositeMode::Screen => s + d - s * d,
+        CompositeMode::Overlay => hard_light(d, s),
+        CompositeMode::Darken => s.min(d),
+        CompositeMode::Lighten => s.max(d),
+        CompositeMode::ColorDodge => {
+            if d == 0.0 {
+                0.0
+            } else if s >= 1.0 {
+                1.0
+            } else {
+                (d / (1.0 - s)).min(1.0)
+            }
+        }
+        CompositeMode::ColorBurn => {
+            if d >= 1.0 {
+                1.0
+            } else if s <= 0.0 {
+                0.0
+            } else {
+                1.0 - ((1.0 - d) / s).min(1.0)
+            }
+        }
+        CompositeMode::HardLight => hard_light(s, d),
+        CompositeMode::SoftLight => {
+            if s <= 0.5 {
+                d - (1.0 - 2.0 * s) * d * (1.0 - d)
+            } else {
+                let dd = if d <= 0.25 {
+                    ((16.0 * d - 12.0) * d + 4.0) * d
+                } else {
+                    d.sqrt()
+                };
+                d + (2.0 * s - 1.0) * (dd - d)
+            }
+        }
+        CompositeMode::Difference => (s - d).abs(),
+        CompositeMode::Exclusion => s + d - 2.0 * s * d,
+        CompositeMode::Multiply => s * d,
+        _ => s,
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    let c = [c[0] + d, c[1] + d, c[2] + d];
+    // Clip back into range, keeping the luminosity
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    let mut ret = c;
+    for v in &mut ret {
+        if n < 0.0 {
+            *v = l + (*v - l) * l / (l - n);
+        }
+        if x > 1.0 {
+            *v = l + (*v - l) * (1.0 - l) / (x - l);
+        }
+    }
+    ret
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+    if max <= min {
+        return [0.0; 3];
+    }
+    let mut ret = [0.0; 3];
+    for (r, v) in ret.iter_mut().zip(&c) {
+        *r = (v - min) * s / (max - min);
+    }
+    ret
+}
@@ -0,0 +1,368 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{size2, Size2D};
+
+use crate::error::*;
+use crate::inflate::zlib_decompress;
+use crate::types::{get_u16, get_u32, Tag};
+
+/// Get size of PNG image from its header, without decoding it
+pub(crate) fn size(data: &[u8]) -> Result<Size2D<u32>> {
+    if data.get(..8) != Some(&SIGNATURE[..]) || get_u32(data, 12)? != Tag::from(b"IHDR").0 {
+        return Err(Error::Invalid);
+    }
+    Ok(size2(get_u32(data, 16)?, get_u32(data, 20)?))
+}
+
+/// Decode PNG image into premultiplied RGBA pixels, row by row from the top
+pub(crate) fn decode(data: &[u8]) -> Result<(Size2D<u32>, Vec<u8>)> {
+    let size = size(data)?;
+    if size.width as u64 * size.height as u64 > MAX_PIXELS {
+        return Err(Error::Invalid);
+    }
+    let (mut header, mut palette, mut transparency) = (None, &[][..], None);
+    let mut compressed = Vec::new();
+    let mut offset = SIGNATURE.len();
+    while offset < data.len() {
+        let len = get_u32(data, offset)? as usize;
+        let chunk = data
+            .get(offset + 8..offset + 8 + len)
+            .ok_or(Error::Invalid)?;
+        match Tag(get_u32(data, offset + 4)?) {
+            t if t == Tag::from(b"IHDR") => header = Some(Header::load(chunk)?),
+            t if t == Tag::from(b"PLTE") => palette = chunk,
+            t if t == Tag::from(b"tRNS") => transparency = Some(chunk),
+            t if t == Tag::from(b"IDAT") => compressed.extend_from_slice(chunk),
+            t if t == Tag::from(b"IEND") => break,
+            _ => {}
+        }
+        offset += len + 12; // Length, type and CRC
+    }
+    let header = header.ok_or(Error::Invalid)?;
+    let passes: &[(u32, u32, u32, u32)] = if header.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+    // Size of each pass, skipping empty ones which have no data
+    let passes = passes
+        .iter()
+        .map(|(x0, y0, dx, dy)| {
+            let pass_size = size2(
+                (size.width.saturating_sub(*x0) + dx - 1) / dx,
+                (size.height.saturating_sub(*y0) + dy - 1) / dy,
+            );
+            (*x0, *y0, *dx, *dy, pass_size)
+        })
+        .filter(|(.., pass_size)| pass_size.width != 0 && pass_size.height != 0)
+        .collect::<Vec<_>>();
+    // Don't decompress more than the image needs
+    let raw_len = passes
+        .iter()
+        .map(|(.., pass_size)| header.filtered_len(*pass_size))
+        .sum();
+    let raw = zlib_decompress(&compressed, raw_len)?;
+
+    let mut pixels = vec![0; (size.width * size.height) as usize * 4];
+    let mut raw = &raw[..];
+    for (x0, y0, dx, dy, pass_size) in passes {
+        let rows = header.unfilter(&mut raw, pass_size)?;
+        let stride = rows.len() / pass_size.height as usize;
+        for (y, row) in rows.chunks(stride).enumerate() {
+            for x in 0..pass_size.width as usize {
+                let color = header.pixel(row, x, palette, transparency)?;
+                let (px, py) = (x0 + x as u32 * dx, y0 + y as u32 * dy);
+                let at = (py * size.width + px) as usize * 4;
+                pixels[at..at + 4].copy_from_slice(&premultiply(color));
+            }
+        }
+    }
+    Ok((size, pixels))
+}
+
+// Image properties from the IHDR chunk
+struct Header {
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn load(data: &[u8]) -> Result<Header> {
+        let header = data.get(8..13).ok_or(Error::Invalid)?;
+        let (bit_depth, color_type) = (header[0], header[1]);
+        let valid_depth = match color_type {
+            0 => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(bit_depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(bit_depth, 8 | 16),
+            _ => false,
+        };
+        if !valid_depth || header[2] != 0 || header[3] != 0 || header[4] > 1 {
+            return Err(Error::Invalid);
+        }
+        Ok(Header {
+            bit_depth,
+            color_type,
+            interlaced: header[4] == 1,
+        })
+    }
+
+    // Bytes in each unfiltered row of an image of the given width
+    fn stride(&self, width: u32) -> usize {
+        (width as usize * self.channels() * self.bit_depth as usize + 7) / 8
+    }
+
+    // Bytes taken by the filtered rows of an image, each with a leading filter type byte
+    fn filtered_len(&self, size: Size2D<u32>) -> usize {
+        (self.stride(size.width) + 1) * size.height as usize
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    // Undo filters on the rows of an image (or interlaced pass) at the start of the data, and
+    // advance past them
+    fn unfilter(&self, data: &mut &[u8], size: Size2D<u32>) -> Result<Vec<u8>> {
+        let stride = self.stride(size.width);
+        // Filters look at the corresponding byte of the pixel to the left
+        let bpp = ((self.channels() * self.bit_depth as usize + 7) / 8).max(1);
+        let len = self.filtered_len(size);
+        let filtered = data.get(..len).ok_or(Error::Invalid)?;
+        *data = &data[len..];
+        let mut rows = vec![0u8; stride * size.height as usize];
+        for (y, line) in filtered.chunks(stride + 1).enumerate() {
+            let (filter, line) = (line[0], &line[1..]);
+            let (prev, cur) = rows.split_at_mut(y * stride);
+            let prev = if y == 0 {
+                None
+            } else {
+                Some(&prev[(y - 1) * stride..])
+            };
+            let cur = &mut cur[..stride];
+            for i in 0..stride {
+                let a = if i >= bpp { cur[i - bpp] } else { 0 };
+                let b = prev.map(|p| p[i]).unwrap_or(0);
+                let c = match prev {
+                    Some(p) if i >= bpp => p[i - bpp],
+                    _ => 0,
+                };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    _ => return Err(Error::Invalid),
+                };
+                cur[i] = line[i].wrapping_add(predicted);
+            }
+        }
+        Ok(rows)
+    }
+
+    // Get straight RGBA color of pixel in an unfiltered row
+    fn pixel(
+        &self,
+        row: &[u8],
+        x: usize,
+        palette: &[u8],
+        transparency: Option<&[u8]>,
+    ) -> Result<[u8; 4]> {
+        let depth = self.bit_depth as usize;
+        // Raw sample value, and the sample scaled to 8 bits
+        let sample = |i: usize| -> (u16, u8) {
+            match depth {
+                16 => {
+                    let value = u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]);
+                    (value, (value >> 8) as u8)
+                }
+                8 => (row[i] as u16, row[i]),
+                _ => {
+                    let bit = i * depth;
+                    let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1);
+                    (
+                        value as u16,
+                        (value as u32 * 255 / ((1 << depth) - 1)) as u8,
+                    )
+                }
+            }
+        };
+        // Colors matching the tRNS chunk are fully transparent
+        let keyed = |values: &[u16]| match transparency {
+            Some(t) => (0..values.len()).all(|i| get_u16(t, i * 2).ok() == Some(values[i])),
+            None => false,
+        };
+        Ok(match self.color_type {
+            0 => {
+                let (raw, gray) = sample(x);
+                let alpha = if keyed(&[raw]) { 0 } else { 255 };
+                [gray, gray, gray, alpha]
+            }
+            2 => {
+                let (r, g, b) = (sample(x * 3), sample(x * 3 + 1), sample(x * 3 + 2));
+                let alpha = if keyed(&[r.0, g.0, b.0]) { 0 } else { 255 };
+                [r.1, g.1, b.1, alpha]
+            }
+            3 => {
+                let index = sample(x).0 as usize;
+                let rgb = palette
+                    .get(index * 3..index * 3 + 3)
+                    .ok_or(Error::Invalid)?;
+                let alpha = transparency
+                    .and_then(|t| t.get(index))
+                    .copied()
+                    .unwrap_or(255);
+                [rgb[0], rgb[1], rgb[2], alpha]
+            }
+            4 => {
+                let (gray, alpha) = (sample(x * 2).1, sample(x * 2 + 1).1);
+                [gray, gray, gray, alpha]
+            }
+            _ => [
+                sample(x * 4).1,
+                sample(x * 4 + 1).1,
+                sample(x * 4 + 2).1,
+                sample(x * 4 + 3).1,
+            ],
+        })
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn premultiply(color: [u8; 4]) -> [u8; 4] {
+    let alpha = color[3] as u32;
+    let mul = |c: u8| ((c as u32 * alpha + 127) / 255) as u8;
+    [mul(color[0]), mul(color[1]), mul(color[2]), color[3]]
+}
+
+// Limit on size of decoded images, which are expected to be glyphs
+const MAX_PIXELS: u64 = 1 << 24;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// Origin and spacing of pixels in each pass of Adam7 interlacing
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a PNG with the filtered image data in a single stored deflate block. CRCs are left
+    // as zero since they aren't checked
+    fn png(
+        size: (u32, u32),
+        depth: u8,
+        color: u8,
+        interlaced: bool,
+        chunks: &[(&[u8; 4], &[u8])],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&size.0.to_be_bytes());
+        header.extend_from_slice(&size.1.to_be_bytes());
+        header.extend_from_slice(&[depth, color, 0, 0, interlaced as u8]);
+        let mut idat = vec![0x78, 0x01, 0x01];
+        idat.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+        idat.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+        idat.extend_from_slice(raw);
+        let mut data = SIGNATURE.to_vec();
+        let chunks = [(b"IHDR", &header[..])]
+            .iter()
+            .chain(chunks)
+            .chain(&[(b"IDAT", &idat[..]), (b"IEND", &[][..])])
+            .copied()
+            .collect::<Vec<_>>();
+        for (tag, chunk) in chunks {
+            data.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            data.extend_from_slice(tag);
+            data.extend_from_slice(chunk);
+            data.extend_from_slice(&[0; 4]);
+        }
+        data
+    }
+
+    fn pixel(depth: u8, color: u8, chunks: &[(&[u8; 4], &[u8])], raw: &[u8]) -> Vec<u8> {
+        let (size, pixels) = decode(&png((1, 1), depth, color, false, chunks, raw)).unwrap();
+        assert_eq!(size, size2(1, 1));
+        pixels
+    }
+
+    #[test]
+    fn gray() {
+        assert_eq!(pixel(8, 0, &[], &[0, 0x80]), [0x80, 0x80, 0x80, 0xff]);
+        assert_eq!(pixel(1, 0, &[], &[0, 0x80]), [0xff, 0xff, 0xff, 0xff]);
+        let trns: &[u8] = &[0x00, 0x80];
+        assert_eq!(pixel(8, 0, &[(b"tRNS", trns)], &[0, 0x80]), [0; 4]);
+    }
+
+    #[test]
+    fn rgb() {
+        assert_eq!(pixel(8, 2, &[], &[0, 0xff, 0x00, 0x00]), [0xff, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn palette() {
+        let plte: &[u8] = &[0, 0, 0, 0, 0, 0xff];
+        let trns: &[u8] = &[0xff, 0x80];
+        let chunks = [(b"PLTE", plte), (b"tRNS", trns)];
+        assert_eq!(pixel(8, 3, &chunks, &[0, 1]), [0, 0, 0x80, 0x80]);
+        // Indices outside the palette are invalid
+        assert!(decode(&png((1, 1), 8, 3, false, &chunks, &[0, 2])).is_err());
+    }
+
+    #[test]
+    fn gray_alpha() {
+        assert_eq!(pixel(8, 4, &[], &[0, 0xc8, 0xff]), [0xc8, 0xc8, 0xc8, 0xff]);
+    }
+
+    #[test]
+    fn rgba() {
+        let raw = [0, 0xff, 0xff, 0, 0, 0, 0, 0x80, 0];
+        assert_eq!(pixel(16, 6, &[], &raw), [0x80, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn adam7() {
+        // Of a 2x2 image, pass 1 has the top-left pixel, pass 6 the top-right one and pass 7 the
+        // bottom row, which uses the sub filter
+        let raw = [0, 0x10, 0, 0x20, 1, 0x30, 0x10];
+        let (size, pixels) = decode(&png((2, 2), 8, 0, true, &[], &raw)).unwrap();
+        assert_eq!(size, size2(2, 2));
+        let gray = pixels.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(gray, [0x10, 0x20, 0x30, 0x40]);
+    }
+
+    #[test]
+    fn excess_data() {
+        assert!(decode(&png((1, 1), 8, 0, false, &[], &[0, 0x80, 0])).is_err());
+    }
+}
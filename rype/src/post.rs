@@ -0,0 +1,30 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::Result;
+use crate::types::{get_i16, get_u32};
+
+/// Wrapper around PostScript table. Glyph names aren't loaded
+#[derive(Debug)]
+pub(crate) struct Post {
+    pub(crate) italic_angle: f32, // Degrees counter-clockwise from vertical
+    pub(crate) underline_position: i16, // Top of underline
+    pub(crate) underline_thickness: i16,
+}
+
+impl Post {
+    pub(crate) fn load(data: &[u8]) -> Result<Post> {
+        // 16.16 fixed-point
+        let italic_angle = get_u32(data, offsets::ITALIC_ANGLE)? as i32 as f32 / 65536.0;
+        Ok(Post {
+            italic_angle,
+            underline_position: get_i16(data, offsets::UNDERLINE_POSITION)?,
+            underline_thickness: get_i16(data, offsets::UNDERLINE_THICKNESS)?,
+        })
+    }
+}
+
+mod offsets {
+    pub(super) const ITALIC_ANGLE: usize = 4;
+    pub(super) const UNDERLINE_POSITION: usize = 8;
+    pub(super) const UNDERLINE_THICKNESS: usize = 10;
+}
@@ -0,0 +1,427 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{point2, size2, vec2, Point2D, Size2D, Vector2D};
+
+use crate::outline::{Outline, Segment};
+
+/// Coverage bitmap of a rasterized glyph
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    pub size: Size2D<u32>,
+    pub bearing: Vector2D<i32>, // Offset of top-left pixel from glyph origin, with y pointing up
+    pub format: BitmapFormat,
+    pub data: Vec<u8>, // Pixels row by row from the top
+}
+
+/// Format of pixels in a bitmap
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BitmapFormat {
+    Gray, // One byte of coverage per pixel
+    Rgb,  // Coverage for the red, green and blue subpixels of each pixel, in that order
+    Rgba, // Premultiplied red, green, blue and alpha of each pixel, for color glyphs
+}
+
+/// How glyphs are rasterized
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RenderMode {
+    /// Grayscale anti-aliasing
+    Gray,
+    /// Subpixel rendering for LCD screens, with 3x oversampling along the subpixel axis
+    Lcd {
+        order: SubpixelOrder,
+        filter: LcdFilter,
+    },
+}
+
+impl Default for RenderMode {
+    fn default() -> RenderMode {
+        RenderMode::Gray
+    }
+}
+
+/// Physical order of subpixels on an LCD screen
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SubpixelOrder {
+    Rgb,  // Left to right
+    Bgr,  // Left to right
+    VRgb, // Top to bottom
+    VBgr, // Top to bottom
+}
+
+impl SubpixelOrder {
+    fn is_vertical(&self) -> bool {
+        matches!(self, SubpixelOrder::VRgb | SubpixelOrder::VBgr)
+    }
+
+    // Color channel of the subpixel at the given index within a pixel
+    fn channel(&self, subpixel: usize) -> usize {
+        match self {
+            SubpixelOrder::Rgb | SubpixelOrder::VRgb => subpixel,
+            SubpixelOrder::Bgr | SubpixelOrder::VBgr => 2 - subpixel,
+        }
+    }
+}
+
+/// Weights of the FIR filter applied across subpixels to reduce color fringes. The weights are
+/// normalized by their sum
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LcdFilter(pub [u8; 5]);
+
+impl LcdFilter {
+    /// Spread energy over neighbouring subpixels, for minimal color fringes
+    pub const DEFAULT: LcdFilter = LcdFilter([0x08, 0x4d, 0x56, 0x4d, 0x08]);
+    /// Sharper, with more color fringes
+    pub const LIGHT: LcdFilter = LcdFilter([0x00, 0x55, 0x56, 0x55, 0x00]);
+}
+
+impl Default for LcdFilter {
+    fn default() -> LcdFilter {
+        LcdFilter::DEFAULT
+    }
+}
+
+// Half height of the band sampled in each row when smoothing across the subpixel axis is off
+const POINT_SAMPLE: f32 = 1.0 / 64.0;
+
+/// Rasterize outline, moved by the given (subpixel) offset, with non-zero winding. Without
+/// symmetric smoothing, LCD modes don't anti-alias along the axis across the subpixels
+pub(crate) fn rasterize(
+    outline: &Outline,
+    offset: Vector2D<f32>,
+    mode: RenderMode,
+    symmetric_smoothing: bool,
+) -> Bitmap {
+    let format = match mode {
+        RenderMode::Gray => BitmapFormat::Gray,
+        RenderMode::Lcd { .. } => BitmapFormat::Rgb,
+    };
+    let (min, max) = match outline.control_bounds() {
+        Some(bounds) => bounds,
+        None => {
+            return Bitmap {
+                size: size2(0, 0),
+                bearing: vec2(0, 0),
+                format,
+                data: Vec::new(),
+            }
+        }
+    };
+    let (min, max) = (min + offset, max + offset);
+    match mode {
+        RenderMode::Gray => {
+            let left = min.x.floor() as i32;
+            let top = max.y.ceil() as i32;
+            let width = (max.x.ceil() as i32 - left).max(1) as usize;
+            let height = (top - min.y.floor() as i32).max(1) as usize;
+            let mut raster = Raster::new(width, height, 0.5);
+            // Pixel coordinates, with y pointing down
+            raster.draw(outline, |p| {
+                point2(p.x + offset.x - left as f32, top as f32 - p.y - offset.y)
+            });
+            Bitmap {
+                size: size2(width as u32, height as u32),
+                bearing: vec2(left, top),
+                format,
+                data: raster.coverage().iter().map(|c| to_u8(*c)).collect(),
+            }
+        }
+        RenderMode::Lcd { order, filter } => {
+            // Leave room for the filter to spread coverage along the subpixel axis
+            let vertical = order.is_vertical();
+            let (pad_x, pad_y) = if vertical { (0, 1) } else { (1, 0) };
+            let left = min.x.floor() as i32 - pad_x;
+            let top = max.y.ceil() as i32 + pad_y;
+            let width = (max.x.ceil() as i32 + pad_x - left).max(1) as usize;
+            let height = (top - min.y.floor() as i32 + pad_y).max(1) as usize;
+            let band = if symmetric_smoothing {
+                0.5
+            } else {
+                POINT_SAMPLE
+            };
+            // Subpixels are always along the rows of the raster, so vertical orders are
+            // rasterized transposed
+            let raster = if vertical {
+                let mut raster = Raster::new(height * 3, width, band);
+                raster.draw(outline, |p| {
+                    point2(
+                        (top as f32 - p.y - offset.y) * 3.0,
+                        p.x + offset.x - left as f32,
+                    )
+                });
+                raster
+            } else {
+                let mut raster = Raster::new(width * 3, height, band);
+                raster.draw(outline, |p| {
+                    point2(
+                        (p.x + offset.x - left as f32) * 3.0,
+                        top as f32 - p.y - offset.y,
+                    )
+                });
+                raster
+            };
+            let coverage = raster.coverage();
+            let mut data = vec![0; width * height * 3];
+            for (i, row) in coverage.chunks(raster.width).enumerate() {
+                for (j, c) in filter.apply(row).iter().enumerate() {
+                    let (pixel, subpixel) = (j / 3, j % 3);
+                    let (x, y) = if vertical { (i, pixel) } else { (pixel, i) };
+                    data[(y * width + x) * 3 + order.channel(subpixel)] = to_u8(*c);
+                }
+            }
+            Bitmap {
+                size: size2(width as u32, height as u32),
+                bearing: vec2(left, top),
+                format,
+                data,
+            }
+        }
+    }
+}
+
+/// Get coverage of each pixel in a frame by the outline, with non-zero winding. The top-left pixel
+/// of the frame is at `left` and `top`, with y pointing up
+pub(crate) fn coverage(outline: &Outline, left: i32, top: i32, size: Size2D<u32>) -> Vec<f32> {
+    let mut raster = Raster::new(size.width as usize, size.height as usize, 0.5);
+    raster.draw(outline, |p| point2(p.x - left as f32, top as f32 - p.y));
+    raster.coverage()
+}
+
+impl LcdFilter {
+    fn apply(&self, row: &[f32]) -> Vec<f32> {
+        let sum = self.0.iter().map(|w| *w as f32).sum::<f32>().max(1.0);
+        (0..row.len())
+            .map(|i| {
+                self.0
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(k, w)| {
+                        (i + k)
+                            .checked_sub(2)
+                            .and_then(|j| row.get(j))
+                            .map(|c| c * *w as f32)
+                    })
+                    .sum::<f32>()
+                    / sum
+            })
+            .collect()
+    }
+}
+
+fn to_u8(coverage: f32) -> u8 {
+    (coverage * 255.0 + 0.5) as u8
+}
+
+// Accumulates signed area covered by lines in each pixel. Summing along a row gives the
+// winding-weighted coverage of each pixel
+struct Raster {
+    width: usize,
+    height: usize,
+    stride: usize, // Extra columns for area to the right of the last pixel
+    band: f32,     // Half the height of the band around the middle of each row that is sampled
+    acc: Vec<f32>,
+}
+
+impl Raster {
+    fn new(width: usize, height: usize, band: f32) -> Raster {
+        let stride = width + 2;
+        Raster {
+            width,
+            height,
+            stride,
+            band,
+            acc: vec![0.0; stride * height],
+        }
+    }
+
+    // Draw outline, after transforming it to pixel coordinates with y pointing down
+    fn draw<F: Fn(Point2D<f32>) -> Point2D<f32>>(&mut self, outline: &Outline, to_pixel: F) {
+        let mut start = point2(0.0, 0.0);
+        let mut pos = start;
+        for segment in outline.segments() {
+            match *segment {
+                Segment::MoveTo(p) => {
+                    self.line(pos, start);
+                    start = to_pixel(p);
+                    pos = start;
+                }
+                Segment::LineTo(p) => {
+                    let p = to_pixel(p);
+                    self.line(pos, p);
+                    pos = p;
+                }
+                Segment::QuadTo(c, p) => {
+                    let p = to_pixel(p);
+                    self.quad(pos, to_pixel(c), p);
+                    pos = p;
+                }
+                Segment::CubicTo(c1, c2, p) => {
+                    let p = to_pixel(p);
+                    self.cubic(pos, to_pixel(c1), to_pixel(c2), p);
+                    pos = p;
+                }
+            }
+        }
+        self.line(pos, start);
+    }
+
+    fn line(&mut self, p0: Point2D<f32>, p1: Point2D<f32>) {
+        if p0.y == p1.y {
+            return;
+        }
+        let (dir, p0, p1) = if p0.y < p1.y {
+            (1.0, p0, p1)
+        } else {
+            (-1.0, p1, p0)
+        };
+        let dxdy = (p1.x - p0.x) / (p1.y - p0.y);
+        let max_x = self.width as f32;
+        let y_start = p0.y.max(0.0).floor() as usize;
+        let y_end = (p1.y.ceil().max(0.0) as usize).min(self.height);
+        for y in y_start..y_end {
+            let row = y * self.stride;
+            // Part of the line within the sampled band of this row
+            let middle = y as f32 + 0.5;
+            let y0 = p0.y.max(middle - self.band);
+            let y1 = p1.y.min(middle + self.band);
+            if y1 <= y0 {
+                continue;
+            }
+            let x = p0.x + (y0 - p0.y) * dxdy;
+            let x_next = p0.x + (y1 - p0.y) * dxdy;
+            let d = (y1 - y0) / (2.0 * self.band) * dir;
+            let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+            let (x0, x1) = (x0.max(0.0).min(max_x), x1.max(0.0).min(max_x));
+            let (x0_floor, x1_ceil) = (x0.floor(), x1.ceil());
+            let (x0i, x1i) = (x0_floor as usize, x1_ceil as usize);
+            if x1i <= x0i + 1 {
+                // Line within a single pixel
+                let x_mid = 0.5 * (x0 + x1) - x0_floor;
+                self.acc[row + x0i] += d - d * x_mid;
+                self.acc[row + x0i + 1] += d * x_mid;
+            } else {
+                // Line spans pixels. Split the area of the trapezoid between them
+                let s = (x1 - x0).recip();
+                let x0f = x0 - x0_floor;
+                let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+                let x1f = x1 - x1_ceil + 1.0;
+                let am = 0.5 * s * x1f * x1f;
+                self.acc[row + x0i] += d * a0;
+                if x1i == x0i + 2 {
+                    self.acc[row + x0i + 1] += d * (1.0 - a0 - am);
+                } else {
+                    let a1 = s * (1.5 - x0f);
+                    self.acc[row + x0i + 1] += d * (a1 - a0);
+                    for xi in x0i + 2..x1i - 1 {
+                        self.acc[row + xi] += d * s;
+                    }
+                    let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                    self.acc[row + x1i - 1] += d * (1.0 - a2 - am);
+                }
+                self.acc[row + x1i] += d * am;
+            }
+        }
+    }
+
+    fn quad(&mut self, p0: Point2D<f32>, p1: Point2D<f32>, p2: Point2D<f32>) {
+        let n = segments(deviation(p0, p1, p2));
+        let mut prev = p0;
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let mt = 1.0 - t;
+            let p = point2(
+                mt * mt * p0.x + 2.0 * mt * t * p1.x + t * t * p2.x,
+                mt * mt * p0.y + 2.0 * mt * t * p1.y + t * t * p2.y,
+            );
+            self.line(prev, p);
+            prev = p;
+        }
+    }
+
+    fn cubic(&mut self, p0: Point2D<f32>, p1: Point2D<f32>, p2: Point2D<f32>, p3: Point2D<f32>) {
+        let n = segments(1.5 * deviation(p0, p1, p2).max(deviation(p1, p2, p3)));
+        let mut prev = p0;
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let mt = 1.0 - t;
+            let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+            let p = point2(
+                a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+                a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+            );
+            self.line(prev, p);
+            prev = p;
+        }
+    }
+
+    // Sum up rows to get coverage
+    fn coverage(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for row in self.acc.chunks(self.stride) {
+            let mut sum = 0.0;
+            for a in &row[..self.width] {
+                sum += a;
+                data.push(sum.abs().min(1.0));
+            }
+        }
+        data
+    }
+}
+
+// Distance of the middle point from the midpoint of the others, times two
+fn deviation(a: Point2D<f32>, b: Point2D<f32>, c: Point2D<f32>) -> f32 {
+    (a.x - 2.0 * b.x + c.x).hypot(a.y - 2.0 * b.y + c.y)
+}
+
+// Number of lines to flatten a curve into, given how far it deviates from a line
+fn segments(deviation: f32) -> usize {
+    1 + (3.0 * deviation * deviation).sqrt().sqrt() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Square with the given corners, clockwise if `hole` is set and counter-clockwise otherwise
+    fn square(outline: &mut Outline, min: f32, max: f32, hole: bool) {
+        let corners = [(min, min), (max, min), (max, max), (min, max)];
+        outline.move_to(point2(min, min));
+        let mut rest = corners[1..].to_vec();
+        if hole {
+            rest.reverse();
+        }
+        for (x, y) in rest {
+            outline.line_to(point2(x, y));
+        }
+        outline.line_to(point2(min, min));
+    }
+
+    #[test]
+    fn unit_square() {
+        let mut outline = Outline::default();
+        square(&mut outline, 0.0, 1.0, false);
+        let bitmap = rasterize(&outline, vec2(0.0, 0.0), RenderMode::Gray, false);
+        assert_eq!(bitmap.size, size2(1, 1));
+        assert_eq!(bitmap.bearing, vec2(0, 1));
+        assert_eq!(bitmap.data, [255]);
+        // Straddling two pixels, each half covered
+        let bitmap = rasterize(&outline, vec2(0.5, 0.0), RenderMode::Gray, false);
+        assert_eq!(bitmap.size, size2(2, 1));
+        assert_eq!(bitmap.data, [128, 128]);
+    }
+
+    #[test]
+    fn hole() {
+        let mut outline = Outline::default();
+        square(&mut outline, 0.0, 3.0, false);
+        square(&mut outline, 1.0, 2.0, true);
+        let bitmap = rasterize(&outline, vec2(0.0, 0.0), RenderMode::Gray, false);
+        assert_eq!(bitmap.size, size2(3, 3));
+        assert_eq!(bitmap.data, [255, 255, 255, 255, 0, 255, 255, 255, 255]);
+        // The same contour twice covers pixels no more than once
+        let mut outline = Outline::default();
+        square(&mut outline, 0.0, 1.0, false);
+        square(&mut outline, 0.0, 1.0, false);
+        let bitmap = rasterize(&outline, vec2(0.0, 0.0), RenderMode::Gray, false);
+        assert_eq!(bitmap.data, [255]);
+    }
+}
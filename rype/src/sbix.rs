@@ -0,0 +1,121 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::vec2;
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::png;
+use crate::raster::{Bitmap, BitmapFormat};
+use crate::strike::BitmapMetrics;
+use crate::types::{get_i16, get_u16, get_u32, Tag};
+
+/// Wrapper around standard bitmap graphics table
+#[derive(Debug)]
+pub(crate) struct Sbix(Vec<Strike>);
+
+impl Sbix {
+    pub(crate) fn load(data: &[u8], num_glyphs: usize) -> Result<Sbix> {
+        let num_strikes = get_u32(data, offsets::NUM_STRIKES)? as usize;
+        let strikes = (0..num_strikes)
+            .map(|i| {
+                let off = get_u32(data, offsets::STRIKE_OFFSETS + i * 4)? as usize;
+                Strike::load(data.get(off..).ok_or(Error::Invalid)?, num_glyphs)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Sbix(strikes))
+    }
+
+    /// Pixels per em of each strike
+    pub(crate) fn strike_ppems(&self) -> impl Iterator<Item = u16> + '_ {
+        self.0.iter().map(|s| s.ppem)
+    }
+
+    /// Get metrics of glyph in strike, in pixels, without decoding its image
+    pub(crate) fn metrics(&self, strike: usize, glyph: GlyphID) -> Option<BitmapMetrics> {
+        let (origin, image) = self.0[strike].image(glyph)?;
+        let size = png::size(image).ok()?;
+        Some(BitmapMetrics {
+            size,
+            bearing: vec2(origin.0, origin.1 + size.height as i32),
+        })
+    }
+
+    /// Decode bitmap of glyph in strike into premultiplied RGBA. Only PNG images are supported
+    pub(crate) fn bitmap(&self, strike: usize, glyph: GlyphID) -> Option<Bitmap> {
+        let (origin, image) = self.0[strike].image(glyph)?;
+        let (size, data) = png::decode(image).ok()?;
+        Some(Bitmap {
+            size,
+            // The origin offset is to the bottom-left of the image
+            bearing: vec2(origin.0, origin.1 + size.height as i32),
+            format: BitmapFormat::Rgba,
+            data,
+        })
+    }
+}
+
+// Images for all glyphs at one size
+#[derive(Debug)]
+struct Strike {
+    ppem: u16,
+    glyphs: Vec<Option<StrikeGlyph>>,
+}
+
+#[derive(Debug)]
+struct StrikeGlyph {
+    origin: (i32, i32),
+    graphic_type: Tag,
+    data: Vec<u8>,
+}
+
+impl Strike {
+    fn load(data: &[u8], num_glyphs: usize) -> Result<Strike> {
+        let ppem = get_u16(data, offsets::PPEM)?;
+        let glyphs = (0..num_glyphs)
+            .map(|i| {
+                let start = get_u32(data, offsets::GLYPH_DATA_OFFSETS + i * 4)? as usize;
+                let end = get_u32(data, offsets::GLYPH_DATA_OFFSETS + i * 4 + 4)? as usize;
+                if end <= start {
+                    return Ok(None);
+                }
+                let glyph = data.get(start..end).ok_or(Error::Invalid)?;
+                Ok(Some(StrikeGlyph {
+                    origin: (
+                        get_i16(glyph, offsets::ORIGIN_OFFSET_X)? as i32,
+                        get_i16(glyph, offsets::ORIGIN_OFFSET_Y)? as i32,
+                    ),
+                    graphic_type: Tag(get_u32(glyph, offsets::GRAPHIC_TYPE)?),
+                    data: glyph[offsets::DATA..].to_vec(),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Strike { ppem, glyphs })
+    }
+
+    // Get origin offset and PNG image of glyph, following duplicates
+    fn image(&self, glyph: GlyphID) -> Option<((i32, i32), &[u8])> {
+        let mut glyph = self.glyphs.get(glyph.0 as usize)?.as_ref()?;
+        // A duplicate refers to another glyph's image, and shouldn't refer to a duplicate itself
+        if glyph.graphic_type == Tag::from(b"dupe") {
+            let id = get_u16(&glyph.data, 0).ok()?;
+            glyph = self.glyphs.get(id as usize)?.as_ref()?;
+        }
+        if glyph.graphic_type != Tag::from(b"png ") {
+            return None;
+        }
+        Some((glyph.origin, &glyph.data))
+    }
+}
+
+mod offsets {
+    pub(super) const NUM_STRIKES: usize = 4;
+    pub(super) const STRIKE_OFFSETS: usize = 8;
+
+    pub(super) const PPEM: usize = 0;
+    pub(super) const GLYPH_DATA_OFFSETS: usize = 4;
+
+    pub(super) const ORIGIN_OFFSET_X: usize = 0;
+    pub(super) const ORIGIN_OFFSET_Y: usize = 2;
+    pub(super) const GRAPHIC_TYPE: usize = 4;
+    pub(super) const DATA: usize = 8;
+}
@@ -4,7 +4,7 @@ use fnv::FnvHashMap;
 
 use crate::error::*;
 use crate::types::{get_tag, get_u16, Tag};
-use crate::Script;
+use crate::{Language, Script};
 
 /// Wrapper around ScriptList table common to GSUB and GPOS
 #[derive(Debug)]
@@ -17,18 +17,23 @@ impl ScriptList {
         for record_off in (2..2 + record_count * 6).step_by(6) {
             let tag = get_tag(data, record_off)?;
             let table_off = get_u16(data, record_off + 4)? as usize;
-            let script_table = ScriptTable::load(&data[table_off..])?;
+            let script_table = ScriptTable::load(data.get(table_off..).ok_or(Error::Invalid)?)?;
             table.insert(tag, script_table);
         }
         Ok(ScriptList(table))
     }
 
-    pub(crate) fn feature_indices(&self, script: Script) -> Vec<u16> {
+    /// Get feature indices for script and language. Falls back to the default language system
+    pub(crate) fn feature_indices(&self, script: Script, language: Language) -> Vec<u16> {
         let tag = script.tag();
         self.0
             .get(&tag)
             .or_else(|| self.0.get(&Script::Default.tag()))
-            .and_then(|st| st.default_lang_sys.as_ref())
+            .and_then(|st| {
+                st.lang_sys_records
+                    .get(&language.tag())
+                    .or(st.default_lang_sys.as_ref())
+            })
             .map(|lt| {
                 let mut ret = Vec::new();
                 if lt.required_feature_index.is_some() {
@@ -52,13 +57,14 @@ impl ScriptTable {
         let mut lang_sys_records = FnvHashMap::default();
         let default_lang_sys = match get_u16(data, 0)? as usize {
             0 => None,
-            off => Some(LangSysTable::load(&data[off..])?),
+            off => Some(LangSysTable::load(data.get(off..).ok_or(Error::Invalid)?)?),
         };
         let lang_sys_rec_count = get_u16(data, 2)? as usize;
         for lang_sys_rec_off in (4..4 + lang_sys_rec_count * 6).step_by(6) {
             let tag = get_tag(data, lang_sys_rec_off)?;
             let lang_sys_off = get_u16(data, lang_sys_rec_off + 4)? as usize;
-            let lang_sys_table = LangSysTable::load(&data[lang_sys_off..])?;
+            let lang_sys_table =
+                LangSysTable::load(data.get(lang_sys_off..).ok_or(Error::Invalid)?)?;
             lang_sys_records.insert(tag, lang_sys_table);
         }
         Ok(ScriptTable {
@@ -0,0 +1,259 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cmp::Ordering;
+
+use crate::arabic;
+use crate::cmap::Cmap;
+use crate::common::{GlyphID, SubstGlyph};
+use crate::features::Features;
+use crate::gsub::Gsub;
+use crate::indic;
+use crate::options::{FeatureValues, ShapingOptions};
+use crate::universal;
+use crate::Script;
+
+/// Script-specific shaping behaviour
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Shaper {
+    Default,
+    Arabic,
+    Indic,
+    Universal,
+}
+
+impl Shaper {
+    pub(crate) fn for_script(script: Script) -> Shaper {
+        match script {
+            Script::Arabic | Script::Mongolian | Script::NKo | Script::Phagspa | Script::Syriac => {
+                Shaper::Arabic
+            }
+            Script::Bengali
+            | Script::BengaliV2
+            | Script::Devanagari
+            | Script::DevanagariV2
+            | Script::Gujarati
+            | Script::GujaratiV2
+            | Script::Gurmukhi
+            | Script::GurmukhiV2
+            | Script::Kannada
+            | Script::KannadaV2
+            | Script::Malayalam
+            | Script::MalayalamV2
+            | Script::Odia
+            | Script::OdiaV2
+            | Script::Tamil
+            | Script::TamilV2
+            | Script::Telugu
+            | Script::TeluguV2 => Shaper::Indic,
+            Script::Balinese
+            | Script::Batak
+            | Script::Brahmi
+            | Script::Buginese
+            | Script::Chakma
+            | Script::Grantha
+            | Script::Javanese
+            | Script::Kaithi
+            | Script::Lepcha
+            | Script::Newa
+            | Script::Rejang
+            | Script::Saurashtra
+            | Script::Sharada
+            | Script::Sinhala
+            | Script::Sundanese
+            | Script::Tibetan
+            | Script::Tirhuta => Shaper::Universal,
+            _ => Shaper::Default,
+        }
+    }
+
+    /// Features enabled by the shaper, in addition to the defaults for the direction
+    pub(crate) fn features(&self) -> &'static [Features] {
+        match self {
+            Shaper::Default => &[],
+            Shaper::Arabic => &arabic::ARABIC_FEATURES,
+            Shaper::Indic => &indic::INDIC_FEATURES,
+            Shaper::Universal => &universal::USE_FEATURES,
+        }
+    }
+
+    /// Substitute glyphs, which are expected to map one-to-one to the characters in the text
+    pub(crate) fn substitute(
+        &self,
+        glyphs: &mut Vec<SubstGlyph>,
+        codepoints: &[char],
+        cmap: &Cmap,
+        gsub: Option<&Gsub>,
+        options: &ShapingOptions,
+        features: &FeatureValues,
+    ) {
+        match self {
+            Shaper::Default => substitute(glyphs, gsub, options, features),
+            Shaper::Arabic => {
+                arabic::setup_glyph_features(codepoints, glyphs);
+                substitute(glyphs, gsub, options, features);
+            }
+            Shaper::Indic => indic::substitute(glyphs, codepoints, cmap, gsub, options, features),
+            Shaper::Universal => {
+                universal::substitute(glyphs, codepoints, cmap, gsub, options, features)
+            }
+        }
+    }
+}
+
+/// Apply the given features, if the face has a GSUB table
+pub(crate) fn substitute(
+    glyphs: &mut Vec<SubstGlyph>,
+    gsub: Option<&Gsub>,
+    options: &ShapingOptions,
+    features: &FeatureValues,
+) {
+    if let Some(gsub) = gsub {
+        gsub.substitute(
+            glyphs,
+            options.script,
+            options.language,
+            options.direction,
+            features,
+        );
+    }
+}
+
+/// Replace characters with their parts as given in `splits`, and set glyph categories. Expects
+/// glyphs to map one-to-one to characters.
+pub(crate) fn decompose(
+    glyphs: &mut Vec<SubstGlyph>,
+    codepoints: &[char],
+    cmap: &Cmap,
+    splits: &[(char, [char; 2])],
+    category: fn(char) -> Category,
+) {
+    let mut decomposed = Vec::with_capacity(glyphs.len());
+    for glyph in glyphs.iter() {
+        let c = codepoints[glyph.cluster];
+        let parts = match splits.iter().find(|(s, _)| *s == c) {
+            Some((_, parts)) => &parts[..],
+            None => std::slice::from_ref(&c),
+        };
+        for part in parts {
+            let mut glyph = *glyph;
+            glyph.glyph = cmap.glyph_id_for_codepoint(*part as u32);
+            glyph.syllable.category = category(*part);
+            decomposed.push(glyph);
+        }
+    }
+    *glyphs = decomposed;
+}
+
+/// Split glyphs into syllables, and number them. Syllables starting with a mark get a dotted
+/// circle as their base, if the face has one
+pub(crate) fn segment(
+    glyphs: &mut Vec<SubstGlyph>,
+    cmap: &Cmap,
+    is_mark: fn(Category) -> bool,
+    syllable_end: fn(&[SubstGlyph], usize) -> usize,
+) {
+    let dotted_circle = cmap.glyph_id_for_codepoint(0x25CC);
+    let mut start = 0;
+    let mut index = 0;
+    while start < glyphs.len() {
+        if is_mark(glyphs[start].syllable.category) && dotted_circle != GlyphID(0) {
+            let mut circle = glyphs[start];
+            circle.glyph = dotted_circle;
+            circle.syllable.category = Category::Placeholder;
+            glyphs.insert(start, circle);
+        }
+        let end = syllable_end(glyphs, start);
+        for glyph in &mut glyphs[start..end] {
+            glyph.syllable.index = index;
+        }
+        start = end;
+        index += 1;
+    }
+}
+
+/// Call function for the glyphs in each syllable
+pub(crate) fn for_each_syllable<F: FnMut(&mut [SubstGlyph])>(glyphs: &mut [SubstGlyph], mut f: F) {
+    let mut start = 0;
+    while start < glyphs.len() {
+        let index = glyphs[start].syllable.index;
+        let len = glyphs[start..]
+            .iter()
+            .take_while(|g| g.syllable.index == index)
+            .count();
+        f(&mut glyphs[start..start + len]);
+        start += len;
+    }
+}
+
+/// Look up category of character in a sorted table of ranges. Joiners and placeholders have
+/// the same category for all shapers
+pub(crate) fn category(table: &[(u32, u32, Category)], c: char) -> Category {
+    match c {
+        '\u{200C}' => return Category::Zwnj,
+        '\u{200D}' => return Category::Zwj,
+        '\u{00A0}' | '\u{25CC}' => return Category::Placeholder,
+        _ => {}
+    }
+    let c = c as u32;
+    table
+        .binary_search_by(|(start, end, _)| {
+            if *end < c {
+                Ordering::Less
+            } else if *start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map(|i| table[i].2)
+        .unwrap_or(Category::Other)
+}
+
+/// Character categories, for shapers which work on syllables
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Category {
+    Other,
+    Consonant,
+    Ra, // Consonant which may form a reph
+    Vowel,
+    Matra,
+    PreMatra, // Matra displayed before the base consonant
+    Halant,
+    Nukta,
+    Modifier,
+    Zwj,
+    Zwnj,
+    Placeholder, // Stands in for a consonant, e.g. the dotted circle
+    Repha,       // Reph, either encoded or formed by substitution
+    Subjoined,   // Consonant stacked below the base
+}
+
+impl Default for Category {
+    fn default() -> Category {
+        Category::Other
+    }
+}
+
+/// Position of glyph within syllable, in visual order
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Position {
+    Reph,
+    PreMatra,
+    PreBase,
+    Base,
+    PostBase,
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position::Base
+    }
+}
+
+/// Per-glyph state kept by shapers which work on syllables, between substitution stages
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SyllableInfo {
+    pub(crate) index: usize,
+    pub(crate) category: Category,
+    pub(crate) position: Position,
+}
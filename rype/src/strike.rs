@@ -0,0 +1,79 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use geom::{size2, vec2, Size2D, Vector2D};
+
+use crate::raster::{Bitmap, BitmapFormat};
+
+/// Size and position of an embedded bitmap, without its pixels
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BitmapMetrics {
+    pub(crate) size: Size2D<u32>,
+    pub(crate) bearing: Vector2D<i32>, // Offset of top-left pixel from glyph origin, with y up
+}
+
+/// Pick the strike to draw glyphs at the given pixels per em from: the smallest strike that is at
+/// least as large, or the largest strike if there's none
+pub(crate) fn best_strike<I: Iterator<Item = u16>>(ppems: I, ppem: u16) -> Option<usize> {
+    let mut best: Option<(usize, u16)> = None;
+    for (i, strike_ppem) in ppems.enumerate() {
+        best = match best {
+            None => Some((i, strike_ppem)),
+            Some((_, b)) if b < ppem && strike_ppem > b => Some((i, strike_ppem)),
+            Some((_, b)) if strike_ppem >= ppem && strike_ppem < b => Some((i, strike_ppem)),
+            _ => best,
+        };
+    }
+    best.map(|(i, _)| i)
+}
+
+/// Scale bitmap, averaging the source pixels each destination pixel covers
+pub(crate) fn resample(bitmap: &Bitmap, scale: f32) -> Bitmap {
+    if scale == 1.0 || bitmap.size.width == 0 || bitmap.size.height == 0 {
+        return bitmap.clone();
+    }
+    let channels = match bitmap.format {
+        BitmapFormat::Gray => 1,
+        BitmapFormat::Rgb => 3,
+        BitmapFormat::Rgba => 4,
+    };
+    let (bx, by) = (bitmap.bearing.x as f32, bitmap.bearing.y as f32);
+    let (width, height) = (bitmap.size.width as f32, bitmap.size.height as f32);
+    let left = (bx * scale).floor() as i32;
+    let top = (by * scale).ceil() as i32;
+    let right = ((bx + width) * scale).ceil() as i32;
+    let bottom = ((by - height) * scale).floor() as i32;
+    let size = size2((right - left).max(0) as u32, (top - bottom).max(0) as u32);
+
+    // Range of source pixels (from the top-left of the source) that a destination pixel covers
+    let src_x = |x: u32| ((left + x as i32) as f32 / scale - bx).max(0.0).min(width);
+    let src_y = |y: u32| (by - (top - y as i32) as f32 / scale).max(0.0).min(height);
+    let area = 1.0 / (scale * scale);
+    let mut data = vec![0; (size.width * size.height) as usize * channels];
+    for y in 0..size.height {
+        let (y0, y1) = (src_y(y), src_y(y + 1));
+        for x in 0..size.width {
+            let (x0, x1) = (src_x(x), src_x(x + 1));
+            let mut sum = [0.0f32; 4];
+            for sy in y0.floor() as u32..(y1.ceil() as u32).min(bitmap.size.height) {
+                let cover_y = (y1.min(sy as f32 + 1.0) - y0.max(sy as f32)).max(0.0);
+                for sx in x0.floor() as u32..(x1.ceil() as u32).min(bitmap.size.width) {
+                    let cover = cover_y * (x1.min(sx as f32 + 1.0) - x0.max(sx as f32)).max(0.0);
+                    let at = (sy * bitmap.size.width + sx) as usize * channels;
+                    for (s, v) in sum.iter_mut().zip(&bitmap.data[at..at + channels]) {
+                        *s += *v as f32 * cover;
+                    }
+                }
+            }
+            let at = (y * size.width + x) as usize * channels;
+            for c in 0..channels {
+                data[at + c] = (sum[c] / area).round().min(255.0) as u8;
+            }
+        }
+    }
+    Bitmap {
+        size,
+        bearing: vec2(left, top),
+        format: bitmap.format,
+        data,
+    }
+}
@@ -22,6 +22,15 @@ pub(crate) fn get_i16(b: &[u8], offset: usize) -> Result<i16> {
     }
 }
 
+/// Get big-endian 24-bit unsigned integer
+pub(crate) fn get_u24(b: &[u8], offset: usize) -> Result<u32> {
+    if b.len() < offset + 3 {
+        Err(Error::Invalid)
+    } else {
+        Ok(((b[offset] as u32) << 16) | ((b[offset + 1] as u32) << 8) | (b[offset + 2] as u32))
+    }
+}
+
 /// Get big-endian u32
 pub(crate) fn get_u32(b: &[u8], offset: usize) -> Result<u32> {
     if b.len() < offset + 4 {
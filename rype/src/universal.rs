@@ -0,0 +1,480 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use fnv::FnvHashSet;
+
+use crate::cmap::Cmap;
+use crate::common::SubstGlyph;
+use crate::features::{Features, GlyphFeatures};
+use crate::gsub::Gsub;
+use crate::options::{FeatureValues, ShapingOptions};
+use crate::shaper::{self, Category};
+use Category::{
+    Consonant, Halant, Matra, Modifier, Nukta, Placeholder, PreMatra, Repha, Subjoined, Vowel,
+};
+
+/// Features applied by the Universal Shaping Engine, on top of the defaults
+pub(crate) static USE_FEATURES: [Features; 16] = [
+    Features::NuktaForms,
+    Features::Akhand,
+    Features::RephForm,
+    Features::PreBaseForms,
+    Features::RakarForms,
+    Features::AboveBaseForms,
+    Features::BelowBaseForms,
+    Features::HalfForms,
+    Features::PostBaseForms,
+    Features::VattuVariants,
+    Features::ConjunctForms,
+    Features::AboveBaseSubstitutions,
+    Features::BelowBaseSubstitutions,
+    Features::HalantForms,
+    Features::PreBaseSubstitutions,
+    Features::PostBaseSubstitutions,
+];
+
+// Features applied before reordering, in stages. Features within a stage are applied together.
+// Everything else is applied after reordering.
+static PREPROCESSING: [Features; 4] = [
+    Features::LocalizedForms,
+    Features::GlyphCompositionDecomposition,
+    Features::NuktaForms,
+    Features::Akhand,
+];
+static REPH: [Features; 1] = [Features::RephForm];
+static PREF: [Features; 1] = [Features::PreBaseForms];
+static ORTHOGRAPHIC: [Features; 7] = [
+    Features::RakarForms,
+    Features::AboveBaseForms,
+    Features::BelowBaseForms,
+    Features::HalfForms,
+    Features::PostBaseForms,
+    Features::VattuVariants,
+    Features::ConjunctForms,
+];
+
+/// Split text into clusters, reorder them, and substitute glyphs in stages
+pub(crate) fn substitute(
+    glyphs: &mut Vec<SubstGlyph>,
+    codepoints: &[char],
+    cmap: &Cmap,
+    gsub: Option<&Gsub>,
+    options: &ShapingOptions,
+    features: &FeatureValues,
+) {
+    // Split vowels are decomposed, so that the part displayed before the base can be reordered
+    shaper::decompose(glyphs, codepoints, cmap, &SPLIT_VOWELS, category);
+    shaper::segment(glyphs, cmap, is_mark, cluster_end);
+    shaper::for_each_syllable(glyphs, setup_cluster);
+
+    let mut applied = Vec::new();
+    let mut stage = |glyphs: &mut Vec<SubstGlyph>, stage: &[Features]| {
+        let tags = stage.iter().map(|f| f.tag()).collect::<Vec<_>>();
+        let lig_ids = glyphs.iter().map(|g| g.lig.id).collect::<FnvHashSet<_>>();
+        shaper::substitute(glyphs, gsub, options, &features.only(&tags));
+        applied.extend(tags);
+        lig_ids
+    };
+    stage(glyphs, &PREPROCESSING);
+    // Ligatures formed by these features take part in reordering as reph and pre-base forms
+    let lig_ids = stage(glyphs, &REPH);
+    for glyph in glyphs.iter_mut() {
+        if formed_in_stage(glyph, &lig_ids) && glyph.features.contains(GlyphFeatures::RPHF) {
+            glyph.syllable.category = Repha;
+        }
+    }
+    let lig_ids = stage(glyphs, &PREF);
+    for glyph in glyphs.iter_mut() {
+        if formed_in_stage(glyph, &lig_ids) {
+            glyph.syllable.category = PreMatra;
+        }
+    }
+    stage(glyphs, &ORTHOGRAPHIC);
+    shaper::for_each_syllable(glyphs, reorder);
+    shaper::substitute(glyphs, gsub, options, &features.except(&applied));
+}
+
+// Was glyph formed as a ligature by a stage, given the ligature IDs in the text before it?
+fn formed_in_stage(glyph: &SubstGlyph, lig_ids: &FnvHashSet<u16>) -> bool {
+    glyph.lig.num_components > 0 && !lig_ids.contains(&glyph.lig.id)
+}
+
+// Find the end of the cluster starting at the given index
+fn cluster_end(glyphs: &[SubstGlyph], start: usize) -> usize {
+    let cat = |i: usize| {
+        glyphs
+            .get(i)
+            .map(|g| g.syllable.category)
+            .unwrap_or(Category::Other)
+    };
+    let mut i = start;
+    if cat(i) == Repha {
+        i += 1;
+    }
+    match cat(i) {
+        Consonant | Vowel | Placeholder => loop {
+            // Consonants joined by halants, or subjoined consonants
+            i += 1;
+            while cat(i) == Nukta {
+                i += 1;
+            }
+            if cat(i) == Subjoined {
+                continue;
+            }
+            if cat(i) != Halant {
+                break;
+            }
+            let mut j = i + 1;
+            if cat(j) == Category::Zwj || cat(j) == Category::Zwnj {
+                j += 1;
+            }
+            if cat(j) != Consonant {
+                break;
+            }
+            i = j;
+        },
+        c if is_mark(c) => {}
+        _ => return start + 1,
+    }
+    while is_mark(cat(i)) {
+        i += 1;
+    }
+    i.max(start + 1)
+}
+
+// Merge the cluster, and enable features. Only the first glyphs may form a reph, and other
+// features apply everywhere
+fn setup_cluster(cluster: &mut [SubstGlyph]) {
+    let first = cluster.iter().map(|g| g.cluster).min().unwrap_or(0);
+    let reph_len = if cluster[0].syllable.category == Repha {
+        1
+    } else {
+        cluster.len().min(3)
+    };
+    for (i, glyph) in cluster.iter_mut().enumerate() {
+        glyph.cluster = first;
+        glyph.features |= GlyphFeatures::PREF
+            | GlyphFeatures::HALF
+            | GlyphFeatures::BLWF
+            | GlyphFeatures::ABVF
+            | GlyphFeatures::PSTF;
+        if i < reph_len {
+            glyph.features |= GlyphFeatures::RPHF;
+        }
+    }
+}
+
+// Move reph after the base, and pre-base vowels before it
+fn reorder(cluster: &mut [SubstGlyph]) {
+    let len = cluster.len();
+    // Reph goes before the first post-base glyph, or to the end
+    if len > 1 && cluster[0].syllable.category == Repha {
+        let target = (1..len)
+            .find(|i| is_post_base(cluster[*i].syllable.category))
+            .map(|i| i - 1)
+            .unwrap_or(len - 1);
+        cluster[..=target].rotate_left(1);
+    }
+    // Pre-base vowels go after the last halant before them, or to the start
+    let mut j = 0;
+    for i in 0..len {
+        match cluster[i].syllable.category {
+            Halant => j = i + 1,
+            PreMatra if j < i => {
+                cluster[j..=i].rotate_right(1);
+                j += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_mark(category: Category) -> bool {
+    matches!(
+        category,
+        Matra | PreMatra | Halant | Nukta | Modifier | Subjoined
+    )
+}
+
+fn is_post_base(category: Category) -> bool {
+    matches!(category, Matra | PreMatra | Halant | Modifier)
+}
+
+fn category(c: char) -> Category {
+    shaper::category(&CATEGORIES, c)
+}
+
+// Vowels which are displayed partly before and partly after the base
+static SPLIT_VOWELS: [(char, [char; 2]); 10] = [
+    ('\u{0DDA}', ['\u{0DD9}', '\u{0DCA}']),
+    ('\u{0DDC}', ['\u{0DD9}', '\u{0DCF}']),
+    ('\u{0DDE}', ['\u{0DD9}', '\u{0DDF}']),
+    ('\u{1B40}', ['\u{1B3E}', '\u{1B35}']),
+    ('\u{1B41}', ['\u{1B3F}', '\u{1B35}']),
+    ('\u{1134B}', ['\u{11347}', '\u{1133E}']),
+    ('\u{1134C}', ['\u{11347}', '\u{11357}']),
+    ('\u{114BB}', ['\u{114B9}', '\u{114BA}']),
+    ('\u{114BC}', ['\u{114B9}', '\u{114B0}']),
+    ('\u{114BE}', ['\u{114B9}', '\u{114BD}']),
+];
+
+// Categories for ranges of characters in scripts handled by the Universal Shaping Engine
+static CATEGORIES: [(u32, u32, Category); 175] = [
+    (0x0D81, 0x0D83, Modifier),
+    (0x0D85, 0x0D96, Vowel),
+    (0x0D9A, 0x0D9D, Vowel),
+    (0x0D9E, 0x0D9E, Consonant),
+    (0x0D9F, 0x0DA3, Vowel),
+    (0x0DA4, 0x0DA5, Consonant),
+    (0x0DA6, 0x0DB1, Vowel),
+    (0x0DB3, 0x0DBB, Vowel),
+    (0x0DBD, 0x0DBD, Vowel),
+    (0x0DC0, 0x0DC6, Vowel),
+    (0x0DCA, 0x0DCA, Halant),
+    (0x0DCF, 0x0DD4, Matra),
+    (0x0DD6, 0x0DD6, Matra),
+    (0x0DD8, 0x0DD8, Matra),
+    (0x0DD9, 0x0DDB, PreMatra),
+    (0x0DDC, 0x0DDF, Matra),
+    (0x0DF2, 0x0DF3, Matra),
+    (0x0F18, 0x0F19, Modifier),
+    (0x0F35, 0x0F35, Modifier),
+    (0x0F37, 0x0F37, Modifier),
+    (0x0F39, 0x0F39, Modifier),
+    (0x0F3E, 0x0F3F, Modifier),
+    (0x0F40, 0x0F47, Consonant),
+    (0x0F49, 0x0F67, Consonant),
+    (0x0F68, 0x0F68, Vowel),
+    (0x0F69, 0x0F6C, Consonant),
+    (0x0F71, 0x0F7D, Matra),
+    (0x0F7E, 0x0F7F, Modifier),
+    (0x0F80, 0x0F81, Matra),
+    (0x0F82, 0x0F83, Modifier),
+    (0x0F84, 0x0F84, Halant),
+    (0x0F86, 0x0F87, Modifier),
+    (0x0F8D, 0x0F97, Subjoined),
+    (0x0F99, 0x0FBC, Subjoined),
+    (0x0FC6, 0x0FC6, Modifier),
+    (0x1A00, 0x1A14, Consonant),
+    (0x1A15, 0x1A15, Vowel),
+    (0x1A16, 0x1A16, Consonant),
+    (0x1A17, 0x1A18, Matra),
+    (0x1A19, 0x1A19, PreMatra),
+    (0x1A1A, 0x1A1B, Matra),
+    (0x1B00, 0x1B04, Modifier),
+    (0x1B05, 0x1B33, Consonant),
+    (0x1B34, 0x1B34, Modifier),
+    (0x1B35, 0x1B3D, Matra),
+    (0x1B3E, 0x1B3F, PreMatra),
+    (0x1B40, 0x1B43, Matra),
+    (0x1B44, 0x1B44, Halant),
+    (0x1B45, 0x1B4C, Consonant),
+    (0x1B6B, 0x1B73, Modifier),
+    (0x1B80, 0x1B82, Modifier),
+    (0x1B83, 0x1B88, Vowel),
+    (0x1B89, 0x1BA0, Consonant),
+    (0x1BA1, 0x1BA5, Matra),
+    (0x1BA6, 0x1BA6, PreMatra),
+    (0x1BA7, 0x1BA9, Matra),
+    (0x1BAA, 0x1BAB, Halant),
+    (0x1BAC, 0x1BAD, Matra),
+    (0x1BAE, 0x1BAF, Consonant),
+    (0x1BBB, 0x1BBF, Consonant),
+    (0x1BC0, 0x1BC0, Vowel),
+    (0x1BC1, 0x1BE3, Consonant),
+    (0x1BE4, 0x1BE5, Vowel),
+    (0x1BE6, 0x1BE6, Modifier),
+    (0x1BE7, 0x1BF1, Matra),
+    (0x1BF2, 0x1BF3, Halant),
+    (0x1C00, 0x1C22, Consonant),
+    (0x1C23, 0x1C23, Vowel),
+    (0x1C24, 0x1C25, Subjoined),
+    (0x1C26, 0x1C35, Matra),
+    (0x1C36, 0x1C36, Modifier),
+    (0x1C37, 0x1C37, Nukta),
+    (0x1C4D, 0x1C4F, Consonant),
+    (0xA880, 0xA881, Modifier),
+    (0xA882, 0xA891, Vowel),
+    (0xA892, 0xA8B3, Consonant),
+    (0xA8B4, 0xA8C3, Matra),
+    (0xA8C4, 0xA8C4, Halant),
+    (0xA8C5, 0xA8C5, Modifier),
+    (0xA930, 0xA945, Consonant),
+    (0xA946, 0xA946, Vowel),
+    (0xA947, 0xA952, Matra),
+    (0xA953, 0xA953, Halant),
+    (0xA980, 0xA983, Modifier),
+    (0xA984, 0xA984, Vowel),
+    (0xA985, 0xA985, Consonant),
+    (0xA986, 0xA988, Vowel),
+    (0xA989, 0xA98B, Consonant),
+    (0xA98C, 0xA98E, Vowel),
+    (0xA98F, 0xA9B2, Consonant),
+    (0xA9B3, 0xA9B3, Modifier),
+    (0xA9B4, 0xA9B9, Matra),
+    (0xA9BA, 0xA9BB, PreMatra),
+    (0xA9BC, 0xA9BF, Matra),
+    (0xA9C0, 0xA9C0, Halant),
+    (0x11000, 0x11002, Modifier),
+    (0x11005, 0x11012, Vowel),
+    (0x11013, 0x11037, Consonant),
+    (0x11038, 0x11045, Matra),
+    (0x11046, 0x11046, Halant),
+    (0x11070, 0x11070, Halant),
+    (0x11071, 0x11072, Consonant),
+    (0x11073, 0x11074, Matra),
+    (0x11075, 0x11075, Consonant),
+    (0x1107F, 0x1107F, Halant),
+    (0x11080, 0x11082, Modifier),
+    (0x11083, 0x1108C, Vowel),
+    (0x1108D, 0x110AF, Consonant),
+    (0x110B0, 0x110B0, Matra),
+    (0x110B1, 0x110B1, PreMatra),
+    (0x110B2, 0x110B8, Matra),
+    (0x110B9, 0x110B9, Halant),
+    (0x110BA, 0x110BA, Nukta),
+    (0x110C2, 0x110C2, Matra),
+    (0x11100, 0x11102, Modifier),
+    (0x11103, 0x11106, Vowel),
+    (0x11107, 0x11126, Consonant),
+    (0x11127, 0x11130, Matra),
+    (0x11131, 0x11132, Modifier),
+    (0x11133, 0x11134, Halant),
+    (0x11144, 0x11144, Consonant),
+    (0x11145, 0x11146, Matra),
+    (0x11147, 0x11147, Consonant),
+    (0x11180, 0x11182, Modifier),
+    (0x11183, 0x11190, Vowel),
+    (0x11191, 0x111B2, Consonant),
+    (0x111B3, 0x111B3, Matra),
+    (0x111B4, 0x111B4, PreMatra),
+    (0x111B5, 0x111BF, Matra),
+    (0x111C0, 0x111C0, Halant),
+    (0x111C9, 0x111C9, Modifier),
+    (0x111CA, 0x111CA, Nukta),
+    (0x111CB, 0x111CC, Modifier),
+    (0x111CE, 0x111CE, Matra),
+    (0x111CF, 0x111CF, Modifier),
+    (0x11300, 0x11303, Modifier),
+    (0x11305, 0x1130C, Vowel),
+    (0x1130F, 0x11310, Vowel),
+    (0x11313, 0x11314, Vowel),
+    (0x11315, 0x11328, Consonant),
+    (0x1132A, 0x11330, Consonant),
+    (0x11332, 0x11333, Consonant),
+    (0x11335, 0x11339, Consonant),
+    (0x1133B, 0x1133B, Modifier),
+    (0x1133C, 0x1133C, Nukta),
+    (0x1133E, 0x11344, Matra),
+    (0x11347, 0x11348, PreMatra),
+    (0x1134B, 0x1134C, Matra),
+    (0x1134D, 0x1134D, Halant),
+    (0x11357, 0x11357, Modifier),
+    (0x1135E, 0x1135F, Consonant),
+    (0x11360, 0x11361, Vowel),
+    (0x11362, 0x11363, Matra),
+    (0x11366, 0x1136C, Modifier),
+    (0x11370, 0x11374, Modifier),
+    (0x11400, 0x1140D, Vowel),
+    (0x1140E, 0x11434, Consonant),
+    (0x11435, 0x11435, Matra),
+    (0x11436, 0x11436, PreMatra),
+    (0x11437, 0x11441, Matra),
+    (0x11442, 0x11442, Halant),
+    (0x11443, 0x11445, Modifier),
+    (0x11446, 0x11446, Nukta),
+    (0x1145E, 0x1145E, Modifier),
+    (0x1145F, 0x1145F, Consonant),
+    (0x11481, 0x1148E, Vowel),
+    (0x1148F, 0x114AF, Consonant),
+    (0x114B0, 0x114B0, Matra),
+    (0x114B1, 0x114B1, PreMatra),
+    (0x114B2, 0x114B8, Matra),
+    (0x114B9, 0x114B9, PreMatra),
+    (0x114BA, 0x114BE, Matra),
+    (0x114BF, 0x114C1, Modifier),
+    (0x114C2, 0x114C2, Halant),
+    (0x114C3, 0x114C3, Nukta),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{GlyphID, LigatureInfo};
+    use crate::direction::Direction;
+    use crate::shaper::SyllableInfo;
+    use crate::Script;
+
+    fn push(data: &mut Vec<u8>, values: &[u16]) {
+        for v in values {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    // Map each of the given characters to glyphs, numbered in order
+    fn cmap(chars: &[char]) -> Cmap {
+        let mut data = Vec::new();
+        push(&mut data, &[0, 1, 3, 10, 0, 12]);
+        let len = 16 + chars.len() as u32 * 12;
+        push(
+            &mut data,
+            &[12, 0, 0, len as u16, 0, 0, 0, chars.len() as u16],
+        );
+        for (i, c) in chars.iter().enumerate() {
+            let c = *c as u32;
+            push(&mut data, &[0, c as u16, 0, c as u16, 0, i as u16 + 1]);
+        }
+        Cmap::load(&data).unwrap()
+    }
+
+    // GSUB with rphf and pref features, each forming a ligature from a pair of glyphs
+    fn gsub(rphf: [u16; 3], pref: [u16; 3]) -> Gsub {
+        let ligature = |[first, second, lig]: [u16; 3]| {
+            [4, 0, 1, 8, 1, 8, 1, 14, 1, 1, first, 1, 4, lig, 2, second]
+        };
+        let mut data = Vec::new();
+        push(&mut data, &[1, 0, 10, 32, 58]);
+        // Script list, with only the default language system
+        push(&mut data, &[1, 0x4446, 0x4c54, 8, 4, 0, 0, 0xffff, 2, 0, 1]);
+        // Feature list
+        push(&mut data, &[2, 0x7270, 0x6866, 14, 0x7072, 0x6566, 20]);
+        push(&mut data, &[0, 1, 0, 0, 1, 1]);
+        // Lookup list
+        push(&mut data, &[2, 6, 38]);
+        push(&mut data, &ligature(rphf));
+        push(&mut data, &ligature(pref));
+        Gsub::load(&data, None).unwrap()
+    }
+
+    #[test]
+    fn reph_and_pre_base_form() {
+        // KA, followed by a cluster of RA, ADEG ADEG, KA, ADEG ADEG, YA
+        let chars = ['\u{1B13}', '\u{1B2C}', '\u{1B2D}', '\u{1B44}'];
+        let text = [chars[0], chars[2], chars[3], chars[0], chars[3], chars[1]];
+        let cmap = cmap(&chars);
+        let gsub = gsub([3, 4, 5], [4, 2, 6]);
+        let mut glyphs = text
+            .iter()
+            .enumerate()
+            .map(|(i, c)| SubstGlyph {
+                glyph: cmap.glyph_id_for_codepoint(*c as u32),
+                cluster: i,
+                lig: LigatureInfo::default(),
+                features: GlyphFeatures::empty(),
+                syllable: SyllableInfo::default(),
+            })
+            .collect::<Vec<_>>();
+        // A ligature formed earlier, so that IDs wrap around while forming the pre-base form
+        glyphs[0].lig = LigatureInfo {
+            id: 65534,
+            component: 0,
+            num_components: 2,
+        };
+        let options = ShapingOptions::new(Script::Balinese, Direction::LeftToRight);
+        let features = options.feature_values(&USE_FEATURES);
+        substitute(&mut glyphs, &text, &cmap, Some(&gsub), &options, &features);
+        let ids = glyphs.iter().map(|g| g.glyph).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 6, 1, 5].map(GlyphID::new));
+        assert_eq!(glyphs[1].syllable.category, PreMatra);
+        assert_eq!(glyphs[3].syllable.category, Repha);
+    }
+}
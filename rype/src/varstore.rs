@@ -0,0 +1,218 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::*;
+use crate::types::{get_i16, get_u16, get_u32};
+
+/// Maps indices of items (like glyphs) to the outer and inner indices of their delta sets
+#[derive(Debug)]
+pub(crate) struct DeltaSetIndexMap(Vec<(u16, u16)>);
+
+impl DeltaSetIndexMap {
+    pub(crate) fn load(data: &[u8]) -> Result<DeltaSetIndexMap> {
+        let format = *data.get(offsets::MAP_FORMAT).ok_or(Error::Invalid)?;
+        let entry_format = *data.get(offsets::MAP_ENTRY_FORMAT).ok_or(Error::Invalid)?;
+        let (count, mut off) = match format {
+            0 => (get_u16(data, offsets::MAP_COUNT)? as usize, 4),
+            1 => (get_u32(data, offsets::MAP_COUNT)? as usize, 6),
+            _ => return Err(Error::Invalid),
+        };
+        let entry_size = ((entry_format & 0x30) >> 4) as usize + 1;
+        let inner_bits = (entry_format & 0x0f) as u32 + 1;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let bytes = data.get(off..off + entry_size).ok_or(Error::Invalid)?;
+            let entry = bytes.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+            entries.push((
+                (entry >> inner_bits) as u16,
+                (entry & ((1 << inner_bits) - 1)) as u16,
+            ));
+            off += entry_size;
+        }
+        Ok(DeltaSetIndexMap(entries))
+    }
+
+    /// Get outer and inner indices for item. Items past the end use the last entry
+    pub(crate) fn get(&self, index: u32) -> Option<(u16, u16)> {
+        self.0
+            .get(index as usize)
+            .or_else(|| self.0.last())
+            .copied()
+    }
+}
+
+/// Wrapper around item variation store, which holds deltas for values in a variable font
+#[derive(Debug)]
+pub(crate) struct ItemVariationStore {
+    regions: Vec<Vec<RegionAxis>>, // Coordinates on each axis, for each region
+    data: Vec<ItemVariationData>,
+}
+
+impl ItemVariationStore {
+    pub(crate) fn load(data: &[u8]) -> Result<ItemVariationStore> {
+        if get_u16(data, offsets::FORMAT)? != 1 {
+            return Err(Error::Invalid);
+        }
+        let region_list_off = get_u32(data, offsets::REGION_LIST)? as usize;
+        let axis_count = get_u16(data, region_list_off + offsets::AXIS_COUNT)? as usize;
+        let region_count = get_u16(data, region_list_off + offsets::REGION_COUNT)? as usize;
+        let mut off = region_list_off + offsets::REGIONS;
+        let mut regions = Vec::with_capacity(region_count);
+        for _ in 0..region_count {
+            let mut axes = Vec::with_capacity(axis_count);
+            for _ in 0..axis_count {
+                axes.push(RegionAxis {
+                    start: get_f2dot14(data, off)?,
+                    peak: get_f2dot14(data, off + 2)?,
+                    end: get_f2dot14(data, off + 4)?,
+                });
+                off += sizes::REGION_AXIS;
+            }
+            regions.push(axes);
+        }
+        let count = get_u16(data, offsets::DATA_COUNT)? as usize;
+        let mut var_data = Vec::with_capacity(count);
+        for i in 0..count {
+            let data_off = get_u32(data, offsets::DATA_OFFSETS + i * 4)? as usize;
+            var_data.push(ItemVariationData::load(data, data_off)?);
+        }
+        Ok(ItemVariationStore {
+            regions,
+            data: var_data,
+        })
+    }
+
+    /// Get scalars for each region referenced by the given item variation data, at the given
+    /// normalized coordinates
+    pub(crate) fn region_scalars(&self, outer: usize, coords: &[f32]) -> Result<Vec<f32>> {
+        let data = self.data.get(outer).ok_or(Error::Invalid)?;
+        data.region_indices
+            .iter()
+            .map(|i| {
+                let region = self.regions.get(*i as usize).ok_or(Error::Invalid)?;
+                Ok(region
+                    .iter()
+                    .enumerate()
+                    .map(|(axis, r)| r.scalar(coords.get(axis).copied().unwrap_or(0.0)))
+                    .product())
+            })
+            .collect()
+    }
+
+    /// Get delta for an item at the given normalized coordinates
+    pub(crate) fn delta(&self, outer: u16, inner: u16, coords: &[f32]) -> Result<f32> {
+        if coords.iter().all(|c| *c == 0.0) {
+            return Ok(0.0);
+        }
+        let scalars = self.region_scalars(outer as usize, coords)?;
+        let data = &self.data[outer as usize];
+        let start = inner as usize * scalars.len();
+        let deltas = data
+            .deltas
+            .get(start..start + scalars.len())
+            .ok_or(Error::Invalid)?;
+        Ok(deltas
+            .iter()
+            .zip(&scalars)
+            .map(|(d, s)| *d as f32 * s)
+            .sum())
+    }
+}
+
+#[derive(Debug)]
+struct ItemVariationData {
+    region_indices: Vec<u16>,
+    deltas: Vec<i32>, // Delta for each region, for each item
+}
+
+impl ItemVariationData {
+    fn load(data: &[u8], offset: usize) -> Result<ItemVariationData> {
+        let item_count = get_u16(data, offset + offsets::ITEM_COUNT)? as usize;
+        let word_delta_count = get_u16(data, offset + offsets::WORD_DELTA_COUNT)?;
+        let region_index_count = get_u16(data, offset + offsets::REGION_INDEX_COUNT)? as usize;
+        let region_indices = (0..region_index_count)
+            .map(|i| get_u16(data, offset + offsets::REGION_INDICES + i * 2))
+            .collect::<Result<Vec<_>>>()?;
+        // The first few deltas in each row are wider than the rest
+        let long_words = word_delta_count & LONG_WORDS != 0;
+        let word_count = (word_delta_count & !LONG_WORDS) as usize;
+        let (word_size, short_size) = if long_words { (4, 2) } else { (2, 1) };
+        let mut off = offset + offsets::REGION_INDICES + region_index_count * 2;
+        let mut deltas = Vec::new();
+        for _ in 0..item_count {
+            for i in 0..region_index_count {
+                let size = if i < word_count {
+                    word_size
+                } else {
+                    short_size
+                };
+                deltas.push(match size {
+                    4 => get_u32(data, off)? as i32,
+                    2 => get_i16(data, off)? as i32,
+                    _ => *data.get(off).ok_or(Error::Invalid)? as i8 as i32,
+                });
+                off += size;
+            }
+        }
+        Ok(ItemVariationData {
+            region_indices,
+            deltas,
+        })
+    }
+}
+
+const LONG_WORDS: u16 = 0x8000;
+
+/// Region of the variation space along a single axis
+#[derive(Debug)]
+pub(crate) struct RegionAxis {
+    pub(crate) start: f32,
+    pub(crate) peak: f32,
+    pub(crate) end: f32,
+}
+
+impl RegionAxis {
+    /// Get scalar for normalized coordinate along this axis
+    pub(crate) fn scalar(&self, coord: f32) -> f32 {
+        let RegionAxis { start, peak, end } = *self;
+        // Axes with invalid ranges or a zero peak have no effect
+        let ignored = start > peak || peak > end || (start < 0.0 && end > 0.0) || peak == 0.0;
+        if ignored || coord == peak {
+            1.0
+        } else if coord <= start || coord >= end {
+            0.0
+        } else if coord < peak {
+            (coord - start) / (peak - start)
+        } else {
+            (end - coord) / (end - peak)
+        }
+    }
+}
+
+/// Get big-endian 2.14 fixed-point number
+pub(crate) fn get_f2dot14(data: &[u8], offset: usize) -> Result<f32> {
+    get_i16(data, offset).map(|v| v as f32 / 16384.0)
+}
+
+mod offsets {
+    pub(super) const MAP_FORMAT: usize = 0;
+    pub(super) const MAP_ENTRY_FORMAT: usize = 1;
+    pub(super) const MAP_COUNT: usize = 2;
+
+    pub(super) const FORMAT: usize = 0;
+    pub(super) const REGION_LIST: usize = 2;
+    pub(super) const DATA_COUNT: usize = 6;
+    pub(super) const DATA_OFFSETS: usize = 8;
+
+    pub(super) const AXIS_COUNT: usize = 0;
+    pub(super) const REGION_COUNT: usize = 2;
+    pub(super) const REGIONS: usize = 4;
+
+    pub(super) const ITEM_COUNT: usize = 0;
+    pub(super) const WORD_DELTA_COUNT: usize = 2;
+    pub(super) const REGION_INDEX_COUNT: usize = 4;
+    pub(super) const REGION_INDICES: usize = 6;
+}
+
+mod sizes {
+    pub(super) const REGION_AXIS: usize = 6;
+}
@@ -0,0 +1,21 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::error::Result;
+use crate::types::get_u16;
+
+/// Wrapper around the vertical head table
+#[derive(Debug)]
+pub(crate) struct Vhea {
+    pub(crate) num_v_metrics: u16,
+}
+
+impl Vhea {
+    pub(crate) fn load(data: &[u8]) -> Result<Vhea> {
+        let num_v_metrics = get_u16(data, offsets::NUM_V_METRICS)?;
+        Ok(Vhea { num_v_metrics })
+    }
+}
+
+mod offsets {
+    pub(super) const NUM_V_METRICS: usize = 34;
+}
@@ -0,0 +1,55 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::types::{get_i16, get_u16};
+
+/// Vertical metrics for a glyph
+#[derive(Debug)]
+pub(crate) struct GlyphVerMetrics {
+    pub(crate) advance_height: u16,
+    pub(crate) tsb: i16, // Top side bearing
+}
+
+/// Wrapper around vertical metrics table
+#[derive(Debug)]
+pub(crate) struct Vmtx {
+    advance_heights: Vec<u16>, // Glyphs past the last share its advance height
+    tsbs: Vec<i16>,
+}
+
+impl Vmtx {
+    pub(crate) fn load(data: &[u8], num_glyphs: usize, num_v_metrics: usize) -> Result<Vmtx> {
+        if num_v_metrics == 0 || num_v_metrics > num_glyphs {
+            return Err(Error::Invalid);
+        }
+        let advance_heights = (0..num_v_metrics)
+            .map(|i| get_u16(data, i * 4))
+            .collect::<Result<Vec<_>>>()?;
+        let tsbs = (0..num_glyphs)
+            .map(|i| {
+                if i < num_v_metrics {
+                    get_i16(data, i * 4 + 2)
+                } else {
+                    get_i16(data, num_v_metrics * 4 + (i - num_v_metrics) * 2)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Vmtx {
+            advance_heights,
+            tsbs,
+        })
+    }
+
+    pub(crate) fn get_metrics(&self, glyph: GlyphID) -> GlyphVerMetrics {
+        let glyph = glyph.0 as usize;
+        GlyphVerMetrics {
+            advance_height: *self
+                .advance_heights
+                .get(glyph)
+                .or_else(|| self.advance_heights.last())
+                .unwrap(),
+            tsb: self.tsbs.get(glyph).copied().unwrap_or(0),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::common::GlyphID;
+use crate::error::*;
+use crate::types::{get_i16, get_u16};
+
+/// Wrapper around vertical origin table, for fonts with CFF outlines
+#[derive(Debug)]
+pub(crate) struct Vorg {
+    default_origin_y: i16,
+    origins: Vec<(u16, i16)>, // Glyphs with origins other than the default, sorted by glyph
+}
+
+impl Vorg {
+    pub(crate) fn load(data: &[u8]) -> Result<Vorg> {
+        let default_origin_y = get_i16(data, offsets::DEFAULT_VERT_ORIGIN_Y)?;
+        let num_metrics = get_u16(data, offsets::NUM_VERT_ORIGIN_Y_METRICS)? as usize;
+        let origins = (0..num_metrics)
+            .map(|i| {
+                let off = offsets::VERT_ORIGIN_Y_METRICS + i * sizes::VERT_ORIGIN_Y_METRICS;
+                Ok((get_u16(data, off)?, get_i16(data, off + 2)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Vorg {
+            default_origin_y,
+            origins,
+        })
+    }
+
+    /// Get y-coordinate of the vertical origin of glyph, in font units
+    pub(crate) fn origin_y(&self, glyph: GlyphID) -> i16 {
+        match self
+            .origins
+            .binary_search_by_key(&glyph.0, |(g, _)| *g as u32)
+        {
+            Ok(i) => self.origins[i].1,
+            Err(_) => self.default_origin_y,
+        }
+    }
+}
+
+mod offsets {
+    pub(super) const DEFAULT_VERT_ORIGIN_Y: usize = 4;
+    pub(super) const NUM_VERT_ORIGIN_Y_METRICS: usize = 6;
+    pub(super) const VERT_ORIGIN_Y_METRICS: usize = 8;
+}
+
+mod sizes {
+    pub(super) const VERT_ORIGIN_Y_METRICS: usize = 4;
+}